@@ -2,7 +2,7 @@ use std::mem;
 
 use bevy::prelude::*;
 use bevy::render::camera::ExtractedCamera as BevyExtractedCamera;
-use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_resource::PrimitiveTopology;
 use bevy::render::renderer::{RenderDevice, RenderQueue};
@@ -36,87 +36,70 @@ pub(crate) fn meshes(
             continue;
         }
 
-        let mesh_positions = mesh
+        let Some(mesh_positions) = mesh
             .mesh
             .attribute(Mesh::ATTRIBUTE_POSITION)
             .and_then(VertexAttributeValues::as_float3)
-            .unwrap_or_else(|| {
-                panic!("mesh {:?} has no positions", mesh.handle);
-            });
+        else {
+            warn!("Mesh {:?} has no positions; skipping it", mesh.handle);
+            continue;
+        };
 
         let mesh_normals = mesh
             .mesh
             .attribute(Mesh::ATTRIBUTE_NORMAL)
             .and_then(VertexAttributeValues::as_float3)
-            .unwrap_or_else(|| {
-                panic!("mesh {:?} has no normals", mesh.handle);
-            });
-
-        let mesh_uvs = mesh
-            .mesh
-            .attribute(Mesh::ATTRIBUTE_UV_0)
-            .map(|uvs| match uvs {
-                VertexAttributeValues::Float32x2(uvs) => uvs,
-                _ => {
-                    panic!(
-                        "mesh {:?} uses unsupported format for UVs",
-                        mesh.handle
-                    )
-                }
-            })
-            .map(|uvs| uvs.as_slice())
             .unwrap_or(&[]);
 
-        let mesh_tans = mesh
-            .mesh
-            .attribute(Mesh::ATTRIBUTE_TANGENT)
-            .map(|uvs| match uvs {
-                VertexAttributeValues::Float32x4(tangents) => tangents,
-                _ => panic!(
-                    "mesh {:?} uses unsupported format for tangents",
-                    mesh.handle
-                ),
-            })
-            .map(|tangents| tangents.as_slice())
-            .unwrap_or(&[]);
+        let mesh_uvs = match mesh.mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => uvs.as_slice(),
+            Some(_) => {
+                warn!("Mesh {:?} has unsupported UVs", mesh.handle);
+                &[]
+            }
+            None => &[],
+        };
 
-        let mesh_indices: Vec<_> = mesh
-            .mesh
-            .indices()
-            .unwrap_or_else(|| {
-                panic!("mesh {:?} has no indices", mesh.handle);
-            })
-            .iter()
-            .collect();
-
-        let mesh_triangles: Vec<_> = mesh_indices
-            .chunks(3)
-            .map(|vs| {
-                let position0 = mesh_positions[vs[0]];
-                let position1 = mesh_positions[vs[1]];
-                let position2 = mesh_positions[vs[2]];
-
-                let normal0 = mesh_normals[vs[0]];
-                let normal1 = mesh_normals[vs[1]];
-                let normal2 = mesh_normals[vs[2]];
-
-                let uv0 = mesh_uvs.get(vs[0]).copied().unwrap_or_default();
-                let uv1 = mesh_uvs.get(vs[1]).copied().unwrap_or_default();
-                let uv2 = mesh_uvs.get(vs[2]).copied().unwrap_or_default();
-
-                let tan0 = mesh_tans.get(vs[0]).copied().unwrap_or_default();
-                let tan1 = mesh_tans.get(vs[1]).copied().unwrap_or_default();
-                let tan2 = mesh_tans.get(vs[2]).copied().unwrap_or_default();
-
-                st::MeshTriangle::default()
-                    .with_positions([position0, position1, position2])
-                    .with_normals([normal0, normal1, normal2])
-                    .with_uvs([uv0, uv1, uv2])
-                    .with_tangents([tan0, tan1, tan2])
-            })
-            .collect();
-
-        engine.insert_mesh(mesh.handle, st::Mesh::new(mesh_triangles));
+        let mesh_tans = match mesh.mesh.attribute(Mesh::ATTRIBUTE_TANGENT) {
+            Some(VertexAttributeValues::Float32x4(tans)) => tans.as_slice(),
+            Some(_) => {
+                warn!("Mesh {:?} has unsupported tangents", mesh.handle);
+                &[]
+            }
+            None => &[],
+        };
+
+        let mesh_colors = match mesh.mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+            Some(VertexAttributeValues::Float32x4(colors)) => colors.as_slice(),
+            Some(_) => {
+                warn!("Mesh {:?} has unsupported colors", mesh.handle);
+                &[]
+            }
+            None => &[],
+        };
+
+        let mut st_mesh = st::Mesh::default()
+            .with_positions(mesh_positions.iter().copied())
+            .with_normals(mesh_normals.iter().copied())
+            .with_uvs(mesh_uvs.iter().copied())
+            .with_tangents(mesh_tans.iter().copied())
+            .with_colors(mesh_colors.iter().copied());
+
+        match mesh.mesh.indices() {
+            Some(Indices::U16(indices)) => {
+                st_mesh = st_mesh.with_indices(indices.clone());
+            }
+            Some(Indices::U32(indices)) => {
+                st_mesh = st_mesh.with_indices(indices.clone());
+            }
+            None => (),
+        }
+
+        if mesh_normals.is_empty() {
+            st_mesh.preprocess(st::MeshPreprocessing::default());
+        }
+
+        engine.insert_mesh(mesh.handle, st_mesh);
     }
 }
 
@@ -134,13 +117,6 @@ pub(crate) fn materials(
 
             match mat.alpha_mode {
                 AlphaMode::Opaque => color.xyz().extend(1.0),
-                AlphaMode::Mask(mask) => {
-                    if color.w >= mask {
-                        color.xyz().extend(1.0)
-                    } else {
-                        color.xyz().extend(0.0)
-                    }
-                }
                 _ => color,
             }
         };
@@ -149,6 +125,7 @@ pub(crate) fn materials(
 
         let alpha_mode = match mat.alpha_mode {
             AlphaMode::Opaque => st::AlphaMode::Opaque,
+            AlphaMode::Mask(cutoff) => st::AlphaMode::Mask { cutoff },
             _ => st::AlphaMode::Blend,
         };
 
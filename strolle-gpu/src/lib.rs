@@ -11,6 +11,7 @@ mod camera;
 mod gbuffer;
 mod hit;
 mod light;
+mod light_tree;
 mod lights;
 mod material;
 mod materials;
@@ -33,6 +34,7 @@ pub use self::camera::*;
 pub use self::gbuffer::*;
 pub use self::hit::*;
 pub use self::light::*;
+pub use self::light_tree::*;
 pub use self::lights::*;
 pub use self::material::*;
 pub use self::materials::*;
@@ -67,9 +69,11 @@ pub type BvhStack<'a> = &'a mut [u32; BVH_STACK_SIZE * 8 * 8];
 
 /// Maximum stack size per each workgroup-thread when traversing the BVH.
 ///
-/// Affects the maximum size of BVH tree (it must not grow larger than
-/// `2 ^ BVH_STACK_SIZE`).
-pub const BVH_STACK_SIZE: usize = 24;
+/// Affects the maximum size of BVH tree - since the stack is shared between the
+/// top-level tree and the mesh's tree we're currently inside of, their combined
+/// depth must not exceed this value (traversal never overflows the stack, but
+/// nodes that don't fit are skipped).
+pub const BVH_STACK_SIZE: usize = 32;
 
 /// Golden angle, used for spatial filters.
 pub const GOLDEN_ANGLE: f32 = 2.39996;
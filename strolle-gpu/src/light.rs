@@ -5,49 +5,130 @@ use glam::{vec2, vec4, Vec2, Vec3, Vec4, Vec4Swizzles};
 #[cfg(target_arch = "spirv")]
 use spirv_std::num_traits::Float;
 
-use crate::{DiffuseBrdf, F32Ext, Hit, Normal, Ray, WhiteNoise};
+use crate::{DiffuseBrdf, F32Ext, Hit, Normal, Ray, Vec3Ext, WhiteNoise};
 
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
 pub struct Light {
-    /// x - position x
-    /// y - position y
-    /// z - position z
-    /// w - radius
+    /// x - position x (or, if it's a directional light: direction x; if it's
+    ///     a triangle light: first vertex x)
+    /// y - position y (or, if it's a directional light: direction y; if it's
+    ///     a triangle light: first vertex y)
+    /// z - position z (or, if it's a directional light: direction z; if it's
+    ///     a triangle light: first vertex z)
+    /// w - radius (or, if it's a directional light: angular radius; if it's
+    ///     an area light: unused)
     pub d0: Vec4,
 
     /// x - color r
     /// y - color g
     /// z - color b
-    /// w - range
+    /// w - range (or, if it's a triangle light: cumulative power, see
+    ///     [`crate::LightsView::sample_triangle()`])
     pub d1: Vec4,
 
-    /// x - (as u32) light type: 0 - point light, 1 - spot light
-    /// y - if it's a spot light: direction
-    /// z - if it's a spot light: direction
-    /// w - if it's a spot light: angle
+    /// x - (as u32) light type: 0 - point light, 1 - spot light, 2 -
+    ///     directional light, 3 - rectangle light, 4 - disk light, 5 -
+    ///     triangle light, 6 - tombstone (see [`Self::tombstone()`])
+    /// y - if it's a spot light: direction; if it's an area light: u-axis x
+    /// z - if it's a spot light: direction; if it's an area light: u-axis y
+    /// w - if it's a spot light: angle; if it's an area light: u-axis z
     pub d2: Vec4,
+
+    /// x - if it's an area light: v-axis x
+    /// y - if it's an area light: v-axis y
+    /// z - if it's an area light: v-axis z
+    /// w - if it's an area light: (as u32) whether it's two-sided
+    pub d3: Vec4,
 }
 
 impl Light {
     pub const TYPE_POINT: u32 = 0;
     pub const TYPE_SPOT: u32 = 1;
-
-    pub fn sun(position: Vec3, color: Vec3) -> Self {
+    pub const TYPE_DIRECTIONAL: u32 = 2;
+    pub const TYPE_RECT: u32 = 3;
+    pub const TYPE_DISK: u32 = 4;
+    pub const TYPE_TRIANGLE: u32 = 5;
+    pub const TYPE_TOMBSTONE: u32 = 6;
+
+    /// Distance from which shadow rays of directional lights are cast, i.e.
+    /// how far from the shaded point an occluder can be and still cast a
+    /// shadow.
+    ///
+    /// Shadow rays go from the light towards the shaded point (so that they
+    /// don't hit the surface they start from) and making this distance larger
+    /// would make them imprecise near their end.
+    pub const DIRECTIONAL_DISTANCE: f32 = 1000.0;
+
+    /// Angular radius of the sun, in radians.
+    ///
+    /// This is a few times larger than the actual sun's radius, which makes
+    /// the shadows a bit softer.
+    pub const SUN_ANGULAR_RADIUS: f32 = 0.025;
+
+    /// Creates a directional light; `direction` is the (normalized) direction
+    /// the light travels in.
+    pub fn directional(
+        direction: Vec3,
+        color: Vec3,
+        angular_radius: f32,
+    ) -> Self {
         Self {
-            // TODO incorrect
-            d0: position.extend(25.0),
+            d0: direction.extend(angular_radius),
             d1: color.extend(f32::INFINITY),
             d2: vec4(
-                f32::from_bits(Self::TYPE_POINT),
+                f32::from_bits(Self::TYPE_DIRECTIONAL),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+            ),
+            d3: Default::default(),
+        }
+    }
+
+    /// Creates a two-sided triangle light, used for emissive geometry.
+    ///
+    /// Since the triangle's area is encoded through its edges, this uses the
+    /// same fields as rectangle and disk lights, with `area_u()` and
+    /// `area_v()` returning the edges going out of the first vertex.
+    pub fn triangle(positions: [Vec3; 3], color: Vec3) -> Self {
+        let u = positions[1] - positions[0];
+        let v = positions[2] - positions[0];
+
+        Self {
+            d0: positions[0].extend(Default::default()),
+            d1: color.extend(Default::default()),
+            d2: vec4(f32::from_bits(Self::TYPE_TRIANGLE), u.x, u.y, u.z),
+            d3: v.extend(f32::from_bits(1)),
+        }
+    }
+
+    /// Creates a tombstone, i.e. a placeholder that occupies slot of a light
+    /// that has been removed, so that ids of other lights don't change.
+    ///
+    /// Tombstones don't emit any light and samples pointing at them are
+    /// invalid.
+    pub fn tombstone() -> Self {
+        Self {
+            d0: Default::default(),
+            d1: Default::default(),
+            d2: vec4(
+                f32::from_bits(Self::TYPE_TOMBSTONE),
                 Default::default(),
                 Default::default(),
                 Default::default(),
             ),
+            d3: Default::default(),
         }
     }
 
+    /// Creates the sun; `sun_direction` is the (normalized) direction towards
+    /// the sun, see [`crate::World::sun_direction()`].
+    pub fn sun(sun_direction: Vec3, color: Vec3) -> Self {
+        Self::directional(-sun_direction, color, Self::SUN_ANGULAR_RADIUS)
+    }
+
     pub fn center(&self) -> Vec3 {
         self.d0.xyz()
     }
@@ -68,6 +149,80 @@ impl Light {
         self.d2.x.to_bits() == Self::TYPE_POINT
     }
 
+    pub fn is_directional(&self) -> bool {
+        self.d2.x.to_bits() == Self::TYPE_DIRECTIONAL
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.d2.x.to_bits() == Self::TYPE_TOMBSTONE
+    }
+
+    /// Returns the direction a directional light travels in.
+    pub fn directional_direction(&self) -> Vec3 {
+        self.d0.xyz()
+    }
+
+    pub fn directional_angular_radius(&self) -> f32 {
+        self.d0.w
+    }
+
+    /// Returns whether this is a rectangle, a disk or a triangle light.
+    pub fn is_area(&self) -> bool {
+        let ty = self.d2.x.to_bits();
+
+        ty == Self::TYPE_RECT
+            || ty == Self::TYPE_DISK
+            || ty == Self::TYPE_TRIANGLE
+    }
+
+    pub fn is_rect(&self) -> bool {
+        self.d2.x.to_bits() == Self::TYPE_RECT
+    }
+
+    pub fn is_triangle(&self) -> bool {
+        self.d2.x.to_bits() == Self::TYPE_TRIANGLE
+    }
+
+    /// Returns sum of powers of all triangle lights up to (and including)
+    /// this one.
+    pub fn triangle_cdf(&self) -> f32 {
+        self.d1.w
+    }
+
+    pub fn with_triangle_cdf(mut self, cdf: f32) -> Self {
+        self.d1.w = cdf;
+        self
+    }
+
+    /// Returns estimated power of a triangle light, i.e. its area times its
+    /// luminance.
+    pub fn triangle_power(&self) -> f32 {
+        0.5 * self.area_u().cross(self.area_v()).length() * self.color().luma()
+    }
+
+    /// Returns area light's half-extent along its first axis, i.e. the vector
+    /// from its center to the middle of its edge (or to its rim, for disks;
+    /// for triangles this is the edge going from the first to the second
+    /// vertex).
+    pub fn area_u(&self) -> Vec3 {
+        self.d2.yzw()
+    }
+
+    /// Like [`Self::area_u()`], but along the second axis (for triangles
+    /// this is the edge going from the first to the third vertex).
+    pub fn area_v(&self) -> Vec3 {
+        self.d3.xyz()
+    }
+
+    /// Returns the direction area light emits towards (if it's one-sided).
+    pub fn area_normal(&self) -> Vec3 {
+        -self.area_u().cross(self.area_v()).normalize()
+    }
+
+    pub fn is_two_sided(&self) -> bool {
+        self.d3.w.to_bits() != 0
+    }
+
     pub fn spot_direction(&self) -> Vec3 {
         Normal::decode(self.d2.yz())
     }
@@ -77,6 +232,24 @@ impl Light {
     }
 
     pub fn radiance(&self, hit: Hit) -> Vec3 {
+        if self.is_tombstone() {
+            return Vec3::ZERO;
+        }
+
+        if self.is_directional() {
+            let cosine_factor = hit
+                .gbuffer
+                .normal
+                .dot(-self.directional_direction())
+                .saturate();
+
+            return self.color() * cosine_factor;
+        }
+
+        if self.is_area() {
+            return self.color() * self.area_factor(hit);
+        }
+
         let l = self.center() - hit.point;
 
         let conical_factor = if self.is_point() {
@@ -123,6 +296,18 @@ impl Light {
     }
 
     pub fn ray_wnoise(&self, noise: &mut WhiteNoise, hit_point: Vec3) -> Ray {
+        if self.is_directional() {
+            let sample = vec2(noise.sample(), noise.sample());
+
+            return self.directional_ray(sample, hit_point);
+        }
+
+        if self.is_area() {
+            let sample = vec2(noise.sample(), noise.sample());
+
+            return self.area_ray(sample, hit_point);
+        }
+
         let light_pos = self.center() + self.radius() * noise.sample_sphere();
         let light_to_hit = hit_point - light_pos;
 
@@ -131,6 +316,14 @@ impl Light {
     }
 
     pub fn ray_bnoise(&self, sample: Vec2, hit_point: Vec3) -> Ray {
+        if self.is_directional() {
+            return self.directional_ray(sample, hit_point);
+        }
+
+        if self.is_area() {
+            return self.area_ray(sample, hit_point);
+        }
+
         let to_light = self.center() - hit_point;
         let light_dir = to_light.normalize();
         let light_distance = to_light.length();
@@ -153,6 +346,248 @@ impl Light {
         Ray::new(hit_point + ray_dir * light_distance, -ray_dir)
             .with_length(light_distance)
     }
+
+    /// Returns a shadow ray of a directional light, going through a point on
+    /// the cone of directions covered by the light (sampled uniformly).
+    fn directional_ray(&self, sample: Vec2, hit_point: Vec3) -> Ray {
+        let light_dir = -self.directional_direction();
+        let (light_tangent, light_bitangent) = light_dir.any_orthonormal_pair();
+
+        let cos_theta =
+            1.0 - sample.x * (1.0 - self.directional_angular_radius().cos());
+
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * sample.y;
+
+        let ray_dir = light_tangent * (phi.cos() * sin_theta)
+            + light_bitangent * (phi.sin() * sin_theta)
+            + light_dir * cos_theta;
+
+        let ray_dir = ray_dir.normalize();
+
+        Ray::new(hit_point + ray_dir * Self::DIRECTIONAL_DISTANCE, -ray_dir)
+            .with_length(Self::DIRECTIONAL_DISTANCE)
+    }
+
+    /// Returns whether given point lies on the area light (assuming it lies on
+    /// light's plane).
+    pub fn area_contains(&self, point: Vec3) -> bool {
+        let u = self.area_u();
+        let v = self.area_v();
+        let point = point - self.center();
+
+        // Position of the point in light's coordinates, i.e. from -1.0 to 1.0
+        // on both axes, if the point lies within light's rectangle
+        if self.is_triangle() {
+            let uu = u.dot(u);
+            let uv = u.dot(v);
+            let vv = v.dot(v);
+            let pu = point.dot(u);
+            let pv = point.dot(v);
+            let denom = uu * vv - uv * uv;
+
+            // Barycentric coordinates of the point
+            let b1 = (vv * pu - uv * pv) / denom;
+            let b2 = (uu * pv - uv * pu) / denom;
+
+            return b1 >= -0.001 && b2 >= -0.001 && b1 + b2 <= 1.001;
+        }
+
+        let x = point.dot(u) / u.length_squared();
+        let y = point.dot(v) / v.length_squared();
+
+        if self.is_rect() {
+            x.abs() <= 1.001 && y.abs() <= 1.001
+        } else {
+            x * x + y * y <= 1.001
+        }
+    }
+
+    /// Returns the cosine-weighted solid angle under which the shaded point
+    /// sees the area light (i.e. irradiance per unit of light's radiance),
+    /// ignoring occlusion.
+    fn area_factor(&self, hit: Hit) -> f32 {
+        let to_hit = hit.point - self.center();
+
+        if !self.is_two_sided() && to_hit.dot(self.area_normal()) <= 0.0 {
+            return 0.0;
+        }
+
+        // Lambert's formula for irradiance from a polygon - it's exact, except
+        // for polygons that cross the shaded point's horizon, for which we just
+        // clamp the result
+        let u = self.area_u();
+        let v = self.area_v();
+        let center = self.center() - hit.point;
+        let mut form_factor = Vec3::ZERO;
+
+        if self.is_rect() {
+            let p0 = (center - u - v).normalize();
+            let p1 = (center + u - v).normalize();
+            let p2 = (center + u + v).normalize();
+            let p3 = (center - u + v).normalize();
+
+            form_factor += Self::polygon_edge(p0, p1);
+            form_factor += Self::polygon_edge(p1, p2);
+            form_factor += Self::polygon_edge(p2, p3);
+            form_factor += Self::polygon_edge(p3, p0);
+        } else if self.is_triangle() {
+            let p0 = center.normalize();
+            let p1 = (center + u).normalize();
+            let p2 = (center + v).normalize();
+
+            form_factor += Self::polygon_edge(p0, p1);
+            form_factor += Self::polygon_edge(p1, p2);
+            form_factor += Self::polygon_edge(p2, p0);
+        } else {
+            // Disks are approximated with a 16-gon, scaled so that its area
+            // matches the disk's
+            let scale = (PI / (8.0 * (PI / 8.0).sin())).sqrt();
+            let mut prev = (center + u * scale).normalize();
+            let mut i = 1;
+
+            while i <= 16 {
+                let angle = (i as f32) * (PI / 8.0);
+
+                let curr = (center
+                    + (u * angle.cos() + v * angle.sin()) * scale)
+                    .normalize();
+
+                form_factor += Self::polygon_edge(prev, curr);
+                prev = curr;
+                i += 1;
+            }
+        }
+
+        // Depending on which side of the polygon we're looking at, the edges
+        // go either clockwise or counter-clockwise, so the vector can point
+        // either way
+        if form_factor.dot(center) < 0.0 {
+            form_factor = -form_factor;
+        }
+
+        (0.5 * form_factor.dot(hit.gbuffer.normal)).max(0.0)
+    }
+
+    /// Returns contribution of a polygon's edge going from `a` to `b` (both
+    /// normalized) into the Lambert's formula.
+    fn polygon_edge(a: Vec3, b: Vec3) -> Vec3 {
+        let cross = a.cross(b);
+        let len = cross.length();
+
+        if len > 0.0 {
+            cross * (len.atan2(a.dot(b)) / len)
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    /// Returns a shadow ray of an area light, going through a point on the
+    /// light.
+    ///
+    /// For rectangles the point is sampled uniformly over the solid angle the
+    /// rectangle covers, so that the fraction of unoccluded rays matches the
+    /// fraction of the light that's visible from the shaded point; for disks,
+    /// triangles (and rectangles that are too small to sample their solid
+    /// angle precisely) the point is sampled uniformly over light's area.
+    fn area_ray(&self, sample: Vec2, hit_point: Vec3) -> Ray {
+        let u = self.area_u();
+        let v = self.area_v();
+
+        let light_point = if self.is_rect() {
+            self.sample_rect(sample, hit_point)
+        } else if self.is_triangle() {
+            let x = sample.x.sqrt();
+
+            self.center() + u * (x * (1.0 - sample.y)) + v * (x * sample.y)
+        } else {
+            let angle = 2.0 * PI * sample.x;
+            let radius = sample.y.sqrt();
+
+            self.center()
+                + u * (radius * angle.cos())
+                + v * (radius * angle.sin())
+        };
+
+        let light_to_hit = hit_point - light_point;
+
+        Ray::new(light_point, light_to_hit.normalize())
+            .with_length(light_to_hit.length())
+    }
+
+    /// Samples a point on the rectangle uniformly over the solid angle it
+    /// covers, as seen from `hit_point`.
+    ///
+    /// See: An Area-Preserving Parametrization for Spherical Rectangles
+    /// (Ureña et al., 2013).
+    fn sample_rect(&self, sample: Vec2, hit_point: Vec3) -> Vec3 {
+        let u = self.area_u();
+        let v = self.area_v();
+        let ex = u.normalize();
+        let ey = v.normalize();
+        let mut ez = ex.cross(ey);
+        let corner = self.center() - u - v - hit_point;
+
+        let x0 = corner.dot(ex);
+        let y0 = corner.dot(ey);
+        let mut z0 = corner.dot(ez);
+
+        if z0 > 0.0 {
+            z0 = -z0;
+            ez = -ez;
+        }
+
+        let x1 = x0 + 2.0 * u.length();
+        let y1 = y0 + 2.0 * v.length();
+
+        let v00 = Vec3::new(x0, y0, z0);
+        let v01 = Vec3::new(x0, y1, z0);
+        let v10 = Vec3::new(x1, y0, z0);
+        let v11 = Vec3::new(x1, y1, z0);
+
+        let n0 = v00.cross(v10).normalize();
+        let n1 = v10.cross(v11).normalize();
+        let n2 = v11.cross(v01).normalize();
+        let n3 = v01.cross(v00).normalize();
+
+        let g0 = (-n0.dot(n1)).clamp(-1.0, 1.0).acos();
+        let g1 = (-n1.dot(n2)).clamp(-1.0, 1.0).acos();
+        let g2 = (-n2.dot(n3)).clamp(-1.0, 1.0).acos();
+        let g3 = (-n3.dot(n0)).clamp(-1.0, 1.0).acos();
+
+        let k = 2.0 * PI - g2 - g3;
+        let solid_angle = g0 + g1 - k;
+
+        // Parametrization becomes unstable for tiny solid angles, in which
+        // case sampling the area instead is just as good
+        if solid_angle.is_nan() || solid_angle <= 0.0001 {
+            return self.center()
+                + u * (2.0 * sample.x - 1.0)
+                + v * (2.0 * sample.y - 1.0);
+        }
+
+        let b0 = n0.z;
+        let b1 = n2.z;
+
+        let au = sample.x * solid_angle + k;
+        let fu = (au.cos() * b0 - b1) / au.sin();
+        let cu = (1.0 / (fu * fu + b0 * b0).sqrt()).copysign(fu);
+        let cu = cu.clamp(-1.0, 1.0);
+
+        let xu = (-(cu * z0) / (1.0 - cu * cu).max(0.0).sqrt()).clamp(x0, x1);
+        let d = (xu * xu + z0 * z0).sqrt();
+        let h0 = y0 / (d * d + y0 * y0).sqrt();
+        let h1 = y1 / (d * d + y1 * y1).sqrt();
+        let hv = h0 + sample.y * (h1 - h0);
+
+        let yv = if hv * hv < 0.9999 {
+            (hv * d) / (1.0 - hv * hv).sqrt()
+        } else {
+            y1
+        };
+
+        hit_point + ex * xu + ey * yv + ez * z0
+    }
 }
 
 #[derive(Clone, Copy, Default, PartialEq, Eq)]
@@ -0,0 +1,294 @@
+use glam::{Vec3, Vec4, Vec4Swizzles};
+use spirv_std::arch::IndexUnchecked;
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+use crate::{Hit, LightId, WhiteNoise};
+
+/// Tree of lights, used to pick lights proportionally to their (estimated)
+/// contribution to the shaded point.
+///
+/// Layout:
+///
+/// - the first item is a header, containing (as u32) the number of
+///   directional lights (x) and the number of nodes, including the
+///   directional lights (y),
+///
+/// - then there are directional lights, stored as leaf nodes - since they
+///   don't have any position, they can't be a part of the tree,
+///
+/// - then there are the tree's nodes, in depth-first order (so that the left
+///   child of each node is stored right after it).
+///
+/// Only regular lights are kept in the tree - emissive triangles are picked
+/// separately, see [`crate::LightsView::sample_triangle()`].
+#[derive(Clone, Copy)]
+pub struct LightTreeView<'a> {
+    items: &'a [Vec4],
+}
+
+impl<'a> LightTreeView<'a> {
+    pub fn new(items: &'a [Vec4]) -> Self {
+        Self { items }
+    }
+
+    pub fn directional_light_count(&self) -> u32 {
+        self.header().x.to_bits()
+    }
+
+    /// Returns whether there are any non-directional lights in the tree.
+    pub fn has_nodes(&self) -> bool {
+        self.header().y.to_bits() > self.directional_light_count()
+    }
+
+    pub fn directional_light(&self, idx: u32) -> LightId {
+        self.node(idx).light_id()
+    }
+
+    pub fn node(&self, idx: u32) -> LightTreeNode {
+        let ptr = 1 + 4 * (idx as usize);
+
+        unsafe {
+            LightTreeNode {
+                d0: *self.items.index_unchecked(ptr),
+                d1: *self.items.index_unchecked(ptr + 1),
+                d2: *self.items.index_unchecked(ptr + 2),
+                d3: *self.items.index_unchecked(ptr + 3),
+            }
+        }
+    }
+
+    /// Picks one of the non-directional lights by stochastically traversing
+    /// the tree, going into each child proportionally to its importance;
+    /// returns the light together with the probability of picking it.
+    ///
+    /// Probability of zero means that none of the lights affects given point.
+    ///
+    /// Must be called only if there are any non-directional lights, see
+    /// [`Self::has_nodes()`].
+    pub fn sample(&self, wnoise: &mut WhiteNoise, hit: Hit) -> (LightId, f32) {
+        let mut node_idx = self.directional_light_count();
+        let mut node = self.node(node_idx);
+        let mut pdf = 1.0;
+
+        while !node.is_leaf() {
+            let left_idx = node_idx + 1;
+            let right_idx = node.right_child();
+            let left = self.node(left_idx);
+            let right = self.node(right_idx);
+
+            let left_importance =
+                left.importance(hit.point, hit.gbuffer.normal);
+
+            let right_importance =
+                right.importance(hit.point, hit.gbuffer.normal);
+
+            let importance = left_importance + right_importance;
+
+            if importance <= 0.0 {
+                return (LightId::new(0), 0.0);
+            }
+
+            let left_pdf = left_importance / importance;
+
+            if wnoise.sample() < left_pdf {
+                node_idx = left_idx;
+                node = left;
+                pdf *= left_pdf;
+            } else {
+                node_idx = right_idx;
+                node = right;
+                pdf *= 1.0 - left_pdf;
+            }
+        }
+
+        (node.light_id(), pdf)
+    }
+
+    fn header(&self) -> Vec4 {
+        unsafe { *self.items.index_unchecked(0) }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug, PartialEq))]
+pub struct LightTreeNode {
+    /// x - bounds min x
+    /// y - bounds min y
+    /// z - bounds min z
+    /// w - power
+    pub d0: Vec4,
+
+    /// x - bounds max x
+    /// y - bounds max y
+    /// z - bounds max z
+    /// w - range
+    pub d1: Vec4,
+
+    /// x - emission cone's axis x
+    /// y - emission cone's axis y
+    /// z - emission cone's axis z
+    /// w - cosine of the emission cone's spread (theta_o)
+    pub d2: Vec4,
+
+    /// x - cosine of the emission's falloff angle (theta_e)
+    /// y - (as u32) whether the lights are two-sided
+    /// z - (as u32) whether this is a leaf
+    /// w - (as u32) light id, if this is a leaf; right child's index
+    ///     otherwise
+    pub d3: Vec4,
+}
+
+impl LightTreeNode {
+    pub fn bounds_min(&self) -> Vec3 {
+        self.d0.xyz()
+    }
+
+    pub fn bounds_max(&self) -> Vec3 {
+        self.d1.xyz()
+    }
+
+    /// Returns the total power of lights within this node, which - in order
+    /// to be comparable across different kinds of lights - is expressed as
+    /// irradiance at unit distance.
+    pub fn power(&self) -> f32 {
+        self.d0.w
+    }
+
+    pub fn range(&self) -> f32 {
+        self.d1.w
+    }
+
+    pub fn axis(&self) -> Vec3 {
+        self.d2.xyz()
+    }
+
+    pub fn cos_theta_o(&self) -> f32 {
+        self.d2.w
+    }
+
+    pub fn cos_theta_e(&self) -> f32 {
+        self.d3.x
+    }
+
+    pub fn is_two_sided(&self) -> bool {
+        self.d3.y.to_bits() != 0
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.d3.z.to_bits() != 0
+    }
+
+    pub fn light_id(&self) -> LightId {
+        LightId::new(self.d3.w.to_bits())
+    }
+
+    pub fn right_child(&self) -> u32 {
+        self.d3.w.to_bits()
+    }
+
+    /// Returns a conservative estimate of how much the lights within this
+    /// node can contribute to given point - i.e. it's zero only if none of
+    /// the lights can reach the point.
+    ///
+    /// Thanks to:
+    /// Physically Based Rendering: From Theory to Implementation (4th ed.),
+    /// section 12.6.3.
+    pub fn importance(&self, point: Vec3, normal: Vec3) -> f32 {
+        let min = self.bounds_min();
+        let max = self.bounds_max();
+
+        let distance_to_bounds =
+            (min - point).max(point - max).max(Vec3::ZERO).length();
+
+        if distance_to_bounds > self.range() {
+            return 0.0;
+        }
+
+        let center = (min + max) * 0.5;
+        let radius = (max - min).length() * 0.5;
+        let to_point = point - center;
+
+        // Same as pbrt, we clamp the distance to avoid overestimating the
+        // importance of nodes that are close to the point
+        let distance_squared = to_point.length_squared().max(radius);
+
+        if to_point.length_squared() <= radius * radius {
+            // Point lies within the bounds, so the lights can shine at it
+            // from any direction
+            return self.power() / distance_squared;
+        }
+
+        let light_to_point = to_point.normalize();
+
+        // Angle between the emission cone's axis and the point
+        let mut cos_theta_w = self.axis().dot(light_to_point);
+
+        if self.is_two_sided() {
+            cos_theta_w = cos_theta_w.abs();
+        }
+
+        let sin_theta_w = (1.0 - cos_theta_w * cos_theta_w).max(0.0).sqrt();
+
+        // Angle subtended by the bounds, as seen from the point
+        let sin_theta_b_sq = radius * radius / to_point.length_squared();
+        let sin_theta_b = sin_theta_b_sq.sqrt();
+        let cos_theta_b = (1.0 - sin_theta_b_sq).max(0.0).sqrt();
+
+        let cos_theta_o = self.cos_theta_o();
+        let sin_theta_o = (1.0 - cos_theta_o * cos_theta_o).max(0.0).sqrt();
+
+        // Minimum angle between the point and any direction within the
+        // emission cone
+        let cos_theta_x =
+            cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+
+        let sin_theta_x =
+            sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+
+        let cos_theta_p =
+            cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+
+        if cos_theta_p <= self.cos_theta_e() {
+            return 0.0;
+        }
+
+        // Minimum angle between the surface's normal and any direction
+        // towards the bounds
+        let cos_theta_i = normal.dot(-light_to_point);
+        let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+
+        let cos_theta_ip =
+            cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+
+        (self.power() * cos_theta_p * cos_theta_ip / distance_squared).max(0.0)
+    }
+}
+
+/// Returns `cos(max(0, theta_a - theta_b))`.
+fn cos_sub_clamped(
+    sin_theta_a: f32,
+    cos_theta_a: f32,
+    sin_theta_b: f32,
+    cos_theta_b: f32,
+) -> f32 {
+    if cos_theta_a > cos_theta_b {
+        1.0
+    } else {
+        cos_theta_a * cos_theta_b + sin_theta_a * sin_theta_b
+    }
+}
+
+/// Returns `sin(max(0, theta_a - theta_b))`.
+fn sin_sub_clamped(
+    sin_theta_a: f32,
+    cos_theta_a: f32,
+    sin_theta_b: f32,
+    cos_theta_b: f32,
+) -> f32 {
+    if cos_theta_a > cos_theta_b {
+        0.0
+    } else {
+        sin_theta_a * cos_theta_b - cos_theta_a * sin_theta_b
+    }
+}
@@ -1,6 +1,6 @@
 use spirv_std::arch::IndexUnchecked;
 
-use crate::{Light, LightId};
+use crate::{Light, LightId, World};
 
 #[derive(Clone, Copy)]
 pub struct LightsView<'a> {
@@ -19,4 +19,48 @@ impl<'a> LightsView<'a> {
     pub fn len(&self) -> usize {
         self.items.len()
     }
+
+    /// Picks one of the triangle lights, with probability proportional to
+    /// its power; returns the light together with the probability of picking
+    /// it.
+    ///
+    /// Each triangle light keeps the sum of powers of all triangle lights up
+    /// to itself, so this boils down to a binary search; tombstones placed
+    /// in-between (i.e. slots of removed triangles) keep the sum as well, but
+    /// since they have zero power, they are never picked.
+    ///
+    /// Must be called only if there's at least one triangle light.
+    pub fn sample_triangle(
+        &self,
+        world: &World,
+        sample: f32,
+    ) -> (LightId, f32) {
+        let first_id = world.light_count;
+        let last_id = first_id + world.triangle_light_count - 1;
+        let total_power = self.get(LightId::new(last_id)).triangle_cdf();
+        let power = sample * total_power;
+
+        let mut left = first_id;
+        let mut right = last_id;
+
+        while left < right {
+            let mid = (left + right) / 2;
+
+            if self.get(LightId::new(mid)).triangle_cdf() < power {
+                left = mid + 1;
+            } else {
+                right = mid;
+            }
+        }
+
+        let cdf = self.get(LightId::new(left)).triangle_cdf();
+
+        let prev_cdf = if left > first_id {
+            self.get(LightId::new(left - 1)).triangle_cdf()
+        } else {
+            0.0
+        };
+
+        (LightId::new(left), (cdf - prev_cdf) / total_power)
+    }
 }
@@ -18,6 +18,12 @@ pub struct Material {
     pub ior: f32,
     pub metallic_roughness_texture: Vec4,
     pub normal_map_texture: Vec4,
+
+    /// If greater than zero, base color's alpha gets snapped to either zero
+    /// or one, depending on whether it's below this value (used for
+    /// alpha-masked materials)
+    pub alpha_cutoff: f32,
+    pub _padding: [f32; 3],
 }
 
 impl Material {
@@ -32,13 +38,23 @@ impl Material {
         atlas_sampler: &Sampler,
         hit_uv: Vec2,
     ) -> Vec4 {
-        Self::sample_atlas(
+        let mut base_color = Self::sample_atlas(
             atlas_tex,
             atlas_sampler,
             hit_uv,
             self.base_color,
             self.base_color_texture,
-        )
+        );
+
+        if self.alpha_cutoff > 0.0 {
+            base_color.w = if base_color.w >= self.alpha_cutoff {
+                1.0
+            } else {
+                0.0
+            };
+        }
+
+        base_color
     }
     pub fn metallic_roughness(
         &self,
@@ -71,6 +87,13 @@ impl Material {
         .xyz()
     }
 
+    /// Returns whether material's emission comes from a texture, in which
+    /// case - unlike for materials with a constant emission - its triangles
+    /// are not sampled as lights.
+    pub fn is_textured_emissive(&self) -> bool {
+        self.emissive_texture != Vec4::ZERO
+    }
+
     fn sample_atlas(
         atlas_tex: Tex,
         atlas_sampler: &Sampler,
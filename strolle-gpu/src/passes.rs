@@ -1,5 +1,5 @@
 use bytemuck::{Pod, Zeroable};
-use glam::{vec3a, vec4, Affine3A, Mat3A, Vec4};
+use glam::{vec3, vec3a, vec4, Affine3A, Mat3, Mat3A, Vec4};
 
 #[repr(C)]
 #[derive(Clone, Copy, Default, Pod, Zeroable)]
@@ -13,13 +13,21 @@ pub struct PassParams {
 #[derive(Clone, Copy, Default, Pod, Zeroable)]
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
 pub struct PrimRasterPassParams {
+    /// x - (as u32) instance uuid
+    /// y - (as u32) material id
+    /// z - (as u32) normal matrix, see [`Self::encode_normal_xform()`]
+    /// w - (as u32) ditto
     pub payload: Vec4,
-    pub curr_xform_inv_d0: Vec4,
-    pub curr_xform_inv_d1: Vec4,
-    pub curr_xform_inv_d2: Vec4,
+    pub curr_xform_d0: Vec4,
+    pub curr_xform_d1: Vec4,
+    pub curr_xform_d2: Vec4,
     pub prev_xform_d0: Vec4,
     pub prev_xform_d1: Vec4,
     pub prev_xform_d2: Vec4,
+
+    /// x, y, z - (as u32) normal matrix, see [`Self::encode_normal_xform()`]
+    /// w - unused
+    pub normal_xform: Vec4,
 }
 
 impl PrimRasterPassParams {
@@ -31,11 +39,11 @@ impl PrimRasterPassParams {
         self.payload.y.to_bits()
     }
 
-    pub fn curr_xform_inv(&self) -> Affine3A {
+    pub fn curr_xform(&self) -> Affine3A {
         Self::decode_affine([
-            self.curr_xform_inv_d0,
-            self.curr_xform_inv_d1,
-            self.curr_xform_inv_d2,
+            self.curr_xform_d0,
+            self.curr_xform_d1,
+            self.curr_xform_d2,
         ])
     }
 
@@ -47,6 +55,16 @@ impl PrimRasterPassParams {
         ])
     }
 
+    pub fn normal_xform(&self) -> Mat3 {
+        Self::decode_normal_xform([
+            self.payload.z.to_bits(),
+            self.payload.w.to_bits(),
+            self.normal_xform.x.to_bits(),
+            self.normal_xform.y.to_bits(),
+            self.normal_xform.z.to_bits(),
+        ])
+    }
+
     /// Encodes a 3D affine transformation as three Vec4s; we use this to
     /// overcome padding issues when copying data from CPU into GPU.
     pub fn encode_affine(xform: Affine3A) -> [Vec4; 3] {
@@ -85,6 +103,63 @@ impl PrimRasterPassParams {
             translation: vec3a(d0.w, d1.w, d2.w),
         }
     }
+
+    /// Computes the matrix used to transform normals (i.e. inverse-transpose
+    /// of transformation's linear part) and encodes it as nine snorm16
+    /// numbers packed into five u32s.
+    ///
+    /// Push constants are limited to 128 bytes, which wouldn't fit the matrix
+    /// stored as f32s - but since normals get normalized after transforming
+    /// anyway, we only care about the matrix's direction, so we can rescale
+    /// it into `-1.0..=1.0` and store it with a lower precision.
+    #[cfg(not(target_arch = "spirv"))]
+    pub fn encode_normal_xform(xform: Affine3A) -> [u32; 5] {
+        let mut xform = xform.matrix3.inverse().transpose();
+
+        let scale = xform
+            .x_axis
+            .abs()
+            .max(xform.y_axis.abs())
+            .max(xform.z_axis.abs())
+            .max_element();
+
+        if scale.is_finite() && scale > 0.0 {
+            xform *= 1.0 / scale;
+        } else {
+            // Transformation is degenerate (e.g. has a zero scale), so there's
+            // no meaningful normal matrix anyway
+            xform = Mat3A::IDENTITY;
+        }
+
+        let encode = |x: f32| -> u32 {
+            ((x.clamp(-1.0, 1.0) * 32767.0).round() as i32 as u32) & 0xffff
+        };
+
+        let mut out = [0; 5];
+
+        for (idx, x) in xform.to_cols_array().into_iter().enumerate() {
+            out[idx / 2] |= encode(x) << (16 * (idx % 2));
+        }
+
+        out
+    }
+
+    /// See: [`Self::encode_normal_xform()`].
+    pub fn decode_normal_xform(xs: [u32; 5]) -> Mat3 {
+        Mat3::from_cols(
+            vec3(snorm16_lo(xs[0]), snorm16_hi(xs[0]), snorm16_lo(xs[1])),
+            vec3(snorm16_hi(xs[1]), snorm16_lo(xs[2]), snorm16_hi(xs[2])),
+            vec3(snorm16_lo(xs[3]), snorm16_hi(xs[3]), snorm16_lo(xs[4])),
+        )
+    }
+}
+
+fn snorm16_lo(x: u32) -> f32 {
+    (((x << 16) as i32) >> 16) as f32 / 32767.0
+}
+
+fn snorm16_hi(x: u32) -> f32 {
+    ((x as i32) >> 16) as f32 / 32767.0
 }
 
 #[repr(C)]
@@ -158,3 +233,44 @@ pub struct GiDiffSpatialResamplingPassParams {
     pub frame: u32,
     pub nth: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use glam::{Quat, Vec3};
+
+    use super::*;
+
+    #[test]
+    fn normal_xform() {
+        let xforms = [
+            Affine3A::IDENTITY,
+            Affine3A::from_scale(vec3(1.0, 2.0, 0.25)),
+            Affine3A::from_scale_rotation_translation(
+                vec3(-3.0, 0.5, 10.0),
+                Quat::from_euler(glam::EulerRot::XYZ, 0.3, -1.2, 2.5),
+                vec3(1.0, 2.0, 3.0),
+            ),
+        ];
+
+        let normals =
+            [Vec3::X, Vec3::Y, Vec3::Z, vec3(1.0, -2.0, 3.0).normalize()];
+
+        for xform in xforms {
+            let actual = PrimRasterPassParams::decode_normal_xform(
+                PrimRasterPassParams::encode_normal_xform(xform),
+            );
+
+            let expected = Mat3::from(xform.matrix3.inverse().transpose());
+
+            for normal in normals {
+                let actual = (actual * normal).normalize();
+                let expected = (expected * normal).normalize();
+
+                assert_relative_eq!(actual.x, expected.x, epsilon = 0.001);
+                assert_relative_eq!(actual.y, expected.y, epsilon = 0.001);
+                assert_relative_eq!(actual.z, expected.z, epsilon = 0.001);
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use core::mem;
 
-use glam::{Vec3, Vec4, Vec4Swizzles};
+use glam::{vec3, Vec3, Vec4, Vec4Swizzles};
 use spirv_std::arch::IndexUnchecked;
 #[cfg(target_arch = "spirv")]
 use spirv_std::num_traits::Float;
@@ -124,10 +124,18 @@ impl Ray {
     ) -> usize {
         // An estimation of the memory used when travelling the BVH; useful for
         // debugging
-        let mut used_memory = 0;
+        let mut used_memory = mem::size_of::<Vec4>();
 
-        // Index into the `bvh` array; points at the currently processed node
-        let mut bvh_ptr = 0;
+        // Index into the `bvh` array; points at the currently processed node.
+        //
+        // The buffer starts with a header that says where the top-level tree
+        // begins (or `u32::MAX` if there are no instances in the world).
+        let root_ptr = bvh.get(0).x.to_bits();
+        let mut bvh_ptr = root_ptr;
+
+        if bvh_ptr == u32::MAX {
+            return used_memory;
+        }
 
         // Where this particular thread's stack starts at; see `BvhStack`
         let stack_begins_at = (local_idx as usize) * BVH_STACK_SIZE;
@@ -136,13 +144,63 @@ impl Ray {
         // BVH_STACK_SIZE items
         let mut stack_ptr = stack_begins_at;
 
+        // Where this particular thread's stack ends at.
+        //
+        // Since we index the stack without bounds checking, we must make sure
+        // not to push more items than we've got space for - otherwise we'd
+        // corrupt other threads' stacks; see `is_stackless` for what happens
+        // when the stack is full.
+        let stack_ends_at = stack_begins_at + BVH_STACK_SIZE;
+
+        // Whether we've run out of stack and switched to the stackless
+        // traversal.
+        //
+        // Stackless traversal restarts from the root and walks the trees in
+        // their serialized (depth-first) order: it descends into the first
+        // child that the ray intersects and, once it's done with a subtree (or
+        // if the ray misses all children), it follows node's escape pointer,
+        // which points at whatever follows the subtree in the buffer.
+        //
+        // This visits each node at most once, but - since it can't visit
+        // children front-to-back - it usually visits more nodes than the
+        // stack-based traversal, so we use it only as a fallback; restarting
+        // keeps the hit we've found so far, so it's still correct.
+        let mut is_stackless = false;
+
+        // When travelling stacklessly, where to continue in the top-level tree
+        // once we're done with the current instance's tree
+        let mut instance_next_ptr = 0;
+
+        // Ray we're currently testing against - that's either `self` (when
+        // we're travelling the top-level tree) or `self` transformed into the
+        // object-space of the instance we're currently inside of
+        let mut ray = self;
+
+        // Whether we're currently travelling some instance's tree and, if so,
+        // which item on the stack belongs to the top-level tree; once we pop
+        // that item, we're back at the top-level tree.
+        //
+        // Note that pointers inside meshes' trees are relative to the tree's
+        // root, hence `base_ptr`.
+        let mut in_instance = false;
+        let mut instance_stack_ptr = stack_begins_at;
+        let mut base_ptr = 0;
+
+        // Properties of the instance we're currently inside of; rows are the
+        // world-space -> object-space transformation
+        let mut material_id = MaterialId::new(0);
+        let mut has_alpha_blending = false;
+        let mut xform_r0 = Vec4::ZERO;
+        let mut xform_r1 = Vec4::ZERO;
+        let mut xform_r2 = Vec4::ZERO;
+
         loop {
             used_memory += mem::size_of::<Vec4>();
 
             let d0 = bvh.get(bvh_ptr);
-            let is_internal_node = d0.w.to_bits() == 0;
+            let op = d0.w.to_bits();
 
-            if is_internal_node {
+            if op == OP_INTERNAL {
                 used_memory += 3 * mem::size_of::<Vec4>();
 
                 let d1 = bvh.get(bvh_ptr + 1);
@@ -150,10 +208,24 @@ impl Ray {
                 let d3 = bvh.get(bvh_ptr + 3);
 
                 let mut near_ptr = bvh_ptr + 4;
-                let mut far_ptr = d1.w.to_bits();
+                let mut far_ptr = base_ptr + d1.w.to_bits();
+
+                let mut near_distance = ray.intersect_box(d0.xyz(), d1.xyz());
+                let mut far_distance = ray.intersect_box(d2.xyz(), d3.xyz());
+
+                // (at this point "near" is still the left child and "far" is
+                // the right one, which is the order stackless traversal needs)
+                if is_stackless {
+                    bvh_ptr = if near_distance < hit.distance {
+                        near_ptr
+                    } else if far_distance < hit.distance {
+                        far_ptr
+                    } else {
+                        base_ptr + d2.w.to_bits()
+                    };
 
-                let mut near_distance = self.intersect_box(d0.xyz(), d1.xyz());
-                let mut far_distance = self.intersect_box(d2.xyz(), d3.xyz());
+                    continue;
+                }
 
                 if far_distance < near_distance {
                     mem::swap(&mut near_ptr, &mut far_ptr);
@@ -169,44 +241,221 @@ impl Ray {
                 // triangle (kind of a "cache miss" kind of thing), we still
                 // have to check the other node.
                 if far_distance < hit.distance {
-                    unsafe {
-                        *stack.index_unchecked_mut(stack_ptr) = far_ptr;
-                        stack_ptr += 1;
+                    if stack_ptr < stack_ends_at {
+                        unsafe {
+                            *stack.index_unchecked_mut(stack_ptr) = far_ptr;
+                            stack_ptr += 1;
+                        }
+                    } else {
+                        is_stackless = true;
                     }
                 }
 
+                if is_stackless {
+                    bvh_ptr = root_ptr;
+                    in_instance = false;
+                    ray = self;
+                    base_ptr = 0;
+                    continue;
+                }
+
                 if near_distance < hit.distance {
                     bvh_ptr = near_ptr;
                     continue;
                 }
-            } else {
-                used_memory += mem::size_of::<Triangle>();
+            } else if op == OP_INTERNAL_WIDE {
+                used_memory += 4 * mem::size_of::<Vec4>();
+
+                let d1 = bvh.get(bvh_ptr + 1);
+                let d2 = bvh.get(bvh_ptr + 2);
+                let d3 = bvh.get(bvh_ptr + 3);
+                let d4 = bvh.get(bvh_ptr + 4);
+
+                let children = d1.w.to_bits();
+
+                let mut dist0 = ray.intersect_wide_child(d0, d1, d2, d3, 0);
+                let mut dist1 = ray.intersect_wide_child(d0, d1, d2, d3, 1);
+                let mut dist2 = f32::MAX;
+                let mut dist3 = f32::MAX;
+
+                if children > 2 {
+                    dist2 = ray.intersect_wide_child(d0, d1, d2, d3, 2);
+                }
+
+                if children > 3 {
+                    dist3 = ray.intersect_wide_child(d0, d1, d2, d3, 3);
+                }
+
+                let mut ptr0 = base_ptr + d4.x.to_bits();
+                let mut ptr1 = base_ptr + d4.y.to_bits();
+                let mut ptr2 = base_ptr + d4.z.to_bits();
+                let mut ptr3 = base_ptr + d4.w.to_bits();
+
+                if is_stackless {
+                    bvh_ptr = if dist0 < hit.distance {
+                        ptr0
+                    } else if dist1 < hit.distance {
+                        ptr1
+                    } else if dist2 < hit.distance {
+                        ptr2
+                    } else if dist3 < hit.distance {
+                        ptr3
+                    } else {
+                        base_ptr + d3.z.to_bits()
+                    };
+
+                    continue;
+                }
+
+                // Sort children front-to-back (it's a sorting network, which
+                // is branch-friendlier than a loop), for the same reason we
+                // visit the nearest child first for binary nodes
+                sort_children(&mut dist0, &mut ptr0, &mut dist1, &mut ptr1);
+                sort_children(&mut dist2, &mut ptr2, &mut dist3, &mut ptr3);
+                sort_children(&mut dist0, &mut ptr0, &mut dist2, &mut ptr2);
+                sort_children(&mut dist1, &mut ptr1, &mut dist3, &mut ptr3);
+                sort_children(&mut dist1, &mut ptr1, &mut dist2, &mut ptr2);
+
+                // Push the farther children in reverse order, so that the
+                // nearer ones get popped first
+                let mut pushes = 0;
+
+                if dist3 < hit.distance {
+                    pushes += 1;
+                }
+
+                if dist2 < hit.distance {
+                    pushes += 1;
+                }
+
+                if dist1 < hit.distance {
+                    pushes += 1;
+                }
+
+                if stack_ptr + pushes > stack_ends_at {
+                    is_stackless = true;
+                    bvh_ptr = root_ptr;
+                    in_instance = false;
+                    ray = self;
+                    base_ptr = 0;
+                    continue;
+                }
+
+                if dist3 < hit.distance {
+                    unsafe {
+                        *stack.index_unchecked_mut(stack_ptr) = ptr3;
+                        stack_ptr += 1;
+                    }
+                }
+
+                if dist2 < hit.distance {
+                    unsafe {
+                        *stack.index_unchecked_mut(stack_ptr) = ptr2;
+                        stack_ptr += 1;
+                    }
+                }
+
+                if dist1 < hit.distance {
+                    unsafe {
+                        *stack.index_unchecked_mut(stack_ptr) = ptr1;
+                        stack_ptr += 1;
+                    }
+                }
+
+                if dist0 < hit.distance {
+                    bvh_ptr = ptr0;
+                    continue;
+                }
+            } else if op == OP_INSTANCE {
+                used_memory += 3 * mem::size_of::<Vec4>();
 
                 let flags = d0.x.to_bits();
 
+                if is_stackless {
+                    // Whatever follows this instance (be it the next instance
+                    // within this leaf or the leaf's escape) comes right after
+                    // it in the buffer
+                    instance_next_ptr = bvh_ptr + 4;
+                } else if flags & 1 == 1 {
+                    // There are more instances directly following this
+                    // instance, so we have to get back to them after we're
+                    // done with this instance's tree
+                    if stack_ptr < stack_ends_at {
+                        unsafe {
+                            *stack.index_unchecked_mut(stack_ptr) = bvh_ptr + 4;
+                            stack_ptr += 1;
+                        }
+                    } else {
+                        is_stackless = true;
+                        bvh_ptr = root_ptr;
+                        in_instance = false;
+                        ray = self;
+                        base_ptr = 0;
+                        continue;
+                    }
+                }
+
+                // Whether the instance's material supports alpha blending.
+                //
+                // If this is turned on, we have to load the material and
+                // compute albedo to make sure that the part of triangle we hit
+                // is actually opaque at that particular hit-point.
+                has_alpha_blending = flags & 2 == 2;
+                material_id = MaterialId::new(d0.y.to_bits());
+
+                xform_r0 = bvh.get(bvh_ptr + 1);
+                xform_r1 = bvh.get(bvh_ptr + 2);
+                xform_r2 = bvh.get(bvh_ptr + 3);
+
+                // We don't normalize the direction, so that distances in
+                // object-space match the ones in world-space
+                ray = Ray::new(
+                    vec3(
+                        xform_r0.xyz().dot(self.origin) + xform_r0.w,
+                        xform_r1.xyz().dot(self.origin) + xform_r1.w,
+                        xform_r2.xyz().dot(self.origin) + xform_r2.w,
+                    ),
+                    vec3(
+                        xform_r0.xyz().dot(self.direction),
+                        xform_r1.xyz().dot(self.direction),
+                        xform_r2.xyz().dot(self.direction),
+                    ),
+                );
+
+                in_instance = true;
+                instance_stack_ptr = stack_ptr;
+                base_ptr = d0.z.to_bits();
+                bvh_ptr = base_ptr;
+                continue;
+            } else if op == OP_END {
+                // We can get here only when travelling stacklessly - it means
+                // we're done with the current tree
+                if in_instance {
+                    in_instance = false;
+                    ray = self;
+                    base_ptr = 0;
+                    bvh_ptr = instance_next_ptr;
+                    continue;
+                }
+
+                break;
+            } else {
+                used_memory += mem::size_of::<Triangle>();
+
                 // Whether there are any more triangles directly following this
                 // triangle.
                 //
                 // This corresponds to a single BVH leaf node containing
                 // multiple triangles.
-                let got_more_triangles = flags & 1 == 1;
-
-                // Whether the triangle we're looking at supports alpha
-                // blending.
-                //
-                // If this is turned on, we have to load the triangle's material
-                // and compute albedo to make sure that the part of triangle we
-                // hit is actually opaque at that particular hit-point.
-                let has_alpha_blending = flags & 2 == 2;
+                let got_more_triangles = d0.x.to_bits() & 1 == 1;
 
                 let triangle_id = TriangleId::new(d0.y.to_bits());
-                let material_id = MaterialId::new(d0.z.to_bits());
 
                 let prev_uv = hit.uv;
                 let prev_normal = hit.normal;
                 let prev_distance = hit.distance;
 
-                let mut found_hit = triangles.get(triangle_id).hit(self, hit);
+                let mut found_hit = triangles.get(triangle_id).hit(ray, hit);
 
                 if found_hit && has_alpha_blending {
                     used_memory += mem::size_of::<Material>();
@@ -230,12 +479,23 @@ impl Ray {
                 if found_hit {
                     hit.material_id = material_id;
 
+                    // Triangles are stored in object-space, so we have to
+                    // transform the normal back into world-space (using the
+                    // inverse-transpose matrix, which we get for free since
+                    // we've got the inverse already)
+                    hit.normal = (hit.normal.x * xform_r0.xyz()
+                        + hit.normal.y * xform_r1.xyz()
+                        + hit.normal.z * xform_r2.xyz())
+                    .normalize();
+
                     if let Tracing::ReturnFirst = tracing {
                         break;
                     }
                 }
 
-                if got_more_triangles {
+                // When travelling stacklessly, whatever follows this triangle
+                // comes right after it in the buffer, same as for instances
+                if got_more_triangles || is_stackless {
                     bvh_ptr += 1;
                     continue;
                 }
@@ -248,6 +508,14 @@ impl Ray {
             // stack and investigate it; if the stack is empty, then we've
             // tested all nodes and we can safely bail out.
             if stack_ptr > stack_begins_at {
+                // If we're about to pop an item that belongs to the top-level
+                // tree, it means we're done with the current instance
+                if in_instance && stack_ptr <= instance_stack_ptr {
+                    in_instance = false;
+                    ray = self;
+                    base_ptr = 0;
+                }
+
                 unsafe {
                     stack_ptr -= 1;
                     bvh_ptr = *stack.index_unchecked(stack_ptr);
@@ -300,6 +568,35 @@ impl Ray {
         }
     }
 
+    /// Checks whether this ray hits given child of a wide node and returns
+    /// their nearest intersection distance; see `strolle::BvhLayout::Wide`.
+    fn intersect_wide_child(
+        self,
+        d0: Vec4,
+        d1: Vec4,
+        d2: Vec4,
+        d3: Vec4,
+        child_idx: u32,
+    ) -> f32 {
+        fn unpack(packed: f32, child_idx: u32) -> f32 {
+            ((packed.to_bits() >> (8 * child_idx)) & 0xff) as f32
+        }
+
+        let min = vec3(
+            unpack(d2.x, child_idx),
+            unpack(d2.y, child_idx),
+            unpack(d2.z, child_idx),
+        );
+
+        let max = vec3(
+            unpack(d2.w, child_idx),
+            unpack(d3.x, child_idx),
+            unpack(d3.y, child_idx),
+        );
+
+        self.intersect_box(d0.xyz() + min * d1.xyz(), d0.xyz() + max * d1.xyz())
+    }
+
     pub fn intersect_sphere(self, radius: f32) -> f32 {
         let b = self.origin.dot(self.direction);
         let c = self.origin.dot(self.origin) - radius * radius;
@@ -320,6 +617,26 @@ impl Ray {
     }
 }
 
+/// Kinds of entries inside the BVH buffer; see `strolle::Bvh`.
+const OP_INTERNAL: u32 = 0;
+const OP_INSTANCE: u32 = 2;
+const OP_INTERNAL_WIDE: u32 = 3;
+const OP_END: u32 = 4;
+
+/// Compare-and-swap step of the sorting network used to order wide node's
+/// children.
+fn sort_children(
+    dist_a: &mut f32,
+    ptr_a: &mut u32,
+    dist_b: &mut f32,
+    ptr_b: &mut u32,
+) {
+    if *dist_b < *dist_a {
+        mem::swap(dist_a, dist_b);
+        mem::swap(ptr_a, ptr_b);
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum Tracing {
     ReturnClosest,
@@ -87,6 +87,20 @@ impl DiSample {
 
         let light = lights.get(self.light_id);
 
+        if light.is_tombstone() {
+            return false;
+        }
+
+        // Directional lights don't have a position that the sample could be
+        // checked against
+        if light.is_directional() {
+            return true;
+        }
+
+        if light.is_area() {
+            return light.area_contains(self.light_point);
+        }
+
         light.center().distance(self.light_point) <= light.radius()
     }
 
@@ -106,6 +120,7 @@ mod tests {
     use glam::vec3;
 
     use super::*;
+    use crate::Light;
 
     #[test]
     fn serialization() {
@@ -136,4 +151,24 @@ mod tests {
             assert_eq!(expected, actual);
         }
     }
+
+    #[test]
+    fn is_valid() {
+        let lights = [
+            Light::directional(Vec3::NEG_Y, Vec3::ONE, 0.0),
+            Light::tombstone(),
+        ];
+
+        let lights = LightsView::new(&lights);
+
+        let sample = |light_id| DiSample {
+            light_id: LightId::new(light_id),
+            light_point: Vec3::ZERO,
+            exists: true,
+        };
+
+        assert!(sample(0).is_valid(lights));
+        assert!(!sample(1).is_valid(lights));
+        assert!(!sample(2).is_valid(lights));
+    }
 }
@@ -7,13 +7,21 @@ use spirv_std::num_traits::Float;
 #[derive(Clone, Copy, Default, Pod, Zeroable)]
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
 pub struct World {
+    /// Number of regular lights (including the sun)
     pub light_count: u32,
+
+    /// Number of slots for triangle lights (i.e. emissive triangles), which
+    /// are laid out right after the regular ones; some of the slots might be
+    /// tombstones
+    pub triangle_light_count: u32,
+
     pub sun_azimuth: f32,
     pub sun_altitude: f32,
 }
 
 impl World {
-    // TODO recalculate the distance factor using sun's solid angle
+    /// Distance at which indirect rays that hit nothing are assumed to hit
+    /// the sky.
     pub const SUN_DISTANCE: f32 = 1000.0;
 
     pub fn sun_direction(&self) -> Vec3 {
@@ -23,8 +31,4 @@ impl World {
             -self.sun_altitude.cos() * self.sun_azimuth.cos(),
         )
     }
-
-    pub fn sun_position(&self) -> Vec3 {
-        self.sun_direction() * Self::SUN_DISTANCE
-    }
 }
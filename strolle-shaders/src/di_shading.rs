@@ -1,5 +1,11 @@
 use strolle_gpu::prelude::*;
 
+/// Number of lights picked from the light tree per pixel, if there are any.
+const TREE_CANDIDATES: u32 = 8;
+
+/// Number of emissive triangles considered per pixel, if there are any.
+const TRIANGLE_CANDIDATES: u32 = 8;
+
 #[spirv(compute(threads(8, 8)))]
 #[allow(clippy::too_many_arguments)]
 pub fn main(
@@ -18,6 +24,8 @@ pub fn main(
     #[spirv(descriptor_set = 0, binding = 5)] atlas_tex: Tex,
     #[spirv(descriptor_set = 0, binding = 6)] atlas_sampler: &Sampler,
     #[spirv(descriptor_set = 0, binding = 7, uniform)] world: &World,
+    #[spirv(descriptor_set = 0, binding = 8, storage_buffer)]
+    light_tree: &[Vec4],
     #[spirv(descriptor_set = 1, binding = 0, uniform)] camera: &Camera,
     #[spirv(descriptor_set = 1, binding = 1)] prim_gbuffer_d0: TexRgba32,
     #[spirv(descriptor_set = 1, binding = 2)] prim_gbuffer_d1: TexRgba32,
@@ -32,6 +40,7 @@ pub fn main(
     let bvh = BvhView::new(bvh);
     let materials = MaterialsView::new(materials);
     let lights = LightsView::new(lights);
+    let light_tree = LightTreeView::new(light_tree);
 
     if !camera.contains(screen_pos) {
         return;
@@ -56,11 +65,35 @@ pub fn main(
     let mut res = EphemeralReservoir::default();
     let mut res_pdf = 0.0;
 
-    let light_pdf = 1.0 / (world.light_count as f32);
+    // Directional lights can't be a part of the light tree, but there's
+    // usually just a few of them (most often only the sun), so we go through
+    // all of them; the remaining lights can be counted in hundreds and
+    // emissive triangles in thousands, so instead of going through all of
+    // them, we pick a few - proportionally to their estimated contribution
+    // and to their power, respectively
+    let directional_light_count = light_tree.directional_light_count();
+
+    let tree_candidate_count = if light_tree.has_nodes() {
+        TREE_CANDIDATES
+    } else {
+        0
+    };
+
+    let triangle_candidate_count = if world.triangle_light_count > 0 {
+        TRIANGLE_CANDIDATES
+    } else {
+        0
+    };
+
+    let light_pdf = 1.0
+        / ((directional_light_count
+            + tree_candidate_count
+            + triangle_candidate_count) as f32);
+
     let mut light_idx = 0;
 
-    while light_idx < world.light_count {
-        let light_id = LightId::new(light_idx);
+    while light_idx < directional_light_count {
+        let light_id = light_tree.directional_light(light_idx);
         let light_radiance = lights.get(light_id).radiance(hit);
 
         let sample = EphemeralSample {
@@ -77,6 +110,49 @@ pub fn main(
         light_idx += 1;
     }
 
+    let mut candidate_idx = 0;
+
+    while candidate_idx < tree_candidate_count + triangle_candidate_count {
+        // Together, the candidates of each kind act as a single light
+        // estimated through importance sampling, hence the extra factor
+        let (light_id, candidate_pdf) = if candidate_idx < tree_candidate_count
+        {
+            let (light_id, tree_pdf) = light_tree.sample(&mut wnoise, hit);
+
+            (light_id, tree_pdf * (tree_candidate_count as f32))
+        } else {
+            let (light_id, triangle_pdf) =
+                lights.sample_triangle(world, wnoise.sample());
+
+            (light_id, triangle_pdf * (triangle_candidate_count as f32))
+        };
+
+        if candidate_pdf > 0.0 {
+            let light_radiance = lights.get(light_id).radiance(hit);
+
+            let sample = EphemeralSample {
+                light_id,
+                light_radiance,
+            };
+
+            let sample_pdf = sample.pdf();
+
+            if res.update(
+                &mut wnoise,
+                sample,
+                sample_pdf / (light_pdf * candidate_pdf),
+            ) {
+                res_pdf = sample_pdf;
+            }
+        } else {
+            // None of the lights in the tree reaches this point - the
+            // candidate still counts, though, it just doesn't contribute
+            res.m += 1.0;
+        }
+
+        candidate_idx += 1;
+    }
+
     res.normalize(res_pdf);
 
     // ---
@@ -87,6 +87,13 @@ pub fn main(
             camera.screen_to_idx(reprojection.prev_pos_round()),
         );
 
+        // If the light has been removed since the previous frame, its slot
+        // contains a tombstone - in that case there's nothing to reuse
+        if prev.sample.exists && lights.get(prev.sample.light_id).is_tombstone()
+        {
+            prev = Default::default();
+        }
+
         prev.clamp_m(20.0 * curr_m.max(1.0));
 
         let prev_pdf = if prev.sample.exists {
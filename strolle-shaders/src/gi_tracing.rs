@@ -106,6 +106,16 @@ pub fn main(
 
         gi_material.regularize();
 
+        // Untextured emissive surfaces are sampled as lights during direct
+        // lighting, so picking them up here would count them twice (that's
+        // not a problem for specular lighting, which doesn't sample lights)
+        let emissive =
+            if params.is_diff() && !gi_material.is_textured_emissive() {
+                Vec3::ZERO
+            } else {
+                gi_material.emissive(atlas_tex, atlas_sampler, gi_hit.uv)
+            };
+
         GBufferEntry {
             base_color: gi_material.base_color(
                 atlas_tex,
@@ -114,7 +124,7 @@ pub fn main(
             ),
             normal: gi_hit.normal,
             metallic: gi_material.metallic,
-            emissive: gi_material.emissive(atlas_tex, atlas_sampler, gi_hit.uv),
+            emissive,
             roughness: gi_material.roughness,
             reflectance: gi_material.reflectance,
             depth: prim_hit.point.distance(gi_hit.point),
@@ -21,13 +21,12 @@ pub fn vs(
     out_normal: &mut Vec3,
     out_uv: &mut Vec2,
 ) {
-    let point = vertex_d0.xyz();
+    // Vertices are stored in mesh's object-space
+    let curr_xform = params.curr_xform();
+    let point = curr_xform.transform_point3(vertex_d0.xyz());
+    let prev_point = params.prev_xform().transform_point3(vertex_d0.xyz());
 
-    let prev_point = params
-        .prev_xform()
-        .transform_point3(params.curr_xform_inv().transform_point3(point));
-
-    let normal = vertex_d1.xyz();
+    let normal = params.normal_xform() * vertex_d1.xyz();
     let uv = vec2(vertex_d0.w, vertex_d1.w);
 
     *out_vertex = camera.world_to_clip(point);
@@ -21,6 +21,11 @@ impl DoubleBuffered<Texture> {
             b: texture.with_label(label_b).build(device),
         }
     }
+
+    /// See: [`Texture::allocated_size()`].
+    pub fn allocated_size(&self) -> usize {
+        self.a.allocated_size() + self.b.allocated_size()
+    }
 }
 
 impl DoubleBuffered<&Texture> {
@@ -57,6 +62,11 @@ impl DoubleBuffered<StorageBuffer> {
             b: StorageBuffer::new(device, format!("{}_b", label), size),
         }
     }
+
+    /// See: [`StorageBuffer::allocated_size()`].
+    pub fn allocated_size(&self) -> usize {
+        self.a.allocated_size() + self.b.allocated_size()
+    }
 }
 
 impl DoubleBuffered<&StorageBuffer> {
@@ -65,6 +65,12 @@ where
         &self.buffer
     }
 
+    /// Returns how many bytes this buffer occupies in VRAM; since the buffer
+    /// grows in advance, this can be larger than the data itself.
+    pub fn allocated_size(&self) -> usize {
+        self.buffer.size() as usize
+    }
+
     /// Creates an immutable storage-buffer binding:
     ///
     /// ```
@@ -105,6 +111,36 @@ where
         true
     }
 
+    /// Reallocates the buffer if it's larger than its data requires, freeing
+    /// up VRAM; returns whether the buffer has been reallocated.
+    pub fn shrink(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> bool {
+        let curr_size = self.buffer.size() as usize;
+        let new_size = utils::pad_size(self.data.size());
+
+        // Can't allocate empty buffers, see `Self::new()`
+        if new_size == 0 || curr_size <= new_size {
+            return false;
+        }
+
+        debug!(
+            "Shrinking mapped storage buffer `{}`; \
+             curr-size={curr_size}, new-size={new_size}",
+            self.label,
+        );
+
+        self.buffer.destroy();
+        self.buffer = Self::create_buffer(device, &self.label, new_size);
+        self.dirty = false;
+
+        queue.write_buffer(&self.buffer, 0, self.data.data());
+
+        true
+    }
+
     pub fn flush(
         &mut self,
         device: &wgpu::Device,
@@ -148,7 +184,8 @@ where
             label: Some(label),
             usage: wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::VERTEX,
+                | wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::INDEX,
             size: size as _,
             mapped_at_creation: false,
         })
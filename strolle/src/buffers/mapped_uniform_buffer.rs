@@ -37,6 +37,11 @@ where
         }
     }
 
+    /// Returns how many bytes this buffer occupies in VRAM.
+    pub fn allocated_size(&self) -> usize {
+        self.buffer.size() as usize
+    }
+
     pub fn flush(&mut self, queue: &wgpu::Queue) {
         if !mem::take(&mut self.dirty) {
             return;
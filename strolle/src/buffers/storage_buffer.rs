@@ -34,6 +34,11 @@ impl StorageBuffer {
         Self { buffer }
     }
 
+    /// Returns how many bytes this buffer occupies in VRAM.
+    pub fn allocated_size(&self) -> usize {
+        self.buffer.size() as usize
+    }
+
     /// Creates an immutable storage-buffer binding:
     ///
     /// ```
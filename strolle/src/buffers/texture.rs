@@ -28,6 +28,21 @@ impl Texture {
         &self.view
     }
 
+    /// Returns how many bytes this texture occupies in VRAM (approximately,
+    /// since drivers are free to pad textures).
+    pub fn allocated_size(&self) -> usize {
+        let (block_width, block_height) = self.format.block_dimensions();
+
+        // Depth formats don't have a well-defined size, but it's at most four
+        // bytes per texel for the ones we use
+        let block_size = self.format.block_size(None).unwrap_or(4);
+
+        let blocks_x = self.tex.width().div_ceil(block_width);
+        let blocks_y = self.tex.height().div_ceil(block_height);
+
+        (blocks_x * blocks_y * block_size) as usize
+    }
+
     /// Creates an image + sampler bindings:
     ///
     /// ```
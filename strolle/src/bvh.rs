@@ -1,73 +1,507 @@
+//! Two-level bounding volume hierarchy.
+//!
+//! Each mesh gets its own bottom-level tree (built once, in mesh's
+//! object-space) and all instances are kept in a single top-level tree (built
+//! over instances' world-space bounds); this way moving an instance requires
+//! rebuilding only the top-level tree, which is usually pretty small.
+//!
+//! All trees are serialized into a single buffer:
+//!
+//! ```text
+//! [header] [mesh #1's tree] [mesh #2's tree] ... [top-level tree]
+//! ```
+//!
+//! ... where the header says where the top-level tree begins (or contains
+//! `u32::MAX` if there are no instances) - see `Ray::traverse()` in
+//! `strolle-gpu` for the other side of the story.
+//!
+//! Meshes' trees keep their places in the buffer until they are removed or
+//! change their size - new trees go into holes left by the removed ones (or at
+//! the end, pushing the top-level tree further), so changing a mesh requires
+//! uploading just its tree and the top-level tree.
+//!
+//! Trees are always built as binary trees, but they can be serialized either
+//! as binary or as wide trees - see [`crate::BvhLayout`].
+
+mod blas;
 mod builder;
+mod cache;
+mod exporter;
 mod node;
 mod nodes;
 mod primitive;
 mod primitives;
+mod refitter;
+mod reporter;
+mod sbvh_builder;
 mod serializer;
+mod tlas;
+mod traverser;
 
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::io::{self, Write};
+use std::mem;
 use std::ops::Range;
 
-use spirv_std::glam::Vec4;
+use log::warn;
+use spirv_std::glam::{vec4, Affine3A, Vec3, Vec4};
 
+pub use self::blas::*;
 pub use self::builder::*;
+pub use self::cache::*;
+pub use self::exporter::*;
 pub use self::node::*;
 pub use self::nodes::*;
 pub use self::primitive::*;
 pub use self::primitives::*;
+pub use self::tlas::*;
+use crate::utils::Allocator;
 use crate::{
-    utils, Bindable, BufferFlushOutcome, MappedStorageBuffer, Materials, Params,
+    gpu, AlphaMode, Bindable, BufferFlushOutcome, BvhConfig, BvhReport,
+    BvhUpdatePolicy, Instance, MappedStorageBuffer, Materials, Metrics, Params,
 };
 
 #[derive(Debug)]
-pub struct Bvh {
+pub struct Bvh<P>
+where
+    P: Params,
+{
     buffer: MappedStorageBuffer<Vec<Vec4>>,
-    nodes: BvhNodes,
-    primitives: BvhPrimitives,
+    blases: HashMap<P::MeshHandle, Blas>,
+    tlas: Tlas<P>,
+
+    /// Where the top-level tree begins in the buffer
+    tlas_ptr: usize,
+
+    /// SAH cost of the top-level tree right after its last rebuild; `None` if
+    /// the tree hasn't been built yet
+    tlas_cost: Option<f32>,
+
+    update_policy: BvhUpdatePolicy,
+
+    config: BvhConfig,
+
+    /// On-disk cache of meshes' trees, if enabled
+    cache: Option<BvhCache>,
+
+    /// Number of stack entries the GPU traversal might need to walk the
+    /// entire hierarchy, as of the last refresh
+    stack_size: usize,
+
+    /// Holes between the header and the top-level tree, left by meshes' trees
+    /// that have been removed or resized
+    blas_allocator: Allocator,
+
+    /// Parts of the buffer occupied by meshes' trees that haven't been yet
+    /// sent to the GPU
+    dirty_blases: Vec<Range<usize>>,
+
+    /// Index of the first item in the buffer that hasn't been yet sent to the
+    /// GPU, apart from `dirty_blases` (all items from there to the end of
+    /// the buffer are sent)
+    dirty_from: Option<usize>,
 }
 
-impl Bvh {
+impl<P> Bvh<P>
+where
+    P: Params,
+{
     pub fn new(device: &wgpu::Device) -> Self {
         Self {
-            buffer: MappedStorageBuffer::new_default(device, "bvh"),
-            nodes: Default::default(),
-            primitives: Default::default(),
+            buffer: MappedStorageBuffer::new(
+                device,
+                "bvh",
+                vec![Self::header(None)],
+            ),
+            blases: Default::default(),
+            tlas: Default::default(),
+            tlas_ptr: 1,
+            tlas_cost: None,
+            update_policy: Default::default(),
+            config: Default::default(),
+            cache: None,
+            stack_size: 0,
+            blas_allocator: Default::default(),
+            dirty_blases: Default::default(),
+            dirty_from: Some(0),
         }
     }
 
-    pub fn add(&mut self, prim: BvhPrimitive) {
-        self.primitives.add(prim);
+    fn header(tlas_ptr: Option<usize>) -> Vec4 {
+        let tlas_ptr = tlas_ptr.map_or(u32::MAX, |ptr| ptr as u32);
+
+        vec4(
+            f32::from_bits(tlas_ptr),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
     }
 
-    pub fn update(
-        &mut self,
-        ids: Range<usize>,
-    ) -> impl Iterator<Item = &mut BvhPrimitive> {
-        self.primitives.update(ids)
+    pub fn set_update_policy(&mut self, update_policy: BvhUpdatePolicy) {
+        self.update_policy = update_policy;
     }
 
-    pub fn refresh<P>(&mut self, materials: &Materials<P>)
-    where
-        P: Params,
-    {
-        utils::measure("tick.bvh.begin", || {
-            self.primitives.begin_refresh();
-        });
+    pub fn config(&self) -> &BvhConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: BvhConfig) {
+        self.config = config;
+    }
 
-        utils::measure("tick.bvh.build", || {
-            builder::run(&mut self.nodes, &mut self.primitives);
+    pub fn set_cache(&mut self, cache: Option<BvhCache>) {
+        self.cache = cache;
+    }
+
+    /// Creates or updates tree for given mesh.
+    ///
+    /// `triangles` should contain object-space triangles (together with their
+    /// vertices), with ids pointing into the triangles buffer.
+    pub fn insert_mesh(
+        &mut self,
+        mesh_handle: P::MeshHandle,
+        triangles: impl IntoIterator<Item = (BvhPrimitive, [Vec3; 3])>,
+        metrics: &Metrics,
+    ) {
+        let blas = metrics.measure("tick.bvh.blas", || {
+            Blas::new(triangles, &self.config, self.cache.as_ref())
         });
 
-        utils::measure("tick.bvh.serialize", || {
-            serializer::run(
-                materials,
-                &self.nodes,
-                &self.primitives,
-                &mut self.buffer,
+        self.place(mesh_handle, blas);
+    }
+
+    /// Updates mesh's tree after its triangles have been moved; see
+    /// [`Blas::relocate()`].
+    pub fn relocate_mesh(
+        &mut self,
+        mesh_handle: &P::MeshHandle,
+        old_ids: Range<usize>,
+        new_ids: Range<usize>,
+    ) {
+        // Relocating doesn't change tree's size, so it can stay where it is
+        if let Some(blas) = self.blases.get_mut(mesh_handle) {
+            blas.relocate(old_ids, new_ids, self.config.layout);
+
+            let ids = Self::blas_ids(blas);
+
+            self.buffer[ids.clone()].copy_from_slice(blas.data());
+            self.dirty_blases.push(ids);
+        }
+    }
+
+    pub fn remove_mesh(&mut self, mesh_handle: &P::MeshHandle) {
+        if let Some(blas) = self.blases.remove(mesh_handle) {
+            self.blas_allocator.give(Self::blas_ids(&blas));
+        }
+    }
+
+    /// Puts given mesh's tree into the buffer, reusing the place of mesh's
+    /// previous tree if their sizes match.
+    fn place(&mut self, mesh_handle: P::MeshHandle, mut blas: Blas) {
+        let len = blas.data().len();
+
+        let ptr = match self.blases.remove(&mesh_handle) {
+            Some(prev_blas) if prev_blas.data().len() == len => {
+                prev_blas.ptr() as usize
+            }
+
+            prev_blas => {
+                if let Some(prev_blas) = prev_blas {
+                    self.blas_allocator.give(Self::blas_ids(&prev_blas));
+                }
+
+                self.take(len)
+            }
+        };
+
+        blas.set_ptr(ptr as u32);
+
+        let ids = Self::blas_ids(&blas);
+
+        self.buffer[ids.clone()].copy_from_slice(blas.data());
+        self.dirty_blases.push(ids);
+        self.blases.insert(mesh_handle, blas);
+    }
+
+    /// Finds place for a tree of given size, growing the buffer if there's
+    /// no hole large enough.
+    fn take(&mut self, len: usize) -> usize {
+        if let Some(ids) = self.blas_allocator.take(len) {
+            return ids.start;
+        }
+
+        // The top-level tree lives right after meshes' trees, so we have to
+        // drop it here - it gets serialized again during the refresh anyway,
+        // since it has to point at the new tree
+        let ptr = self.tlas_ptr;
+
+        self.tlas_ptr += len;
+        self.buffer.truncate(ptr);
+        self.buffer.resize(self.tlas_ptr, Vec4::ZERO);
+
+        ptr
+    }
+
+    fn blas_ids(blas: &Blas) -> Range<usize> {
+        let ptr = blas.ptr() as usize;
+
+        ptr..(ptr + blas.data().len())
+    }
+
+    /// Creates or updates given instance; returns `false` if the instance's
+    /// mesh doesn't have a tree yet, in which case the instance is removed
+    /// from the top-level tree until its mesh becomes available.
+    pub fn insert_instance(
+        &mut self,
+        instance_handle: P::InstanceHandle,
+        instance: &Instance<P>,
+        material_id: gpu::MaterialId,
+    ) -> bool {
+        let Some(blas) = self.blases.get(&instance.mesh_handle) else {
+            self.tlas.remove(&instance_handle);
+            return false;
+        };
+
+        let bounds = blas.bounds().transform(instance.transform);
+
+        self.tlas.insert(
+            BvhInstance {
+                handle: instance_handle,
+                mesh_handle: instance.mesh_handle.clone(),
+                material_id,
+                transform_inverse: instance.transform_inverse,
+            },
+            bounds,
+        );
+
+        true
+    }
+
+    pub fn remove_instance(&mut self, instance_handle: &P::InstanceHandle) {
+        self.tlas.remove(instance_handle);
+    }
+
+    pub fn refresh(&mut self, materials: &Materials<P>, metrics: &Metrics) {
+        // Once holes left by removed trees take more than a half of the space
+        // used for meshes' trees, it's time to put the trees next to each other
+        // again
+        if self.blas_allocator.available() > (self.tlas_ptr - 1) / 2 {
+            metrics.measure("tick.bvh.layout", || {
+                self.layout();
+            });
+        }
+
+        let has_dirty_topology = mem::take(&mut self.tlas.has_dirty_topology);
+
+        if self.tlas.is_empty() {
+            // Builder doesn't support empty trees, so let's just drop the
+            // tree altogether and mark it in the header
+            metrics.measure("tick.bvh.begin", || {
+                self.tlas.primitives.begin_refresh();
+            });
+
+            self.tlas.nodes = Default::default();
+            self.tlas_cost = None;
+            self.buffer.truncate(self.tlas_ptr);
+            self.buffer[0] = Self::header(None);
+            self.stack_size = 0;
+        } else {
+            let is_refitted = !has_dirty_topology && self.try_refit(metrics);
+
+            if !is_refitted {
+                metrics.measure("tick.bvh.begin", || {
+                    self.tlas.primitives.begin_refresh();
+                });
+
+                metrics.measure("tick.bvh.build", || {
+                    builder::run(
+                        &mut self.tlas.nodes,
+                        &mut self.tlas.primitives,
+                        &self.config,
+                    );
+                });
+
+                self.tlas_cost = Some(self.tlas.nodes.cost());
+            }
+
+            let tlas_stack_size = metrics.measure("tick.bvh.serialize", || {
+                self.serialize_tlas(materials)
+            });
+
+            self.check_stack_size(tlas_stack_size);
+        }
+
+        self.tlas.primitives.end_refresh();
+
+        self.dirty_from =
+            Some(self.dirty_from.map_or(self.tlas_ptr, |dirty_from| {
+                dirty_from.min(self.tlas_ptr)
+            }));
+    }
+
+    /// Refits the top-level tree, if the update policy allows for that;
+    /// returns `false` if the tree has to be rebuilt instead.
+    fn try_refit(&mut self, metrics: &Metrics) -> bool {
+        let BvhUpdatePolicy::Refit { max_degradation } = self.update_policy
+        else {
+            return false;
+        };
+
+        let Some(built_cost) = self.tlas_cost else {
+            return false;
+        };
+
+        metrics.measure("tick.bvh.refit", || {
+            refitter::try_run(
+                &mut self.tlas.nodes,
+                &mut self.tlas.primitives,
+                built_cost,
+                max_degradation,
+            )
+        })
+    }
+
+    /// Puts all meshes' trees into the buffer, one after another, right after
+    /// the header, getting rid of holes left by removed trees.
+    fn layout(&mut self) {
+        self.buffer.clear();
+        self.buffer.push(Self::header(None));
+
+        for blas in self.blases.values_mut() {
+            blas.set_ptr(self.buffer.len() as u32);
+            self.buffer.extend_from_slice(blas.data());
+        }
+
+        self.tlas_ptr = self.buffer.len();
+        self.blas_allocator = Default::default();
+        self.dirty_blases.clear();
+        self.dirty_from = Some(0);
+    }
+
+    /// Warns if the hierarchy is too deep for the GPU traversal's stack, in
+    /// which case some rays will have to fall back to a (much slower) stackless
+    /// traversal.
+    fn check_stack_size(&mut self, tlas_stack_size: usize) {
+        // Top-level tree's leaves might push one more entry, pointing at the
+        // next instance, before we jump into the instance's tree
+        let stack_size = tlas_stack_size
+            + 1
+            + self
+                .blases
+                .values()
+                .map(|blas| blas.stack_size())
+                .max()
+                .unwrap_or_default();
+
+        let prev_stack_size = mem::replace(&mut self.stack_size, stack_size);
+
+        if stack_size > gpu::BVH_STACK_SIZE
+            && prev_stack_size <= gpu::BVH_STACK_SIZE
+        {
+            warn!(
+                "BVH is too deep: traversing it might require up to \
+                 {stack_size} stack entries, but only {} are supported - rays \
+                 that run out of stack will fall back to a slower traversal; \
+                 consider increasing `BvhConfig::min_leaf_size`",
+                gpu::BVH_STACK_SIZE,
             );
-        });
+        }
+    }
+
+    /// Serializes the top-level tree and returns its stack size; see
+    /// [`serializer::run()`].
+    fn serialize_tlas(&mut self, materials: &Materials<P>) -> usize {
+        let blases = &self.blases;
+        let tlas = &self.tlas;
+        let buffer = &mut *self.buffer;
+
+        buffer.truncate(self.tlas_ptr);
+        buffer[0] = Self::header(Some(self.tlas_ptr));
+
+        serializer::run(
+            &tlas.nodes,
+            &tlas.primitives,
+            buffer,
+            self.config.layout,
+            |buffer, primitive, got_more_entries| {
+                let instance = tlas
+                    .get(primitive.id)
+                    .expect("top-level tree refers to a removed instance");
+
+                let blas_ptr = blases[&instance.mesh_handle].ptr();
+
+                let has_alpha_blending = !matches!(
+                    materials[instance.material_id].alpha_mode,
+                    AlphaMode::Opaque
+                );
+
+                let flags = (got_more_entries as u32)
+                    | ((has_alpha_blending as u32) << 1);
+
+                buffer.push(vec4(
+                    f32::from_bits(flags),
+                    f32::from_bits(instance.material_id.get()),
+                    f32::from_bits(blas_ptr),
+                    f32::from_bits(serializer::OP_INSTANCE),
+                ));
+
+                // World-space -> object-space transformation, stored as rows
+                let xform = instance.transform_inverse;
+
+                for row in 0..3 {
+                    buffer.push(vec4(
+                        xform.matrix3.x_axis[row],
+                        xform.matrix3.y_axis[row],
+                        xform.matrix3.z_axis[row],
+                        xform.translation[row],
+                    ));
+                }
+            },
+        )
+    }
 
-        self.primitives.end_refresh();
+    pub fn report(&self) -> BvhReport<P> {
+        BvhReport {
+            tlas: reporter::run(&self.tlas.nodes),
+            blases: self
+                .blases
+                .iter()
+                .map(|(mesh_handle, blas)| (mesh_handle.clone(), blas.report()))
+                .collect(),
+            buffer_size: self.buffer.len() * mem::size_of::<Vec4>(),
+            stack_size: self.stack_size,
+            max_stack_size: gpu::BVH_STACK_SIZE,
+        }
+    }
+
+    /// Writes bounds of all nodes as a Wavefront OBJ wireframe - the
+    /// top-level tree, followed by each instance's bottom-level tree, all in
+    /// world-space.
+    pub fn export_obj(&self, writer: impl Write) -> io::Result<()> {
+        let mut exporter = Exporter::new(writer);
+
+        exporter.export("tlas", &self.tlas.nodes, Affine3A::IDENTITY)?;
+
+        for (instance_idx, instance) in self.tlas.iter().enumerate() {
+            let Some(blas) = self.blases.get(&instance.mesh_handle) else {
+                continue;
+            };
+
+            exporter.comment(&format!(
+                "instance{instance_idx}: {:?} (mesh {:?})",
+                instance.handle, instance.mesh_handle
+            ))?;
+
+            exporter.export(
+                &format!("instance{instance_idx}"),
+                blas.nodes(),
+                instance.transform_inverse.inverse(),
+            )?;
+        }
+
+        Ok(())
     }
 
     pub fn flush(
@@ -75,11 +509,115 @@ impl Bvh {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) -> BufferFlushOutcome {
-        self.buffer.flush(device, queue)
+        let dirty_blases = mem::take(&mut self.dirty_blases);
+        let dirty_from = self.dirty_from.take();
+
+        if dirty_blases.is_empty() && dirty_from.is_none() {
+            return BufferFlushOutcome::default();
+        }
+
+        let reallocated = self.buffer.reallocate(device, queue);
+
+        if reallocated {
+            // Reallocating already flushes the entire buffer, so there's no
+            // need to flush it again
+        } else {
+            let item_size = mem::size_of::<Vec4>();
+
+            self.buffer.flush_part(queue, 0, item_size);
+
+            for ids in dirty_blases {
+                self.buffer.flush_part(
+                    queue,
+                    ids.start * item_size,
+                    ids.len() * item_size,
+                );
+            }
+
+            if let Some(dirty_from) = dirty_from {
+                let offset = dirty_from.max(1) * item_size;
+                let size = self.buffer.len() * item_size - offset;
+
+                if size > 0 {
+                    self.buffer.flush_part(queue, offset, size);
+                }
+            }
+        }
+
+        BufferFlushOutcome { reallocated }
     }
 
+    /// Finds the closest triangle hit by given world-space ray, as of the last
+    /// [`Self::refresh()`].
+    ///
+    /// `hit_fn` gets called for each potentially-hit triangle together with
+    /// the triangle's instance and the ray transformed into that instance's
+    /// object-space; see [`traverser::run()`] for details.
+    pub fn traverse(
+        &self,
+        ray: gpu::Ray,
+        hit: &mut gpu::TriangleHit,
+        mut hit_fn: impl FnMut(
+            &BvhInstance<P>,
+            gpu::Ray,
+            gpu::TriangleId,
+            &mut gpu::TriangleHit,
+        ) -> bool,
+    ) -> Option<(&BvhInstance<P>, gpu::TriangleId)> {
+        let mut closest = None;
+
+        traverser::run(
+            &self.tlas.nodes,
+            &self.tlas.primitives,
+            ray,
+            hit,
+            |primitive, hit| {
+                let Some(instance) = self.tlas.get(primitive.id) else {
+                    return false;
+                };
+
+                let Some(blas) = self.blases.get(&instance.mesh_handle) else {
+                    return false;
+                };
+
+                // We don't normalize the direction, so that distances in
+                // object-space match the ones in world-space
+                let ray = gpu::Ray::new(
+                    instance.transform_inverse.transform_point3(ray.origin()),
+                    instance
+                        .transform_inverse
+                        .transform_vector3(ray.direction()),
+                );
+
+                let triangle = blas.traverse(ray, hit, |triangle, hit| {
+                    let triangle_id = gpu::TriangleId::new(triangle.id);
+
+                    hit_fn(instance, ray, triangle_id, hit)
+                });
+
+                if let Some(triangle) = triangle {
+                    closest =
+                        Some((instance, gpu::TriangleId::new(triangle.id)));
+
+                    true
+                } else {
+                    false
+                }
+            },
+        );
+
+        closest
+    }
+
+    /// Returns the total number of nodes, across all trees.
     pub fn len(&self) -> usize {
-        self.nodes.nodes.len()
+        self.tlas.nodes.nodes.len()
+            + self.blases.values().map(|blas| blas.len()).sum::<usize>()
+    }
+
+    /// Returns how many bytes the serialized trees occupy in VRAM.
+    pub fn allocated_size(&self) -> usize {
+        self.buffer.allocated_size()
     }
 
     pub fn bind_readable(&self) -> impl Bindable + '_ {
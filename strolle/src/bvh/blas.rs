@@ -0,0 +1,254 @@
+use std::ops::Range;
+
+use glam::{Vec3, Vec4};
+use spirv_std::glam::vec4;
+
+use super::{
+    builder, reporter, sbvh_builder, serializer, traverser, BvhCache,
+    BvhNodeId, BvhNodes, BvhPrimitive, BvhPrimitives,
+};
+use crate::{gpu, BoundingBox, BvhConfig, BvhLayout, BvhTreeReport};
+
+/// Bottom-level tree, built once per mesh over its object-space triangles.
+#[derive(Debug)]
+pub struct Blas {
+    nodes: BvhNodes,
+    primitives: BvhPrimitives,
+    bounds: BoundingBox,
+
+    /// Serialized tree; pointers inside are relative to the tree's root, so
+    /// that the tree can be moved around the buffer without re-serializing it
+    data: Vec<Vec4>,
+
+    /// Number of stack entries the GPU traversal might need to walk this tree
+    stack_size: usize,
+
+    /// Where this tree begins in the BVH buffer
+    ptr: u32,
+}
+
+impl Blas {
+    /// Builds tree over given triangles, using spatial splits if they are
+    /// enabled in the config; if cache is provided, the tree is loaded from
+    /// there (or stored there, once built).
+    pub fn new(
+        triangles: impl IntoIterator<Item = (BvhPrimitive, [Vec3; 3])>,
+        config: &BvhConfig,
+        cache: Option<&BvhCache>,
+    ) -> Self {
+        let mut nodes = BvhNodes::default();
+        let mut primitives = BvhPrimitives::default();
+        let mut data = Vec::new();
+
+        let (triangles, positions): (Vec<_>, Vec<_>) =
+            triangles.into_iter().unzip();
+
+        for &triangle in &triangles {
+            primitives.add(triangle);
+        }
+
+        primitives.begin_refresh();
+
+        let key = cache.map(|_| BvhCache::key(config, &positions));
+
+        let cached = cache
+            .zip(key)
+            .and_then(|(cache, key)| cache.load(key, &triangles));
+
+        if let Some((cached_nodes, cached_primitives)) = cached {
+            nodes = cached_nodes;
+            primitives.replace_current(cached_primitives);
+        } else {
+            if let Some(overlap_threshold) = config.spatial_splits {
+                sbvh_builder::run(
+                    &mut nodes,
+                    &mut primitives,
+                    &positions,
+                    config,
+                    overlap_threshold,
+                );
+            } else {
+                builder::run(&mut nodes, &mut primitives, config);
+            }
+
+            if let Some((cache, key)) = cache.zip(key) {
+                cache.store(
+                    key,
+                    &triangles,
+                    &nodes,
+                    primitives.current(primitives.current_ref()),
+                );
+            }
+        }
+
+        let stack_size =
+            Self::serialize(&nodes, &primitives, &mut data, config.layout);
+
+        primitives.end_refresh();
+
+        let bounds = nodes[BvhNodeId::root()].bounds();
+
+        Self {
+            nodes,
+            primitives,
+            bounds,
+            data,
+            stack_size,
+            ptr: 0,
+        }
+    }
+
+    /// Updates tree after mesh's triangles have been moved from `old_ids` to
+    /// `new_ids` (e.g. during compaction); this is much faster than building
+    /// the tree from scratch, since only the ids change.
+    pub fn relocate(
+        &mut self,
+        old_ids: Range<usize>,
+        new_ids: Range<usize>,
+        layout: BvhLayout,
+    ) {
+        let (old_start, new_start) =
+            (old_ids.start as u32, new_ids.start as u32);
+
+        self.primitives.remap_ids(|id| id - old_start + new_start);
+        self.primitives.begin_reuse();
+        self.data.clear();
+
+        self.stack_size = Self::serialize(
+            &self.nodes,
+            &self.primitives,
+            &mut self.data,
+            layout,
+        );
+
+        self.primitives.end_refresh();
+    }
+
+    fn serialize(
+        nodes: &BvhNodes,
+        primitives: &BvhPrimitives,
+        data: &mut Vec<Vec4>,
+        layout: BvhLayout,
+    ) -> usize {
+        serializer::run(
+            nodes,
+            primitives,
+            data,
+            layout,
+            |buffer, triangle, got_more_entries| {
+                buffer.push(vec4(
+                    f32::from_bits(got_more_entries as u32),
+                    f32::from_bits(triangle.id),
+                    Default::default(),
+                    f32::from_bits(serializer::OP_TRIANGLE),
+                ));
+            },
+        )
+    }
+
+    pub fn bounds(&self) -> BoundingBox {
+        self.bounds
+    }
+
+    pub fn data(&self) -> &[Vec4] {
+        &self.data
+    }
+
+    pub fn stack_size(&self) -> usize {
+        self.stack_size
+    }
+
+    pub fn ptr(&self) -> u32 {
+        self.ptr
+    }
+
+    pub fn set_ptr(&mut self, ptr: u32) {
+        self.ptr = ptr;
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.nodes.len()
+    }
+
+    pub fn report(&self) -> BvhTreeReport {
+        reporter::run(&self.nodes)
+    }
+
+    pub fn nodes(&self) -> &BvhNodes {
+        &self.nodes
+    }
+
+    /// Finds the closest triangle hit by given object-space ray; see
+    /// [`traverser::run()`].
+    pub fn traverse(
+        &self,
+        ray: gpu::Ray,
+        hit: &mut gpu::TriangleHit,
+        hit_fn: impl FnMut(&BvhPrimitive, &mut gpu::TriangleHit) -> bool,
+    ) -> Option<BvhPrimitive> {
+        traverser::run(&self.nodes, &self.primitives, ray, hit, hit_fn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+    use crate::bvh::{BvhPrimitiveId, BvhPrimitivesRef};
+
+    fn triangles(
+        ids: Range<usize>,
+    ) -> impl Iterator<Item = (BvhPrimitive, [Vec3; 3])> {
+        ids.enumerate().map(|(idx, id)| {
+            let a = vec3((idx % 8) as f32, (idx / 8) as f32, 0.0);
+            let b = a + vec3(1.0, 0.0, 0.5);
+            let c = a + vec3(0.0, 1.0, -0.5);
+
+            let primitive = BvhPrimitive {
+                id: id as u32,
+                center: (a + b + c) / 3.0,
+                bounds: [a, b, c].into_iter().collect(),
+            };
+
+            (primitive, [a, b, c])
+        })
+    }
+
+    /// Returns tree's serialized data as bits, since some of the floats are
+    /// actually packed integers that can happen to be NaNs.
+    fn bits(blas: &Blas) -> Vec<[u32; 4]> {
+        blas.data()
+            .iter()
+            .map(|entry| entry.to_array().map(f32::to_bits))
+            .collect()
+    }
+
+    #[test]
+    fn relocate() {
+        for layout in [BvhLayout::Binary, BvhLayout::Wide] {
+            let config = BvhConfig {
+                layout,
+                ..Default::default()
+            };
+
+            let mut target = Blas::new(triangles(100..164), &config, None);
+
+            target.relocate(100..164, 10..74, layout);
+
+            // Relocated tree must be the same as if it's been built for the
+            // new ids in the first place
+            let expected = Blas::new(triangles(10..74), &config, None);
+
+            assert_eq!(bits(&expected), bits(&target));
+            assert_eq!(expected.stack_size(), target.stack_size());
+
+            let primitives = target.primitives.previous(BvhPrimitivesRef::new(
+                BvhPrimitiveId::new(0),
+                BvhPrimitiveId::new(64),
+            ));
+
+            assert!(primitives.iter().all(|p| (10..74).contains(&p.id)));
+        }
+    }
+}
@@ -1,163 +1,444 @@
+//! Binned-SAH builder.
+//!
+//! Building happens in two phases:
+//!
+//! - first we split the topmost nodes on the current thread (binning large
+//!   nodes in parallel), until we reach nodes with at most [`SUBTREE_SIZE`]
+//!   primitives,
+//!
+//! - then we build those nodes' subtrees in parallel, each on its own slice of
+//!   primitives.
+//!
+//! Subtrees don't allocate nodes on their own - instead, each subtree records
+//! which nodes it would like to allocate or release and then we replay those
+//! operations on the current thread, in the order in which subtrees have been
+//! discovered; this way the output doesn't depend on the number of threads or
+//! on the order in which threads happen to finish their work.
+
 use core::f32;
 use std::collections::VecDeque;
 use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::sync::Mutex;
 use std::thread;
 
 use fxhash::FxHasher;
 use glam::UVec3;
 
 use super::{
-    BvhNode, BvhNodeHash, BvhNodeId, BvhNodes, BvhPrimitiveId, BvhPrimitives,
-    BvhPrimitivesRef,
+    BvhNode, BvhNodeHash, BvhNodeId, BvhNodes, BvhPrimitive, BvhPrimitiveId,
+    BvhPrimitives, BvhPrimitivesRef,
 };
-use crate::{Axis, BoundingBox};
+use crate::{Axis, BoundingBox, BvhConfig};
+
+/// Nodes with at most this many primitives get their subtrees built in
+/// parallel.
+///
+/// Note that this must not depend on the number of threads, otherwise the
+/// output would depend on it as well.
+const SUBTREE_SIZE: usize = 4 * 1024;
+
+/// Nodes with at least this many primitives get binned in parallel.
+const PARALLEL_BINNING_SIZE: usize = 64 * 1024;
+
+/// Builds (or rebuilds) the tree.
+///
+/// Must be called between [`BvhPrimitives::begin_refresh()`] and
+/// [`BvhPrimitives::end_refresh()`].
+pub fn run(
+    nodes: &mut BvhNodes,
+    primitives: &mut BvhPrimitives,
+    config: &BvhConfig,
+) {
+    let threads = config.threads.max(1);
+    let primitives_ref = primitives.current_ref();
+
+    let root = nodes.set_root(BvhNode::Leaf {
+        bounds: primitives
+            .current(primitives_ref)
+            .iter()
+            .map(|primitive| primitive.bounds)
+            .collect(),
+        primitives_ref,
+    });
 
-const BINS: usize = 12;
+    let (current, previous) = primitives.current_and_previous_mut();
 
-pub fn run(nodes: &mut BvhNodes, primitives: &mut BvhPrimitives) {
-    thread::scope(|s| {
-        s.spawn(|| {
-            let root = nodes.set_root(BvhNode::Leaf {
-                bounds: Default::default(),
-                primitives_ref: primitives.current_ref(),
+    let top = build(
+        nodes,
+        previous,
+        current,
+        Task {
+            id: BvhNodeId::root(),
+            primitives_ref,
+            ghost: root,
+        },
+        Some(SUBTREE_SIZE),
+        config,
+        threads,
+    );
+
+    let tasks = top.apply(nodes, BvhNodeId::root());
+
+    if tasks.is_empty() {
+        return;
+    }
+
+    // Each task works on a separate part of the primitives, so we can give
+    // each task its own mutable slice
+    let mut slices: Vec<_> = {
+        let mut ranges: Vec<_> = tasks
+            .iter()
+            .enumerate()
+            .map(|(task_idx, task)| (task_idx, task.primitives_ref.as_range()))
+            .collect();
+
+        ranges.sort_by_key(|(_, range)| range.start);
+
+        let mut slices: Vec<_> = (0..tasks.len()).map(|_| None).collect();
+        let mut rest = &mut current[..];
+        let mut rest_offset = 0;
+
+        for (task_idx, range) in ranges {
+            let (_, tail) = rest.split_at_mut(range.start - rest_offset);
+            let (slice, tail) = tail.split_at_mut(range.len());
+
+            slices[task_idx] = Some(slice);
+            rest = tail;
+            rest_offset = range.end;
+        }
+
+        slices
+    };
+
+    let subtrees = {
+        let nodes = &*nodes;
+
+        let jobs =
+            Mutex::new(tasks.iter().zip(slices.iter_mut()).enumerate().map(
+                |(task_idx, (task, slice))| {
+                    (task_idx, task, slice.take().unwrap())
+                },
+            ));
+
+        let subtrees = Mutex::new(Vec::with_capacity(tasks.len()));
+
+        let work = || loop {
+            let Some((task_idx, task, slice)) = jobs.lock().unwrap().next()
+            else {
+                break;
+            };
+
+            let subtree =
+                build(nodes, previous, slice, task.clone(), None, config, 1);
+
+            subtrees.lock().unwrap().push((task_idx, subtree));
+        };
+
+        let threads = threads.min(tasks.len());
+
+        if threads == 1 {
+            work();
+        } else {
+            thread::scope(|s| {
+                for _ in 0..threads {
+                    s.spawn(work);
+                }
             });
+        }
 
-            let root = BvhNodeRef::root(root);
-            let mut stack = VecDeque::from_iter([root]);
+        let mut subtrees = subtrees.into_inner().unwrap();
 
-            while let Some(node) = stack.pop_front() {
-                match balance(nodes, primitives, node) {
-                    (Some(left), Some(right)) => {
-                        stack.push_back(left);
-                        stack.push_back(right);
-                    }
-                    (Some(node), None) | (None, Some(node)) => {
-                        stack.push_back(node);
-                    }
-                    (None, None) => {
-                        //
-                    }
+        subtrees.sort_by_key(|(task_idx, _)| *task_idx);
+        subtrees
+    };
+
+    for (task_idx, subtree) in subtrees {
+        let pending = subtree.apply(nodes, tasks[task_idx].id);
+
+        debug_assert!(pending.is_empty());
+    }
+}
+
+/// Builds subtree of given node.
+///
+/// `primitives` is the slice of the current primitives that belongs to this
+/// node, while `nodes` and `previous` are the tree and its primitives as of
+/// the previous refresh, used to re-use subtrees that haven't changed.
+///
+/// If `stop_at` is set, nodes with at most that many primitives are not split
+/// further and returned as new tasks instead.
+fn build(
+    nodes: &BvhNodes,
+    previous: &[BvhPrimitive],
+    primitives: &mut [BvhPrimitive],
+    task: Task,
+    stop_at: Option<usize>,
+    config: &BvhConfig,
+    threads: usize,
+) -> Subtree {
+    let offset = task.primitives_ref.start().get() as usize;
+
+    let mut subtree = Subtree {
+        nodes: vec![SubtreeNode {
+            bounds: nodes[task.id].bounds(),
+            primitives_ref: task.primitives_ref,
+            children: None,
+        }],
+        ops: Default::default(),
+        tasks: Default::default(),
+    };
+
+    let mut stack = VecDeque::from_iter([(0, task.ghost)]);
+
+    while let Some((node_idx, ghost)) = stack.pop_front() {
+        let node = &subtree.nodes[node_idx];
+        let node_primitives = &mut primitives[range(node, offset)];
+        let node_len = node_primitives.len();
+        let node_area = node.bounds.half_area();
+        let must_split = node_len > config.max_leaf_size;
+
+        let plane = if node_len > config.min_leaf_size {
+            find_splitting_plane(node_primitives, config, threads)
+        } else {
+            None
+        };
+
+        let split = plane
+            .filter(|plane| {
+                must_split
+                    || config.split_cost(node_area, plane.split_cost)
+                        < config.leaf_cost(node_len, node_area)
+            })
+            .map(|plane| partition(node_primitives, plane))
+            .filter(|split| split.pivot > 0 && split.pivot < node_len);
+
+        // If we have to split the node, but couldn't find any good plane (e.g.
+        // because all primitives have the same center), let's just split it
+        // in half
+        let split = if must_split {
+            split.or_else(|| Some(partition_in_half(node_primitives)))
+        } else {
+            split
+        };
+
+        let Some(split) = split else {
+            if let Some(BvhNode::Internal {
+                left_id, right_id, ..
+            }) = ghost
+            {
+                subtree.ops.push(SubtreeOp::RemoveTree(left_id));
+                subtree.ops.push(SubtreeOp::RemoveTree(right_id));
+            }
+
+            continue;
+        };
+
+        let pivot = BvhPrimitiveId::new(
+            node.primitives_ref.start().get() + (split.pivot as u32),
+        );
+
+        let halves = [
+            (
+                BvhPrimitivesRef::new(node.primitives_ref.start(), pivot),
+                split.left_bounds,
+                split.left_hash,
+            ),
+            (
+                BvhPrimitivesRef::new(pivot, node.primitives_ref.end()),
+                split.right_bounds,
+                split.right_hash,
+            ),
+        ];
+
+        let prev_children = match ghost {
+            Some(BvhNode::Internal {
+                left_id,
+                left_hash,
+                right_id,
+                right_hash,
+                ..
+            }) => [Some((left_id, left_hash)), Some((right_id, right_hash))],
+            _ => [None, None],
+        };
+
+        // If the primitives of any of the children haven't changed since the
+        // previous refresh, we can re-use that child's entire subtree; for
+        // the rest we have to keep on splitting
+        let mut children = [None, None];
+        let mut ghosts = [None, None];
+
+        for (child_idx, (primitives_ref, _, hash)) in halves.iter().enumerate()
+        {
+            let Some((prev_id, prev_hash)) = prev_children[child_idx] else {
+                continue;
+            };
+
+            if prev_hash == *hash {
+                let prev_primitives_ref = nodes[prev_id].primitives_ref();
+
+                let dst = primitives_ref.start().get() as usize - offset;
+
+                primitives[dst..dst + primitives_ref.len()]
+                    .copy_from_slice(&previous[prev_primitives_ref.as_range()]);
+
+                let primitives_offset = (primitives_ref.start().get() as i32)
+                    - (prev_primitives_ref.start().get() as i32);
+
+                if primitives_offset != 0 {
+                    subtree
+                        .ops
+                        .push(SubtreeOp::Offset(prev_id, primitives_offset));
                 }
+
+                children[child_idx] = Some(SubtreeChild::Reused(prev_id));
+            } else {
+                ghosts[child_idx] = Some(nodes[prev_id]);
+                subtree.ops.push(SubtreeOp::Remove(prev_id));
             }
+        }
+
+        let children = [0, 1].map(|child_idx| {
+            let (primitives_ref, bounds, hash) = halves[child_idx];
+
+            let child = children[child_idx].unwrap_or_else(|| {
+                let child_idx = subtree.nodes.len();
+
+                subtree.nodes.push(SubtreeNode {
+                    bounds,
+                    primitives_ref,
+                    children: None,
+                });
+
+                subtree.ops.push(SubtreeOp::Add(child_idx));
+
+                SubtreeChild::New(child_idx)
+            });
+
+            (child, hash)
         });
-    });
-}
 
-#[inline(always)]
-fn balance(
-    nodes: &mut BvhNodes,
-    primitives: &mut BvhPrimitives,
-    node_ref: BvhNodeRef,
-) -> (Option<BvhNodeRef>, Option<BvhNodeRef>) {
-    if let Some(plane) = find_splitting_plane(nodes, primitives, node_ref.id) {
-        if plane.split_cost < nodes[node_ref.id].sah_cost() {
-            return split(nodes, primitives, node_ref, plane);
+        for ((child, _), ghost) in children.iter().zip(ghosts) {
+            let SubtreeChild::New(child_idx) = *child else {
+                continue;
+            };
+
+            let is_task = stop_at.map_or(false, |stop_at| {
+                subtree.nodes[child_idx].primitives_ref.len() <= stop_at
+            });
+
+            if is_task {
+                subtree.tasks.push((child_idx, ghost));
+            } else {
+                stack.push_back((child_idx, ghost));
+            }
         }
-    }
 
-    if let Some(BvhNode::Internal {
-        left_id, right_id, ..
-    }) = node_ref.ghost
-    {
-        nodes.remove_tree(left_id);
-        nodes.remove_tree(right_id);
+        subtree.nodes[node_idx].children = Some(children);
     }
 
-    (None, None)
+    subtree
+}
+
+fn range(node: &SubtreeNode, offset: usize) -> Range<usize> {
+    let range = node.primitives_ref.as_range();
+
+    (range.start - offset)..(range.end - offset)
 }
 
 #[inline(always)]
 fn find_splitting_plane(
-    nodes: &BvhNodes,
-    primitives: &BvhPrimitives,
-    node_id: BvhNodeId,
+    primitives: &[BvhPrimitive],
+    config: &BvhConfig,
+    threads: usize,
 ) -> Option<SplittingPlane> {
-    let BvhNode::Leaf { primitives_ref, .. } = nodes[node_id] else {
-        unreachable!();
-    };
-
-    if primitives_ref.len() <= 1 {
+    if primitives.len() <= 1 {
         return None;
     }
 
-    let primitives = primitives.current(primitives_ref);
-
-    // ---
+    let chunk_size = if primitives.len() >= PARALLEL_BINNING_SIZE {
+        primitives.len().div_ceil(threads)
+    } else {
+        primitives.len()
+    };
 
-    let centroid_bb: BoundingBox = primitives
-        .iter()
-        .map(|primitive| primitive.center)
+    let centroid_bb: BoundingBox =
+        map_chunks(primitives, chunk_size, |chunk| {
+            chunk
+                .iter()
+                .map(|primitive| primitive.center)
+                .collect::<BoundingBox>()
+        })
+        .into_iter()
         .collect();
 
-    let mut bins = [[Bin::default(); BINS]; 3];
-    let scale = (BINS as f32) / centroid_bb.extent();
-
-    for primitive in primitives {
-        let bin_id = scale * (primitive.center - centroid_bb.min());
-        let bin_id = bin_id.as_uvec3().min(UVec3::splat((BINS as u32) - 1));
-        let bin_idx = bin_id.x as usize;
-        let bin_idy = bin_id.y as usize;
-        let bin_idz = bin_id.z as usize;
-
-        bins[0][bin_idx].count += 1;
-        bins[0][bin_idx].bounds += primitive.bounds;
-
-        bins[1][bin_idy].count += 1;
-        bins[1][bin_idy].bounds += primitive.bounds;
+    let bins_count = config.bins.max(2);
+
+    let bins = map_chunks(primitives, chunk_size, |chunk| {
+        bin(chunk, centroid_bb, bins_count)
+    })
+    .into_iter()
+    .reduce(|mut a, b| {
+        for axis in 0..3 {
+            for i in 0..bins_count {
+                a[axis][i].count += b[axis][i].count;
+                a[axis][i].bounds += b[axis][i].bounds;
+            }
+        }
 
-        bins[2][bin_idz].count += 1;
-        bins[2][bin_idz].bounds += primitive.bounds;
-    }
+        a
+    })
+    .unwrap();
 
     // ---
 
-    let mut left_areas = [[0.0; BINS - 1]; 3];
-    let mut right_areas = [[0.0; BINS - 1]; 3];
-    let mut left_counts = [[0; BINS - 1]; 3];
-    let mut right_counts = [[0; BINS - 1]; 3];
-    let mut left_bb = [BoundingBox::default(); 3];
-    let mut right_bb = [BoundingBox::default(); 3];
-    let mut left_count = [0; 3];
-    let mut right_count = [0; 3];
+    let mut best: Option<SplittingPlane> = None;
+    let scale = centroid_bb.extent() / (bins_count as f32);
+
+    for (axis, bins) in bins.iter().enumerate() {
+        let mut left_areas = vec![0.0; bins_count - 1];
+        let mut right_areas = vec![0.0; bins_count - 1];
+        let mut left_counts = vec![0; bins_count - 1];
+        let mut right_counts = vec![0; bins_count - 1];
+        let mut left_bb = BoundingBox::default();
+        let mut right_bb = BoundingBox::default();
+        let mut left_count = 0;
+        let mut right_count = 0;
 
-    for axis in 0..3 {
-        for i in 0..(BINS - 1) {
-            let left_bin = bins[axis][i];
+        for i in 0..(bins_count - 1) {
+            let left_bin = bins[i];
 
-            left_count[axis] += left_bin.count;
-            left_counts[axis][i] = left_count[axis];
+            left_count += left_bin.count;
+            left_counts[i] = left_count;
 
             if left_bin.bounds.is_set() {
-                left_bb[axis] += left_bin.bounds;
+                left_bb += left_bin.bounds;
             }
 
-            left_areas[axis][i] = left_bb[axis].half_area();
+            left_areas[i] = left_bb.half_area();
 
             // ---
 
-            let right_bin = bins[axis][BINS - 1 - i];
+            let right_bin = bins[bins_count - 1 - i];
 
-            right_count[axis] += right_bin.count;
-            right_counts[axis][BINS - 2 - i] = right_count[axis];
+            right_count += right_bin.count;
+            right_counts[bins_count - 2 - i] = right_count;
 
             if right_bin.bounds.is_set() {
-                right_bb[axis] += right_bin.bounds;
+                right_bb += right_bin.bounds;
             }
 
-            right_areas[axis][BINS - 2 - i] = right_bb[axis].half_area();
+            right_areas[bins_count - 2 - i] = right_bb.half_area();
         }
-    }
 
-    // ---
-
-    let mut best: Option<SplittingPlane> = None;
-    let scale = centroid_bb.extent() / (BINS as f32);
+        for i in 0..(bins_count - 1) {
+            // Planes that leave one of the sides empty don't split anything
+            if left_counts[i] == 0 || right_counts[i] == 0 {
+                continue;
+            }
 
-    for axis in 0..3 {
-        for i in 0..(BINS - 1) {
-            let split_cost = (left_counts[axis][i] as f32)
-                * left_areas[axis][i]
-                + (right_counts[axis][i] as f32) * right_areas[axis][i];
+            let split_cost = (left_counts[i] as f32) * left_areas[i]
+                + (right_counts[i] as f32) * right_areas[i];
 
             let is_current_bin_better =
                 best.map_or(true, |best| split_cost <= best.split_cost);
@@ -180,26 +461,71 @@ fn find_splitting_plane(
     best
 }
 
-fn split(
-    nodes: &mut BvhNodes,
-    primitives: &mut BvhPrimitives,
-    node_ref: BvhNodeRef,
-    plane: SplittingPlane,
-) -> (Option<BvhNodeRef>, Option<BvhNodeRef>) {
-    let BvhNode::Leaf {
-        bounds,
-        primitives_ref,
-    } = nodes[node_ref.id]
-    else {
-        unreachable!();
-    };
+fn bin(
+    primitives: &[BvhPrimitive],
+    centroid_bb: BoundingBox,
+    bins_count: usize,
+) -> [Vec<Bin>; 3] {
+    let mut bins = [
+        vec![Bin::default(); bins_count],
+        vec![Bin::default(); bins_count],
+        vec![Bin::default(); bins_count],
+    ];
 
-    // ---
+    let scale = (bins_count as f32) / centroid_bb.extent();
+
+    for primitive in primitives {
+        let bin_id = scale * (primitive.center - centroid_bb.min());
+        let bin_id =
+            bin_id.as_uvec3().min(UVec3::splat((bins_count as u32) - 1));
+        let bin_idx = bin_id.x as usize;
+        let bin_idy = bin_id.y as usize;
+        let bin_idz = bin_id.z as usize;
 
-    let primitives_data = &mut primitives.current_mut(primitives_ref);
+        bins[0][bin_idx].count += 1;
+        bins[0][bin_idx].bounds += primitive.bounds;
+
+        bins[1][bin_idy].count += 1;
+        bins[1][bin_idy].bounds += primitive.bounds;
+
+        bins[2][bin_idz].count += 1;
+        bins[2][bin_idz].bounds += primitive.bounds;
+    }
+
+    bins
+}
 
+/// Calls `f` for each chunk of given primitives, using a separate thread for
+/// each chunk (if there's more than one); returns results in the order of
+/// chunks.
+fn map_chunks<T>(
+    primitives: &[BvhPrimitive],
+    chunk_size: usize,
+    f: impl Fn(&[BvhPrimitive]) -> T + Sync,
+) -> Vec<T>
+where
+    T: Send,
+{
+    if chunk_size >= primitives.len() {
+        return vec![f(primitives)];
+    }
+
+    thread::scope(|s| {
+        let f = &f;
+
+        primitives
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || f(chunk)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+fn partition(primitives: &mut [BvhPrimitive], plane: SplittingPlane) -> Split {
     let mut left_prim_idx = 0;
-    let mut right_prim_idx = (primitives_data.len() - 1) as i32;
+    let mut right_prim_idx = (primitives.len() - 1) as i32;
 
     // TODO optimization idea: don't compute hashes when close to leaves
     let mut left_hash = FxHasher::default();
@@ -209,7 +535,7 @@ fn split(
     let mut right_bounds = BoundingBox::default();
 
     while left_prim_idx <= right_prim_idx {
-        let primitive = primitives_data[left_prim_idx as usize];
+        let primitive = primitives[left_prim_idx as usize];
 
         if primitive.center[plane.split_by] < plane.split_at {
             left_prim_idx += 1;
@@ -217,8 +543,7 @@ fn split(
 
             primitive.hash(&mut left_hash);
         } else {
-            primitives_data
-                .swap(left_prim_idx as usize, right_prim_idx as usize);
+            primitives.swap(left_prim_idx as usize, right_prim_idx as usize);
 
             right_prim_idx -= 1;
             right_bounds += primitive.bounds;
@@ -227,112 +552,60 @@ fn split(
         }
     }
 
-    let pivot = BvhPrimitiveId::new(
-        primitives_ref.start().get() + (left_prim_idx as u32),
-    );
-
-    let left_primitives_ref =
-        BvhPrimitivesRef::new(primitives_ref.start(), pivot);
-
-    let right_primitives_ref =
-        BvhPrimitivesRef::new(pivot, primitives_ref.end());
-
-    let left_hash = BvhNodeHash::new(left_hash.finish());
-    let right_hash = BvhNodeHash::new(right_hash.finish());
-
-    // ---
-
-    let mut left_id = None;
-    let mut right_id = None;
-
-    let mut left_ghost = None;
-    let mut right_ghost = None;
-
-    let mut left_continue = true;
-    let mut right_continue = true;
-
-    if let Some(BvhNode::Internal {
-        left_id: prev_left_id,
-        left_hash: prev_left_hash,
-        right_id: prev_right_id,
-        right_hash: prev_right_hash,
-        ..
-    }) = node_ref.ghost
-    {
-        if prev_left_hash == left_hash {
-            left_id = Some(prev_left_id);
-            left_continue = false;
-
-            copy(nodes, primitives, prev_left_id, left_primitives_ref);
-        } else {
-            left_ghost = Some(nodes.remove(prev_left_id));
-        }
-
-        if prev_right_hash == right_hash {
-            right_id = Some(prev_right_id);
-            right_continue = false;
-
-            copy(nodes, primitives, prev_right_id, right_primitives_ref);
-        } else {
-            right_ghost = Some(nodes.remove(prev_right_id));
-        }
+    Split {
+        pivot: left_prim_idx as usize,
+        left_bounds,
+        left_hash: BvhNodeHash::new(left_hash.finish()),
+        right_bounds,
+        right_hash: BvhNodeHash::new(right_hash.finish()),
     }
+}
 
-    // ---
-
-    let left_id = left_id.unwrap_or_else(|| {
-        nodes.add(BvhNode::Leaf {
-            bounds: left_bounds,
-            primitives_ref: left_primitives_ref,
-        })
-    });
+fn partition_in_half(primitives: &mut [BvhPrimitive]) -> Split {
+    let centroid_bb: BoundingBox = primitives
+        .iter()
+        .map(|primitive| primitive.center)
+        .collect();
 
-    let right_id = right_id.unwrap_or_else(|| {
-        nodes.add(BvhNode::Leaf {
-            bounds: right_bounds,
-            primitives_ref: right_primitives_ref,
-        })
-    });
+    let extent = centroid_bb.extent();
 
-    nodes[node_ref.id] = BvhNode::Internal {
-        bounds,
-        primitives_ref,
-        left_id,
-        left_hash,
-        right_id,
-        right_hash,
+    let split_by = if extent.x >= extent.y && extent.x >= extent.z {
+        Axis::X
+    } else if extent.y >= extent.z {
+        Axis::Y
+    } else {
+        Axis::Z
     };
 
-    // ---
-
-    let left = left_continue.then_some(BvhNodeRef {
-        id: left_id,
-        ghost: left_ghost,
-    });
+    let pivot = primitives.len() / 2;
 
-    let right = right_continue.then_some(BvhNodeRef {
-        id: right_id,
-        ghost: right_ghost,
+    primitives.select_nth_unstable_by(pivot, |a, b| {
+        a.center[split_by].total_cmp(&b.center[split_by])
     });
 
-    (left, right)
-}
-
-fn copy(
-    nodes: &mut BvhNodes,
-    primitives: &mut BvhPrimitives,
-    id: BvhNodeId,
-    primitives_ref: BvhPrimitivesRef,
-) {
-    let prev_primitives_ref = nodes[id].primitives_ref();
+    let mut left_hash = FxHasher::default();
+    let mut right_hash = FxHasher::default();
 
-    primitives.copy_previous_to_current(prev_primitives_ref, primitives_ref);
+    for primitive in &primitives[..pivot] {
+        primitive.hash(&mut left_hash);
+    }
 
-    let primitives_offset = (primitives_ref.start().get() as i32)
-        - (prev_primitives_ref.start().get() as i32);
+    for primitive in &primitives[pivot..] {
+        primitive.hash(&mut right_hash);
+    }
 
-    if primitives_offset != 0 {
-        offset_primitives(nodes, primitives_offset, id);
+    Split {
+        pivot,
+        left_bounds: primitives[..pivot]
+            .iter()
+            .map(|primitive| primitive.bounds)
+            .collect(),
+        left_hash: BvhNodeHash::new(left_hash.finish()),
+        right_bounds: primitives[pivot..]
+            .iter()
+            .map(|primitive| primitive.bounds)
+            .collect(),
+        right_hash: BvhNodeHash::new(right_hash.finish()),
     }
 }
 
@@ -360,23 +633,187 @@ struct SplittingPlane {
     split_cost: f32,
 }
 
+#[derive(Clone, Copy, Debug)]
+struct Split {
+    pivot: usize,
+    left_bounds: BoundingBox,
+    left_hash: BvhNodeHash,
+    right_bounds: BoundingBox,
+    right_hash: BvhNodeHash,
+}
+
 #[derive(Clone, Copy, Default, Debug)]
 struct Bin {
     bounds: BoundingBox,
     count: u32,
 }
 
-#[derive(Debug)]
-struct BvhNodeRef {
+/// Node whose subtree is yet to be built.
+#[derive(Clone, Debug)]
+struct Task {
     id: BvhNodeId,
+    primitives_ref: BvhPrimitivesRef,
+
+    /// What this node contained before the refresh
     ghost: Option<BvhNode>,
 }
 
-impl BvhNodeRef {
-    fn root(ghost: Option<BvhNode>) -> Self {
-        Self {
-            id: BvhNodeId::root(),
-            ghost,
+/// Subtree built by [`build()`], not yet applied into the tree.
+#[derive(Debug)]
+struct Subtree {
+    /// Nodes of this subtree; the first node is the subtree's root
+    nodes: Vec<SubtreeNode>,
+
+    /// Operations on the tree, in the order they would've been performed if
+    /// the subtree was built directly into the tree
+    ops: Vec<SubtreeOp>,
+
+    /// Nodes that haven't been split, because they are small enough to be
+    /// built as separate tasks
+    tasks: Vec<(usize, Option<BvhNode>)>,
+}
+
+impl Subtree {
+    /// Puts this subtree into the tree, with its root at `root_id`; returns
+    /// tasks that have yet to be built.
+    fn apply(self, nodes: &mut BvhNodes, root_id: BvhNodeId) -> Vec<Task> {
+        let mut ids = vec![root_id; self.nodes.len()];
+
+        for op in self.ops {
+            match op {
+                SubtreeOp::Add(node_idx) => {
+                    ids[node_idx] = nodes.add(Default::default());
+                }
+                SubtreeOp::Remove(id) => {
+                    nodes.remove(id);
+                }
+                SubtreeOp::RemoveTree(id) => {
+                    nodes.remove_tree(id);
+                }
+                SubtreeOp::Offset(id, offset) => {
+                    offset_primitives(nodes, offset, id);
+                }
+            }
+        }
+
+        let resolve = |child: SubtreeChild| match child {
+            SubtreeChild::New(node_idx) => ids[node_idx],
+            SubtreeChild::Reused(id) => id,
+        };
+
+        for (node_idx, node) in self.nodes.iter().enumerate() {
+            nodes[ids[node_idx]] = match node.children {
+                Some([(left, left_hash), (right, right_hash)]) => {
+                    BvhNode::Internal {
+                        bounds: node.bounds,
+                        primitives_ref: node.primitives_ref,
+                        left_id: resolve(left),
+                        left_hash,
+                        right_id: resolve(right),
+                        right_hash,
+                    }
+                }
+
+                None => BvhNode::Leaf {
+                    bounds: node.bounds,
+                    primitives_ref: node.primitives_ref,
+                },
+            };
+        }
+
+        self.tasks
+            .into_iter()
+            .map(|(node_idx, ghost)| Task {
+                id: ids[node_idx],
+                primitives_ref: self.nodes[node_idx].primitives_ref,
+                ghost,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct SubtreeNode {
+    bounds: BoundingBox,
+    primitives_ref: BvhPrimitivesRef,
+    children: Option<[(SubtreeChild, BvhNodeHash); 2]>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SubtreeChild {
+    /// Node created by this subtree
+    New(usize),
+
+    /// Node re-used from the previous refresh
+    Reused(BvhNodeId),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SubtreeOp {
+    Add(usize),
+    Remove(BvhNodeId),
+    RemoveTree(BvhNodeId),
+    Offset(BvhNodeId, i32),
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Vec3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn build(threads: usize) -> (BvhNodes, Vec<u32>) {
+        let mut rng = StdRng::seed_from_u64(1234);
+        let mut nodes = BvhNodes::default();
+        let mut primitives = BvhPrimitives::default();
+
+        // Large enough to go through both parallel binning and parallel
+        // subtrees
+        for id in 0..((PARALLEL_BINNING_SIZE + SUBTREE_SIZE) as u32) {
+            let center = vec3(rng.gen(), rng.gen(), rng.gen()) * 100.0;
+            let extent = Vec3::splat(rng.gen_range(0.01..1.0));
+
+            primitives.add(BvhPrimitive {
+                id,
+                center,
+                bounds: BoundingBox::new(center - extent, center + extent),
+            });
+        }
+
+        primitives.begin_refresh();
+
+        run(
+            &mut nodes,
+            &mut primitives,
+            &BvhConfig {
+                threads,
+                ..Default::default()
+            },
+        );
+
+        let ids = primitives
+            .current(primitives.current_ref())
+            .iter()
+            .map(|primitive| primitive.id)
+            .collect();
+
+        primitives.end_refresh();
+
+        (nodes, ids)
+    }
+
+    #[test]
+    fn output_does_not_depend_on_threads() {
+        let (nodes_1, ids_1) = build(1);
+
+        for threads in [3, 8] {
+            let (nodes_n, ids_n) = build(threads);
+
+            assert_eq!(nodes_1.nodes, nodes_n.nodes, "threads = {threads}");
+            assert_eq!(nodes_1.free_nodes, nodes_n.free_nodes);
+            assert!(ids_1 == ids_n, "threads = {threads}");
         }
     }
 }
@@ -0,0 +1,623 @@
+use std::hash::Hasher;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::{fs, mem, process};
+
+use fxhash::FxHasher64;
+use glam::{vec3, Vec3};
+use log::{debug, warn};
+
+use super::{
+    BvhNode, BvhNodeHash, BvhNodeId, BvhNodes, BvhPrimitive, BvhPrimitiveId,
+    BvhPrimitivesRef,
+};
+use crate::{BoundingBox, BvhConfig};
+
+const MAGIC: &[u8; 8] = b"STRLBVH\0";
+
+/// Version of the file format; must be bumped whenever the format or the
+/// builders change in a way that affects the built trees.
+const VERSION: u32 = 1;
+
+const NODE_INTERNAL: u32 = 0;
+const NODE_LEAF: u32 = 1;
+
+/// On-disk cache of meshes' trees.
+///
+/// Trees are keyed by the mesh's triangles and the builder's config, so that
+/// modifying any of those causes the tree to be rebuilt; since meshes' trees
+/// are built in object-space, instances' transforms don't play any role here.
+///
+/// Files are laid out as:
+///
+/// ```text
+/// magic, version, key, nodes, free nodes, primitives, checksum
+/// ```
+///
+/// ... where primitives' ids are stored relative to the mesh's first
+/// triangle, since triangle ids can change between launches.
+///
+/// Triangles themselves are not cached - we need them anyway to compute the
+/// key (which hashes their positions) and to upload them to the GPU, so the
+/// only thing worth skipping is building the tree.
+#[derive(Clone, Debug)]
+pub struct BvhCache {
+    dir: PathBuf,
+}
+
+impl BvhCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Returns key under which tree for given triangles is stored.
+    pub fn key(config: &BvhConfig, positions: &[[Vec3; 3]]) -> u64 {
+        let mut hasher = FxHasher64::default();
+
+        hasher.write_u32(VERSION);
+        hasher.write_u64(config.bins as u64);
+        hasher.write_u64(config.min_leaf_size as u64);
+        hasher.write_u64(config.max_leaf_size as u64);
+        hasher.write_u32(config.traversal_cost.to_bits());
+        hasher.write_u32(config.intersection_cost.to_bits());
+
+        if let Some(overlap_threshold) = config.spatial_splits {
+            hasher.write_u8(1);
+            hasher.write_u32(overlap_threshold.to_bits());
+        } else {
+            hasher.write_u8(0);
+        }
+
+        hasher.write_u64(positions.len() as u64);
+
+        for position in positions.iter().flatten() {
+            hasher.write_u32(position.x.to_bits());
+            hasher.write_u32(position.y.to_bits());
+            hasher.write_u32(position.z.to_bits());
+        }
+
+        hasher.finish()
+    }
+
+    /// Loads tree with given key, returning its nodes and primitives (as laid
+    /// out by the builder); returns `None` if the tree is not cached or the
+    /// file is invalid.
+    ///
+    /// `triangles` must be the same triangles the tree has been built for.
+    pub fn load(
+        &self,
+        key: u64,
+        triangles: &[BvhPrimitive],
+    ) -> Option<(BvhNodes, Vec<BvhPrimitive>)> {
+        let path = self.path(key);
+
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return None;
+            }
+
+            Err(err) => {
+                warn!(
+                    "Couldn't read BVH cache from `{}`: {err}",
+                    path.display()
+                );
+                return None;
+            }
+        };
+
+        let tree = Reader::new(&data).read(key, triangles);
+
+        if tree.is_none() {
+            warn!(
+                "BVH cache at `{}` is invalid, rebuilding the tree",
+                path.display()
+            );
+        } else {
+            debug!("Loaded BVH from `{}`", path.display());
+        }
+
+        tree
+    }
+
+    /// Stores tree with given key; errors are logged and otherwise ignored,
+    /// since the cache is just an optimization.
+    ///
+    /// `triangles` must be the same triangles the tree has been built for.
+    pub fn store(
+        &self,
+        key: u64,
+        triangles: &[BvhPrimitive],
+        nodes: &BvhNodes,
+        primitives: &[BvhPrimitive],
+    ) {
+        let path = self.path(key);
+        let data = Writer::default().write(key, triangles, nodes, primitives);
+
+        if let Err(err) = Self::write_atomically(&path, &data) {
+            warn!("Couldn't write BVH cache to `{}`: {err}", path.display());
+        }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.bvh"))
+    }
+
+    /// Writes file through a temporary one, so that other processes (or
+    /// crashes) don't observe half-written files.
+    fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let tmp_path = path.with_extension(format!("{}.tmp", process::id()));
+
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+/// Returns id of the mesh's first triangle; ids inside the file are relative
+/// to it.
+fn base_id(triangles: &[BvhPrimitive]) -> u32 {
+    triangles
+        .iter()
+        .map(|triangle| triangle.id)
+        .min()
+        .unwrap_or_default()
+}
+
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn write(
+        mut self,
+        key: u64,
+        triangles: &[BvhPrimitive],
+        nodes: &BvhNodes,
+        primitives: &[BvhPrimitive],
+    ) -> Vec<u8> {
+        let base_id = base_id(triangles);
+
+        self.data.extend_from_slice(MAGIC);
+        self.u32(VERSION);
+        self.u64(key);
+
+        self.u32(nodes.nodes.len() as u32);
+
+        for node in &nodes.nodes {
+            match *node {
+                BvhNode::Internal {
+                    bounds,
+                    primitives_ref,
+                    left_id,
+                    left_hash,
+                    right_id,
+                    right_hash,
+                } => {
+                    self.u32(NODE_INTERNAL);
+                    self.bounds(bounds);
+                    self.primitives_ref(primitives_ref);
+                    self.u32(left_id.get());
+                    self.u64(left_hash.get());
+                    self.u32(right_id.get());
+                    self.u64(right_hash.get());
+                }
+
+                BvhNode::Leaf {
+                    bounds,
+                    primitives_ref,
+                } => {
+                    self.u32(NODE_LEAF);
+                    self.bounds(bounds);
+                    self.primitives_ref(primitives_ref);
+                }
+            }
+        }
+
+        self.u32(nodes.free_nodes.len() as u32);
+
+        for id in &nodes.free_nodes {
+            self.u32(id.get());
+        }
+
+        self.u32(primitives.len() as u32);
+
+        for primitive in primitives {
+            self.u32(primitive.id - base_id);
+            self.vec3(primitive.center);
+            self.bounds(primitive.bounds);
+        }
+
+        let checksum = checksum(&self.data);
+
+        self.u64(checksum);
+        self.data
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    fn vec3(&mut self, value: Vec3) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+    }
+
+    fn bounds(&mut self, bounds: BoundingBox) {
+        self.vec3(bounds.min());
+        self.vec3(bounds.max());
+    }
+
+    fn primitives_ref(&mut self, primitives_ref: BvhPrimitivesRef) {
+        self.u32(primitives_ref.start().get());
+        self.u32(primitives_ref.end().get());
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn read(
+        mut self,
+        key: u64,
+        triangles: &[BvhPrimitive],
+    ) -> Option<(BvhNodes, Vec<BvhPrimitive>)> {
+        let (data, checksum_data) =
+            self.data.split_at(self.data.len().checked_sub(8)?);
+
+        if checksum(data).to_le_bytes() != checksum_data {
+            return None;
+        }
+
+        self.data = data;
+
+        if self.bytes(MAGIC.len())? != MAGIC
+            || self.u32()? != VERSION
+            || self.u64()? != key
+        {
+            return None;
+        }
+
+        let mut nodes = BvhNodes::default();
+
+        for _ in 0..self.len(4 + 24 + 8)? {
+            let node = match self.u32()? {
+                NODE_INTERNAL => BvhNode::Internal {
+                    bounds: self.bounds()?,
+                    primitives_ref: self.primitives_ref()?,
+                    left_id: BvhNodeId::new(self.u32()?),
+                    left_hash: BvhNodeHash::new(self.u64()?),
+                    right_id: BvhNodeId::new(self.u32()?),
+                    right_hash: BvhNodeHash::new(self.u64()?),
+                },
+
+                NODE_LEAF => BvhNode::Leaf {
+                    bounds: self.bounds()?,
+                    primitives_ref: self.primitives_ref()?,
+                },
+
+                _ => {
+                    return None;
+                }
+            };
+
+            nodes.nodes.push(node);
+        }
+
+        for _ in 0..self.len(4)? {
+            nodes.free_nodes.push(BvhNodeId::new(self.u32()?));
+        }
+
+        let base_id = base_id(triangles);
+        let mut primitives = Vec::new();
+
+        for _ in 0..self.len(4 + 12 + 24)? {
+            let id = self.u32()?;
+
+            if id as usize >= triangles.len() {
+                return None;
+            }
+
+            primitives.push(BvhPrimitive {
+                id: base_id + id,
+                center: self.vec3()?,
+                bounds: self.bounds()?,
+            });
+        }
+
+        if !self.data.is_empty() || !Self::validate(&nodes, &primitives) {
+            return None;
+        }
+
+        Some((nodes, primitives))
+    }
+
+    /// Checks that the tree refers only to existing nodes and primitives, so
+    /// that we don't crash when traversing it.
+    fn validate(nodes: &BvhNodes, primitives: &[BvhPrimitive]) -> bool {
+        if nodes.nodes.is_empty() {
+            return false;
+        }
+
+        let is_valid_id =
+            |id: BvhNodeId| (id.get() as usize) < nodes.nodes.len();
+
+        let is_valid_ref = |primitives_ref: BvhPrimitivesRef| {
+            primitives_ref.start().get() <= primitives_ref.end().get()
+                && primitives_ref.end().get() as usize <= primitives.len()
+        };
+
+        // Walking the tree (instead of just checking each node separately)
+        // makes sure there are no cycles, which would hang the traversal
+        let mut visited = vec![false; nodes.nodes.len()];
+        let mut stack = vec![BvhNodeId::root()];
+
+        while let Some(id) = stack.pop() {
+            if mem::replace(&mut visited[id.get() as usize], true) {
+                return false;
+            }
+
+            let node = nodes[id];
+
+            if !is_valid_ref(node.primitives_ref()) {
+                return false;
+            }
+
+            if let BvhNode::Internal {
+                left_id, right_id, ..
+            } = node
+            {
+                if !is_valid_id(left_id) || !is_valid_id(right_id) {
+                    return false;
+                }
+
+                stack.push(left_id);
+                stack.push(right_id);
+            }
+        }
+
+        nodes.free_nodes.iter().all(|&id| is_valid_id(id))
+    }
+
+    /// Reads a length prefix, making sure that there's enough data left for
+    /// that many items of given size (so that corrupted files don't make us
+    /// allocate tons of memory).
+    fn len(&mut self, item_size: usize) -> Option<usize> {
+        let len = self.u32()? as usize;
+
+        if len.checked_mul(item_size)? <= self.data.len() {
+            Some(len)
+        } else {
+            None
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.data.len() {
+            return None;
+        }
+
+        let (bytes, data) = self.data.split_at(len);
+
+        self.data = data;
+
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_bits(self.u32()?))
+    }
+
+    fn vec3(&mut self) -> Option<Vec3> {
+        Some(vec3(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn bounds(&mut self) -> Option<BoundingBox> {
+        Some(BoundingBox::new(self.vec3()?, self.vec3()?))
+    }
+
+    fn primitives_ref(&mut self) -> Option<BvhPrimitivesRef> {
+        Some(BvhPrimitivesRef::new(
+            BvhPrimitiveId::new(self.u32()?),
+            BvhPrimitiveId::new(self.u32()?),
+        ))
+    }
+}
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut hasher = FxHasher64::default();
+
+    hasher.write(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::{builder, BvhPrimitives};
+
+    const KEY: u64 = 0x1234_5678_9abc_def0;
+
+    fn triangles() -> Vec<BvhPrimitive> {
+        (0..64)
+            .map(|idx| {
+                let center = vec3((idx % 8) as f32, (idx / 8) as f32, 0.0);
+
+                BvhPrimitive {
+                    // Ids don't start at zero, to make sure they are stored
+                    // relative to the first triangle
+                    id: 100 + idx,
+                    center,
+                    bounds: BoundingBox::new(center - 0.25, center + 0.25),
+                }
+            })
+            .collect()
+    }
+
+    fn build(triangles: &[BvhPrimitive]) -> (BvhNodes, Vec<BvhPrimitive>) {
+        let mut nodes = BvhNodes::default();
+        let mut primitives = BvhPrimitives::default();
+
+        for &triangle in triangles {
+            primitives.add(triangle);
+        }
+
+        primitives.begin_refresh();
+        builder::run(&mut nodes, &mut primitives, &Default::default());
+
+        let primitives = primitives.current(primitives.current_ref()).to_vec();
+
+        (nodes, primitives)
+    }
+
+    fn write(triangles: &[BvhPrimitive]) -> Vec<u8> {
+        let (nodes, primitives) = build(triangles);
+
+        Writer::default().write(KEY, triangles, &nodes, &primitives)
+    }
+
+    /// Replaces file's checksum with a valid one, so that we can check what
+    /// happens for files that got corrupted in other ways.
+    fn fix_checksum(data: &mut Vec<u8>) {
+        data.truncate(data.len() - 8);
+
+        let checksum = checksum(data);
+
+        data.extend_from_slice(&checksum.to_le_bytes());
+    }
+
+    fn summarize(primitives: &[BvhPrimitive]) -> Vec<(u32, Vec3, Vec3)> {
+        primitives
+            .iter()
+            .map(|primitive| {
+                (primitive.id, primitive.center, primitive.bounds.min())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let triangles = triangles();
+        let (nodes, primitives) = build(&triangles);
+        let data =
+            Writer::default().write(KEY, &triangles, &nodes, &primitives);
+
+        let (actual_nodes, actual_primitives) =
+            Reader::new(&data).read(KEY, &triangles).unwrap();
+
+        assert_eq!(nodes.nodes, actual_nodes.nodes);
+        assert_eq!(nodes.free_nodes, actual_nodes.free_nodes);
+        assert_eq!(summarize(&primitives), summarize(&actual_primitives));
+    }
+
+    #[test]
+    fn round_trip_with_different_ids() {
+        let triangles = triangles();
+        let data = write(&triangles);
+
+        // Same mesh, but its triangles got allocated somewhere else
+        let moved_triangles: Vec<_> = triangles
+            .iter()
+            .map(|triangle| BvhPrimitive {
+                id: triangle.id + 1000,
+                ..*triangle
+            })
+            .collect();
+
+        let (_, primitives) =
+            Reader::new(&data).read(KEY, &moved_triangles).unwrap();
+
+        assert!(primitives
+            .iter()
+            .all(|primitive| { (1100..1164).contains(&primitive.id) }));
+    }
+
+    #[test]
+    fn wrong_key() {
+        let triangles = triangles();
+        let data = write(&triangles);
+
+        assert!(Reader::new(&data).read(KEY + 1, &triangles).is_none());
+    }
+
+    #[test]
+    fn wrong_magic() {
+        let triangles = triangles();
+        let mut data = write(&triangles);
+
+        data[0] = b'X';
+        fix_checksum(&mut data);
+
+        assert!(Reader::new(&data).read(KEY, &triangles).is_none());
+    }
+
+    #[test]
+    fn wrong_version() {
+        let triangles = triangles();
+        let mut data = write(&triangles);
+
+        data[MAGIC.len()..][..4].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        fix_checksum(&mut data);
+
+        assert!(Reader::new(&data).read(KEY, &triangles).is_none());
+    }
+
+    #[test]
+    fn truncated() {
+        let triangles = triangles();
+        let data = write(&triangles);
+
+        for len in [0, 4, 8, data.len() / 2, data.len() - 1] {
+            let mut data = data[..len].to_vec();
+
+            assert!(Reader::new(&data).read(KEY, &triangles).is_none());
+
+            // Also with a valid checksum, so that the reader has to notice
+            // the missing data by itself
+            if data.len() >= 8 {
+                fix_checksum(&mut data);
+
+                assert!(Reader::new(&data).read(KEY, &triangles).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let triangles = triangles();
+        let mut data = write(&triangles);
+        let idx = data.len() / 2;
+
+        data[idx] ^= 0xff;
+
+        assert!(Reader::new(&data).read(KEY, &triangles).is_none());
+    }
+}
@@ -0,0 +1,280 @@
+use std::io::{self, Write};
+
+use glam::{vec3, Affine3A};
+
+use super::{BvhNode, BvhNodeId, BvhNodes};
+
+/// Writes bounds of tree's nodes as a Wavefront OBJ wireframe, one box (8
+/// vertices and 12 lines) per node.
+///
+/// Nodes are grouped by their depth (`g {name}.depth{n}`), so that particular
+/// levels can be toggled on and off in the viewer; boxes are transformed by
+/// `xform`, which allows to export bottom-level trees in world-space.
+pub struct Exporter<W> {
+    writer: W,
+
+    /// Number of vertices written so far; OBJ refers to vertices by their
+    /// global, one-based indices
+    vertices: usize,
+}
+
+impl<W> Exporter<W>
+where
+    W: Write,
+{
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            vertices: 0,
+        }
+    }
+
+    pub fn comment(&mut self, comment: &str) -> io::Result<()> {
+        for line in comment.lines() {
+            writeln!(self.writer, "# {line}")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn export(
+        &mut self,
+        name: &str,
+        nodes: &BvhNodes,
+        xform: Affine3A,
+    ) -> io::Result<()> {
+        if nodes.nodes.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(self.writer, "o {name}")?;
+
+        // Traversing breadth-first visits nodes level by level, so each group
+        // gets written just once
+        let mut level = vec![BvhNodeId::root()];
+        let mut depth = 0;
+
+        while !level.is_empty() {
+            writeln!(self.writer, "g {name}.depth{depth}")?;
+
+            let mut next_level = Vec::new();
+
+            for id in level {
+                let node = nodes[id];
+
+                self.write_box(node, xform)?;
+
+                if let BvhNode::Internal {
+                    left_id, right_id, ..
+                } = node
+                {
+                    next_level.push(left_id);
+                    next_level.push(right_id);
+                }
+            }
+
+            level = next_level;
+            depth += 1;
+        }
+
+        Ok(())
+    }
+
+    fn write_box(&mut self, node: BvhNode, xform: Affine3A) -> io::Result<()> {
+        const EDGES: [(usize, usize); 12] = [
+            (0, 1),
+            (1, 3),
+            (3, 2),
+            (2, 0),
+            (4, 5),
+            (5, 7),
+            (7, 6),
+            (6, 4),
+            (0, 4),
+            (1, 5),
+            (2, 6),
+            (3, 7),
+        ];
+
+        let bounds = node.bounds();
+        let (min, max) = (bounds.min(), bounds.max());
+
+        for corner in 0..8 {
+            let vertex = xform.transform_point3(vec3(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            ));
+
+            writeln!(self.writer, "v {} {} {}", vertex.x, vertex.y, vertex.z)?;
+        }
+
+        for (a, b) in EDGES {
+            writeln!(
+                self.writer,
+                "l {} {}",
+                self.vertices + a + 1,
+                self.vertices + b + 1,
+            )?;
+        }
+
+        self.vertices += 8;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::bvh::{BvhNodeHash, BvhPrimitiveId, BvhPrimitivesRef};
+    use crate::BoundingBox;
+
+    fn primitives_ref(start: u32, end: u32) -> BvhPrimitivesRef {
+        BvhPrimitivesRef::new(
+            BvhPrimitiveId::new(start),
+            BvhPrimitiveId::new(end),
+        )
+    }
+
+    fn leaf(min: Vec3, max: Vec3) -> BvhNode {
+        BvhNode::Leaf {
+            bounds: BoundingBox::new(min, max),
+            primitives_ref: primitives_ref(0, 1),
+        }
+    }
+
+    /// Builds a tree with the root and two leaves.
+    fn tree() -> BvhNodes {
+        BvhNodes {
+            nodes: vec![
+                BvhNode::Internal {
+                    bounds: BoundingBox::new(Vec3::ZERO, vec3(2.0, 1.0, 1.0)),
+                    primitives_ref: primitives_ref(0, 2),
+                    left_id: BvhNodeId::new(1),
+                    left_hash: BvhNodeHash::new(0),
+                    right_id: BvhNodeId::new(2),
+                    right_hash: BvhNodeHash::new(0),
+                },
+                leaf(Vec3::ZERO, Vec3::ONE),
+                leaf(vec3(1.0, 0.0, 0.0), vec3(2.0, 1.0, 1.0)),
+            ],
+            free_nodes: Default::default(),
+        }
+    }
+
+    fn export(f: impl FnOnce(&mut Exporter<&mut Vec<u8>>)) -> String {
+        let mut out = Vec::new();
+
+        f(&mut Exporter::new(&mut out));
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn single_node() {
+        let nodes = BvhNodes {
+            nodes: vec![leaf(Vec3::ZERO, vec3(1.0, 2.0, 3.0))],
+            free_nodes: Default::default(),
+        };
+
+        let actual = export(|exporter| {
+            exporter.comment("first\nsecond").unwrap();
+            exporter
+                .export(
+                    "leaf",
+                    &nodes,
+                    Affine3A::from_translation(vec3(10.0, 0.0, 0.0)),
+                )
+                .unwrap();
+        });
+
+        let expected = "\
+# first
+# second
+o leaf
+g leaf.depth0
+v 10 0 0
+v 11 0 0
+v 10 2 0
+v 11 2 0
+v 10 0 3
+v 11 0 3
+v 10 2 3
+v 11 2 3
+l 1 2
+l 2 4
+l 4 3
+l 3 1
+l 5 6
+l 6 8
+l 8 7
+l 7 5
+l 1 5
+l 2 6
+l 3 7
+l 4 8
+";
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn many_trees() {
+        let actual = export(|exporter| {
+            exporter.export("a", &tree(), Affine3A::IDENTITY).unwrap();
+
+            exporter
+                .export("empty", &BvhNodes::default(), Affine3A::IDENTITY)
+                .unwrap();
+
+            exporter.export("b", &tree(), Affine3A::IDENTITY).unwrap();
+        });
+
+        let lines: Vec<_> = actual.lines().collect();
+
+        let headers: Vec<_> = lines
+            .iter()
+            .filter(|line| line.starts_with("o ") || line.starts_with("g "))
+            .copied()
+            .collect();
+
+        assert_eq!(
+            vec![
+                "o a",
+                "g a.depth0",
+                "g a.depth1",
+                "o b",
+                "g b.depth0",
+                "g b.depth1",
+            ],
+            headers
+        );
+
+        let vertices =
+            lines.iter().filter(|line| line.starts_with("v ")).count();
+
+        let edges: Vec<(usize, usize)> = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("l "))
+            .map(|line| {
+                let (a, b) = line.split_once(' ').unwrap();
+
+                (a.parse().unwrap(), b.parse().unwrap())
+            })
+            .collect();
+
+        assert_eq!(2 * 3 * 8, vertices);
+        assert_eq!(2 * 3 * 12, edges.len());
+
+        // Indices are one-based and keep counting across trees, so the first
+        // edge of `b` refers to the vertices following all of `a`'s
+        assert_eq!((1, 2), edges[0]);
+        assert_eq!((3 * 8 + 1, 3 * 8 + 2), edges[3 * 12]);
+
+        assert!(edges.iter().all(|&(a, b)| (1..=vertices).contains(&a)
+            && (1..=vertices).contains(&b)));
+    }
+}
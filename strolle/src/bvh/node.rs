@@ -1,7 +1,7 @@
 use super::BvhPrimitivesRef;
 use crate::BoundingBox;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BvhNode {
     Internal {
         bounds: BoundingBox,
@@ -79,4 +79,8 @@ impl BvhNodeHash {
     pub fn new(hash: u64) -> Self {
         Self(hash)
     }
+
+    pub fn get(&self) -> u64 {
+        self.0
+    }
 }
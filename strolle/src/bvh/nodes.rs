@@ -39,6 +39,51 @@ impl BvhNodes {
         self.free_nodes.push(id);
     }
 
+    /// Returns the tree's SAH cost, without normalizing it by the root's area.
+    ///
+    /// This is what refitting compares against, since normalizing would hide
+    /// the degradation when the root grows together with its children (e.g.
+    /// when instances spread apart).
+    pub fn cost(&self) -> f32 {
+        if self.nodes.is_empty() {
+            0.0
+        } else {
+            self.subtree_cost(BvhNodeId::root())
+        }
+    }
+
+    /// Returns the tree's SAH cost, normalized by the root's area.
+    pub fn sah_cost(&self) -> f32 {
+        let Some(root) = self.nodes.first() else {
+            return 0.0;
+        };
+
+        let root_area = root.bounds().half_area();
+
+        if root_area > 0.0 {
+            self.subtree_cost(BvhNodeId::root()) / root_area
+        } else {
+            0.0
+        }
+    }
+
+    fn subtree_cost(&self, id: BvhNodeId) -> f32 {
+        match self[id] {
+            BvhNode::Internal {
+                bounds,
+                left_id,
+                right_id,
+                ..
+            } => {
+                bounds.half_area()
+                    + self.subtree_cost(left_id)
+                    + self.subtree_cost(right_id)
+            }
+
+            node @ BvhNode::Leaf { .. } => node.sah_cost(),
+        }
+    }
+
     pub fn set_root(&mut self, node: BvhNode) -> Option<BvhNode> {
         if self.nodes.is_empty() {
             self.nodes.push(node);
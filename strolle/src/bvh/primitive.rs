@@ -3,13 +3,12 @@ use std::ops::Range;
 
 use glam::Vec3;
 
-use crate::gpu;
 use crate::utils::BoundingBox;
 
 #[derive(Clone, Copy, Debug)]
 pub struct BvhPrimitive {
-    pub triangle_id: gpu::TriangleId,
-    pub material_id: gpu::MaterialId,
+    /// Triangle id (for meshes' trees) or instance id (for the top-level tree)
+    pub id: u32,
     pub center: Vec3,
     pub bounds: BoundingBox,
 }
@@ -29,9 +28,19 @@ impl Hash for BvhPrimitive {
     where
         H: Hasher,
     {
+        self.id.hash(state);
         self.center.x.to_bits().hash(state);
         self.center.y.to_bits().hash(state);
         self.center.z.to_bits().hash(state);
+
+        // Instances can get rotated around their center, so center alone is
+        // not enough to tell whether a primitive has changed
+        self.bounds.min().x.to_bits().hash(state);
+        self.bounds.min().y.to_bits().hash(state);
+        self.bounds.min().z.to_bits().hash(state);
+        self.bounds.max().x.to_bits().hash(state);
+        self.bounds.max().y.to_bits().hash(state);
+        self.bounds.max().z.to_bits().hash(state);
     }
 }
 
@@ -48,7 +57,7 @@ impl BvhPrimitiveId {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BvhPrimitivesRef {
     start: BvhPrimitiveId,
     end: BvhPrimitiveId,
@@ -36,23 +36,25 @@ impl BvhPrimitives {
         &self.current[start..end]
     }
 
-    pub fn current_mut(
-        &mut self,
-        range: BvhPrimitivesRef,
-    ) -> &mut [BvhPrimitive] {
-        let start = range.start().get() as usize;
-        let end = range.end().get() as usize;
-
-        &mut self.current[start..end]
+    /// Returns primitives as laid out by the last refresh, i.e. the ones that
+    /// nodes refer to once the refresh is done.
+    pub fn previous(&self, range: BvhPrimitivesRef) -> &[BvhPrimitive] {
+        &self.previous[range.as_range()]
     }
 
-    pub fn copy_previous_to_current(
+    /// Returns all current primitives together with the previous ones; used
+    /// by the builder to work on different parts of the current primitives
+    /// at once.
+    pub fn current_and_previous_mut(
         &mut self,
-        previous: BvhPrimitivesRef,
-        current: BvhPrimitivesRef,
-    ) {
-        self.current[current.as_range()]
-            .copy_from_slice(&self.previous[previous.as_range()]);
+    ) -> (&mut [BvhPrimitive], &[BvhPrimitive]) {
+        (&mut self.current, &self.previous)
+    }
+
+    /// Replaces current primitives; used by builders that can reference a
+    /// single primitive from many leaves.
+    pub fn replace_current(&mut self, current: Vec<BvhPrimitive>) {
+        self.current = current;
     }
 
     pub fn begin_refresh(&mut self) {
@@ -60,6 +62,38 @@ impl BvhPrimitives {
             self.all.iter().filter(|p| p.is_alive()).copied().collect();
     }
 
+    /// Starts a refresh that keeps the layout from the previous refresh and
+    /// only updates primitives' data; used for refitting.
+    ///
+    /// Requires that primitives' ids correspond to the order in which they
+    /// have been added (as is the case for the top-level tree) and that no
+    /// primitive has been killed since the previous refresh.
+    pub fn begin_refit(&mut self) {
+        self.current = self
+            .previous
+            .iter()
+            .map(|primitive| self.all[primitive.id as usize])
+            .collect();
+    }
+
+    /// Starts a refresh that keeps primitives exactly as laid out by the
+    /// previous refresh; used to re-serialize trees without rebuilding them.
+    pub fn begin_reuse(&mut self) {
+        self.current = self.previous.clone();
+    }
+
+    /// Changes ids of all primitives.
+    pub fn remap_ids(&mut self, f: impl Fn(u32) -> u32) {
+        for primitive in self
+            .all
+            .iter_mut()
+            .chain(&mut self.current)
+            .chain(&mut self.previous)
+        {
+            primitive.id = f(primitive.id);
+        }
+    }
+
     pub fn end_refresh(&mut self) {
         self.previous = mem::take(&mut self.current);
     }
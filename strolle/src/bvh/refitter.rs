@@ -0,0 +1,148 @@
+use log::debug;
+
+use super::{BvhNode, BvhNodeId, BvhNodes, BvhPrimitives};
+use crate::BoundingBox;
+
+/// Refits the tree and checks whether its SAH cost is still within
+/// `max_degradation` times `built_cost` (the cost recorded right after the
+/// tree was built); returns `false` if the tree has to be rebuilt instead.
+///
+/// Starts the refresh by itself, see [`BvhPrimitives::begin_refit()`].
+pub fn try_run(
+    nodes: &mut BvhNodes,
+    primitives: &mut BvhPrimitives,
+    built_cost: f32,
+    max_degradation: f32,
+) -> bool {
+    primitives.begin_refit();
+    run(nodes, primitives);
+
+    let cost = nodes.cost();
+
+    if cost <= built_cost * max_degradation {
+        true
+    } else {
+        debug!(
+            "Top-level tree has degraded too much (from {built_cost} to \
+             {cost}), rebuilding"
+        );
+
+        false
+    }
+}
+
+/// Recomputes bounds of all nodes bottom-up, keeping tree's topology intact.
+///
+/// Must be called between [`BvhPrimitives::begin_refit()`] and
+/// [`BvhPrimitives::end_refresh()`].
+pub fn run(nodes: &mut BvhNodes, primitives: &BvhPrimitives) {
+    refit(nodes, primitives, BvhNodeId::root());
+}
+
+fn refit(
+    nodes: &mut BvhNodes,
+    primitives: &BvhPrimitives,
+    id: BvhNodeId,
+) -> BoundingBox {
+    let new_bounds = match nodes[id] {
+        BvhNode::Internal {
+            left_id, right_id, ..
+        } => {
+            refit(nodes, primitives, left_id)
+                + refit(nodes, primitives, right_id)
+        }
+
+        BvhNode::Leaf { primitives_ref, .. } => primitives
+            .current(primitives_ref)
+            .iter()
+            .map(|primitive| primitive.bounds)
+            .collect(),
+    };
+
+    match &mut nodes[id] {
+        BvhNode::Internal { bounds, .. } | BvhNode::Leaf { bounds, .. } => {
+            *bounds = new_bounds;
+        }
+    }
+
+    new_bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Vec3};
+
+    use super::*;
+    use crate::bvh::{builder, BvhPrimitive};
+    use crate::BvhConfig;
+
+    fn primitive(id: u32, center: Vec3) -> BvhPrimitive {
+        BvhPrimitive {
+            id,
+            center,
+            bounds: BoundingBox::new(center - 0.5, center + 0.5),
+        }
+    }
+
+    fn build(nodes: &mut BvhNodes, primitives: &mut BvhPrimitives) -> f32 {
+        primitives.begin_refresh();
+        builder::run(nodes, primitives, &BvhConfig::default());
+        primitives.end_refresh();
+
+        nodes.cost()
+    }
+
+    fn grid(offset: impl Fn(u32) -> Vec3) -> Vec<BvhPrimitive> {
+        (0..64)
+            .map(|id| {
+                let center =
+                    vec3((id % 8) as f32 * 2.0, 0.0, (id / 8) as f32 * 2.0);
+
+                primitive(id, center + offset(id))
+            })
+            .collect()
+    }
+
+    fn update(primitives: &mut BvhPrimitives, new: Vec<BvhPrimitive>) {
+        for (prim, new) in primitives.update(0..new.len()).zip(new) {
+            *prim = new;
+        }
+    }
+
+    #[test]
+    fn small_change_is_refitted() {
+        let mut nodes = BvhNodes::default();
+        let mut primitives = BvhPrimitives::default();
+
+        for prim in grid(|_| Vec3::ZERO) {
+            primitives.add(prim);
+        }
+
+        let built_cost = build(&mut nodes, &mut primitives);
+
+        update(&mut primitives, grid(|_| vec3(0.1, 0.0, 0.0)));
+
+        assert!(try_run(&mut nodes, &mut primitives, built_cost, 1.5));
+    }
+
+    #[test]
+    fn large_change_forces_rebuild() {
+        let mut nodes = BvhNodes::default();
+        let mut primitives = BvhPrimitives::default();
+
+        for prim in grid(|_| Vec3::ZERO) {
+            primitives.add(prim);
+        }
+
+        let built_cost = build(&mut nodes, &mut primitives);
+
+        // Swap the instances around, so that each leaf ends up spanning most
+        // of the scene
+        update(
+            &mut primitives,
+            grid(|id| vec3(if id % 2 == 0 { 14.0 } else { -14.0 }, 0.0, 0.0)),
+        );
+
+        assert!(!try_run(&mut nodes, &mut primitives, built_cost, 1.5));
+    }
+}
@@ -0,0 +1,195 @@
+use super::{BvhNode, BvhNodeId, BvhNodes};
+use crate::BvhTreeReport;
+
+/// Walks the tree and gathers statistics about it.
+pub fn run(nodes: &BvhNodes) -> BvhTreeReport {
+    let mut report = BvhTreeReport::default();
+
+    if nodes.nodes.is_empty() {
+        return report;
+    }
+
+    let mut stack = vec![(BvhNodeId::root(), 0)];
+    let mut total_overlap = 0.0;
+
+    while let Some((id, depth)) = stack.pop() {
+        report.node_count += 1;
+
+        match nodes[id] {
+            BvhNode::Internal {
+                bounds,
+                left_id,
+                right_id,
+                ..
+            } => {
+                let overlap = nodes[left_id]
+                    .bounds()
+                    .intersection(nodes[right_id].bounds())
+                    .map_or(0.0, |overlap| overlap.half_area());
+
+                let overlap = if bounds.half_area() > 0.0 {
+                    overlap / bounds.half_area()
+                } else {
+                    0.0
+                };
+
+                total_overlap += overlap;
+                report.max_overlap = report.max_overlap.max(overlap);
+
+                stack.push((right_id, depth + 1));
+                stack.push((left_id, depth + 1));
+            }
+
+            BvhNode::Leaf { primitives_ref, .. } => {
+                let len = primitives_ref.len();
+
+                report.leaf_count += 1;
+                report.primitive_count += len;
+
+                increment(&mut report.depth_histogram, depth);
+                increment(&mut report.leaf_size_histogram, len);
+            }
+        }
+    }
+
+    let internal_count = report.node_count - report.leaf_count;
+
+    if internal_count > 0 {
+        report.avg_overlap = total_overlap / (internal_count as f32);
+    }
+
+    report.sah_cost = nodes.sah_cost();
+    report
+}
+
+fn increment(histogram: &mut Vec<usize>, idx: usize) {
+    if histogram.len() <= idx {
+        histogram.resize(idx + 1, 0);
+    }
+
+    histogram[idx] += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Vec3};
+
+    use super::*;
+    use crate::bvh::{BvhNodeHash, BvhPrimitiveId, BvhPrimitivesRef};
+    use crate::BoundingBox;
+
+    fn primitives_ref(start: u32, end: u32) -> BvhPrimitivesRef {
+        BvhPrimitivesRef::new(
+            BvhPrimitiveId::new(start),
+            BvhPrimitiveId::new(end),
+        )
+    }
+
+    fn internal(
+        bounds: BoundingBox,
+        (start, end): (u32, u32),
+        left_id: u32,
+        right_id: u32,
+    ) -> BvhNode {
+        BvhNode::Internal {
+            bounds,
+            primitives_ref: primitives_ref(start, end),
+            left_id: BvhNodeId::new(left_id),
+            left_hash: BvhNodeHash::new(0),
+            right_id: BvhNodeId::new(right_id),
+            right_hash: BvhNodeHash::new(0),
+        }
+    }
+
+    fn leaf(bounds: BoundingBox, start: u32, end: u32) -> BvhNode {
+        BvhNode::Leaf {
+            bounds,
+            primitives_ref: primitives_ref(start, end),
+        }
+    }
+
+    fn bounds(min_x: f32, max_x: f32) -> BoundingBox {
+        BoundingBox::new(vec3(min_x, 0.0, 0.0), vec3(max_x, 1.0, 1.0))
+    }
+
+    /// Builds a tree whose root (half-area 9) contains a leaf with three
+    /// primitives (half-area 5) overlapping an internal node (half-area 7);
+    /// that internal node contains two disjoint leaves, with one primitive
+    /// (half-area 5) and two primitives (half-area 2).
+    fn tree() -> BvhNodes {
+        BvhNodes {
+            nodes: vec![
+                internal(bounds(0.0, 4.0), (0, 6), 1, 2),
+                leaf(bounds(0.0, 2.0), 0, 3),
+                internal(bounds(1.0, 4.0), (3, 6), 3, 4),
+                leaf(bounds(1.0, 3.0), 3, 4),
+                leaf(bounds(3.5, 4.0), 4, 6),
+            ],
+            free_nodes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let report = run(&tree());
+
+        assert_eq!(5, report.node_count);
+        assert_eq!(3, report.leaf_count);
+        assert_eq!(6, report.primitive_count);
+        assert_eq!(vec![0, 1, 2], report.depth_histogram);
+        assert_eq!(vec![0, 1, 1, 1], report.leaf_size_histogram);
+        assert_eq!(2, report.max_depth());
+
+        // (9 + 7 + 3 * 5 + 1 * 5 + 2 * 2) / 9
+        assert!((report.sah_cost - 40.0 / 9.0).abs() < 1e-5);
+
+        // Root's children overlap over a unit cube (half-area 3), while the
+        // other internal node's children don't overlap at all
+        assert!((report.max_overlap - 3.0 / 9.0).abs() < 1e-5);
+        assert!((report.avg_overlap - 3.0 / 9.0 / 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn single_leaf() {
+        let nodes = BvhNodes {
+            nodes: vec![leaf(bounds(0.0, 1.0), 0, 2)],
+            free_nodes: Default::default(),
+        };
+
+        let report = run(&nodes);
+
+        assert_eq!(1, report.node_count);
+        assert_eq!(1, report.leaf_count);
+        assert_eq!(vec![1], report.depth_histogram);
+        assert_eq!(vec![0, 0, 1], report.leaf_size_histogram);
+        assert_eq!(0, report.max_depth());
+        assert_eq!(2.0, report.sah_cost);
+        assert_eq!(0.0, report.avg_overlap);
+        assert_eq!(0.0, report.max_overlap);
+    }
+
+    #[test]
+    fn empty_tree() {
+        assert_eq!(BvhTreeReport::default(), run(&BvhNodes::default()));
+    }
+
+    #[test]
+    fn degenerate_root() {
+        let point = BoundingBox::new(Vec3::ZERO, Vec3::ZERO);
+
+        let nodes = BvhNodes {
+            nodes: vec![
+                internal(point, (0, 2), 1, 2),
+                leaf(point, 0, 1),
+                leaf(point, 1, 2),
+            ],
+            free_nodes: Default::default(),
+        };
+
+        let report = run(&nodes);
+
+        assert_eq!(0.0, report.sah_cost);
+        assert_eq!(0.0, report.avg_overlap);
+        assert_eq!(0.0, report.max_overlap);
+    }
+}
@@ -0,0 +1,618 @@
+//! Spatial-split BVH builder.
+//!
+//! Works like the binned-SAH builder, but when children of the best object
+//! split overlap too much, it also considers splitting the node in space -
+//! triangles crossing such a plane get referenced from both sides (with their
+//! bounds clipped to each side), which yields tighter nodes for long, thin
+//! triangles at the cost of having more references.
+//!
+//! Since it's more expensive, we use this builder only for meshes' trees, which
+//! are built once and never rebuilt incrementally.
+//!
+//! Thanks to:
+//! https://www.nvidia.com/docs/IO/77714/sbvh.pdf
+
+use glam::Vec3;
+
+use super::{
+    BvhNode, BvhNodeHash, BvhNodeId, BvhNodes, BvhPrimitive, BvhPrimitiveId,
+    BvhPrimitives, BvhPrimitivesRef,
+};
+use crate::{Axis, BoundingBox, BvhConfig};
+
+/// Deeper nodes always become leaves; protects us from splitting degenerate
+/// triangles forever.
+const MAX_DEPTH: usize = 64;
+
+/// How many references (relative to the number of triangles) spatial splits
+/// are allowed to add; once the budget runs out, we fall back to object splits
+/// only, which protects us from blowing up the memory on pathological meshes.
+const MAX_DUPLICATES: f32 = 0.5;
+
+/// Builds the tree over given triangles, replacing current primitives with
+/// (possibly duplicated) references to them.
+///
+/// `overlap_threshold` says when to consider spatial splits - it's the area of
+/// the overlap between children of the best object split, relative to the area
+/// of the root; the original paper suggests values around `1e-5`.
+///
+/// Must be called between [`BvhPrimitives::begin_refresh()`] and
+/// [`BvhPrimitives::end_refresh()`].
+pub fn run(
+    nodes: &mut BvhNodes,
+    primitives: &mut BvhPrimitives,
+    positions: &[[Vec3; 3]],
+    config: &BvhConfig,
+    overlap_threshold: f32,
+) {
+    let triangles = primitives.current(primitives.current_ref()).to_vec();
+
+    assert_eq!(triangles.len(), positions.len());
+
+    let refs: Vec<_> = triangles
+        .iter()
+        .enumerate()
+        .map(|(triangle_idx, triangle)| Reference {
+            triangle_idx,
+            bounds: triangle.bounds,
+        })
+        .collect();
+
+    let bounds: BoundingBox = refs.iter().map(|r| r.bounds).collect();
+
+    let mut builder = Builder {
+        triangles: &triangles,
+        positions,
+        min_overlap: overlap_threshold * bounds.half_area(),
+        config,
+        bins: config.bins.max(2),
+        budget: ((triangles.len() as f32) * MAX_DUPLICATES) as usize,
+        nodes,
+        output: Vec::with_capacity(triangles.len()),
+    };
+
+    builder.nodes.nodes.clear();
+    builder.nodes.free_nodes.clear();
+    builder.nodes.add(Default::default());
+    builder.build(BvhNodeId::root(), refs, bounds, 0);
+
+    let output = builder.output;
+
+    primitives.replace_current(output);
+}
+
+struct Builder<'a> {
+    triangles: &'a [BvhPrimitive],
+    positions: &'a [[Vec3; 3]],
+    min_overlap: f32,
+    config: &'a BvhConfig,
+    bins: usize,
+
+    /// How many more references spatial splits can add, see
+    /// [`MAX_DUPLICATES`]
+    budget: usize,
+
+    nodes: &'a mut BvhNodes,
+    output: Vec<BvhPrimitive>,
+}
+
+impl Builder<'_> {
+    fn build(
+        &mut self,
+        id: BvhNodeId,
+        refs: Vec<Reference>,
+        bounds: BoundingBox,
+        depth: usize,
+    ) {
+        let start = BvhPrimitiveId::new(self.output.len() as u32);
+        let area = bounds.half_area();
+        let must_split = refs.len() > self.config.max_leaf_size;
+
+        let split = if refs.len() > self.config.min_leaf_size.max(1)
+            && depth < MAX_DEPTH
+        {
+            self.find_split(&refs, bounds).filter(|split| {
+                must_split
+                    || self.config.split_cost(area, split.cost)
+                        < self.config.leaf_cost(refs.len(), area)
+            })
+        } else {
+            None
+        };
+
+        let Some(split) = split else {
+            self.output.extend(refs.into_iter().map(|r| BvhPrimitive {
+                bounds: r.bounds,
+                ..self.triangles[r.triangle_idx]
+            }));
+
+            self.nodes[id] = BvhNode::Leaf {
+                bounds,
+                primitives_ref: BvhPrimitivesRef::new(
+                    start,
+                    BvhPrimitiveId::new(self.output.len() as u32),
+                ),
+            };
+
+            return;
+        };
+
+        let (left_refs, right_refs) = match split.kind {
+            SplitKind::Object => refs
+                .into_iter()
+                .partition(|r| r.bounds.center()[split.by] < split.at),
+
+            SplitKind::Spatial => {
+                let refs_len = refs.len();
+                let (left, right) =
+                    self.split_spatially(refs, split.by, split.at);
+
+                // (clipping might drop some references, hence the saturation)
+                let duplicates =
+                    (left.len() + right.len()).saturating_sub(refs_len);
+
+                self.budget = self.budget.saturating_sub(duplicates);
+
+                (left, right)
+            }
+        };
+
+        // Due to the floating-point shenanigans, it might happen that all
+        // references land on one side - in that case let's just split by the
+        // middle reference, so that we're guaranteed to make progress
+        let (left_refs, right_refs) =
+            if left_refs.is_empty() || right_refs.is_empty() {
+                let mut refs = left_refs;
+
+                refs.extend(right_refs);
+                refs.sort_by(|a, b| {
+                    let a = a.bounds.center()[split.by];
+                    let b = b.bounds.center()[split.by];
+
+                    a.total_cmp(&b)
+                });
+
+                let right_refs = refs.split_off(refs.len() / 2);
+
+                (refs, right_refs)
+            } else {
+                (left_refs, right_refs)
+            };
+
+        let left_bounds: BoundingBox =
+            left_refs.iter().map(|r| r.bounds).collect();
+
+        let right_bounds: BoundingBox =
+            right_refs.iter().map(|r| r.bounds).collect();
+        let left_id = self.nodes.add(Default::default());
+        let right_id = self.nodes.add(Default::default());
+
+        self.build(left_id, left_refs, left_bounds, depth + 1);
+        self.build(right_id, right_refs, right_bounds, depth + 1);
+
+        self.nodes[id] = BvhNode::Internal {
+            bounds,
+            primitives_ref: BvhPrimitivesRef::new(
+                start,
+                BvhPrimitiveId::new(self.output.len() as u32),
+            ),
+            left_id,
+            left_hash: BvhNodeHash::new(0),
+            right_id,
+            right_hash: BvhNodeHash::new(0),
+        };
+    }
+
+    fn find_split(
+        &self,
+        refs: &[Reference],
+        bounds: BoundingBox,
+    ) -> Option<Split> {
+        let object_split = self.find_object_split(refs);
+
+        let overlap = object_split.map_or(f32::MAX, |(_, overlap)| overlap);
+        let object_split = object_split.map(|(split, _)| split);
+
+        if overlap <= self.min_overlap {
+            return object_split;
+        }
+
+        let spatial_split = self.find_spatial_split(refs, bounds);
+
+        match (object_split, spatial_split) {
+            (Some(object_split), Some(spatial_split)) => {
+                if spatial_split.cost < object_split.cost {
+                    Some(spatial_split)
+                } else {
+                    Some(object_split)
+                }
+            }
+            (object_split, spatial_split) => object_split.or(spatial_split),
+        }
+    }
+
+    /// Finds the best object split, together with the area of overlap between
+    /// its sides.
+    fn find_object_split(&self, refs: &[Reference]) -> Option<(Split, f32)> {
+        let centroid_bb: BoundingBox =
+            refs.iter().map(|r| r.bounds.center()).collect();
+
+        let mut best: Option<(Split, f32)> = None;
+
+        for by in Axis::all() {
+            let extent = centroid_bb.extent()[by];
+
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let mut bins = vec![Bin::default(); self.bins];
+
+            for r in refs {
+                let bin_idx = (self.bins as f32)
+                    * (r.bounds.center()[by] - centroid_bb.min()[by])
+                    / extent;
+
+                let bin = &mut bins[(bin_idx as usize).min(self.bins - 1)];
+
+                bin.bounds += r.bounds;
+                bin.entries += 1;
+            }
+
+            for (split_idx, (left, right)) in
+                sweep(&bins, |bin| bin.entries).into_iter().enumerate()
+            {
+                if left.count == 0 || right.count == 0 {
+                    continue;
+                }
+
+                let split = Split {
+                    kind: SplitKind::Object,
+                    by,
+                    at: centroid_bb.min()[by]
+                        + extent * ((split_idx + 1) as f32)
+                            / (self.bins as f32),
+                    cost: left.cost() + right.cost(),
+                };
+
+                if best.map_or(true, |(best, _)| split.cost < best.cost) {
+                    let overlap = left
+                        .bounds
+                        .intersection(right.bounds)
+                        .map_or(0.0, |overlap| overlap.half_area());
+
+                    best = Some((split, overlap));
+                }
+            }
+        }
+
+        best
+    }
+
+    fn find_spatial_split(
+        &self,
+        refs: &[Reference],
+        bounds: BoundingBox,
+    ) -> Option<Split> {
+        let mut best: Option<Split> = None;
+
+        for by in Axis::all() {
+            let min = bounds.min()[by];
+            let extent = bounds.extent()[by];
+
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let bin_size = extent / (self.bins as f32);
+            let bin_of = |value: f32| {
+                (((value - min) / bin_size).max(0.0) as usize)
+                    .min(self.bins - 1)
+            };
+
+            let mut bins = vec![Bin::default(); self.bins];
+
+            for r in refs {
+                let first_bin_idx = bin_of(r.bounds.min()[by]);
+                let last_bin_idx = bin_of(r.bounds.max()[by]);
+
+                for (bin_idx, bin) in bins
+                    .iter_mut()
+                    .enumerate()
+                    .take(last_bin_idx + 1)
+                    .skip(first_bin_idx)
+                {
+                    let bin_min = min + bin_size * (bin_idx as f32);
+
+                    if let Some(clipped) =
+                        self.clip(r, by, bin_min, bin_min + bin_size)
+                    {
+                        bin.bounds += clipped;
+                    }
+                }
+
+                bins[first_bin_idx].entries += 1;
+                bins[last_bin_idx].exits += 1;
+            }
+
+            let splits = sweep(&bins, |bin| bin.entries)
+                .into_iter()
+                .zip(sweep(&bins, |bin| bin.exits));
+
+            for (split_idx, ((left, _), (_, right))) in splits.enumerate() {
+                // Planes with all references on one side would leave the
+                // other child empty, which doesn't get us anywhere
+                if left.count == 0 || right.count == 0 {
+                    continue;
+                }
+
+                // References crossing the plane land on both sides, so each
+                // of them costs us one more reference
+                let duplicates =
+                    (left.count + right.count).saturating_sub(refs.len());
+
+                if duplicates > self.budget {
+                    continue;
+                }
+
+                let split = Split {
+                    kind: SplitKind::Spatial,
+                    by,
+                    at: min + bin_size * ((split_idx + 1) as f32),
+                    cost: left.cost() + right.cost(),
+                };
+
+                if best.map_or(true, |best| split.cost < best.cost) {
+                    best = Some(split);
+                }
+            }
+        }
+
+        best
+    }
+
+    fn split_spatially(
+        &self,
+        refs: Vec<Reference>,
+        by: Axis,
+        at: f32,
+    ) -> (Vec<Reference>, Vec<Reference>) {
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+
+        for r in refs {
+            if r.bounds.max()[by] <= at {
+                left.push(r);
+            } else if r.bounds.min()[by] >= at {
+                right.push(r);
+            } else {
+                if let Some(bounds) = self.clip(&r, by, f32::MIN, at) {
+                    left.push(Reference { bounds, ..r });
+                }
+
+                if let Some(bounds) = self.clip(&r, by, at, f32::MAX) {
+                    right.push(Reference { bounds, ..r });
+                }
+            }
+        }
+
+        (left, right)
+    }
+
+    /// Returns bounds of the part of reference's triangle that lies within
+    /// `min..=max` on given axis.
+    fn clip(
+        &self,
+        r: &Reference,
+        by: Axis,
+        min: f32,
+        max: f32,
+    ) -> Option<BoundingBox> {
+        let positions = self.positions[r.triangle_idx];
+        let mut bounds = BoundingBox::default();
+
+        for vertex_idx in 0..3 {
+            let a = positions[vertex_idx];
+            let b = positions[(vertex_idx + 1) % 3];
+
+            if a[by] >= min && a[by] <= max {
+                bounds += a;
+            }
+
+            for plane in [min, max] {
+                if (a[by] < plane) != (b[by] < plane) {
+                    let mut point =
+                        a.lerp(b, (plane - a[by]) / (b[by] - a[by]));
+
+                    point[by] = plane;
+                    bounds += point;
+                }
+            }
+        }
+
+        if bounds.is_set() {
+            bounds.intersection(r.bounds)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns bounds and counts of the left and right side for each plane
+/// in-between the bins.
+fn sweep(bins: &[Bin], count: impl Fn(&Bin) -> usize) -> Vec<(Side, Side)> {
+    let mut sides = vec![(Side::default(), Side::default()); bins.len() - 1];
+    let mut left = Side::default();
+    let mut right = Side::default();
+
+    for i in 0..(bins.len() - 1) {
+        left.add(&bins[i], &count);
+        sides[i].0 = left;
+
+        right.add(&bins[bins.len() - 1 - i], &count);
+        sides[bins.len() - 2 - i].1 = right;
+    }
+
+    sides
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Reference {
+    /// Index into the triangles we've been given
+    triangle_idx: usize,
+
+    /// Bounds of the part of the triangle this reference refers to
+    bounds: BoundingBox,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Split {
+    kind: SplitKind,
+    by: Axis,
+    at: f32,
+    cost: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SplitKind {
+    /// Splits references by their centers; each reference lands on exactly
+    /// one side
+    Object,
+
+    /// Splits space by a plane; references crossing that plane land on both
+    /// sides
+    Spatial,
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+struct Bin {
+    bounds: BoundingBox,
+
+    /// Number of references that start in this bin; for object splits that's
+    /// simply the number of references in this bin
+    entries: usize,
+
+    /// Number of references that end in this bin
+    exits: usize,
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+struct Side {
+    bounds: BoundingBox,
+    count: usize,
+}
+
+impl Side {
+    fn add(&mut self, bin: &Bin, count: impl Fn(&Bin) -> usize) {
+        if bin.bounds.is_set() {
+            self.bounds += bin.bounds;
+        }
+
+        self.count += count(bin);
+    }
+
+    fn cost(&self) -> f32 {
+        if self.count == 0 {
+            0.0
+        } else {
+            (self.count as f32) * self.bounds.half_area()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use glam::vec3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn triangles(count: usize) -> Vec<[Vec3; 3]> {
+        let mut rng = StdRng::seed_from_u64(1234);
+
+        (0..count)
+            .map(|_| {
+                let a = vec3(rng.gen(), rng.gen(), rng.gen()) * 100.0;
+
+                // Long and thin triangles, so that spatial splits kick in
+                let b = a + vec3(rng.gen(), rng.gen(), rng.gen()) * 50.0;
+                let c = a + vec3(rng.gen(), rng.gen(), rng.gen());
+
+                [a, b, c]
+            })
+            .collect()
+    }
+
+    fn build(positions: &[[Vec3; 3]]) -> (BvhNodes, BvhPrimitives) {
+        let mut nodes = BvhNodes::default();
+        let mut primitives = BvhPrimitives::default();
+
+        for (id, triangle) in positions.iter().enumerate() {
+            let bounds: BoundingBox = triangle.iter().copied().collect();
+
+            primitives.add(BvhPrimitive {
+                id: id as u32,
+                center: bounds.center(),
+                bounds,
+            });
+        }
+
+        primitives.begin_refresh();
+        run(
+            &mut nodes,
+            &mut primitives,
+            positions,
+            &Default::default(),
+            0.0,
+        );
+
+        (nodes, primitives)
+    }
+
+    /// Returns ids of all triangles reachable from given node, together with
+    /// the number of references
+    fn reachable(
+        nodes: &BvhNodes,
+        primitives: &BvhPrimitives,
+        id: BvhNodeId,
+        out: &mut HashSet<u32>,
+    ) -> usize {
+        match nodes[id] {
+            BvhNode::Internal {
+                left_id, right_id, ..
+            } => {
+                reachable(nodes, primitives, left_id, out)
+                    + reachable(nodes, primitives, right_id, out)
+            }
+
+            BvhNode::Leaf { primitives_ref, .. } => {
+                let leaf = primitives.current(primitives_ref);
+
+                out.extend(leaf.iter().map(|primitive| primitive.id));
+                leaf.len()
+            }
+        }
+    }
+
+    #[test]
+    fn all_triangles_are_reachable() {
+        let positions = triangles(2048);
+        let (nodes, primitives) = build(&positions);
+
+        let mut ids = HashSet::new();
+        let refs = reachable(&nodes, &primitives, BvhNodeId::root(), &mut ids);
+
+        assert_eq!((0..positions.len() as u32).collect::<HashSet<_>>(), ids);
+
+        // Spatial splits should've actually happened, but within the budget
+        assert!(refs > positions.len());
+
+        assert!(
+            refs <= positions.len()
+                + ((positions.len() as f32) * MAX_DUPLICATES) as usize
+        );
+    }
+}
@@ -1,38 +1,118 @@
-use glam::Vec4;
+use glam::{Vec3, Vec4};
 use spirv_std::glam::vec4;
 
-use super::{BvhNodeId, BvhNodes, BvhPrimitives};
-use crate::{AlphaMode, BvhNode, Materials, Params};
+use super::{BvhNodeId, BvhNodes, BvhPrimitive, BvhPrimitives};
+use crate::{BoundingBox, BvhLayout, BvhNode};
 
-pub fn run<P>(
-    materials: &Materials<P>,
+pub const OP_INTERNAL: u32 = 0;
+pub const OP_TRIANGLE: u32 = 1;
+pub const OP_INSTANCE: u32 = 2;
+pub const OP_INTERNAL_WIDE: u32 = 3;
+pub const OP_END: u32 = 4;
+
+/// Maximum number of children of a wide node.
+const WIDTH: usize = 4;
+
+/// Appends given tree into the buffer.
+///
+/// Pointers to right children are indices into `buffer` - that is, if the
+/// buffer is empty when this function gets called, the pointers are relative
+/// to the tree's root (that's what we do for meshes' trees) and otherwise
+/// they're absolute (that's what we do for the top-level tree).
+///
+/// `serialize_primitive` gets called for each primitive inside each leaf,
+/// together with a flag saying whether that leaf contains any more primitives
+/// following the current one.
+///
+/// Each internal node also stores its escape pointer - pointer to whatever
+/// follows the node's subtree in the buffer (i.e. its next sibling, the next
+/// sibling of one of its ancestors, or the [`OP_END`] entry we append at the
+/// end of the tree); this allows the GPU to walk the tree without a stack when
+/// its stack runs out, see `Ray::traverse()`.
+///
+/// Returns the maximum number of stack entries the GPU traversal might need
+/// to walk this tree (not including the entries pushed for leaves, which the
+/// caller knows better about).
+pub fn run(
     nodes: &BvhNodes,
     primitives: &BvhPrimitives,
     buffer: &mut Vec<Vec4>,
-) where
-    P: Params,
-{
-    buffer.clear();
+    layout: BvhLayout,
+    mut serialize_primitive: impl FnMut(&mut Vec<Vec4>, &BvhPrimitive, bool),
+) -> usize {
+    let (_, stack_size) = serialize(
+        nodes,
+        primitives,
+        buffer,
+        layout,
+        &mut serialize_primitive,
+        BvhNodeId::root(),
+    );
 
-    serialize(materials, nodes, primitives, buffer, BvhNodeId::root());
+    buffer.push(vec4(
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        f32::from_bits(OP_END),
+    ));
+
+    stack_size
 }
 
-fn serialize<P>(
-    materials: &Materials<P>,
+fn serialize(
     nodes: &BvhNodes,
     primitives: &BvhPrimitives,
     buffer: &mut Vec<Vec4>,
+    layout: BvhLayout,
+    serialize_primitive: &mut impl FnMut(&mut Vec<Vec4>, &BvhPrimitive, bool),
     id: BvhNodeId,
-) -> u32
-where
-    P: Params,
-{
-    const OP_INTERNAL: u32 = 0;
-    const OP_LEAF: u32 = 1;
-
+) -> (u32, usize) {
     let ptr = buffer.len();
+    let mut stack_size = 0;
 
     match nodes[id] {
+        BvhNode::Internal { .. } if layout == BvhLayout::Wide => {
+            let children = collapse(nodes, id);
+
+            buffer.resize(ptr + 5, Default::default());
+
+            let mut children_ptrs = [0; WIDTH];
+
+            for (child_ptr, &child_id) in
+                children_ptrs.iter_mut().zip(&children)
+            {
+                let child_stack_size;
+
+                (*child_ptr, child_stack_size) = serialize(
+                    nodes,
+                    primitives,
+                    buffer,
+                    layout,
+                    serialize_primitive,
+                    child_id,
+                );
+
+                stack_size = stack_size.max(child_stack_size);
+            }
+
+            // All children except the nearest one might get pushed onto the
+            // stack
+            stack_size += children.len() - 1;
+
+            let children_bounds: Vec<_> = children
+                .iter()
+                .map(|&child_id| nodes[child_id].bounds())
+                .collect();
+
+            let escape_ptr = buffer.len() as u32;
+
+            buffer[ptr..ptr + 5].copy_from_slice(&encode_wide_node(
+                &children_bounds,
+                children_ptrs,
+                escape_ptr,
+            ));
+        }
+
         BvhNode::Internal {
             left_id, right_id, ..
         } => {
@@ -44,11 +124,27 @@ where
             let left_bb = nodes[left_id].bounds();
             let right_bb = nodes[right_id].bounds();
 
-            let _left_ptr =
-                serialize(materials, nodes, primitives, buffer, left_id);
+            let (_left_ptr, left_stack_size) = serialize(
+                nodes,
+                primitives,
+                buffer,
+                layout,
+                serialize_primitive,
+                left_id,
+            );
+
+            let (right_ptr, right_stack_size) = serialize(
+                nodes,
+                primitives,
+                buffer,
+                layout,
+                serialize_primitive,
+                right_id,
+            );
+
+            stack_size = 1 + left_stack_size.max(right_stack_size);
 
-            let right_ptr =
-                serialize(materials, nodes, primitives, buffer, right_id);
+            let escape_ptr = buffer.len() as u32;
 
             buffer[ptr] = vec4(
                 left_bb.min().x,
@@ -68,7 +164,7 @@ where
                 right_bb.min().x,
                 right_bb.min().y,
                 right_bb.min().z,
-                Default::default(),
+                f32::from_bits(escape_ptr),
             );
 
             buffer[ptr + 3] = vec4(
@@ -83,28 +179,358 @@ where
             for (primitive_idx, primitive) in
                 primitives.current(primitives_ref).iter().enumerate()
             {
-                let material = &materials[primitive.material_id];
+                let got_more_entries = primitive_idx + 1 < primitives_ref.len();
+
+                serialize_primitive(buffer, primitive, got_more_entries);
+            }
+        }
+    }
+
+    (ptr as u32, stack_size)
+}
+
+/// Collapses given binary node into up to [`WIDTH`] nodes, by repeatedly
+/// replacing the largest internal child with its own children.
+fn collapse(nodes: &BvhNodes, id: BvhNodeId) -> Vec<BvhNodeId> {
+    let mut children = vec![id];
+
+    while children.len() < WIDTH {
+        let largest = children
+            .iter()
+            .enumerate()
+            .filter(|(_, &child_id)| {
+                matches!(nodes[child_id], BvhNode::Internal { .. })
+            })
+            .max_by(|(_, &a), (_, &b)| {
+                let a = nodes[a].bounds().half_area();
+                let b = nodes[b].bounds().half_area();
+
+                a.total_cmp(&b)
+            })
+            .map(|(idx, _)| idx);
+
+        let Some(largest) = largest else {
+            break;
+        };
+
+        let BvhNode::Internal {
+            left_id, right_id, ..
+        } = nodes[children[largest]]
+        else {
+            unreachable!();
+        };
+
+        children[largest] = left_id;
+        children.insert(largest + 1, right_id);
+    }
+
+    children
+}
 
-                let flags = {
-                    let got_more_entries =
-                        primitive_idx + 1 < primitives_ref.len();
+/// Encodes a wide node, with children's bounds quantized to 8 bits per axis
+/// relative to the node's bounds:
+///
+/// ```text
+/// d0 = [ origin.x, origin.y, origin.z, OP_INTERNAL_WIDE ]
+/// d1 = [ scale.x, scale.y, scale.z, number of children ]
+/// d2 = [ min.x, min.y, min.z, max.x ]
+/// d3 = [ max.y, max.z, escape ptr, - ]
+/// d4 = [ child #0's ptr, child #1's ptr, child #2's ptr, child #3's ptr ]
+/// ```
+///
+/// ... where each of `min.*` and `max.*` packs one byte per child, and child's
+/// bounds are `origin + min * scale` to `origin + max * scale`.
+///
+/// Quantized bounds are always conservative, i.e. they contain the original
+/// bounds (they can be larger, though, which costs a few more intersection
+/// tests during traversal).
+fn encode_wide_node(
+    children_bounds: &[BoundingBox],
+    children_ptrs: [u32; WIDTH],
+    escape_ptr: u32,
+) -> [Vec4; 5] {
+    let bounds: BoundingBox = children_bounds.iter().copied().collect();
+    let origin = bounds.min();
 
-                    let has_alpha_blending =
-                        matches!(material.alpha_mode, AlphaMode::Blend);
+    // Slightly overestimate the scale, so that rounding errors don't cause
+    // children's bounds to stick out of the grid
+    let scale = (bounds.extent() / 255.0 * (1.0 + 1e-5))
+        .max(Vec3::splat(f32::MIN_POSITIVE));
 
-                    (got_more_entries as u32)
-                        | ((has_alpha_blending as u32) << 1)
-                };
+    let mut qmin = [0u32; 3];
+    let mut qmax = [0u32; 3];
+
+    for (child_idx, child_bounds) in children_bounds.iter().enumerate() {
+        for axis in 0..3 {
+            let min = quantize_min(
+                origin[axis],
+                scale[axis],
+                child_bounds.min()[axis],
+            );
+
+            let max = quantize_max(
+                origin[axis],
+                scale[axis],
+                child_bounds.max()[axis],
+            );
+
+            qmin[axis] |= min << (8 * child_idx);
+            qmax[axis] |= max << (8 * child_idx);
+        }
+    }
+
+    [
+        vec4(
+            origin.x,
+            origin.y,
+            origin.z,
+            f32::from_bits(OP_INTERNAL_WIDE),
+        ),
+        vec4(
+            scale.x,
+            scale.y,
+            scale.z,
+            f32::from_bits(children_bounds.len() as u32),
+        ),
+        vec4(
+            f32::from_bits(qmin[0]),
+            f32::from_bits(qmin[1]),
+            f32::from_bits(qmin[2]),
+            f32::from_bits(qmax[0]),
+        ),
+        vec4(
+            f32::from_bits(qmax[1]),
+            f32::from_bits(qmax[2]),
+            f32::from_bits(escape_ptr),
+            Default::default(),
+        ),
+        vec4(
+            f32::from_bits(children_ptrs[0]),
+            f32::from_bits(children_ptrs[1]),
+            f32::from_bits(children_ptrs[2]),
+            f32::from_bits(children_ptrs[3]),
+        ),
+    ]
+}
+
+fn quantize_min(origin: f32, scale: f32, value: f32) -> u32 {
+    let mut q = ((value - origin) / scale).floor().clamp(0.0, 255.0);
+
+    while q > 0.0 && origin + q * scale > value {
+        q -= 1.0;
+    }
 
-                buffer.push(vec4(
-                    f32::from_bits(flags),
-                    f32::from_bits(primitive.triangle_id.get()),
-                    f32::from_bits(primitive.material_id.get()),
-                    f32::from_bits(OP_LEAF),
-                ));
+    q as u32
+}
+
+fn quantize_max(origin: f32, scale: f32, value: f32) -> u32 {
+    let mut q = ((value - origin) / scale).ceil().clamp(0.0, 255.0);
+
+    while q < 255.0 && origin + q * scale < value {
+        q += 1.0;
+    }
+
+    q as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+    use crate::bvh::{builder, BvhNodeHash, BvhPrimitiveId, BvhPrimitivesRef};
+    use crate::BvhConfig;
+
+    /// Checks escape pointers of given node (and its descendants); returns
+    /// where node's subtree ends.
+    fn check_escapes(buffer: &[Vec4], ptr: usize) -> usize {
+        let d0 = buffer[ptr];
+
+        match d0.w.to_bits() {
+            OP_INTERNAL => {
+                let left_end = check_escapes(buffer, ptr + 4);
+                let right_ptr = buffer[ptr + 1].w.to_bits() as usize;
+
+                assert_eq!(left_end, right_ptr);
+
+                let end = check_escapes(buffer, right_ptr);
+
+                assert_eq!(end, buffer[ptr + 2].w.to_bits() as usize);
+                end
+            }
+
+            OP_INTERNAL_WIDE => {
+                let children = buffer[ptr + 1].w.to_bits() as usize;
+                let children_ptrs = buffer[ptr + 4].to_array();
+                let mut end = ptr + 5;
+
+                for child_ptr in &children_ptrs[..children] {
+                    assert_eq!(end, child_ptr.to_bits() as usize);
+
+                    end = check_escapes(buffer, end);
+                }
+
+                assert_eq!(end, buffer[ptr + 3].z.to_bits() as usize);
+                end
+            }
+
+            op => {
+                assert_eq!(OP_TRIANGLE, op);
+
+                if d0.x.to_bits() == 1 {
+                    check_escapes(buffer, ptr + 1)
+                } else {
+                    ptr + 1
+                }
             }
         }
     }
 
-    ptr as u32
+    #[test]
+    fn escape_pointers() {
+        for layout in [BvhLayout::Binary, BvhLayout::Wide] {
+            let mut nodes = BvhNodes::default();
+            let mut primitives = BvhPrimitives::default();
+
+            for id in 0..100 {
+                let center = vec3((id * 7 % 13) as f32, (id % 10) as f32, 0.0);
+
+                primitives.add(BvhPrimitive {
+                    id,
+                    center,
+                    bounds: BoundingBox::new(center - 0.5, center + 0.5),
+                });
+            }
+
+            primitives.begin_refresh();
+
+            builder::run(
+                &mut nodes,
+                &mut primitives,
+                &BvhConfig {
+                    min_leaf_size: 1,
+                    max_leaf_size: 2,
+                    ..Default::default()
+                },
+            );
+
+            let mut buffer = Vec::new();
+
+            run(
+                &nodes,
+                &primitives,
+                &mut buffer,
+                layout,
+                |buffer, primitive, got_more_entries| {
+                    buffer.push(vec4(
+                        f32::from_bits(got_more_entries as u32),
+                        f32::from_bits(primitive.id),
+                        Default::default(),
+                        f32::from_bits(OP_TRIANGLE),
+                    ));
+                },
+            );
+
+            primitives.end_refresh();
+
+            let end = check_escapes(&buffer, 0);
+
+            assert_eq!(buffer.len() - 1, end, "{layout:?}");
+            assert_eq!(OP_END, buffer[end].w.to_bits(), "{layout:?}");
+        }
+    }
+
+    #[test]
+    fn serialized_size() {
+        let mut primitives = BvhPrimitives::default();
+
+        for id in 0..3 {
+            let center = vec3(id as f32, 0.0, 0.0);
+
+            primitives.add(BvhPrimitive {
+                id,
+                center,
+                bounds: BoundingBox::new(center - 0.5, center + 0.5),
+            });
+        }
+
+        primitives.begin_refresh();
+
+        let primitives_ref = |start, end| {
+            BvhPrimitivesRef::new(
+                BvhPrimitiveId::new(start),
+                BvhPrimitiveId::new(end),
+            )
+        };
+
+        let bounds = |min_x: f32, max_x: f32| {
+            BoundingBox::new(vec3(min_x, -0.5, -0.5), vec3(max_x, 0.5, 0.5))
+        };
+
+        // root -> [leaf with primitive #0, node -> [leaf with primitive #1,
+        // leaf with primitive #2]]
+        let nodes = BvhNodes {
+            nodes: vec![
+                BvhNode::Internal {
+                    bounds: bounds(-0.5, 2.5),
+                    primitives_ref: primitives_ref(0, 3),
+                    left_id: BvhNodeId::new(1),
+                    left_hash: BvhNodeHash::new(0),
+                    right_id: BvhNodeId::new(2),
+                    right_hash: BvhNodeHash::new(0),
+                },
+                BvhNode::Leaf {
+                    bounds: bounds(-0.5, 0.5),
+                    primitives_ref: primitives_ref(0, 1),
+                },
+                BvhNode::Internal {
+                    bounds: bounds(0.5, 2.5),
+                    primitives_ref: primitives_ref(1, 3),
+                    left_id: BvhNodeId::new(3),
+                    left_hash: BvhNodeHash::new(0),
+                    right_id: BvhNodeId::new(4),
+                    right_hash: BvhNodeHash::new(0),
+                },
+                BvhNode::Leaf {
+                    bounds: bounds(0.5, 1.5),
+                    primitives_ref: primitives_ref(1, 2),
+                },
+                BvhNode::Leaf {
+                    bounds: bounds(1.5, 2.5),
+                    primitives_ref: primitives_ref(2, 3),
+                },
+            ],
+            free_nodes: Default::default(),
+        };
+
+        // Binary layout: two internal nodes (4 entries each), three
+        // primitives (1 entry each) and the end marker.
+        //
+        // Wide layout: the root collapses into a single node with three
+        // children (5 entries), followed by the primitives and the end
+        // marker.
+        for (layout, expected_len, expected_stack_size) in
+            [(BvhLayout::Binary, 12, 2), (BvhLayout::Wide, 9, 2)]
+        {
+            let mut buffer = Vec::new();
+
+            let stack_size = run(
+                &nodes,
+                &primitives,
+                &mut buffer,
+                layout,
+                |buffer, primitive, _| {
+                    buffer.push(vec4(
+                        Default::default(),
+                        f32::from_bits(primitive.id),
+                        Default::default(),
+                        f32::from_bits(OP_TRIANGLE),
+                    ));
+                },
+            );
+
+            assert_eq!(expected_len, buffer.len(), "{layout:?}");
+            assert_eq!(expected_stack_size, stack_size, "{layout:?}");
+        }
+    }
 }
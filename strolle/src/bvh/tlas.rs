@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use derivative::Derivative;
+use glam::Affine3A;
+
+use super::{BvhNodes, BvhPrimitive, BvhPrimitives};
+use crate::{gpu, BoundingBox, Params};
+
+/// Top-level tree, built over instances' world-space bounds.
+#[derive(Debug, Derivative)]
+#[derivative(Default(bound = ""))]
+pub struct Tlas<P>
+where
+    P: Params,
+{
+    pub nodes: BvhNodes,
+    pub primitives: BvhPrimitives,
+
+    /// Instances, indexed by primitives' ids; `None` for removed ones
+    instances: Vec<Option<BvhInstance<P>>>,
+    index: HashMap<P::InstanceHandle, usize>,
+    free_ids: Vec<usize>,
+
+    /// Whether any instance has been added or removed since the last
+    /// refresh, in which case the tree cannot be refitted
+    pub has_dirty_topology: bool,
+}
+
+impl<P> Tlas<P>
+where
+    P: Params,
+{
+    pub fn insert(&mut self, instance: BvhInstance<P>, bounds: BoundingBox) {
+        let primitive = |id: usize| BvhPrimitive {
+            id: id as u32,
+            center: bounds.center(),
+            bounds,
+        };
+
+        if let Some(&id) = self.index.get(&instance.handle) {
+            for prim in self.primitives.update(id..id + 1) {
+                *prim = primitive(id);
+            }
+
+            self.instances[id] = Some(instance);
+        } else if let Some(id) = self.free_ids.pop() {
+            self.has_dirty_topology = true;
+
+            for prim in self.primitives.update(id..id + 1) {
+                *prim = primitive(id);
+            }
+
+            self.index.insert(instance.handle.clone(), id);
+            self.instances[id] = Some(instance);
+        } else {
+            self.has_dirty_topology = true;
+
+            let id = self.instances.len();
+
+            self.primitives.add(primitive(id));
+            self.index.insert(instance.handle.clone(), id);
+            self.instances.push(Some(instance));
+        }
+    }
+
+    pub fn remove(&mut self, instance_handle: &P::InstanceHandle) -> bool {
+        let Some(id) = self.index.remove(instance_handle) else {
+            return false;
+        };
+
+        for prim in self.primitives.update(id..id + 1) {
+            prim.kill();
+        }
+
+        self.instances[id] = None;
+        self.free_ids.push(id);
+        self.has_dirty_topology = true;
+
+        true
+    }
+
+    /// Returns instance with given id, or `None` if it's been removed since
+    /// the last refresh.
+    pub fn get(&self, id: u32) -> Option<&BvhInstance<P>> {
+        self.instances.get(id as usize)?.as_ref()
+    }
+
+    /// Returns all alive instances.
+    pub fn iter(&self) -> impl Iterator<Item = &BvhInstance<P>> + '_ {
+        self.instances.iter().flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
+
+/// Instance, as seen by the top-level tree.
+#[derive(Debug)]
+pub struct BvhInstance<P>
+where
+    P: Params,
+{
+    pub handle: P::InstanceHandle,
+    pub mesh_handle: P::MeshHandle,
+    pub material_id: gpu::MaterialId,
+    pub transform_inverse: Affine3A,
+}
@@ -0,0 +1,237 @@
+use super::{BvhNodeId, BvhNodes, BvhPrimitive, BvhPrimitives};
+use crate::{gpu, BvhNode};
+
+/// Walks the tree the same way `Ray::traverse()` does on the GPU, calling
+/// `hit_fn` for each primitive whose node intersects the ray; returns the last
+/// primitive for which `hit_fn` returned `true`.
+///
+/// `hit_fn` should intersect the primitive and update `hit` - since `hit_fn` is
+/// supposed to reject intersections that are further than `hit.distance`, the
+/// last accepted primitive is the closest one.
+pub fn run(
+    nodes: &BvhNodes,
+    primitives: &BvhPrimitives,
+    ray: gpu::Ray,
+    hit: &mut gpu::TriangleHit,
+    mut hit_fn: impl FnMut(&BvhPrimitive, &mut gpu::TriangleHit) -> bool,
+) -> Option<BvhPrimitive> {
+    if nodes.nodes.is_empty() {
+        return None;
+    }
+
+    let mut closest = None;
+    let mut stack = vec![BvhNodeId::root()];
+
+    while let Some(id) = stack.pop() {
+        match nodes[id] {
+            BvhNode::Internal {
+                left_id, right_id, ..
+            } => {
+                let mut near_id = left_id;
+                let mut far_id = right_id;
+
+                let mut near_distance = intersect(ray, nodes, near_id);
+                let mut far_distance = intersect(ray, nodes, far_id);
+
+                if far_distance < near_distance {
+                    (near_id, far_id) = (far_id, near_id);
+                    (near_distance, far_distance) =
+                        (far_distance, near_distance);
+                }
+
+                // Push the farther node first so that the nearer one gets
+                // popped (i.e. visited) first
+                if far_distance < hit.distance {
+                    stack.push(far_id);
+                }
+
+                if near_distance < hit.distance {
+                    stack.push(near_id);
+                }
+            }
+
+            BvhNode::Leaf { primitives_ref, .. } => {
+                for primitive in primitives.previous(primitives_ref) {
+                    if hit_fn(primitive, hit) {
+                        closest = Some(*primitive);
+                    }
+                }
+            }
+        }
+    }
+
+    closest
+}
+
+fn intersect(ray: gpu::Ray, nodes: &BvhNodes, id: BvhNodeId) -> f32 {
+    let bounds = nodes[id].bounds();
+
+    ray.intersect_box(bounds.min(), bounds.max())
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, vec4, Vec3, Vec4};
+
+    use super::*;
+    use crate::bvh::{BvhNodeHash, BvhPrimitiveId, BvhPrimitivesRef};
+    use crate::BoundingBox;
+
+    /// Returns a square-ish triangle lying at given depth, facing the rays
+    /// cast by the tests.
+    fn triangle(z: f32) -> gpu::Triangle {
+        gpu::Triangle {
+            d0: vec4(-1.0, -1.0, z, 0.0),
+            d1: Vec4::Z,
+            d3: vec4(3.0, -1.0, z, 0.0),
+            d4: Vec4::Z,
+            d6: vec4(-1.0, 3.0, z, 0.0),
+            d7: Vec4::Z,
+            ..Default::default()
+        }
+    }
+
+    fn primitives_ref(start: u32, end: u32) -> BvhPrimitivesRef {
+        BvhPrimitivesRef::new(
+            BvhPrimitiveId::new(start),
+            BvhPrimitiveId::new(end),
+        )
+    }
+
+    /// Builds a tree with two overlapping leaves: the left one contains
+    /// triangles at depths 6 and 9, the right one contains a triangle at
+    /// depth 3 - so the leaf we enter first doesn't contain the closest hit.
+    fn tree() -> (BvhNodes, BvhPrimitives, Vec<gpu::Triangle>) {
+        let triangles = vec![triangle(6.0), triangle(9.0), triangle(3.0)];
+        let mut primitives = BvhPrimitives::default();
+
+        for (id, triangle) in triangles.iter().enumerate() {
+            let bounds: BoundingBox =
+                triangle.positions().into_iter().collect();
+
+            primitives.add(BvhPrimitive {
+                id: id as u32,
+                center: bounds.center(),
+                bounds,
+            });
+        }
+
+        primitives.begin_refresh();
+        primitives.end_refresh();
+
+        let left_bounds =
+            BoundingBox::new(vec3(-1.0, -1.0, 2.0), vec3(3.0, 3.0, 9.0));
+
+        let right_bounds =
+            BoundingBox::new(vec3(-1.0, -1.0, 2.5), vec3(3.0, 3.0, 4.0));
+
+        let nodes = BvhNodes {
+            nodes: vec![
+                BvhNode::Internal {
+                    bounds: left_bounds + right_bounds,
+                    primitives_ref: primitives_ref(0, 3),
+                    left_id: BvhNodeId::new(1),
+                    left_hash: BvhNodeHash::new(0),
+                    right_id: BvhNodeId::new(2),
+                    right_hash: BvhNodeHash::new(0),
+                },
+                BvhNode::Leaf {
+                    bounds: left_bounds,
+                    primitives_ref: primitives_ref(0, 2),
+                },
+                BvhNode::Leaf {
+                    bounds: right_bounds,
+                    primitives_ref: primitives_ref(2, 3),
+                },
+            ],
+            free_nodes: Default::default(),
+        };
+
+        (nodes, primitives, triangles)
+    }
+
+    fn ray() -> gpu::Ray {
+        gpu::Ray::new(vec3(0.25, 0.25, 0.0), Vec3::Z)
+    }
+
+    /// Traverses the tree, returning the id of the closest triangle and ids of
+    /// all visited triangles, in order.
+    fn traverse(hit: &mut gpu::TriangleHit) -> (Option<u32>, Vec<u32>) {
+        let (nodes, primitives, triangles) = tree();
+        let mut visited = Vec::new();
+
+        let closest = run(&nodes, &primitives, ray(), hit, |primitive, hit| {
+            visited.push(primitive.id);
+            triangles[primitive.id as usize].hit(ray(), hit)
+        });
+
+        (closest.map(|primitive| primitive.id), visited)
+    }
+
+    #[test]
+    fn closest_hit() {
+        let mut hit = gpu::TriangleHit::none();
+        let (closest, visited) = traverse(&mut hit);
+
+        assert_eq!(Some(2), closest);
+        assert_eq!(3.0, hit.distance);
+
+        // Left leaf is entered first, but it doesn't contain the closest hit,
+        // so the right one must be visited as well
+        assert_eq!(vec![0, 1, 2], visited);
+    }
+
+    #[test]
+    fn max_distance() {
+        // Right leaf begins at 2.5, so it shouldn't be visited at all
+        let mut hit = gpu::TriangleHit {
+            distance: 2.5,
+            ..gpu::TriangleHit::none()
+        };
+
+        let (closest, visited) = traverse(&mut hit);
+
+        assert_eq!(None, closest);
+        assert_eq!(2.5, hit.distance);
+        assert_eq!(vec![0, 1], visited);
+
+        // Closest triangle is at 3.0, so it shouldn't be hit either, but the
+        // right leaf should be visited now
+        let mut hit = gpu::TriangleHit {
+            distance: 3.0,
+            ..gpu::TriangleHit::none()
+        };
+
+        let (closest, visited) = traverse(&mut hit);
+
+        assert_eq!(None, closest);
+        assert_eq!(3.0, hit.distance);
+        assert_eq!(vec![0, 1, 2], visited);
+
+        let mut hit = gpu::TriangleHit {
+            distance: 7.0,
+            ..gpu::TriangleHit::none()
+        };
+
+        let (closest, _) = traverse(&mut hit);
+
+        assert_eq!(Some(2), closest);
+        assert_eq!(3.0, hit.distance);
+    }
+
+    #[test]
+    fn empty_tree() {
+        let mut hit = gpu::TriangleHit::none();
+
+        let closest = run(
+            &BvhNodes::default(),
+            &BvhPrimitives::default(),
+            ray(),
+            &mut hit,
+            |_, _| panic!(),
+        );
+
+        assert!(closest.is_none());
+        assert!(hit.is_none());
+    }
+}
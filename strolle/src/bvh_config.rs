@@ -0,0 +1,93 @@
+use std::thread;
+
+/// Parameters used to build BVHs; see [`crate::Engine::set_bvh_config()`].
+///
+/// Defaults let the surface area heuristic alone decide when to stop splitting
+/// (i.e. leaves don't have any size limit) - for static levels it's usually
+/// worth enabling spatial splits and increasing the number of bins, while
+/// highly dynamic scenes might prefer larger leaves (which are faster to
+/// build).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BvhConfig {
+    /// Number of bins used to find the best splitting plane; more bins yield
+    /// better trees at the cost of slower building.
+    pub bins: usize,
+
+    /// Nodes with at most this many primitives are never split.
+    pub min_leaf_size: usize,
+
+    /// Nodes with more than this many primitives are always split, even if
+    /// the surface area heuristic says that it's not worth it; unlimited by
+    /// default.
+    pub max_leaf_size: usize,
+
+    /// Estimated cost of visiting a node, relative to
+    /// [`Self::intersection_cost`]; the larger it is, the shallower the trees.
+    pub traversal_cost: f32,
+
+    /// Estimated cost of intersecting a primitive.
+    pub intersection_cost: f32,
+
+    /// Overlap threshold for spatial splits, if enabled; see
+    /// [`crate::Engine::set_bvh_config()`].
+    pub spatial_splits: Option<f32>,
+
+    /// Layout of nodes inside the buffer used for traversal on the GPU.
+    pub layout: BvhLayout,
+
+    /// Maximum number of threads used for building; defaults to the number of
+    /// available CPU cores.
+    ///
+    /// Note that the built tree doesn't depend on the number of threads, it's
+    /// only a matter of how fast it gets built.
+    pub threads: usize,
+}
+
+impl BvhConfig {
+    /// Returns the estimated cost of having given primitives in a leaf.
+    pub(crate) fn leaf_cost(&self, count: usize, half_area: f32) -> f32 {
+        self.intersection_cost * (count as f32) * half_area
+    }
+
+    /// Returns the estimated cost of splitting a node, given the node's area
+    /// and the sum of `count * half_area` of its children.
+    pub(crate) fn split_cost(&self, half_area: f32, children_cost: f32) -> f32 {
+        self.traversal_cost * half_area + self.intersection_cost * children_cost
+    }
+}
+
+impl Default for BvhConfig {
+    fn default() -> Self {
+        Self {
+            bins: 12,
+            min_leaf_size: 1,
+            max_leaf_size: usize::MAX,
+            traversal_cost: 0.0,
+            intersection_cost: 1.0,
+            spatial_splits: None,
+            layout: Default::default(),
+            threads: thread::available_parallelism()
+                .map_or(1, |threads| threads.get()),
+        }
+    }
+}
+
+/// Layout of nodes inside the buffer used for traversal on the GPU.
+///
+/// Trees are always built as binary trees, this only affects how they are
+/// serialized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BvhLayout {
+    /// Each internal node is stored with full-precision bounds of its two
+    /// children (64 bytes per node).
+    #[default]
+    Binary,
+
+    /// Tree is collapsed so that each internal node has up to four children,
+    /// with their bounds quantized to 8 bits per axis (80 bytes per node).
+    ///
+    /// Wide trees take about a third less memory and require visiting about a
+    /// half less nodes, at the cost of slightly looser bounds - this usually
+    /// pays off on GPUs without hardware ray-tracing support.
+    Wide,
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use crate::Params;
+
+/// Statistics describing quality of the BVH; see
+/// [`crate::Engine::bvh_report()`].
+#[derive(Clone, Debug)]
+pub struct BvhReport<P>
+where
+    P: Params,
+{
+    /// Top-level tree, built over instances
+    pub tlas: BvhTreeReport,
+
+    /// Bottom-level trees, built over meshes' triangles
+    pub blases: HashMap<P::MeshHandle, BvhTreeReport>,
+
+    /// Size of the buffer all trees get serialized into, in bytes
+    pub buffer_size: usize,
+
+    /// Number of stack entries a ray might need to traverse the trees on the
+    /// GPU, i.e. the top-level tree followed by the deepest mesh's tree
+    pub stack_size: usize,
+
+    /// Number of stack entries supported by the GPU traversal; if
+    /// [`Self::stack_size`] is larger than that, rays that run out of stack
+    /// fall back to a slower, stackless traversal
+    pub max_stack_size: usize,
+}
+
+/// Statistics describing quality of a single tree.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BvhTreeReport {
+    /// Tree's SAH cost, normalized by the root's area - roughly, the expected
+    /// number of nodes and primitives a random ray passing through the root
+    /// has to check; the lower, the better
+    pub sah_cost: f32,
+
+    /// Number of nodes, both internal and leaves
+    pub node_count: usize,
+
+    /// Number of leaves
+    pub leaf_count: usize,
+
+    /// Number of primitives referenced by leaves; with spatial splits enabled
+    /// this can be larger than the number of triangles, since a single
+    /// triangle can be then referenced from many leaves
+    pub primitive_count: usize,
+
+    /// Number of leaves at given depth (with root being at depth zero)
+    pub depth_histogram: Vec<usize>,
+
+    /// Number of leaves with given number of primitives
+    pub leaf_size_histogram: Vec<usize>,
+
+    /// Area of the overlap between children of internal nodes, relative to
+    /// their parent's area, averaged over all internal nodes; the lower, the
+    /// fewer subtrees a ray has to visit
+    pub avg_overlap: f32,
+
+    /// Like [`Self::avg_overlap`], but the worst one found
+    pub max_overlap: f32,
+}
+
+impl BvhTreeReport {
+    /// Returns the depth of the deepest leaf.
+    pub fn max_depth(&self) -> usize {
+        self.depth_histogram.len().saturating_sub(1)
+    }
+}
@@ -0,0 +1,31 @@
+/// Specifies how the top-level BVH gets updated when instances change.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BvhUpdatePolicy {
+    /// Always rebuilds the tree from scratch (well, re-using subtrees that
+    /// haven't changed).
+    ///
+    /// Yields the best tree, at the cost of longer ticks for scenes with lots
+    /// of moving instances.
+    #[default]
+    Rebuild,
+
+    /// When instances have only changed their transforms, keeps tree's
+    /// topology and just recomputes bounds of its nodes.
+    ///
+    /// Refitting is much faster than rebuilding, but the tree's quality
+    /// degrades as instances move away from their original positions - so
+    /// when tree's SAH cost gets larger than `max_degradation` times its cost
+    /// right after the last rebuild, the tree gets rebuilt anyway.
+    ///
+    /// Adding or removing instances always triggers a rebuild.
+    Refit { max_degradation: f32 },
+}
+
+impl BvhUpdatePolicy {
+    /// Returns a refit policy with a reasonable degradation threshold.
+    pub fn refit() -> Self {
+        Self::Refit {
+            max_degradation: 1.5,
+        }
+    }
+}
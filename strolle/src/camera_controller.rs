@@ -1,8 +1,12 @@
 mod buffers;
 mod pass;
 mod passes;
+mod profiler;
+mod readback;
 
 use std::ops::DerefMut;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use log::{debug, info};
 use rand::Rng;
@@ -10,13 +14,19 @@ use rand::Rng;
 pub use self::buffers::*;
 pub use self::pass::*;
 pub use self::passes::*;
-use crate::{gpu, Camera, CameraMode, Engine, Params};
+pub use self::profiler::*;
+pub use self::readback::*;
+use crate::{
+    gpu, Camera, CameraImage, CameraMode, Engine, Error, Params, Result,
+};
 
 #[derive(Debug)]
 pub struct CameraController {
     camera: Camera,
     buffers: CameraBuffers,
     passes: CameraPasses,
+    readback: Mutex<Option<CameraReadback>>,
+    profiler: Mutex<Option<CameraProfiler>>,
     frame: u32,
 }
 
@@ -38,6 +48,8 @@ impl CameraController {
             camera,
             buffers,
             passes,
+            readback: Default::default(),
+            profiler: Mutex::new(CameraProfiler::new(device)),
             frame: 0,
         }
     }
@@ -52,6 +64,12 @@ impl CameraController {
     {
         let is_invalidated = self.camera.is_invalidated_by(&camera);
 
+        // Readback's texture and copy region depend on the viewport's position
+        // as well, which doesn't invalidate the rest of the buffers
+        if self.camera.viewport.position != camera.viewport.position {
+            self.readback = Default::default();
+        }
+
         self.camera = camera;
         *self.buffers.prev_camera.deref_mut() = *self.buffers.camera;
         *self.buffers.camera.deref_mut() = self.camera.serialize();
@@ -66,6 +84,7 @@ impl CameraController {
         debug!("Rebuilding buffers for camera `{}`", self.camera);
 
         self.buffers = CameraBuffers::new(device, &self.camera);
+        self.readback = Default::default();
     }
 
     fn rebuild_passes<P>(&mut self, engine: &Engine<P>, device: &wgpu::Device)
@@ -78,10 +97,30 @@ impl CameraController {
             CameraPasses::new(engine, device, &self.camera, &self.buffers);
     }
 
+    /// Returns how many bytes camera's buffers occupy in VRAM.
+    pub fn allocated_size(&self) -> usize {
+        self.buffers.allocated_size()
+    }
+
+    /// Returns GPU timings of passes from one of the recently rendered
+    /// frames; see [`CameraProfiler`].
+    pub fn pass_timings(&self) -> Vec<(String, Duration)> {
+        self.profiler
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|profiler| profiler.timings().to_vec())
+            .unwrap_or_default()
+    }
+
     pub fn flush(&mut self, frame: u32, queue: &wgpu::Queue) {
         self.frame = frame;
         self.buffers.camera.flush(queue);
         self.buffers.prev_camera.flush(queue);
+
+        if let Some(profiler) = self.profiler.get_mut().unwrap() {
+            profiler.poll(queue);
+        }
     }
 
     pub fn render<P>(
@@ -92,6 +131,10 @@ impl CameraController {
     ) where
         P: Params,
     {
+        if let Some(profiler) = self.profiler.lock().unwrap().as_mut() {
+            profiler.begin_frame();
+        }
+
         match self.camera.mode {
             CameraMode::BvhHeatmap => {
                 self.passes.bvh_heatmap.run(self, encoder);
@@ -174,6 +217,42 @@ impl CameraController {
                 self.passes.frame_composition.run(self, encoder, view);
             }
         }
+
+        if let Some(profiler) = self.profiler.lock().unwrap().as_mut() {
+            profiler.end_frame(encoder);
+        }
+    }
+
+    pub fn render_to_image<P>(
+        &self,
+        engine: &Engine<P>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<CameraReadbackJob>
+    where
+        P: Params,
+    {
+        if !CameraImage::supports(self.camera.viewport.format) {
+            return Err(Error::UnsupportedReadbackFormat(
+                self.camera.viewport.format,
+            ));
+        }
+
+        let mut readback = self.readback.lock().unwrap();
+
+        let readback = readback
+            .get_or_insert_with(|| CameraReadback::new(device, &self.camera));
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("strolle_readback"),
+            });
+
+        self.render(engine, &mut encoder, readback.view());
+        let buffer = readback.copy(device, &mut encoder);
+        queue.submit([encoder.finish()]);
+
+        Ok(readback.map(buffer))
     }
 
     pub fn invalidate<P>(&mut self, engine: &Engine<P>, device: &wgpu::Device)
@@ -183,6 +262,31 @@ impl CameraController {
         self.rebuild_passes(engine, device);
     }
 
+    /// Runs given pass, surrounding it with GPU timestamps if the device
+    /// supports them.
+    fn profile<T>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        f: impl FnOnce(&mut wgpu::CommandEncoder) -> T,
+    ) -> T {
+        let mut profiler = self.profiler.lock().unwrap();
+
+        let Some(profiler) = profiler.as_mut() else {
+            return f(encoder);
+        };
+
+        if !profiler.begin_pass(encoder) {
+            return f(encoder);
+        }
+
+        let result = f(encoder);
+
+        profiler.end_pass(encoder, label);
+
+        result
+    }
+
     /// Returns whether the current frame should use the first or the second
     /// resource when given resource is double-buffered.
     fn is_alternate(&self) -> bool {
@@ -365,4 +365,88 @@ impl CameraBuffers {
             ref_colors,
         }
     }
+
+    /// Returns how many bytes all of the buffers occupy in VRAM.
+    pub fn allocated_size(&self) -> usize {
+        // Destructuring makes sure no buffer gets forgotten here
+        let Self {
+            camera,
+            prev_camera,
+            atmosphere_transmittance_lut,
+            atmosphere_scattering_lut,
+            atmosphere_sky_lut,
+            prim_depth,
+            prim_gbuffer_d0,
+            prim_gbuffer_d1,
+            prim_surface_map,
+            reprojection_map,
+            velocity_map,
+            di_prev_reservoirs,
+            di_curr_reservoirs,
+            di_next_reservoirs,
+            di_diff_samples,
+            di_diff_prev_colors,
+            di_diff_curr_colors,
+            di_diff_moments,
+            di_diff_stash,
+            gi_rays,
+            gi_gbuffer_d0,
+            gi_gbuffer_d1,
+            gi_samples,
+            gi_diff_temporal_reservoirs,
+            gi_diff_spatial_reservoirs_a,
+            gi_diff_spatial_reservoirs_b,
+            gi_diff_samples,
+            gi_diff_prev_colors,
+            gi_diff_curr_colors,
+            gi_diff_moments,
+            gi_diff_stash,
+            gi_spec_samples,
+            gi_spec_reservoirs,
+            ref_hits,
+            ref_rays,
+            ref_colors,
+        } = self;
+
+        [
+            camera.allocated_size(),
+            prev_camera.allocated_size(),
+            atmosphere_transmittance_lut.allocated_size(),
+            atmosphere_scattering_lut.allocated_size(),
+            atmosphere_sky_lut.allocated_size(),
+            prim_depth.allocated_size(),
+            prim_gbuffer_d0.allocated_size(),
+            prim_gbuffer_d1.allocated_size(),
+            prim_surface_map.allocated_size(),
+            reprojection_map.allocated_size(),
+            velocity_map.allocated_size(),
+            di_prev_reservoirs.allocated_size(),
+            di_curr_reservoirs.allocated_size(),
+            di_next_reservoirs.allocated_size(),
+            di_diff_samples.allocated_size(),
+            di_diff_prev_colors.allocated_size(),
+            di_diff_curr_colors.allocated_size(),
+            di_diff_moments.allocated_size(),
+            di_diff_stash.allocated_size(),
+            gi_rays.allocated_size(),
+            gi_gbuffer_d0.allocated_size(),
+            gi_gbuffer_d1.allocated_size(),
+            gi_samples.allocated_size(),
+            gi_diff_temporal_reservoirs.allocated_size(),
+            gi_diff_spatial_reservoirs_a.allocated_size(),
+            gi_diff_spatial_reservoirs_b.allocated_size(),
+            gi_diff_samples.allocated_size(),
+            gi_diff_prev_colors.allocated_size(),
+            gi_diff_curr_colors.allocated_size(),
+            gi_diff_moments.allocated_size(),
+            gi_diff_stash.allocated_size(),
+            gi_spec_samples.allocated_size(),
+            gi_spec_reservoirs.allocated_size(),
+            ref_hits.allocated_size(),
+            ref_rays.allocated_size(),
+            ref_colors.allocated_size(),
+        ]
+        .into_iter()
+        .sum()
+    }
 }
@@ -39,27 +39,30 @@ where
     ) {
         let label = format!("strolle_{}_pass", self.label);
 
-        let mut pass =
-            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some(&label),
-            });
-
-        pass.set_pipeline(&self.pipeline);
-
-        if mem::size_of::<P>() > 0 {
-            pass.set_push_constants(0, bytemuck::bytes_of(&params));
-        }
-
-        for (bind_group_idx, bind_group) in self.bind_groups.iter().enumerate()
-        {
-            pass.set_bind_group(
-                bind_group_idx as u32,
-                bind_group.get(camera.is_alternate()),
-                &[],
-            );
-        }
-
-        pass.dispatch_workgroups(size.x, size.y, 1);
+        camera.profile(encoder, &self.label, |encoder| {
+            let mut pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some(&label),
+                });
+
+            pass.set_pipeline(&self.pipeline);
+
+            if mem::size_of::<P>() > 0 {
+                pass.set_push_constants(0, bytemuck::bytes_of(&params));
+            }
+
+            for (bind_group_idx, bind_group) in
+                self.bind_groups.iter().enumerate()
+            {
+                pass.set_bind_group(
+                    bind_group_idx as u32,
+                    bind_group.get(camera.is_alternate()),
+                    &[],
+                );
+            }
+
+            pass.dispatch_workgroups(size.x, size.y, 1);
+        });
     }
 }
 
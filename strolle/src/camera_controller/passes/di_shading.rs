@@ -27,6 +27,7 @@ impl DiShadingPass {
                 &engine.lights.bind_readable(),
                 &engine.images.bind_atlas(),
                 &engine.world.bind_readable(),
+                &engine.lights.bind_tree(),
             ])
             .bind([
                 &buffers.camera.bind_readable(),
@@ -192,8 +192,14 @@ impl PrimRasterPass {
         pass.set_pipeline(&self.pipeline);
         pass.set_bind_group(0, self.bg0.get(alternate), &[]);
         pass.set_bind_group(1, self.bg1.get(alternate), &[]);
+        pass.set_vertex_buffer(0, engine.triangles.vertex_buffer());
 
-        for (instance_handle, instance_entry) in engine.instances.iter() {
+        pass.set_index_buffer(
+            engine.triangles.index_buffer(),
+            wgpu::IndexFormat::Uint32,
+        );
+
+        for (_, instance_entry) in engine.instances.iter() {
             let instance = &instance_entry.instance;
 
             let Some(material_id) =
@@ -203,45 +209,51 @@ impl PrimRasterPass {
             };
 
             let params = {
-                let curr_xform_inv = gpu::PrimRasterPassParams::encode_affine(
-                    instance.transform_inverse,
+                let curr_xform = gpu::PrimRasterPassParams::encode_affine(
+                    instance.transform,
                 );
 
                 let prev_xform = gpu::PrimRasterPassParams::encode_affine(
                     instance_entry.prev_transform,
                 );
 
+                let normal_xform = instance_entry.normal_xform;
+
                 gpu::PrimRasterPassParams {
                     payload: vec4(
                         f32::from_bits(instance_entry.uuid),
                         f32::from_bits(material_id.get()),
-                        Default::default(),
-                        Default::default(),
+                        f32::from_bits(normal_xform[0]),
+                        f32::from_bits(normal_xform[1]),
                     ),
-                    curr_xform_inv_d0: curr_xform_inv[0],
-                    curr_xform_inv_d1: curr_xform_inv[1],
-                    curr_xform_inv_d2: curr_xform_inv[2],
+                    curr_xform_d0: curr_xform[0],
+                    curr_xform_d1: curr_xform[1],
+                    curr_xform_d2: curr_xform[2],
                     prev_xform_d0: prev_xform[0],
                     prev_xform_d1: prev_xform[1],
                     prev_xform_d2: prev_xform[2],
+                    normal_xform: vec4(
+                        f32::from_bits(normal_xform[2]),
+                        f32::from_bits(normal_xform[3]),
+                        f32::from_bits(normal_xform[4]),
+                        Default::default(),
+                    ),
                 }
             };
 
-            let Some((vertices, vertex_buffer)) =
-                engine.triangles.as_vertex_buffer(instance_handle)
+            let Some((indices, base_vertex)) =
+                engine.triangles.draw_range(&instance.mesh_handle)
             else {
                 continue;
             };
 
-            pass.set_vertex_buffer(0, vertex_buffer);
-
             pass.set_push_constants(
                 wgpu::ShaderStages::VERTEX_FRAGMENT,
                 0,
                 bytemuck::bytes_of(&params),
             );
 
-            pass.draw(0..(vertices as u32), 0..1);
+            pass.draw_indexed(indices, base_vertex, 0..1);
         }
     }
 }
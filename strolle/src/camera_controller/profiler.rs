@@ -0,0 +1,241 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+/// GPU timestamps written around camera's passes; see
+/// [`crate::Engine::camera_pass_timings()`].
+///
+/// Timestamps have to be resolved into a buffer and read back, which happens
+/// asynchronously - so the timings lag a frame or two behind and, since a new
+/// frame is profiled only after the previous one has been read back, they are
+/// refreshed every few frames instead of on every frame.
+#[derive(Debug)]
+pub struct CameraProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    state: CameraProfilerState,
+    timings: Vec<(String, Duration)>,
+}
+
+impl CameraProfiler {
+    /// Maximum number of passes profiled per frame; passes past this limit
+    /// (e.g. when rendering in the reference mode with a large depth) are
+    /// skipped.
+    const MAX_PASSES: u32 = 64;
+
+    /// Creates the profiler, if the device supports timestamp queries.
+    pub fn new(device: &wgpu::Device) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            debug!(
+                "Device doesn't support timestamp queries, pass timings won't \
+                 be available"
+            );
+
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("strolle_profiler"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2 * Self::MAX_PASSES,
+        });
+
+        let size = (2 * Self::MAX_PASSES as usize * mem::size_of::<u64>()) as _;
+
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("strolle_profiler_resolve"),
+            usage: wgpu::BufferUsages::QUERY_RESOLVE
+                | wgpu::BufferUsages::COPY_SRC,
+            size,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("strolle_profiler_readback"),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            size,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            state: CameraProfilerState::Idle,
+            timings: Default::default(),
+        })
+    }
+
+    /// Starts profiling a frame, unless the previous one is still being read
+    /// back.
+    pub fn begin_frame(&mut self) {
+        if let CameraProfilerState::Idle = self.state {
+            self.state = CameraProfilerState::Recording {
+                labels: Default::default(),
+            };
+        }
+    }
+
+    /// Writes timestamp marking the beginning of a pass; returns whether the
+    /// pass is being profiled, in which case [`Self::end_pass()`] must be
+    /// called after the pass.
+    pub fn begin_pass(&mut self, encoder: &mut wgpu::CommandEncoder) -> bool {
+        let CameraProfilerState::Recording { labels } = &self.state else {
+            return false;
+        };
+
+        let pass_idx = labels.len() as u32;
+
+        if pass_idx >= Self::MAX_PASSES {
+            return false;
+        }
+
+        encoder.write_timestamp(&self.query_set, 2 * pass_idx);
+
+        true
+    }
+
+    pub fn end_pass(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+    ) {
+        let CameraProfilerState::Recording { labels } = &mut self.state else {
+            unreachable!();
+        };
+
+        let pass_idx = labels.len() as u32;
+
+        encoder.write_timestamp(&self.query_set, 2 * pass_idx + 1);
+        labels.push(label.to_owned());
+    }
+
+    /// Copies timestamps of the current frame into the readback buffer.
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let CameraProfilerState::Recording { labels } = &mut self.state else {
+            return;
+        };
+
+        if labels.is_empty() {
+            self.state = CameraProfilerState::Idle;
+            return;
+        }
+
+        let query_count = 2 * labels.len() as u32;
+        let size = (query_count as usize * mem::size_of::<u64>()) as _;
+
+        encoder.resolve_query_set(
+            &self.query_set,
+            0..query_count,
+            &self.resolve_buffer,
+            0,
+        );
+
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            size,
+        );
+
+        self.state = CameraProfilerState::Recorded {
+            labels: mem::take(labels),
+        };
+    }
+
+    /// Advances the readback: starts mapping the buffer if a frame has been
+    /// recorded (and so, by now, submitted) or reads the timings if the
+    /// mapping has finished.
+    pub fn poll(&mut self, queue: &wgpu::Queue) {
+        match mem::replace(&mut self.state, CameraProfilerState::Idle) {
+            CameraProfilerState::Recorded { labels } => {
+                let result = Arc::new(Mutex::new(None));
+
+                self.readback_buffer.slice(..).map_async(
+                    wgpu::MapMode::Read,
+                    {
+                        let result = result.clone();
+
+                        move |mapped| {
+                            *result.lock().unwrap() = Some(mapped);
+                        }
+                    },
+                );
+
+                self.state = CameraProfilerState::Mapping { labels, result };
+            }
+
+            CameraProfilerState::Mapping { labels, result } => {
+                let mapped = result.lock().unwrap().take();
+
+                match mapped {
+                    Some(Ok(())) => {
+                        self.read(labels, queue.get_timestamp_period());
+                    }
+
+                    Some(Err(err)) => {
+                        warn!("Couldn't read pass timings back: {err}");
+                    }
+
+                    None => {
+                        self.state =
+                            CameraProfilerState::Mapping { labels, result };
+                    }
+                }
+            }
+
+            state => {
+                self.state = state;
+            }
+        }
+    }
+
+    pub fn timings(&self) -> &[(String, Duration)] {
+        &self.timings
+    }
+
+    /// Reads timings from the mapped buffer; `period` is the number of
+    /// nanoseconds per timestamp's tick.
+    fn read(&mut self, labels: Vec<String>, period: f32) {
+        {
+            let mapped = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&mapped);
+
+            self.timings = labels
+                .into_iter()
+                .zip(timestamps.chunks_exact(2))
+                .map(|(label, timestamps)| {
+                    let ticks = timestamps[1].saturating_sub(timestamps[0]);
+                    let nanos = (ticks as f64) * (period as f64);
+
+                    (label, Duration::from_nanos(nanos as u64))
+                })
+                .collect();
+        }
+
+        self.readback_buffer.unmap();
+    }
+}
+
+#[derive(Debug)]
+enum CameraProfilerState {
+    /// Waiting for the next frame
+    Idle,
+
+    /// Writing timestamps of the current frame
+    Recording { labels: Vec<String> },
+
+    /// Frame has been recorded, but probably not submitted yet
+    Recorded { labels: Vec<String> },
+
+    /// Frame has been submitted and we're waiting for the GPU to map the
+    /// buffer
+    Mapping {
+        labels: Vec<String>,
+        result: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+    },
+}
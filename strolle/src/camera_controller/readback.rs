@@ -0,0 +1,232 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use log::debug;
+use spirv_std::glam::UVec2;
+
+use crate::{Camera, CameraImage, Error, Result, Texture};
+
+/// Intermediate texture used to read camera's image back into RAM.
+///
+/// This is allocated lazily, the first time someone asks for the camera's image
+/// (see [`crate::Engine::render_camera_to_image()`]).
+///
+/// Each readback gets its own host-visible buffer (see [`Self::copy()`]), so
+/// that starting a new readback while the previous one is still pending (or
+/// got dropped without being awaited) doesn't try to map the same buffer
+/// twice.
+#[derive(Debug)]
+pub struct CameraReadback {
+    texture: Texture,
+    format: wgpu::TextureFormat,
+    position: UVec2,
+    size: UVec2,
+    bytes_per_pixel: u32,
+    padded_bytes_per_row: u32,
+}
+
+impl CameraReadback {
+    pub fn new(device: &wgpu::Device, camera: &Camera) -> Self {
+        let format = camera.viewport.format;
+        let position = camera.viewport.position;
+        let size = camera.viewport.size;
+
+        assert!(
+            CameraImage::supports(format),
+            "camera `{camera}` uses a format that cannot be read back"
+        );
+
+        debug!("Initializing readback for camera `{camera}`");
+
+        let bytes_per_pixel = format.block_size(None).unwrap();
+
+        let padded_bytes_per_row = wgpu::util::align_to(
+            size.x * bytes_per_pixel,
+            wgpu::COPY_BYTES_PER_ROW_ALIGNMENT,
+        );
+
+        // Frame composition writes into the viewport's rectangle (i.e. it
+        // honors `viewport.position`), so our texture must be large enough to
+        // fit the entire rectangle; we copy just the rectangle later, though
+        let texture = Texture::builder("readback")
+            .with_size(position + size)
+            .with_format(format)
+            .with_usage(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            .with_usage(wgpu::TextureUsages::COPY_SRC)
+            .build(device);
+
+        Self {
+            texture,
+            format,
+            position,
+            size,
+            bytes_per_pixel,
+            padded_bytes_per_row,
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        self.texture.view()
+    }
+
+    /// Schedules copying the texture into a freshly allocated buffer and
+    /// returns that buffer.
+    pub fn copy(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> wgpu::Buffer {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("strolle_readback"),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            size: (self.padded_bytes_per_row * self.size.y) as _,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: self.texture.tex(),
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: self.position.x,
+                    y: self.position.y,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: self.size.x,
+                height: self.size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        buffer
+    }
+
+    /// Starts mapping the buffer returned from [`Self::copy()`]; must be called
+    /// after the commands issued by [`Self::copy()`] have been submitted.
+    pub fn map(&self, buffer: wgpu::Buffer) -> CameraReadbackJob {
+        let state = Arc::new(Mutex::new(CameraReadbackState::default()));
+
+        buffer.slice(..).map_async(wgpu::MapMode::Read, {
+            let state = state.clone();
+
+            move |result| {
+                let mut state = state.lock().unwrap();
+
+                state.result = Some(result);
+
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+
+        CameraReadbackJob {
+            buffer,
+            format: self.format,
+            size: self.size,
+            bytes_per_pixel: self.bytes_per_pixel,
+            padded_bytes_per_row: self.padded_bytes_per_row,
+            state,
+        }
+    }
+}
+
+/// Pending readback; resolves into [`CameraImage`] once the GPU is done.
+///
+/// Dropping the job without awaiting it is fine - the buffer gets unmapped (if
+/// the mapping has already finished) and released.
+#[derive(Debug)]
+pub struct CameraReadbackJob {
+    buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    size: UVec2,
+    bytes_per_pixel: u32,
+    padded_bytes_per_row: u32,
+    state: Arc<Mutex<CameraReadbackState>>,
+}
+
+impl CameraReadbackJob {
+    /// Blocks until the GPU is done and returns the image.
+    pub fn wait(self, device: &wgpu::Device) -> Result<CameraImage> {
+        device.poll(wgpu::Maintain::Wait);
+
+        self.try_finish().unwrap_or(Err(Error::ReadbackNotMapped))
+    }
+
+    fn try_finish(&self) -> Option<Result<CameraImage>> {
+        let result = self.state.lock().unwrap().result.take()?;
+
+        if let Err(err) = result {
+            return Some(Err(Error::ReadbackFailed(err)));
+        }
+
+        let row_size = (self.size.x * self.bytes_per_pixel) as usize;
+        let mut data = Vec::with_capacity(row_size * (self.size.y as usize));
+
+        {
+            let mapped = self.buffer.slice(..).get_mapped_range();
+
+            // Rows in the buffer are padded to `COPY_BYTES_PER_ROW_ALIGNMENT`,
+            // so we have to strip the padding before building the image
+            for row in mapped.chunks(self.padded_bytes_per_row as usize) {
+                data.extend_from_slice(&row[..row_size]);
+            }
+        }
+
+        self.buffer.unmap();
+
+        Some(Ok(CameraImage::from_raw(self.format, self.size, data)))
+    }
+}
+
+impl Future for CameraReadbackJob {
+    type Output = Result<CameraImage>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(image) = self.try_finish() {
+            return Poll::Ready(image);
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        // Mapping could've finished in-between `try_finish()` and us locking
+        // the state again - if that's the case, let's just try again
+        if state.result.is_some() {
+            cx.waker().wake_by_ref();
+        } else {
+            state.waker = Some(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for CameraReadbackJob {
+    fn drop(&mut self) {
+        // If the mapping has finished but nobody has read it, unmap the buffer
+        // before releasing it; if the mapping is still in flight, dropping the
+        // buffer cancels it
+        if let Some(Ok(())) = self.state.lock().unwrap().result.take() {
+            self.buffer.unmap();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CameraReadbackState {
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<Waker>,
+}
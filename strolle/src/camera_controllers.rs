@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::{CameraController, CameraHandle};
+use crate::{CameraController, CameraHandle, Error, Result};
 
 #[derive(Debug, Default)]
 pub struct CameraControllers {
@@ -18,19 +18,30 @@ impl CameraControllers {
         handle
     }
 
-    pub fn get(&self, camera_handle: CameraHandle) -> &CameraController {
-        self.cameras.get(&camera_handle).unwrap_or_else(|| {
-            panic!("camera does not exist: {:?}", camera_handle)
-        })
+    pub fn get(
+        &self,
+        camera_handle: CameraHandle,
+    ) -> Result<&CameraController> {
+        self.cameras
+            .get(&camera_handle)
+            .ok_or(Error::UnknownCamera(camera_handle))
     }
 
     pub fn get_mut(
         &mut self,
         camera_handle: CameraHandle,
-    ) -> &mut CameraController {
-        self.cameras.get_mut(&camera_handle).unwrap_or_else(|| {
-            panic!("camera does not exist: {:?}", camera_handle)
-        })
+    ) -> Result<&mut CameraController> {
+        self.cameras
+            .get_mut(&camera_handle)
+            .ok_or(Error::UnknownCamera(camera_handle))
+    }
+
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (CameraHandle, &CameraController)> + '_ {
+        self.cameras
+            .iter()
+            .map(|(camera_handle, camera)| (*camera_handle, camera))
     }
 
     pub fn iter_mut(
@@ -39,7 +50,10 @@ impl CameraControllers {
         self.cameras.values_mut()
     }
 
-    pub fn remove(&mut self, camera_handle: CameraHandle) {
-        self.cameras.remove(&camera_handle);
+    pub fn remove(&mut self, camera_handle: CameraHandle) -> Result<()> {
+        self.cameras
+            .remove(&camera_handle)
+            .map(drop)
+            .ok_or(Error::UnknownCamera(camera_handle))
     }
 }
@@ -0,0 +1,183 @@
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
+use spirv_std::glam::{uvec2, UVec2};
+
+/// Image read back from a camera; see [`crate::Engine::render_camera_to_image()`].
+#[derive(Clone, Debug)]
+pub enum CameraImage {
+    /// Image rendered into an 8-bit viewport, e.g. `Rgba8UnormSrgb`.
+    ///
+    /// Note that BGRA viewports are converted into RGBA as well.
+    Rgba8(RgbaImage),
+
+    /// Image rendered into a floating-point viewport, e.g. `Rgba16Float`.
+    Rgba32F(Rgba32FImage),
+}
+
+impl CameraImage {
+    /// Returns whether given viewport's texture format can be read back.
+    pub(crate) fn supports(format: wgpu::TextureFormat) -> bool {
+        use wgpu::TextureFormat as F;
+
+        matches!(
+            format,
+            F::Rgba8Unorm
+                | F::Rgba8UnormSrgb
+                | F::Bgra8Unorm
+                | F::Bgra8UnormSrgb
+                | F::Rgba16Float
+                | F::Rgba32Float
+        )
+    }
+
+    /// Converts tightly-packed texels (i.e. without any row padding) into an
+    /// image.
+    pub(crate) fn from_raw(
+        format: wgpu::TextureFormat,
+        size: UVec2,
+        data: Vec<u8>,
+    ) -> Self {
+        use wgpu::TextureFormat as F;
+
+        let image = match format {
+            F::Rgba8Unorm | F::Rgba8UnormSrgb => {
+                RgbaImage::from_raw(size.x, size.y, data).map(Self::Rgba8)
+            }
+
+            F::Bgra8Unorm | F::Bgra8UnormSrgb => {
+                let mut data = data;
+
+                for texel in data.chunks_exact_mut(4) {
+                    texel.swap(0, 2);
+                }
+
+                RgbaImage::from_raw(size.x, size.y, data).map(Self::Rgba8)
+            }
+
+            F::Rgba16Float => {
+                let data = data
+                    .chunks_exact(2)
+                    .map(|bytes| {
+                        f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]]))
+                    })
+                    .collect();
+
+                Rgba32FImage::from_raw(size.x, size.y, data).map(Self::Rgba32F)
+            }
+
+            F::Rgba32Float => {
+                let data = data
+                    .chunks_exact(4)
+                    .map(|bytes| {
+                        f32::from_le_bytes([
+                            bytes[0], bytes[1], bytes[2], bytes[3],
+                        ])
+                    })
+                    .collect();
+
+                Rgba32FImage::from_raw(size.x, size.y, data).map(Self::Rgba32F)
+            }
+
+            format => {
+                panic!("unsupported viewport format: {format:?}");
+            }
+        };
+
+        image.expect("readback buffer is smaller than the viewport")
+    }
+
+    pub fn size(&self) -> UVec2 {
+        match self {
+            CameraImage::Rgba8(image) => uvec2(image.width(), image.height()),
+            CameraImage::Rgba32F(image) => uvec2(image.width(), image.height()),
+        }
+    }
+
+    /// Converts this image into an 8-bit one, e.g. so that it can be saved as
+    /// a PNG.
+    ///
+    /// Floating-point images are clamped into `0.0..=1.0` - no tonemapping is
+    /// performed.
+    pub fn to_rgba8(&self) -> RgbaImage {
+        match self {
+            CameraImage::Rgba8(image) => image.clone(),
+            CameraImage::Rgba32F(image) => {
+                DynamicImage::ImageRgba32F(image.clone()).to_rgba8()
+            }
+        }
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits >> 15) as u32) << 31;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+
+    match (exponent, mantissa) {
+        (0, 0) => f32::from_bits(sign),
+
+        // Subnormal numbers
+        (0, _) => {
+            let value = (mantissa as f32) * 2.0f32.powi(-24);
+
+            if sign == 0 {
+                value
+            } else {
+                -value
+            }
+        }
+
+        // Infinities and NaNs
+        (0x1f, _) => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f16() {
+        assert_eq!(0.0, f16_to_f32(0x0000));
+        assert_eq!(1.0, f16_to_f32(0x3c00));
+        assert_eq!(-2.0, f16_to_f32(0xc000));
+        assert_eq!(0.5, f16_to_f32(0x3800));
+        assert_eq!(65504.0, f16_to_f32(0x7bff));
+        assert_eq!(2.0f32.powi(-24), f16_to_f32(0x0001));
+        assert_eq!(f32::INFINITY, f16_to_f32(0x7c00));
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn from_raw() {
+        let target = CameraImage::from_raw(
+            wgpu::TextureFormat::Bgra8UnormSrgb,
+            uvec2(2, 1),
+            vec![1, 2, 3, 4, 5, 6, 7, 8],
+        );
+
+        let CameraImage::Rgba8(target) = target else {
+            panic!();
+        };
+
+        assert_eq!(&[3, 2, 1, 4, 7, 6, 5, 8], target.as_raw().as_slice());
+
+        // ---
+
+        let target = CameraImage::from_raw(
+            wgpu::TextureFormat::Rgba16Float,
+            uvec2(1, 1),
+            [0x3c00u16, 0x3800, 0x0000, 0xc000]
+                .into_iter()
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+        );
+
+        let CameraImage::Rgba32F(target) = target else {
+            panic!();
+        };
+
+        assert_eq!(&[1.0, 0.5, 0.0, -2.0], target.as_raw().as_slice());
+    }
+}
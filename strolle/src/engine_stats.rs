@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use crate::CameraHandle;
+
+/// Statistics describing the engine's state, as of the last
+/// [`crate::Engine::tick()`]; see [`crate::Engine::stats()`].
+#[derive(Clone, Debug, Default)]
+pub struct EngineStats {
+    pub meshes: usize,
+    pub instances: usize,
+
+    /// Number of triangles, across all meshes
+    pub triangles: usize,
+
+    /// Number of BVH nodes, across all trees
+    pub bvh_nodes: usize,
+
+    pub materials: usize,
+    pub images: usize,
+
+    /// Number of lights, not counting the sun
+    pub lights: usize,
+
+    /// Number of emissive triangles sampled as lights, across all instances
+    pub triangle_lights: usize,
+
+    pub cameras: usize,
+
+    /// Size of buffers keeping triangles, vertices and indices, in bytes
+    pub triangles_size: usize,
+
+    /// Size of the buffer keeping serialized BVH, in bytes
+    pub bvh_size: usize,
+
+    /// Size of the buffer keeping materials, in bytes
+    pub materials_size: usize,
+
+    /// Size of the buffer keeping lights, in bytes
+    pub lights_size: usize,
+
+    /// Size of the texture atlas, in bytes
+    pub atlas_size: usize,
+
+    /// Which part of the texture atlas is occupied by images, from `0.0`
+    /// (empty) to `1.0` (full)
+    pub atlas_usage: f32,
+
+    /// Size of each camera's buffers (textures, reservoirs etc.), in bytes
+    pub camera_sizes: Vec<(CameraHandle, usize)>,
+
+    /// CPU time spent in particular parts of the last tick, in the order they
+    /// finished; nested parts (e.g. `tick.bvh.build`) are reported both on
+    /// their own and as a part of their parent (e.g. `tick.bvh`), and the
+    /// entire tick is reported as `tick`
+    pub timings: Vec<(&'static str, Duration)>,
+}
+
+impl EngineStats {
+    /// Returns the total size of buffers allocated by the engine, in bytes.
+    pub fn total_size(&self) -> usize {
+        self.triangles_size
+            + self.bvh_size
+            + self.materials_size
+            + self.lights_size
+            + self.atlas_size
+            + self
+                .camera_sizes
+                .iter()
+                .map(|(_, size)| size)
+                .sum::<usize>()
+    }
+
+    /// Returns how much time given part of the last tick took, if it was
+    /// measured.
+    pub fn timing(&self, name: &str) -> Option<Duration> {
+        self.timings
+            .iter()
+            .find(|(name2, _)| *name2 == name)
+            .map(|(_, timing)| *timing)
+    }
+}
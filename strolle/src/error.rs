@@ -0,0 +1,105 @@
+use std::{error, fmt, result};
+
+use crate::CameraHandle;
+
+pub type Result<T, E = Error> = result::Result<T, E>;
+
+/// Error returned by the fallible (`try_*`) functions of [`crate::Engine`].
+///
+/// Handles of meshes, materials etc. are kept in their debug representation,
+/// so that the error doesn't depend on [`crate::Params`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Mesh contains no valid triangles (see [`crate::Mesh::validate()`]).
+    EmptyMesh(String),
+
+    UnknownMesh(String),
+    UnknownMaterial(String),
+    UnknownImage(String),
+    UnknownInstance(String),
+    UnknownLight(String),
+    UnknownCamera(CameraHandle),
+
+    /// Instance's transform contains NaNs or infinities or cannot be
+    /// inverted (e.g. because it has zero scale).
+    InvalidTransform(String),
+
+    /// Material's parameter is NaN or out of its range (e.g. roughness larger
+    /// than one).
+    InvalidMaterial(String, &'static str),
+
+    /// Light's parameter is NaN or out of its range (e.g. negative radius).
+    InvalidLight(String, &'static str),
+
+    /// There's no more space for the image in the texture atlas.
+    AtlasFull(String),
+
+    /// Camera's viewport uses a format that cannot be read back, see
+    /// [`crate::Engine::render_camera_to_image()`].
+    UnsupportedReadbackFormat(wgpu::TextureFormat),
+
+    /// GPU couldn't map the buffer used to read camera's image back.
+    ReadbackFailed(wgpu::BufferAsyncError),
+
+    /// GPU didn't map the buffer used to read camera's image back, even
+    /// though we've waited for it (e.g. because the device has been lost).
+    ReadbackNotMapped,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::EmptyMesh(handle) => {
+                write!(f, "mesh {handle} contains no valid triangles")
+            }
+            Error::UnknownMesh(handle) => {
+                write!(f, "mesh {handle} does not exist")
+            }
+            Error::UnknownMaterial(handle) => {
+                write!(f, "material {handle} does not exist")
+            }
+            Error::UnknownImage(handle) => {
+                write!(f, "image {handle} does not exist")
+            }
+            Error::UnknownInstance(handle) => {
+                write!(f, "instance {handle} does not exist")
+            }
+            Error::UnknownLight(handle) => {
+                write!(f, "light {handle} does not exist")
+            }
+            Error::UnknownCamera(handle) => {
+                write!(f, "camera {handle:?} does not exist")
+            }
+            Error::InvalidTransform(handle) => {
+                write!(f, "instance {handle} has invalid transform")
+            }
+            Error::InvalidMaterial(handle, parameter) => {
+                write!(f, "material {handle} has invalid {parameter}")
+            }
+            Error::InvalidLight(handle, parameter) => {
+                write!(f, "light {handle} has invalid {parameter}")
+            }
+            Error::AtlasFull(handle) => {
+                write!(f, "no more space in the atlas for image {handle}")
+            }
+            Error::UnsupportedReadbackFormat(format) => {
+                write!(f, "format {format:?} cannot be read back")
+            }
+            Error::ReadbackFailed(err) => {
+                write!(f, "couldn't map readback buffer: {err}")
+            }
+            Error::ReadbackNotMapped => {
+                write!(f, "readback buffer hasn't been mapped")
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::ReadbackFailed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
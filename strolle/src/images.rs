@@ -4,9 +4,8 @@ use std::mem;
 use derivative::Derivative;
 use glam::{uvec2, vec4, Vec4};
 use guillotiere::{size2, Allocation, AtlasAllocator};
-use log::warn;
 
-use crate::{Bindable, Image, ImageData, Params, Texture};
+use crate::{Bindable, Error, Image, ImageData, Params, Result, Texture};
 
 #[derive(Derivative)]
 #[derivative(Debug)]
@@ -51,7 +50,14 @@ where
         }
     }
 
-    pub fn insert(&mut self, image_handle: P::ImageHandle, image: Image<P>) {
+    /// Creates or updates given image; returns an error if there's no more
+    /// space for it in the atlas, in which case the previous version of the
+    /// image (if any) is removed.
+    pub fn insert(
+        &mut self,
+        image_handle: P::ImageHandle,
+        image: Image<P>,
+    ) -> Result<()> {
         let image_size = size2(
             image.texture_descriptor.size.width as i32,
             image.texture_descriptor.size.height as i32,
@@ -71,16 +77,27 @@ where
 
         let Some(image_alloc) = image_alloc else {
             // TODO allocate new atlas, up to 16 (Metal's limit)
-            warn!(
-                "Cannot add image `{:?}` - no more space in the atlas",
-                image_handle
-            );
-            return;
+            self.images.remove(&image_handle);
+
+            return Err(Error::AtlasFull(format!("{image_handle:?}")));
         };
 
         self.images.insert(image_handle, image_alloc);
 
-        match image.data {
+        let mut data = image.data;
+
+        // Atlas is sRGB, so linear images (e.g. normal maps) have to be
+        // encoded into sRGB for the sampler to decode them back into their
+        // original values
+        if let ImageData::Raw { data } = &mut data {
+            if image.texture_descriptor.format
+                == wgpu::TextureFormat::Rgba8Unorm
+            {
+                linear_to_srgb(data);
+            }
+        }
+
+        match data {
             data @ (ImageData::Raw { .. }
             | ImageData::Texture {
                 is_dynamic: false, ..
@@ -101,6 +118,8 @@ where
                 self.dynamic_textures.push((texture, image_alloc));
             }
         }
+
+        Ok(())
     }
 
     pub fn remove(&mut self, image_handle: &P::ImageHandle) {
@@ -111,6 +130,28 @@ where
         self.atlas.deallocate(image_alloc.id);
     }
 
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    /// Returns which part of the atlas is occupied by images, from `0.0`
+    /// (empty) to `1.0` (full).
+    pub fn atlas_usage(&self) -> f32 {
+        let used_area: i32 = self
+            .images
+            .values()
+            .map(|alloc| alloc.rectangle.area())
+            .sum();
+
+        (used_area as f32)
+            / ((Self::ATLAS_WIDTH as f32) * (Self::ATLAS_HEIGHT as f32))
+    }
+
+    /// Returns how many bytes the atlas occupies in VRAM.
+    pub fn allocated_size(&self) -> usize {
+        self.atlas_texture.allocated_size()
+    }
+
     pub fn lookup(&self, image_handle: &P::ImageHandle) -> Option<Vec4> {
         self.images.get(image_handle).map(|alloc| {
             vec4(
@@ -242,3 +283,41 @@ where
         data: ImageData<P>,
     },
 }
+
+/// Encodes RGBA8 texels (in place) from linear space into sRGB, leaving the
+/// alpha channel as-is.
+fn linear_to_srgb(data: &mut [u8]) {
+    let lut: Vec<u8> = (0..=255)
+        .map(|value| {
+            let value = (value as f32) / 255.0;
+
+            let value = if value <= 0.0031308 {
+                value * 12.92
+            } else {
+                1.055 * value.powf(1.0 / 2.4) - 0.055
+            };
+
+            (value * 255.0).round() as u8
+        })
+        .collect();
+
+    for texel in data.chunks_exact_mut(4) {
+        for channel in &mut texel[..3] {
+            *channel = lut[*channel as usize];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_to_srgb() {
+        let mut data = vec![0, 128, 255, 128, 1, 10, 64, 0];
+
+        super::linear_to_srgb(&mut data);
+
+        assert_eq!(vec![0, 188, 255, 128, 13, 56, 137, 0], data);
+    }
+}
@@ -29,4 +29,10 @@ where
             transform_inverse: transform.inverse(),
         }
     }
+
+    /// Returns whether instance's transform is finite and can be inverted.
+    pub(crate) fn has_valid_transform(&self) -> bool {
+        self.transform.is_finite()
+            && self.transform.matrix3.determinant() != 0.0
+    }
 }
@@ -1,5 +1,5 @@
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::mem;
 
 use derivative::Derivative;
@@ -8,9 +8,7 @@ use rand::Rng;
 
 use crate::bvh::Bvh;
 use crate::materials::Materials;
-use crate::meshes::Meshes;
-use crate::triangles::Triangles;
-use crate::{Instance, Params};
+use crate::{gpu, Instance, Params};
 
 #[derive(Debug, Derivative)]
 #[derivative(Default(bound = ""))]
@@ -36,6 +34,10 @@ where
                 let entry = entry.get_mut();
 
                 entry.prev_transform = entry.instance.transform;
+                entry.normal_xform =
+                    gpu::PrimRasterPassParams::encode_normal_xform(
+                        instance.transform,
+                    );
                 entry.instance = instance;
                 entry.dirty = true;
             }
@@ -43,6 +45,10 @@ where
             Entry::Vacant(entry) => {
                 entry.insert(InstanceEntry {
                     prev_transform: instance.transform,
+                    normal_xform:
+                        gpu::PrimRasterPassParams::encode_normal_xform(
+                            instance.transform,
+                        ),
                     uuid: rand::thread_rng().gen(),
                     dirty: true,
                     instance,
@@ -53,6 +59,15 @@ where
         self.dirty = true;
     }
 
+    pub fn get(
+        &self,
+        instance_handle: &P::InstanceHandle,
+    ) -> Option<&Instance<P>> {
+        self.instances
+            .get(instance_handle)
+            .map(|instance_entry| &instance_entry.instance)
+    }
+
     pub fn iter(
         &self,
     ) -> impl Iterator<Item = (&P::InstanceHandle, &InstanceEntry<P>)> + Clone + '_
@@ -68,80 +83,104 @@ where
         self.dirty |= self.instances.remove(instance_handle).is_some();
     }
 
+    /// Removes instances that refer to given mesh, returning their handles.
+    pub fn remove_using_mesh(
+        &mut self,
+        mesh_handle: &P::MeshHandle,
+    ) -> Vec<P::InstanceHandle> {
+        self.remove_where(|instance| instance.mesh_handle == *mesh_handle)
+    }
+
+    /// Removes instances that refer to given material, returning their
+    /// handles.
+    pub fn remove_using_material(
+        &mut self,
+        material_handle: &P::MaterialHandle,
+    ) -> Vec<P::InstanceHandle> {
+        self.remove_where(|instance| {
+            instance.material_handle == *material_handle
+        })
+    }
+
+    /// Removes instances matching given predicate, returning their handles.
+    fn remove_where(
+        &mut self,
+        mut predicate: impl FnMut(&Instance<P>) -> bool,
+    ) -> Vec<P::InstanceHandle> {
+        let instance_handles: Vec<_> = self
+            .instances
+            .iter()
+            .filter(|(_, entry)| predicate(&entry.instance))
+            .map(|(instance_handle, _)| instance_handle.clone())
+            .collect();
+
+        for instance_handle in &instance_handles {
+            self.remove(instance_handle);
+        }
+
+        instance_handles
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.instances.is_empty()
     }
 
+    /// Updates top-level tree with instances that have changed since the last
+    /// refresh (or whose meshes have changed); returns instances that have
+    /// been updated.
     pub fn refresh(
         &mut self,
-        meshes: &Meshes<P>,
+        changed_meshes: &HashSet<P::MeshHandle>,
         materials: &Materials<P>,
-        triangles: &mut Triangles<P>,
-        bvh: &mut Bvh,
-    ) -> bool {
-        if !mem::take(&mut self.dirty) {
-            return false;
+        bvh: &mut Bvh<P>,
+    ) -> Vec<P::InstanceHandle> {
+        if !mem::take(&mut self.dirty) && changed_meshes.is_empty() {
+            return Default::default();
         }
 
+        let mut changed_instances = Vec::new();
+
         for (instance_handle, entry) in &mut self.instances {
-            if !mem::take(&mut entry.dirty) {
+            let is_dirty = mem::take(&mut entry.dirty)
+                || changed_meshes.contains(&entry.instance.mesh_handle);
+
+            if !is_dirty {
                 continue;
             }
 
-            let Some(mesh) = meshes.get(&entry.instance.mesh_handle) else {
-                // If the mesh is not yet available, it might be still being
-                // loaded in the background - in that case let's try again next
-                // frame
-                entry.dirty = true;
-                self.dirty = true;
-                continue;
-            };
+            changed_instances.push(instance_handle.clone());
 
             let Some(material_id) =
                 materials.lookup(&entry.instance.material_handle)
             else {
-                // Same for materials
+                // If the material is not yet available, it might be still
+                // being loaded in the background - in that case let's try
+                // again next frame
+                bvh.remove_instance(instance_handle);
+
                 entry.dirty = true;
                 self.dirty = true;
                 continue;
             };
 
-            let mesh_triangles = mesh.triangles().iter().map(|triangle| {
-                triangle.build(
-                    entry.instance.transform,
-                    entry.instance.transform_inverse,
-                )
-            });
-
-            if let Some(count) = triangles.count(instance_handle) {
-                if mesh.triangles().len() == count {
-                    triangles.update(
-                        bvh,
-                        instance_handle,
-                        mesh_triangles,
-                        material_id,
-                    );
-                } else {
-                    triangles.remove(bvh, instance_handle);
-
-                    triangles.create(
-                        bvh,
-                        instance_handle.to_owned(),
-                        mesh_triangles,
-                        material_id,
-                    );
-                }
-            } else {
-                triangles.create(
-                    bvh,
-                    instance_handle.to_owned(),
-                    mesh_triangles,
-                    material_id,
-                );
+            let is_inserted = bvh.insert_instance(
+                instance_handle.to_owned(),
+                &entry.instance,
+                material_id,
+            );
+
+            if !is_inserted {
+                // Same for meshes
+                entry.dirty = true;
+                self.dirty = true;
             }
         }
 
-        true
+        changed_instances
     }
 }
 
@@ -153,5 +192,82 @@ where
     pub instance: Instance<P>,
     pub uuid: u32,
     pub prev_transform: Affine3A,
+
+    /// Encoded normal matrix of `instance.transform`, computed once here so
+    /// that the rasterizer doesn't have to invert the transformation for each
+    /// vertex
+    pub normal_xform: [u32; 5],
+
     pub dirty: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestParams;
+
+    impl Params for TestParams {
+        type ImageHandle = &'static str;
+        type ImageTexture = Arc<wgpu::Texture>;
+        type InstanceHandle = &'static str;
+        type LightHandle = &'static str;
+        type MaterialHandle = &'static str;
+        type MeshHandle = &'static str;
+    }
+
+    /// Creates instances `a` (mesh `x`, material `m`), `b` (mesh `y`,
+    /// material `m`) and `c` (mesh `x`, material `n`).
+    fn instances() -> Instances<TestParams> {
+        let mut instances = Instances::default();
+
+        for (instance_handle, mesh_handle, material_handle) in
+            [("a", "x", "m"), ("b", "y", "m"), ("c", "x", "n")]
+        {
+            instances.insert(
+                instance_handle,
+                Instance::new(mesh_handle, material_handle, Affine3A::IDENTITY),
+            );
+        }
+
+        instances
+    }
+
+    fn sorted(mut instance_handles: Vec<&'static str>) -> Vec<&'static str> {
+        instance_handles.sort();
+        instance_handles
+    }
+
+    #[test]
+    fn remove_using_mesh() {
+        let mut instances = instances();
+
+        assert_eq!(vec!["a", "c"], sorted(instances.remove_using_mesh(&"x")));
+
+        assert_eq!(1, instances.len());
+        assert!(instances.get(&"b").is_some());
+
+        assert!(instances.remove_using_mesh(&"x").is_empty());
+        assert!(instances.remove_using_mesh(&"z").is_empty());
+        assert_eq!(1, instances.len());
+    }
+
+    #[test]
+    fn remove_using_material() {
+        let mut instances = instances();
+
+        assert_eq!(
+            vec!["a", "b"],
+            sorted(instances.remove_using_material(&"m"))
+        );
+
+        assert_eq!(1, instances.len());
+        assert!(instances.get(&"c").is_some());
+
+        assert_eq!(vec!["c"], instances.remove_using_material(&"n"));
+        assert!(instances.is_empty());
+    }
+}
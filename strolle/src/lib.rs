@@ -48,21 +48,33 @@
 
 mod buffers;
 mod bvh;
+mod bvh_config;
+mod bvh_report;
+mod bvh_update_policy;
 mod camera;
 mod camera_controller;
 mod camera_controllers;
+mod camera_image;
+mod engine_stats;
+mod error;
 mod image;
 mod images;
 mod instance;
 mod instances;
 mod light;
+mod light_tree;
 mod lights;
+pub mod loaders;
 mod material;
 mod materials;
 mod mesh;
+mod mesh_preprocessing;
 mod mesh_triangle;
 mod meshes;
+mod metrics_sink;
 mod noise;
+mod ray_hit;
+mod scene_issue;
 mod shaders;
 mod sun;
 mod triangle;
@@ -70,32 +82,46 @@ mod triangles;
 mod utils;
 
 use std::fmt::Debug;
+use std::future::Future;
 use std::hash::Hash;
 use std::ops::Deref;
-use std::time::Instant;
-use std::{env, mem};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{env, io, mem};
 
 pub use glam;
-use log::{info, trace};
+use glam::Vec3;
+use log::{info, trace, warn};
 use strolle_gpu as gpu;
 
 pub(crate) use self::buffers::*;
 pub(crate) use self::bvh::*;
+pub use self::bvh_config::*;
+pub use self::bvh_report::*;
+pub use self::bvh_update_policy::*;
 pub use self::camera::*;
 pub(crate) use self::camera_controller::*;
 pub(crate) use self::camera_controllers::*;
+pub use self::camera_image::*;
+pub use self::engine_stats::*;
+pub use self::error::*;
 pub use self::image::*;
 pub(crate) use self::images::*;
 pub use self::instance::*;
 pub(crate) use self::instances::*;
 pub use self::light::*;
+pub(crate) use self::light_tree::*;
 pub(crate) use self::lights::*;
 pub use self::material::*;
 pub(crate) use self::materials::*;
 pub use self::mesh::*;
+pub use self::mesh_preprocessing::*;
 pub use self::mesh_triangle::*;
 pub(crate) use self::meshes::*;
+pub use self::metrics_sink::*;
 pub(crate) use self::noise::*;
+pub use self::ray_hit::*;
+pub use self::scene_issue::*;
 pub(crate) use self::shaders::*;
 pub use self::sun::*;
 pub(crate) use self::triangle::*;
@@ -112,14 +138,18 @@ where
     meshes: Meshes<P>,
     instances: Instances<P>,
     triangles: Triangles<P>,
-    bvh: Bvh,
+    bvh: Bvh<P>,
     lights: Lights<P>,
     images: Images<P>,
     materials: Materials<P>,
     world: MappedUniformBuffer<gpu::World>,
     cameras: CameraControllers,
     sun: Sun,
+    metrics: Metrics,
     frame: u32,
+    compaction_threshold: Option<f32>,
+    has_pending_compaction: bool,
+    cascading_removals: bool,
     has_dirty_materials: bool,
     has_dirty_images: bool,
     has_dirty_sun: bool,
@@ -133,6 +163,11 @@ where
     pub fn new(device: &wgpu::Device) -> Self {
         info!("Initializing");
 
+        let metrics = Metrics::default();
+
+        #[cfg(feature = "metrics")]
+        metrics.set_sink(Some(Box::new(LogMetricsSink::from_env())));
+
         Self {
             shaders: Shaders::new(device),
             noise: Noise::new(device),
@@ -150,7 +185,11 @@ where
             ),
             cameras: Default::default(),
             sun: Default::default(),
+            metrics,
             frame: 0,
+            compaction_threshold: Some(0.5),
+            has_pending_compaction: false,
+            cascading_removals: false,
             has_dirty_materials: false,
             has_dirty_images: false,
             has_dirty_sun: true,
@@ -159,16 +198,77 @@ where
     }
 
     /// Creates or updates a mesh.
+    ///
+    /// Invalid triangles (e.g. the ones with NaN positions) are dropped, see
+    /// [`Mesh::validate()`]; meshes without any valid triangles are ignored
+    /// (with a warning), leaving the previous version of the mesh (if any)
+    /// intact.
     pub fn insert_mesh(&mut self, mesh_handle: P::MeshHandle, mesh: Mesh) {
+        match self.try_insert_mesh(mesh_handle.clone(), mesh) {
+            Ok(report) => {
+                if !report.is_unchanged() {
+                    warn!(
+                        "Mesh {mesh_handle:?} contains invalid data: {report:?}"
+                    );
+                }
+            }
+
+            Err(err) => {
+                warn!("{err}");
+            }
+        }
+    }
+
+    /// Fallible version of [`Self::insert_mesh()`]; returns what has been
+    /// dropped from the mesh during validation.
+    ///
+    /// Meshes without any valid triangles are rejected, leaving the previous
+    /// version of the mesh (if any) intact.
+    pub fn try_insert_mesh(
+        &mut self,
+        mesh_handle: P::MeshHandle,
+        mut mesh: Mesh,
+    ) -> Result<MeshReport> {
+        let report = mesh.validate();
+
+        if mesh.triangle_count() == 0 {
+            return Err(Error::EmptyMesh(format!("{mesh_handle:?}")));
+        }
+
         self.meshes.insert(mesh_handle, mesh);
+
+        Ok(report)
     }
 
     /// Removes a mesh.
     ///
-    /// Note that removing a mesh doesn't automatically remove instances that
-    /// refer to this mesh.
+    /// Note that unless cascading removals are enabled (see
+    /// [`Self::set_cascading_removals()`]), removing a mesh doesn't remove
+    /// instances that refer to this mesh.
     pub fn remove_mesh(&mut self, mesh_handle: &P::MeshHandle) {
         self.meshes.remove(mesh_handle);
+
+        if self.cascading_removals {
+            let instance_handles =
+                self.instances.remove_using_mesh(mesh_handle);
+
+            self.forget_instances(&instance_handles);
+        }
+    }
+
+    /// Fallible version of [`Self::remove_mesh()`]; returns an error if the
+    /// mesh doesn't exist.
+    pub fn try_remove_mesh(
+        &mut self,
+        mesh_handle: &P::MeshHandle,
+    ) -> Result<()> {
+        if !self.meshes.contains(mesh_handle) {
+            return Err(Error::UnknownMesh(format!("{mesh_handle:?}")));
+        }
+
+        self.remove_mesh(mesh_handle);
+
+        Ok(())
     }
 
     /// Creates or updates a material.
@@ -181,6 +281,36 @@ where
         self.has_dirty_materials = true;
     }
 
+    /// Fallible version of [`Self::insert_material()`]; returns an error if
+    /// any of material's parameters is out of its range or if the material
+    /// refers to an image that doesn't exist.
+    ///
+    /// [`Self::insert_material()`] accepts such materials, so that e.g. images
+    /// can be loaded after the materials using them - this function is meant
+    /// for cases where everything is expected to be already in place.
+    pub fn try_insert_material(
+        &mut self,
+        material_handle: P::MaterialHandle,
+        material: Material<P>,
+    ) -> Result<()> {
+        if let Some(&parameter) = material.invalid_parameters().first() {
+            return Err(Error::InvalidMaterial(
+                format!("{material_handle:?}"),
+                parameter,
+            ));
+        }
+
+        for image_handle in material.textures() {
+            if self.images.lookup(image_handle).is_none() {
+                return Err(Error::UnknownImage(format!("{image_handle:?}")));
+            }
+        }
+
+        self.insert_material(material_handle, material);
+
+        Ok(())
+    }
+
     /// Returns whether given material exists.
     pub fn has_material(&self, material_handle: &P::MaterialHandle) -> bool {
         self.materials.has(material_handle)
@@ -188,30 +318,163 @@ where
 
     /// Removes a material.
     ///
-    /// Note that removing a material doesn't automatically remove instances
-    /// that refer to this material.
+    /// Note that unless cascading removals are enabled (see
+    /// [`Self::set_cascading_removals()`]), removing a material doesn't remove
+    /// instances that refer to this material.
     pub fn remove_material(&mut self, material_handle: &P::MaterialHandle) {
         self.materials.remove(material_handle);
         self.has_dirty_materials = true;
+
+        if self.cascading_removals {
+            let instance_handles =
+                self.instances.remove_using_material(material_handle);
+
+            self.forget_instances(&instance_handles);
+        }
+    }
+
+    /// Fallible version of [`Self::remove_material()`]; returns an error if
+    /// the material doesn't exist.
+    pub fn try_remove_material(
+        &mut self,
+        material_handle: &P::MaterialHandle,
+    ) -> Result<()> {
+        if !self.materials.has(material_handle) {
+            return Err(Error::UnknownMaterial(format!("{material_handle:?}")));
+        }
+
+        self.remove_material(material_handle);
+
+        Ok(())
     }
 
     /// Creates or updates an image.
+    ///
+    /// Images that don't fit into the texture atlas are ignored (with a
+    /// warning); see [`Self::try_insert_image()`].
     pub fn insert_image(
         &mut self,
         image_handle: P::ImageHandle,
         image: Image<P>,
     ) {
-        self.images.insert(image_handle, image);
+        if let Err(err) = self.try_insert_image(image_handle, image) {
+            warn!("{err}");
+        }
+    }
+
+    /// Fallible version of [`Self::insert_image()`]; returns an error if
+    /// there's no more space for the image in the texture atlas, in which case
+    /// the previous version of the image (if any) is removed.
+    pub fn try_insert_image(
+        &mut self,
+        image_handle: P::ImageHandle,
+        image: Image<P>,
+    ) -> Result<()> {
+        let result = self.images.insert(image_handle, image);
+
+        // Even if the image doesn't fit, its previous version might've been
+        // removed from the atlas, so materials have to be refreshed anyway
         self.has_dirty_images = true;
+
+        result
     }
 
     /// Removes an image.
     ///
-    /// Note that removing an image doesn't automatically remove materials that
-    /// refer to this image.
+    /// Note that unless cascading removals are enabled (see
+    /// [`Self::set_cascading_removals()`]), removing an image doesn't update
+    /// materials that refer to this image - they are rendered as if they
+    /// didn't have this texture, but they will pick the image up once it's
+    /// inserted again.
     pub fn remove_image(&mut self, image_handle: &P::ImageHandle) {
         self.images.remove(image_handle);
         self.has_dirty_images = true;
+
+        if self.cascading_removals {
+            self.materials.detach_image(image_handle);
+            self.has_dirty_materials = true;
+        }
+    }
+
+    /// Fallible version of [`Self::remove_image()`]; returns an error if the
+    /// image doesn't exist.
+    pub fn try_remove_image(
+        &mut self,
+        image_handle: &P::ImageHandle,
+    ) -> Result<()> {
+        if self.images.lookup(image_handle).is_none() {
+            return Err(Error::UnknownImage(format!("{image_handle:?}")));
+        }
+
+        self.remove_image(image_handle);
+
+        Ok(())
+    }
+
+    /// Enables (or disables) cascading removals:
+    ///
+    /// - removing a mesh or a material removes instances that refer to it,
+    /// - removing an image detaches it from materials that refer to it.
+    ///
+    /// By default this is disabled, in which case instances that refer to
+    /// missing meshes or materials are kept (but not rendered) until the
+    /// mesh or material gets inserted - this allows for e.g. meshes to be
+    /// loaded in the background, but makes it easy to leave dangling
+    /// references behind; see [`Self::validate()`].
+    pub fn set_cascading_removals(&mut self, enabled: bool) {
+        self.cascading_removals = enabled;
+    }
+
+    /// Returns problems found in the scene, such as instances that refer to
+    /// missing meshes or materials and lights with out-of-range parameters.
+    ///
+    /// This is meant for debugging - none of those problems is fatal, but
+    /// they usually cause objects to go missing or to look wrong.
+    pub fn validate(&self) -> Vec<SceneIssue<P>> {
+        let mut issues = Vec::new();
+
+        for (instance_handle, entry) in self.instances.iter() {
+            issues.extend(SceneIssue::check_instance(
+                instance_handle,
+                &entry.instance,
+                |mesh_handle| self.meshes.contains(mesh_handle),
+                |material_handle| self.materials.has(material_handle),
+            ));
+        }
+
+        for (material_handle, material) in self.materials.iter() {
+            issues.extend(SceneIssue::check_material(
+                material_handle,
+                material,
+                |image_handle| self.images.lookup(image_handle).is_some(),
+            ));
+        }
+
+        for (light_handle, light) in self.lights.iter() {
+            issues.extend(SceneIssue::check_light(light_handle, light));
+        }
+
+        issues
+    }
+
+    /// Compacts the triangle buffer during the next [`Self::tick()`], getting
+    /// rid of holes left by removed meshes and shrinking the buffer.
+    ///
+    /// Compaction happens automatically when the buffer gets fragmented
+    /// enough (see [`Self::set_compaction_threshold()`]), but it might be
+    /// worth triggering it manually e.g. after unloading a level.
+    pub fn compact(&mut self) {
+        self.has_pending_compaction = true;
+    }
+
+    /// Changes how much of the triangle buffer (from `0.0` to `1.0`) can be
+    /// wasted on holes left by removed meshes before the buffer gets
+    /// compacted automatically; `None` disables automatic compaction.
+    ///
+    /// Compaction requires re-uploading the entire buffer, so it's not free -
+    /// defaults to `0.5`.
+    pub fn set_compaction_threshold(&mut self, threshold: Option<f32>) {
+        self.compaction_threshold = threshold;
     }
 
     /// Creates or updates an instance.
@@ -223,10 +486,73 @@ where
         self.instances.insert(instance_handle, instance);
     }
 
+    /// Fallible version of [`Self::insert_instance()`]; returns an error if
+    /// instance's transform is invalid or if the instance refers to a mesh or
+    /// a material that doesn't exist.
+    ///
+    /// [`Self::insert_instance()`] accepts such instances (they are just not
+    /// rendered), so that e.g. meshes can be loaded in the background - this
+    /// function is meant for cases where everything is expected to be already
+    /// in place.
+    pub fn try_insert_instance(
+        &mut self,
+        instance_handle: P::InstanceHandle,
+        instance: Instance<P>,
+    ) -> Result<()> {
+        if !instance.has_valid_transform() {
+            return Err(Error::InvalidTransform(format!(
+                "{instance_handle:?}"
+            )));
+        }
+
+        if !self.meshes.contains(&instance.mesh_handle) {
+            return Err(Error::UnknownMesh(format!(
+                "{:?}",
+                instance.mesh_handle
+            )));
+        }
+
+        if !self.materials.has(&instance.material_handle) {
+            return Err(Error::UnknownMaterial(format!(
+                "{:?}",
+                instance.material_handle
+            )));
+        }
+
+        self.insert_instance(instance_handle, instance);
+
+        Ok(())
+    }
+
     /// Removes an instance.
     pub fn remove_instance(&mut self, instance_handle: &P::InstanceHandle) {
         self.instances.remove(instance_handle);
-        self.triangles.remove(&mut self.bvh, instance_handle);
+        self.bvh.remove_instance(instance_handle);
+        self.lights.remove_instance(instance_handle);
+    }
+
+    /// Fallible version of [`Self::remove_instance()`]; returns an error if
+    /// the instance doesn't exist.
+    pub fn try_remove_instance(
+        &mut self,
+        instance_handle: &P::InstanceHandle,
+    ) -> Result<()> {
+        if self.instances.get(instance_handle).is_none() {
+            return Err(Error::UnknownInstance(format!("{instance_handle:?}")));
+        }
+
+        self.remove_instance(instance_handle);
+
+        Ok(())
+    }
+
+    /// Removes given instances, which have been already removed from
+    /// `self.instances`, from the top-level tree and lights.
+    fn forget_instances(&mut self, instance_handles: &[P::InstanceHandle]) {
+        for instance_handle in instance_handles {
+            self.bvh.remove_instance(instance_handle);
+            self.lights.remove_instance(instance_handle);
+        }
     }
 
     /// Creates or updates a light.
@@ -234,17 +560,155 @@ where
         self.lights.insert(light_handle, light);
     }
 
+    /// Fallible version of [`Self::insert_light()`]; returns an error if any
+    /// of light's parameters is out of its range.
+    pub fn try_insert_light(
+        &mut self,
+        light_handle: P::LightHandle,
+        light: Light,
+    ) -> Result<()> {
+        if let Some(&parameter) = light.invalid_parameters().first() {
+            return Err(Error::InvalidLight(
+                format!("{light_handle:?}"),
+                parameter,
+            ));
+        }
+
+        self.insert_light(light_handle, light);
+
+        Ok(())
+    }
+
     /// Removes a light.
     pub fn remove_light(&mut self, light_handle: &P::LightHandle) {
         self.lights.remove(light_handle);
     }
 
+    /// Fallible version of [`Self::remove_light()`]; returns an error if the
+    /// light doesn't exist.
+    pub fn try_remove_light(
+        &mut self,
+        light_handle: &P::LightHandle,
+    ) -> Result<()> {
+        if !self.lights.has(light_handle) {
+            return Err(Error::UnknownLight(format!("{light_handle:?}")));
+        }
+
+        self.remove_light(light_handle);
+
+        Ok(())
+    }
+
     /// Updates sun's parameters.
     pub fn update_sun(&mut self, sun: Sun) {
         self.sun = sun;
         self.has_dirty_sun = true;
     }
 
+    /// Changes how the BVH gets updated when instances change; see
+    /// [`BvhUpdatePolicy`].
+    pub fn set_bvh_update_policy(&mut self, policy: BvhUpdatePolicy) {
+        self.bvh.set_update_policy(policy);
+    }
+
+    /// Changes parameters used to build BVHs; see [`BvhConfig`].
+    ///
+    /// Spatial splits (see [`BvhConfig::spatial_splits`]) yield better trees
+    /// for meshes with long, thin triangles (common for walls and floors in
+    /// architectural scenes), at the cost of slower building and larger
+    /// trees; the threshold specifies when spatial splits get considered -
+    /// it's relative to the mesh's size, with `1e-5` being a good starting
+    /// point.
+    ///
+    /// Changing the config causes all meshes' trees to be rebuilt during the
+    /// next [`Self::tick()`].
+    pub fn set_bvh_config(&mut self, config: BvhConfig) {
+        if *self.bvh.config() != config {
+            self.bvh.set_config(config);
+            self.meshes.invalidate();
+        }
+    }
+
+    /// Enables (or disables, for `None`) the on-disk cache of meshes' BVHs.
+    ///
+    /// Building trees for large meshes can take a while, so for static levels
+    /// it's worth caching them between launches - with cache enabled, trees
+    /// get stored in given directory once built, and later loaded from there
+    /// (instead of being rebuilt) for meshes with the same triangles and the
+    /// same [`BvhConfig`].
+    ///
+    /// Invalid or outdated cache files are ignored (and eventually
+    /// overwritten), so it's always safe to enable the cache; the directory
+    /// gets created if it doesn't exist.
+    ///
+    /// Should be called before inserting meshes, since it doesn't affect
+    /// meshes that have been already processed.
+    pub fn set_bvh_cache_dir(&mut self, dir: Option<PathBuf>) {
+        self.bvh.set_cache(dir.map(BvhCache::new));
+    }
+
+    /// Returns statistics describing the engine's state - number of meshes,
+    /// sizes of buffers, timings of the last [`Self::tick()`] etc.
+    pub fn stats(&self) -> EngineStats {
+        EngineStats {
+            meshes: self.meshes.len(),
+            instances: self.instances.len(),
+            triangles: self.triangles.len(),
+            bvh_nodes: self.bvh.len(),
+            materials: self.materials.iter().count(),
+            images: self.images.len(),
+
+            lights: self.lights.user_len(),
+            triangle_lights: self.lights.triangle_light_count(),
+
+            cameras: self.cameras.iter().count(),
+            triangles_size: self.triangles.allocated_size(),
+            bvh_size: self.bvh.allocated_size(),
+            materials_size: self.materials.allocated_size(),
+            lights_size: self.lights.allocated_size(),
+            atlas_size: self.images.allocated_size(),
+            atlas_usage: self.images.atlas_usage(),
+            camera_sizes: self
+                .cameras
+                .iter()
+                .map(|(camera_handle, camera)| {
+                    (camera_handle, camera.allocated_size())
+                })
+                .collect(),
+            timings: self.metrics.timings(),
+        }
+    }
+
+    /// Sets where CPU timings of particular parts of [`Self::tick()`] should
+    /// be reported, replacing the previous sink (if any); see
+    /// [`MetricsSink`].
+    ///
+    /// By default there's no sink, unless the `metrics` feature is enabled, in
+    /// which case timings are logged through [`LogMetricsSink`].
+    ///
+    /// Regardless of the sink, timings of the last tick are always available
+    /// through [`Self::stats()`].
+    pub fn set_metrics_sink(&mut self, sink: Option<Box<dyn MetricsSink>>) {
+        self.metrics.set_sink(sink);
+    }
+
+    /// Returns statistics describing quality of the BVH, as of the last
+    /// [`Self::tick()`]; see [`BvhReport`].
+    pub fn bvh_report(&self) -> BvhReport<P> {
+        self.bvh.report()
+    }
+
+    /// Writes bounds of BVH's nodes, as of the last [`Self::tick()`], as a
+    /// Wavefront OBJ wireframe.
+    ///
+    /// The file contains the top-level tree (as object `tlas`) and each
+    /// instance's bottom-level tree (as objects `instance0`, `instance1` etc.),
+    /// all in world-space, with nodes grouped by their depth - this comes handy
+    /// for inspecting trees in Blender & co.
+    pub fn export_bvh(&self, writer: impl io::Write) -> io::Result<()> {
+        self.bvh.export_obj(writer)
+    }
+
     /// Creates a new camera that can be used to render the world.
     ///
     /// Note that this is a pretty heavy operation that allocates per-camera
@@ -260,38 +724,230 @@ where
     }
 
     /// Updates camera, changing its mode, position, size etc.
+    ///
+    /// Panics if the camera doesn't exist; see [`Self::try_update_camera()`].
     pub fn update_camera(
         &mut self,
         device: &wgpu::Device,
         handle: CameraHandle,
         camera: Camera,
     ) {
+        self.try_update_camera(device, handle, camera)
+            .unwrap_or_else(|err| panic!("{err}"));
+    }
+
+    /// Fallible version of [`Self::update_camera()`].
+    pub fn try_update_camera(
+        &mut self,
+        device: &wgpu::Device,
+        handle: CameraHandle,
+        camera: Camera,
+    ) -> Result<()> {
         let mut cameras = mem::take(&mut self.cameras);
 
-        cameras.get_mut(handle).update(self, device, camera);
+        let result = cameras
+            .get_mut(handle)
+            .map(|controller| controller.update(self, device, camera));
 
         self.cameras = cameras;
+
+        result
     }
 
     /// Renders camera to texture.
     ///
     /// Note that `view`'s texture format must be the same as the format given
     /// to [`Self::create_camera()`].
+    ///
+    /// Panics if the camera doesn't exist; see [`Self::try_render_camera()`].
     pub fn render_camera(
         &self,
         handle: CameraHandle,
         encoder: &mut wgpu::CommandEncoder,
         view: &wgpu::TextureView,
     ) {
-        self.cameras.get(handle).render(self, encoder, view);
+        self.try_render_camera(handle, encoder, view)
+            .unwrap_or_else(|err| panic!("{err}"));
+    }
+
+    /// Fallible version of [`Self::render_camera()`].
+    pub fn try_render_camera(
+        &self,
+        handle: CameraHandle,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) -> Result<()> {
+        self.cameras.get(handle)?.render(self, encoder, view);
+
+        Ok(())
+    }
+
+    /// Renders camera into an image kept in RAM, blocking until the GPU is
+    /// done.
+    ///
+    /// This is meant mostly for tests and offline tools - the camera renders
+    /// into its own intermediate texture (allocated on the first call) that
+    /// gets copied into a fresh buffer and read back, so you don't have to provide
+    /// any texture view yourself.
+    ///
+    /// Camera's viewport format must be one of `Rgba8Unorm(Srgb)`,
+    /// `Bgra8Unorm(Srgb)`, `Rgba16Float` or `Rgba32Float`.
+    ///
+    /// Just like for [`Self::render_camera()`], [`Self::tick()`] must be called
+    /// beforehand.
+    ///
+    /// Panics if the camera doesn't exist or its image cannot be read back;
+    /// see [`Self::try_render_camera_to_image()`].
+    pub fn render_camera_to_image(
+        &self,
+        handle: CameraHandle,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> CameraImage {
+        self.try_render_camera_to_image(handle, device, queue)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Fallible version of [`Self::render_camera_to_image()`].
+    pub fn try_render_camera_to_image(
+        &self,
+        handle: CameraHandle,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<CameraImage> {
+        self.cameras
+            .get(handle)?
+            .render_to_image(self, device, queue)?
+            .wait(device)
+    }
+
+    /// Asynchronous version of [`Self::render_camera_to_image()`].
+    ///
+    /// Note that on native platforms wgpu doesn't make progress on its own, so
+    /// the returned future will resolve only if you keep calling
+    /// `device.poll()` in the meantime.
+    ///
+    /// Each call uses its own readback buffer, so it's fine to request another
+    /// image before the previous future resolves, or to drop the future
+    /// without awaiting it.
+    pub fn render_camera_to_image_async(
+        &self,
+        handle: CameraHandle,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = CameraImage> + 'static {
+        let job = self
+            .try_render_camera_to_image_async(handle, device, queue)
+            .unwrap_or_else(|err| panic!("{err}"));
+
+        async move { job.await.unwrap_or_else(|err| panic!("{err}")) }
+    }
+
+    /// Fallible version of [`Self::render_camera_to_image_async()`].
+    pub fn try_render_camera_to_image_async(
+        &self,
+        handle: CameraHandle,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<impl Future<Output = Result<CameraImage>> + 'static> {
+        self.cameras
+            .get(handle)?
+            .render_to_image(self, device, queue)
+    }
+
+    /// Returns how much GPU time particular passes of given camera took, in
+    /// the order they were run.
+    ///
+    /// Timings are measured with timestamp queries, which requires for the
+    /// device to be created with [`wgpu::Features::TIMESTAMP_QUERY`] - if it
+    /// wasn't, this function returns an empty list. Note that only compute
+    /// passes are measured and that, since the timestamps are read back
+    /// asynchronously, the timings are refreshed every few frames.
+    ///
+    /// Panics if the camera doesn't exist; see
+    /// [`Self::try_camera_pass_timings()`].
+    pub fn camera_pass_timings(
+        &self,
+        handle: CameraHandle,
+    ) -> Vec<(String, Duration)> {
+        self.try_camera_pass_timings(handle)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Fallible version of [`Self::camera_pass_timings()`].
+    pub fn try_camera_pass_timings(
+        &self,
+        handle: CameraHandle,
+    ) -> Result<Vec<(String, Duration)>> {
+        Ok(self.cameras.get(handle)?.pass_timings())
     }
 
     /// Deletes a camera.
     ///
     /// After this function is called, updating or rendering this camera will
-    /// panic.
+    /// panic (or fail, for the `try_*` functions).
     pub fn delete_camera(&mut self, handle: CameraHandle) {
-        self.cameras.remove(handle);
+        _ = self.cameras.remove(handle);
+    }
+
+    /// Fallible version of [`Self::delete_camera()`]; returns an error if the
+    /// camera doesn't exist.
+    pub fn try_delete_camera(&mut self, handle: CameraHandle) -> Result<()> {
+        self.cameras.remove(handle)
+    }
+
+    /// Casts a ray into the world and returns the closest thing it hits, if
+    /// anything.
+    ///
+    /// This walks the same BVH that's used for rendering, so the results agree
+    /// with what's visible on the screen, with two caveats:
+    ///
+    /// - the world is seen as of the last [`Self::tick()`] (e.g. instances
+    ///   added after that are not taken into account yet),
+    ///
+    /// - alpha-blended materials are checked only against their base color's
+    ///   alpha, since textures are available only on the GPU.
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+    ) -> Option<RayHit<P>> {
+        let ray = gpu::Ray::new(origin, direction.normalize());
+
+        let mut hit = gpu::TriangleHit {
+            distance: max_distance,
+            ..gpu::TriangleHit::none()
+        };
+
+        let (instance, triangle_id) = self.bvh.traverse(
+            ray,
+            &mut hit,
+            |instance, ray, triangle_id, hit| {
+                RayHit::<P>::hit(
+                    self.triangles.get(triangle_id),
+                    &self.materials[instance.material_id],
+                    ray,
+                    hit,
+                )
+            },
+        )?;
+
+        // If the instance or its mesh has been removed since the last tick, we
+        // pretend we haven't hit anything
+        let triangle_ids = self.triangles.ids(&instance.mesh_handle)?;
+        let material_handle =
+            &self.instances.get(&instance.handle)?.material_handle;
+
+        Some(RayHit::new(
+            instance.handle.clone(),
+            material_handle.clone(),
+            triangle_id.get() as usize - triangle_ids.start,
+            self.triangles.get(triangle_id),
+            instance.transform_inverse,
+            ray,
+            hit,
+        ))
     }
 
     /// Sends all changes to the GPU and prepares it for the upcoming frame.
@@ -301,37 +957,107 @@ where
     /// enough.)
     pub fn tick(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
         let tt = Instant::now();
+
+        self.metrics.begin(self.frame);
+
         let any_material_modified = mem::take(&mut self.has_dirty_materials);
         let any_image_modified = mem::take(&mut self.has_dirty_images);
 
-        utils::measure("tick.noise", || {
+        self.metrics.measure("tick.noise", || {
             self.noise.flush(device, queue);
         });
 
-        utils::measure("tick.images", || {
+        self.metrics.measure("tick.images", || {
             self.images.flush(device, queue);
         });
 
         if any_material_modified || any_image_modified {
-            utils::measure("tick.materials", || {
+            self.metrics.measure("tick.materials", || {
                 self.materials.refresh(&self.images);
             });
         }
 
         // ---
 
-        let any_instance_changed = utils::measure("tick.instances", || {
+        let changed_meshes = self.metrics.measure("tick.meshes", || {
+            self.meshes.refresh(
+                &mut self.triangles,
+                &mut self.bvh,
+                &self.metrics,
+            )
+        });
+
+        let is_fragmented =
+            self.compaction_threshold.is_some_and(|threshold| {
+                self.triangles.fragmentation() > threshold
+            });
+
+        let any_mesh_relocated =
+            if mem::take(&mut self.has_pending_compaction) || is_fragmented {
+                self.metrics.measure("tick.compact", || {
+                    let relocated = self.triangles.compact();
+                    let any_mesh_relocated = !relocated.is_empty();
+
+                    for (mesh_handle, old_ids, new_ids) in relocated {
+                        self.bvh.relocate_mesh(&mesh_handle, old_ids, new_ids);
+                    }
+
+                    any_mesh_relocated
+                })
+            } else {
+                false
+            };
+
+        let changed_instances = self.metrics.measure("tick.instances", || {
             self.instances.refresh(
-                &self.meshes,
+                &changed_meshes,
                 &self.materials,
-                &mut self.triangles,
                 &mut self.bvh,
             )
         });
 
-        if any_instance_changed {
-            utils::measure("tick.bvh", || {
-                self.bvh.refresh(&self.materials);
+        let any_instance_changed = !changed_instances.is_empty();
+
+        // Emissive triangles are kept in world-space, so they have to follow
+        // their instances - and since any material might have become (or
+        // stopped being) emissive, changing materials requires checking all
+        // instances
+        self.metrics.measure("tick.lights", || {
+            if any_material_modified {
+                for (instance_handle, entry) in self.instances.iter() {
+                    self.lights.update_instance(
+                        instance_handle,
+                        &entry.instance,
+                        &self.materials,
+                        &self.triangles,
+                    );
+                }
+            } else {
+                for instance_handle in &changed_instances {
+                    if let Some(instance) = self.instances.get(instance_handle)
+                    {
+                        self.lights.update_instance(
+                            instance_handle,
+                            instance,
+                            &self.materials,
+                            &self.triangles,
+                        );
+                    }
+                }
+            }
+
+            self.lights.refresh();
+        });
+
+        // Materials affect the top-level tree as well, since it keeps track of
+        // which instances are alpha-blended
+        if any_instance_changed
+            || any_material_modified
+            || any_mesh_relocated
+            || !changed_meshes.is_empty()
+        {
+            self.metrics.measure("tick.bvh", || {
+                self.bvh.refresh(&self.materials, &self.metrics);
             });
         }
 
@@ -339,11 +1065,12 @@ where
 
         *self.world = gpu::World {
             light_count: self.lights.len(),
+            triangle_light_count: self.lights.triangle_len(),
             sun_azimuth: self.sun.azimuth,
             sun_altitude: self.sun.altitude,
         };
 
-        utils::measure("tick.world", || {
+        self.metrics.measure("tick.world", || {
             self.world.flush(queue);
         });
 
@@ -351,13 +1078,14 @@ where
             self.lights.update_sun(*self.world);
         }
 
-        let any_buffer_reallocated = utils::measure("tick.buffers", || {
-            false
-                | self.bvh.flush(device, queue).reallocated
-                | self.triangles.flush(device, queue).reallocated
-                | self.lights.flush(device, queue).reallocated
-                | self.materials.flush(device, queue).reallocated
-        });
+        let any_buffer_reallocated =
+            self.metrics.measure("tick.buffers", || {
+                false
+                    | self.bvh.flush(device, queue).reallocated
+                    | self.triangles.flush(device, queue).reallocated
+                    | self.lights.flush(device, queue).reallocated
+                    | self.materials.flush(device, queue).reallocated
+            });
 
         // ---
 
@@ -371,7 +1099,7 @@ where
             self.cameras = cameras;
         }
 
-        utils::measure("tick.cameras", || {
+        self.metrics.measure("tick.cameras", || {
             for camera in self.cameras.iter_mut() {
                 camera.flush(self.frame, queue);
             }
@@ -381,7 +1109,7 @@ where
 
         // ---
 
-        utils::metric("tick", tt);
+        self.metrics.record("tick", tt);
 
         if self.print_stats {
             trace!(
@@ -1,9 +1,13 @@
-use glam::{vec4, Vec3};
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use glam::{vec4, Affine3A, Vec2, Vec3};
 
 use crate::gpu;
 
 #[derive(Clone, Debug)]
 pub enum Light {
+    /// Light emitted from a point - or, if `radius` is non-zero, from a
+    /// sphere, which makes the shadows softer.
     Point {
         position: Vec3,
         radius: f32,
@@ -19,6 +23,53 @@ pub enum Light {
         direction: Vec3,
         angle: f32,
     },
+
+    /// Light coming from infinitely far away, such as the sun (which is
+    /// already provided by the engine, see [`crate::Sun`]).
+    ///
+    /// Note that shadows are cast only by objects closer than 1000 units to
+    /// the shaded point.
+    Directional {
+        /// Direction the light travels in
+        direction: Vec3,
+
+        color: Vec3,
+
+        /// Radius of the light's disk as seen from the scene, in radians;
+        /// the larger, the softer the shadows
+        angular_radius: f32,
+    },
+
+    /// Rectangle lying on the XY plane of given transform, emitting light
+    /// towards the transform's -Z axis (or towards both sides, if it's
+    /// two-sided).
+    Rect {
+        transform: Affine3A,
+
+        /// Width and height of the rectangle, before it's transformed
+        size: Vec2,
+
+        /// Radiance emitted from each point of the rectangle (so, unlike
+        /// point lights, the larger the rectangle, the brighter it gets)
+        color: Vec3,
+
+        two_sided: bool,
+    },
+
+    /// Disk lying on the XY plane of given transform, emitting light towards
+    /// the transform's -Z axis (or towards both sides, if it's two-sided).
+    ///
+    /// Non-uniform scaling turns the disk into an ellipse.
+    Disk {
+        transform: Affine3A,
+        radius: f32,
+
+        /// Radiance emitted from each point of the disk (so, unlike point
+        /// lights, the larger the disk, the brighter it gets)
+        color: Vec3,
+
+        two_sided: bool,
+    },
 }
 
 impl Light {
@@ -38,6 +89,7 @@ impl Light {
                     Default::default(),
                     Default::default(),
                 ),
+                d3: Default::default(),
             },
 
             Light::Spot {
@@ -59,8 +111,141 @@ impl Light {
                         direction.y,
                         *angle,
                     ),
+                    d3: Default::default(),
                 }
             }
+
+            Light::Directional {
+                direction,
+                color,
+                angular_radius,
+            } => gpu::Light::directional(
+                direction.normalize(),
+                *color,
+                *angular_radius,
+            ),
+
+            Light::Rect {
+                transform,
+                size,
+                color,
+                two_sided,
+            } => Self::serialize_area(
+                gpu::Light::TYPE_RECT,
+                transform,
+                *size * 0.5,
+                *color,
+                *two_sided,
+            ),
+
+            Light::Disk {
+                transform,
+                radius,
+                color,
+                two_sided,
+            } => Self::serialize_area(
+                gpu::Light::TYPE_DISK,
+                transform,
+                Vec2::splat(*radius),
+                *color,
+                *two_sided,
+            ),
+        }
+    }
+
+    /// Returns names of parameters that are NaN or out of their range.
+    pub(crate) fn invalid_parameters(&self) -> Vec<&'static str> {
+        let is_positive = |value: f32| !value.is_nan() && value >= 0.0;
+        let is_color =
+            |color: Vec3| color.is_finite() && color.min_element() >= 0.0;
+
+        let is_direction =
+            |direction: Vec3| direction.is_finite() && direction != Vec3::ZERO;
+
+        let params = match self {
+            Light::Point {
+                position,
+                radius,
+                color,
+                range,
+            } => vec![
+                ("position", position.is_finite()),
+                ("radius", radius.is_finite() && is_positive(*radius)),
+                ("color", is_color(*color)),
+                ("range", is_positive(*range)),
+            ],
+
+            Light::Spot {
+                position,
+                radius,
+                color,
+                range,
+                direction,
+                angle,
+            } => vec![
+                ("position", position.is_finite()),
+                ("radius", radius.is_finite() && is_positive(*radius)),
+                ("color", is_color(*color)),
+                ("range", is_positive(*range)),
+                ("direction", is_direction(*direction)),
+                ("angle", (0.0..=PI).contains(angle)),
+            ],
+
+            Light::Directional {
+                direction,
+                color,
+                angular_radius,
+            } => vec![
+                ("direction", is_direction(*direction)),
+                ("color", is_color(*color)),
+                ("angular_radius", (0.0..=FRAC_PI_2).contains(angular_radius)),
+            ],
+
+            Light::Rect {
+                transform,
+                size,
+                color,
+                ..
+            } => vec![
+                ("transform", transform.is_finite()),
+                ("size", size.is_finite() && size.min_element() >= 0.0),
+                ("color", is_color(*color)),
+            ],
+
+            Light::Disk {
+                transform,
+                radius,
+                color,
+                ..
+            } => vec![
+                ("transform", transform.is_finite()),
+                ("radius", radius.is_finite() && is_positive(*radius)),
+                ("color", is_color(*color)),
+            ],
+        };
+
+        params
+            .into_iter()
+            .filter(|(_, is_valid)| !is_valid)
+            .map(|(parameter, _)| parameter)
+            .collect()
+    }
+
+    fn serialize_area(
+        ty: u32,
+        transform: &Affine3A,
+        half_size: Vec2,
+        color: Vec3,
+        two_sided: bool,
+    ) -> gpu::Light {
+        let u = transform.transform_vector3(Vec3::X * half_size.x);
+        let v = transform.transform_vector3(Vec3::Y * half_size.y);
+
+        gpu::Light {
+            d0: Vec3::from(transform.translation).extend(Default::default()),
+            d1: color.extend(f32::INFINITY),
+            d2: vec4(f32::from_bits(ty), u.x, u.y, u.z),
+            d3: v.extend(f32::from_bits(two_sided as u32)),
         }
     }
 }
@@ -0,0 +1,518 @@
+use std::f32::consts::PI;
+
+use glam::{vec4, Quat, Vec3, Vec4};
+
+use crate::gpu::Vec3Ext;
+use crate::{
+    gpu, Axis, Bindable, BoundingBox, BufferFlushOutcome, MappedStorageBuffer,
+};
+
+/// Tree of regular lights, used to importance-sample them in the direct
+/// lighting pass; see [`gpu::LightTreeView`] for the layout.
+#[derive(Debug)]
+pub struct LightTree {
+    buffer: MappedStorageBuffer<Vec<Vec4>>,
+}
+
+impl LightTree {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            buffer: MappedStorageBuffer::new_default(device, "light_tree"),
+        }
+    }
+
+    /// Rebuilds the tree from scratch; expects only regular lights (i.e.
+    /// without the triangle lights).
+    pub fn rebuild(&mut self, lights: &[gpu::Light]) {
+        *self.buffer = Self::build(lights);
+    }
+
+    pub fn allocated_size(&self) -> usize {
+        self.buffer.allocated_size()
+    }
+
+    pub fn flush(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> BufferFlushOutcome {
+        self.buffer.flush(device, queue)
+    }
+
+    pub fn bind_readable(&self) -> impl Bindable + '_ {
+        self.buffer.bind_readable()
+    }
+
+    fn build(lights: &[gpu::Light]) -> Vec<Vec4> {
+        let mut items = vec![Vec4::ZERO];
+        let mut entries = Vec::new();
+        let mut directional_light_count = 0;
+
+        for (light_id, light) in lights.iter().enumerate() {
+            let light_id = light_id as u32;
+
+            if light.is_tombstone() {
+                continue;
+            }
+
+            if light.is_directional() {
+                items.extend([
+                    Vec4::ZERO,
+                    Vec4::ZERO,
+                    Vec4::ZERO,
+                    vec4(0.0, 0.0, f32::from_bits(1), f32::from_bits(light_id)),
+                ]);
+
+                directional_light_count += 1;
+            } else {
+                entries.push((light_id, LightBounds::new(light)));
+            }
+        }
+
+        if !entries.is_empty() {
+            Self::build_node(&mut items, &mut entries);
+        }
+
+        items[0] = vec4(
+            f32::from_bits(directional_light_count),
+            f32::from_bits(Self::node_count(&items)),
+            0.0,
+            0.0,
+        );
+
+        items
+    }
+
+    fn build_node(
+        items: &mut Vec<Vec4>,
+        lights: &mut [(u32, LightBounds)],
+    ) -> LightBounds {
+        let node_ptr = items.len();
+
+        items.extend([Vec4::ZERO; 4]);
+
+        let (bounds, node) = if let [(light_id, bounds)] = lights {
+            (*bounds, bounds.serialize(true, *light_id))
+        } else {
+            let split = Self::partition(lights);
+            let (left, right) = lights.split_at_mut(split);
+            let left = Self::build_node(items, left);
+            let right_idx = Self::node_count(items);
+            let right = Self::build_node(items, right);
+            let bounds = left.union(right);
+
+            (bounds, bounds.serialize(false, right_idx))
+        };
+
+        items[node_ptr..][..4].copy_from_slice(&node);
+
+        bounds
+    }
+
+    /// Splits lights into two groups, using the cost heuristic from pbrt
+    /// (which is like SAH, but also accounts for lights' power and emission
+    /// cones); returns the index at which the second group starts.
+    ///
+    /// Thanks to:
+    /// Physically Based Rendering: From Theory to Implementation (4th ed.),
+    /// section 12.6.3.
+    fn partition(lights: &mut [(u32, LightBounds)]) -> usize {
+        const BUCKETS: usize = 12;
+
+        let bounds: BoundingBox =
+            lights.iter().map(|(_, light)| light.bounds).collect();
+
+        let centroid_bounds: BoundingBox = lights
+            .iter()
+            .map(|(_, light)| light.bounds.center())
+            .collect();
+
+        let bucket = |light: &LightBounds, axis: Axis| -> usize {
+            let offset =
+                light.bounds.center()[axis] - centroid_bounds.min()[axis];
+
+            let offset = offset / centroid_bounds.extent()[axis];
+
+            ((offset * (BUCKETS as f32)) as usize).min(BUCKETS - 1)
+        };
+
+        let mut best_split: Option<(f32, Axis, usize)> = None;
+
+        for axis in Axis::all() {
+            if centroid_bounds.extent()[axis] <= 0.0 {
+                continue;
+            }
+
+            let mut buckets = [None::<LightBounds>; BUCKETS];
+
+            for (_, light) in lights.iter() {
+                let bucket = &mut buckets[bucket(light, axis)];
+
+                *bucket = Some(match bucket {
+                    Some(bucket) => bucket.union(*light),
+                    None => *light,
+                });
+            }
+
+            // Penalizes splitting along the shorter axes, which would yield
+            // thin nodes that are poorly approximated by their bounds
+            let kr = bounds.extent().max_element() / bounds.extent()[axis];
+
+            for split in 1..BUCKETS {
+                let left = buckets[..split]
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .reduce(LightBounds::union);
+
+                let right = buckets[split..]
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .reduce(LightBounds::union);
+
+                let (Some(left), Some(right)) = (left, right) else {
+                    continue;
+                };
+
+                let cost = left.cost(kr) + right.cost(kr);
+
+                let is_better = best_split
+                    .map_or(true, |(best_cost, _, _)| cost < best_cost);
+
+                if is_better {
+                    best_split = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let Some((_, axis, split)) = best_split else {
+            // All lights have the same centroid, so there's nothing smart we
+            // can do
+            return lights.len() / 2;
+        };
+
+        let mut mid = 0;
+
+        for idx in 0..lights.len() {
+            if bucket(&lights[idx].1, axis) < split {
+                lights.swap(idx, mid);
+                mid += 1;
+            }
+        }
+
+        mid
+    }
+
+    fn node_count(items: &[Vec4]) -> u32 {
+        ((items.len() - 1) / 4) as u32
+    }
+}
+
+/// Conservative approximation of a group of lights: their bounds, power and
+/// the cone of directions they emit towards.
+#[derive(Clone, Copy, Debug)]
+struct LightBounds {
+    bounds: BoundingBox,
+    power: f32,
+    range: f32,
+    axis: Vec3,
+    cos_theta_o: f32,
+    cos_theta_e: f32,
+    two_sided: bool,
+}
+
+impl LightBounds {
+    fn new(light: &gpu::Light) -> Self {
+        let center = light.center();
+
+        if light.is_area() {
+            let u = light.area_u();
+            let v = light.area_v();
+
+            let area = if light.is_rect() {
+                4.0 * u.cross(v).length()
+            } else {
+                PI * u.length() * v.length()
+            };
+
+            Self {
+                bounds: [
+                    center - u - v,
+                    center - u + v,
+                    center + u - v,
+                    center + u + v,
+                ]
+                .into_iter()
+                .collect(),
+                power: light.color().luma() * area,
+                range: light.range(),
+                axis: light.area_normal(),
+                cos_theta_o: 1.0,
+                cos_theta_e: 0.0,
+                two_sided: light.is_two_sided(),
+            }
+        } else {
+            let (axis, cos_theta_o, cos_theta_e) = if light.is_point() {
+                (Vec3::Z, -1.0, 0.0)
+            } else {
+                (light.spot_direction(), 1.0, light.spot_angle().cos())
+            };
+
+            Self {
+                bounds: BoundingBox::new(
+                    center - light.radius(),
+                    center + light.radius(),
+                ),
+                power: light.color().luma(),
+                range: light.range(),
+                axis,
+                cos_theta_o,
+                cos_theta_e,
+                two_sided: false,
+            }
+        }
+    }
+
+    fn union(self, other: Self) -> Self {
+        if self.power <= 0.0 {
+            return other;
+        }
+
+        if other.power <= 0.0 {
+            return self;
+        }
+
+        let (axis, cos_theta_o) = Self::union_cones(
+            (self.axis, self.cos_theta_o),
+            (other.axis, other.cos_theta_o),
+        );
+
+        Self {
+            bounds: self.bounds + other.bounds,
+            power: self.power + other.power,
+            range: self.range.max(other.range),
+            axis,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// Returns the smallest cone that contains both cones.
+    fn union_cones(a: (Vec3, f32), b: (Vec3, f32)) -> (Vec3, f32) {
+        let theta_a = a.1.clamp(-1.0, 1.0).acos();
+        let theta_b = b.1.clamp(-1.0, 1.0).acos();
+        let theta_d = a.0.angle_between(b.0);
+
+        if (theta_d + theta_b).min(PI) <= theta_a {
+            return a;
+        }
+
+        if (theta_d + theta_a).min(PI) <= theta_b {
+            return b;
+        }
+
+        let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+
+        if theta_o >= PI {
+            return (a.0, -1.0);
+        }
+
+        let theta_r = theta_o - theta_a;
+        let axis_r = a.0.cross(b.0);
+
+        if axis_r.length_squared() == 0.0 {
+            return (a.0, -1.0);
+        }
+
+        let axis = Quat::from_axis_angle(axis_r.normalize(), theta_r) * a.0;
+
+        (axis, theta_o.cos())
+    }
+
+    fn cost(&self, kr: f32) -> f32 {
+        let cos_theta_o = self.cos_theta_o.clamp(-1.0, 1.0);
+        let sin_theta_o = (1.0 - cos_theta_o * cos_theta_o).max(0.0).sqrt();
+        let theta_o = cos_theta_o.acos();
+        let theta_e = self.cos_theta_e.clamp(-1.0, 1.0).acos();
+        let theta_w = (theta_o + theta_e).min(PI);
+
+        let m_omega = 2.0 * PI * (1.0 - cos_theta_o)
+            + PI / 2.0
+                * (2.0 * theta_w * sin_theta_o
+                    - (theta_o - 2.0 * theta_w).cos()
+                    + cos_theta_o
+                    - 2.0 * theta_o * sin_theta_o);
+
+        self.power * m_omega * kr * self.bounds.half_area()
+    }
+
+    fn serialize(&self, is_leaf: bool, payload: u32) -> [Vec4; 4] {
+        let min = self.bounds.min();
+        let max = self.bounds.max();
+
+        [
+            min.extend(self.power),
+            max.extend(self.range),
+            self.axis.extend(self.cos_theta_o),
+            vec4(
+                self.cos_theta_e,
+                f32::from_bits(self.two_sided as u32),
+                f32::from_bits(is_leaf as u32),
+                f32::from_bits(payload),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec2, vec3, Affine3A};
+
+    use super::*;
+    use crate::Light;
+
+    /// Returns probabilities of picking each light while traversing the tree
+    /// from given point.
+    fn probabilities(
+        tree: gpu::LightTreeView,
+        node_idx: u32,
+        point: Vec3,
+        normal: Vec3,
+        pdf: f32,
+        out: &mut Vec<(u32, f32)>,
+    ) {
+        let node = tree.node(node_idx);
+
+        if node.is_leaf() {
+            out.push((node.light_id().get(), pdf));
+            return;
+        }
+
+        let left_idx = node_idx + 1;
+        let right_idx = node.right_child();
+        let left = tree.node(left_idx).importance(point, normal);
+        let right = tree.node(right_idx).importance(point, normal);
+
+        if left + right <= 0.0 {
+            return;
+        }
+
+        let left_pdf = left / (left + right);
+
+        probabilities(tree, left_idx, point, normal, pdf * left_pdf, out);
+
+        probabilities(
+            tree,
+            right_idx,
+            point,
+            normal,
+            pdf * (1.0 - left_pdf),
+            out,
+        );
+    }
+
+    #[test]
+    fn build() {
+        let mut lights = vec![gpu::Light::sun(Vec3::Y, Vec3::ONE)];
+
+        for x in 0..8 {
+            for z in 0..8 {
+                lights.push(
+                    Light::Point {
+                        position: vec3(x as f32, 1.0, z as f32),
+                        radius: 0.1,
+                        color: vec3(1.0, 0.5, 0.25) * ((x + z) as f32),
+                        range: 5.0,
+                    }
+                    .serialize(),
+                );
+            }
+        }
+
+        lights.push(
+            Light::Spot {
+                position: vec3(4.0, 3.0, 4.0),
+                radius: 0.0,
+                color: Vec3::ONE,
+                range: 20.0,
+                direction: -Vec3::Y,
+                angle: 0.5,
+            }
+            .serialize(),
+        );
+
+        lights.push(
+            Light::Rect {
+                transform: Affine3A::from_translation(vec3(-2.0, 1.0, 0.0)),
+                size: vec2(1.0, 2.0),
+                color: Vec3::ONE,
+                two_sided: false,
+            }
+            .serialize(),
+        );
+
+        let items = LightTree::build(&lights);
+        let tree = gpu::LightTreeView::new(&items);
+
+        assert_eq!(1, tree.directional_light_count());
+        assert_eq!(0, tree.directional_light(0).get());
+        assert!(tree.has_nodes());
+
+        for point in [
+            vec3(0.0, 0.0, 0.0),
+            vec3(3.5, 0.0, 3.5),
+            vec3(-2.0, 0.0, -1.0),
+            vec3(10.0, 0.0, 10.0),
+        ] {
+            let mut out = Vec::new();
+
+            probabilities(tree, 1, point, Vec3::Y, 1.0, &mut out);
+
+            // Some probability might get lost on nodes whose children don't
+            // reach the point (since the nodes' bounds are conservative), but
+            // the probabilities must never add up to more than one
+            let total: f32 = out.iter().map(|(_, pdf)| pdf).sum();
+
+            assert!(total > 0.0 && total < 1.001, "point={point}");
+
+            // Each light must be reachable through at most one path and the
+            // sun must not be a part of the tree
+            out.sort_by_key(|(light_id, _)| *light_id);
+
+            assert!(out.windows(2).all(|pair| pair[0].0 != pair[1].0));
+            assert!(out.iter().all(|(light_id, _)| *light_id > 0));
+
+            // Each light that can affect the point must be reachable
+            for node_idx in 1..LightTree::node_count(&items) {
+                let node = tree.node(node_idx);
+
+                if !node.is_leaf() || node.importance(point, Vec3::Y) <= 0.0 {
+                    continue;
+                }
+
+                let light_id = node.light_id().get();
+
+                assert!(
+                    out.iter().any(|(id, pdf)| *id == light_id && *pdf > 0.0),
+                    "point={point}, light_id={light_id}"
+                );
+            }
+        }
+
+        // Lights right above the point must be preferred over the ones that
+        // are far away
+        let mut out = Vec::new();
+
+        probabilities(tree, 1, vec3(7.0, 0.0, 7.0), Vec3::Y, 1.0, &mut out);
+
+        let pdf_of = |light_id: u32| {
+            out.iter()
+                .find(|(light_id2, _)| *light_id2 == light_id)
+                .map_or(0.0, |(_, pdf)| *pdf)
+        };
+
+        assert!(pdf_of(64) > pdf_of(1));
+    }
+}
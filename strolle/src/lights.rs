@@ -1,11 +1,29 @@
-use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::ops::Range;
+use std::{iter, mem};
 
+use glam::Vec4Swizzles;
+
+use crate::utils::Allocator;
 use crate::{
-    gpu, Bindable, BufferFlushOutcome, Light, MappedStorageBuffer, Params,
+    gpu, Bindable, BufferFlushOutcome, Instance, Light, LightTree,
+    MappedStorageBuffer, Materials, Params, Triangles,
 };
 
+/// Lights of the world: the sun, followed by slots reserved for lights
+/// inserted by the user, followed by triangle lights (i.e. emissive triangles
+/// of all instances).
+///
+/// Each light inserted by the user occupies a slot that stays the same until
+/// the light is removed - removed lights (and reserved slots that haven't been
+/// used yet) are tombstones, whose slots are reused by lights inserted later.
+///
+/// Similarly, emissive triangles of each instance occupy a range of slots
+/// (relative to the first triangle light) that stays the same for as long as
+/// the instance's number of emissive triangles doesn't change; slots of
+/// removed triangles are filled with tombstones as well, which - having zero
+/// power - are never picked by [`gpu::LightsView::sample_triangle()`].
 #[derive(Debug)]
 pub struct Lights<P>
 where
@@ -13,6 +31,43 @@ where
 {
     buffer: MappedStorageBuffer<Vec<gpu::Light>>,
     index: HashMap<P::LightHandle, gpu::LightId>,
+
+    /// Lights as inserted by the user; kept for [`Self::iter()`]
+    lights: HashMap<P::LightHandle, Light>,
+
+    /// Number of slots reserved for regular lights (including the sun);
+    /// triangle lights begin right after them
+    capacity: usize,
+
+    /// Slots of removed lights that can be reused
+    allocator: Allocator,
+
+    /// Slots of lights removed since the last refresh; those are kept as
+    /// tombstones for at least one frame before getting reused, so that the
+    /// previous frame's reservoirs never point at a different light
+    removed: Vec<usize>,
+
+    /// Slots of each instance's emissive triangles, relative to the first
+    /// triangle light
+    triangles: HashMap<P::InstanceHandle, Range<usize>>,
+
+    /// Slots of removed triangles that can be reused, relative to the first
+    /// triangle light
+    triangle_allocator: Allocator,
+
+    /// Slots of triangles removed since the last refresh, relative to the
+    /// first triangle light; see `removed`
+    removed_triangles: Vec<Range<usize>>,
+
+    /// Number of slots occupied by triangle lights (including tombstones)
+    triangle_count: usize,
+
+    /// First triangle slot whose cumulative power has to be recomputed
+    dirty_triangles_from: Option<usize>,
+
+    /// Tree of regular lights, rebuilt whenever they are inserted or removed
+    tree: LightTree,
+    has_dirty_tree: bool,
 }
 
 impl<P> Lights<P>
@@ -30,26 +85,78 @@ where
         Self {
             buffer,
             index: Default::default(),
+            lights: Default::default(),
+            capacity: 1,
+            allocator: Default::default(),
+            removed: Default::default(),
+            triangles: Default::default(),
+            triangle_allocator: Default::default(),
+            removed_triangles: Default::default(),
+            triangle_count: 0,
+            dirty_triangles_from: None,
+            tree: LightTree::new(device),
+            has_dirty_tree: true,
         }
     }
 
     pub fn insert(&mut self, light_handle: P::LightHandle, light: Light) {
-        let light = light.serialize();
+        let light_id = if let Some(light_id) = self.index.get(&light_handle) {
+            light_id.get() as usize
+        } else {
+            let light_id = if let Some(slot) = self.allocator.take(1) {
+                slot.start
+            } else {
+                self.grow()
+            };
 
-        match self.index.entry(light_handle) {
-            Entry::Occupied(entry) => {
-                let light_id = *entry.get();
+            self.index.insert(
+                light_handle.clone(),
+                gpu::LightId::new(light_id as u32),
+            );
 
-                self.buffer[light_id.get() as usize] = light;
-            }
+            light_id
+        };
 
-            Entry::Vacant(entry) => {
-                let light_id = gpu::LightId::new(self.buffer.len() as u32);
+        self.buffer[light_id] = light.serialize();
+        self.lights.insert(light_handle, light);
+        self.has_dirty_tree = true;
+    }
 
-                self.buffer.push(light);
-                entry.insert(light_id);
-            }
-        }
+    /// Makes room for more regular lights by moving triangle lights further
+    /// into the buffer; returns a slot that can be used right away.
+    ///
+    /// This is the only case where triangle lights get moved, and since the
+    /// capacity (at least) doubles each time, it happens only a few times.
+    fn grow(&mut self) -> usize {
+        let old_capacity = self.capacity;
+
+        // New slots cover all slots that triangle lights have occupied so far,
+        // so that the previous frame's reservoirs pointing at triangle lights
+        // see tombstones instead of some other lights
+        let capacity =
+            (2 * old_capacity).max(old_capacity + self.triangle_count + 1);
+
+        self.buffer.splice(
+            old_capacity..old_capacity,
+            iter::repeat(gpu::Light::tombstone()).take(capacity - old_capacity),
+        );
+
+        self.capacity = capacity;
+
+        // ... and for the same reason the new slots (apart from the last one,
+        // which is not covered by any triangle light) can't be used until the
+        // next refresh
+        self.removed.extend(old_capacity..(capacity - 1));
+
+        capacity - 1
+    }
+
+    pub fn has(&self, light_handle: &P::LightHandle) -> bool {
+        self.index.contains_key(light_handle)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&P::LightHandle, &Light)> + '_ {
+        self.lights.iter()
     }
 
     pub fn remove(&mut self, light_handle: &P::LightHandle) {
@@ -57,12 +164,183 @@ where
             return;
         };
 
-        self.buffer.remove(light_id.get() as usize);
+        self.lights.remove(light_handle);
+
+        let light_id = light_id.get() as usize;
+
+        self.buffer[light_id] = gpu::Light::tombstone();
+        self.removed.push(light_id);
+        self.has_dirty_tree = true;
+    }
+
+    /// Collects emissive triangles of given instance - or forgets them, if
+    /// the instance is not emissive (anymore).
+    ///
+    /// Only materials with a constant emission are taken into account, since
+    /// emissive textures are not available on the CPU - materials with
+    /// emissive textures still light up the scene, but only through the
+    /// indirect lighting.
+    pub fn update_instance(
+        &mut self,
+        instance_handle: &P::InstanceHandle,
+        instance: &Instance<P>,
+        materials: &Materials<P>,
+        triangles: &Triangles<P>,
+    ) {
+        let lights = Self::collect_triangles(instance, materials, triangles);
+
+        if lights.is_empty() {
+            self.remove_instance(instance_handle);
+            return;
+        }
+
+        let first_id = self.len() as usize;
+
+        let slots = match self.triangles.get(instance_handle) {
+            Some(slots) if slots.len() == lights.len() => {
+                let prev_lights = &self.buffer
+                    [(first_id + slots.start)..(first_id + slots.end)];
+
+                // Changing materials causes all instances to be updated, so
+                // let's avoid re-uploading the ones that haven't changed
+                let is_unchanged =
+                    prev_lights.iter().zip(&lights).all(|(prev, curr)| {
+                        bytemuck::bytes_of(&prev.with_triangle_cdf(0.0))
+                            == bytemuck::bytes_of(curr)
+                    });
+
+                if is_unchanged {
+                    return;
+                }
+
+                slots.clone()
+            }
+
+            _ => {
+                self.remove_instance(instance_handle);
+                self.take_triangle_slots(lights.len())
+            }
+        };
+
+        self.buffer[(first_id + slots.start)..(first_id + slots.end)]
+            .copy_from_slice(&lights);
+
+        self.invalidate_triangles(slots.start);
+        self.triangles.insert(instance_handle.clone(), slots);
+    }
+
+    pub fn remove_instance(&mut self, instance_handle: &P::InstanceHandle) {
+        let Some(slots) = self.triangles.remove(instance_handle) else {
+            return;
+        };
+
+        let first_id = self.len() as usize;
+
+        self.buffer[(first_id + slots.start)..(first_id + slots.end)]
+            .fill(gpu::Light::tombstone());
+
+        self.invalidate_triangles(slots.start);
+        self.removed_triangles.push(slots);
+    }
+
+    fn take_triangle_slots(&mut self, len: usize) -> Range<usize> {
+        if let Some(slots) = self.triangle_allocator.take(len) {
+            slots
+        } else {
+            let slots = self.triangle_count..(self.triangle_count + len);
+            let buffer_len = self.buffer.len() + len;
+
+            self.buffer.resize(buffer_len, gpu::Light::tombstone());
+            self.triangle_count += len;
+
+            slots
+        }
+    }
+
+    fn invalidate_triangles(&mut self, from: usize) {
+        self.dirty_triangles_from = Some(
+            self.dirty_triangles_from
+                .map_or(from, |dirty_from| dirty_from.min(from)),
+        );
+    }
+
+    fn collect_triangles(
+        instance: &Instance<P>,
+        materials: &Materials<P>,
+        triangles: &Triangles<P>,
+    ) -> Vec<gpu::Light> {
+        let Some(material_id) = materials.lookup(&instance.material_handle)
+        else {
+            return Default::default();
+        };
+
+        let material = &materials[material_id];
+        let color = material.emissive.xyz();
+
+        if material.emissive_texture.is_some() || color.max_element() <= 0.0 {
+            return Default::default();
+        }
+
+        let Some(triangle_ids) = triangles.ids(&instance.mesh_handle) else {
+            return Default::default();
+        };
+
+        triangle_ids
+            .map(|triangle_id| {
+                let positions = triangles
+                    .get(gpu::TriangleId::new(triangle_id as u32))
+                    .positions()
+                    .map(|position| {
+                        instance.transform.transform_point3(position)
+                    });
+
+                gpu::Light::triangle(positions, color)
+            })
+            .filter(|light| light.triangle_power() > 0.0)
+            .collect()
+    }
+
+    /// Recomputes cumulative powers of triangle lights, if any instance's
+    /// emissive triangles have changed since the last refresh, and rebuilds
+    /// the light tree, if any regular light has changed.
+    pub fn refresh(&mut self) {
+        // Lights removed since the last refresh are going to be rendered as
+        // tombstones during the upcoming frame, after which nothing will
+        // point at their slots anymore
+        for slot in self.removed.drain(..) {
+            self.allocator.give(slot..slot + 1);
+        }
 
-        for light_id2 in self.index.values_mut() {
-            if light_id2.get() > light_id.get() {
-                *light_id2.get_mut() -= 1;
+        for slots in self.removed_triangles.drain(..) {
+            self.triangle_allocator.give(slots);
+        }
+
+        let first_id = self.len() as usize;
+
+        if mem::take(&mut self.has_dirty_tree) {
+            self.tree.rebuild(&self.buffer[..first_id]);
+        }
+
+        let Some(dirty_from) = self.dirty_triangles_from.take() else {
+            return;
+        };
+
+        // Cumulative powers of triangles placed before the changed ones stay
+        // the same, so we can start from the first changed triangle
+        let lights = &mut self.buffer[first_id..];
+
+        let mut cdf = if dirty_from > 0 {
+            lights[dirty_from - 1].triangle_cdf()
+        } else {
+            0.0
+        };
+
+        for light in &mut lights[dirty_from..] {
+            if light.is_triangle() {
+                cdf += light.triangle_power();
             }
+
+            *light = light.with_triangle_cdf(cdf);
         }
     }
 
@@ -76,11 +354,38 @@ where
         // TODO feels hacky
         let sun_color = sun_color * gpu::Atmosphere::EXPOSURE * 3.0;
 
-        self.buffer[0] = gpu::Light::sun(world.sun_position(), sun_color);
+        self.buffer[0] = gpu::Light::sun(world.sun_direction(), sun_color);
     }
 
+    /// Returns the number of slots for regular lights, including the sun and
+    /// tombstones.
     pub fn len(&self) -> u32 {
-        self.buffer.len() as u32
+        self.capacity as u32
+    }
+
+    /// Returns the number of lights inserted by the user.
+    pub fn user_len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns the number of slots for triangle lights, including
+    /// tombstones; returns zero if there's nothing to sample there (i.e. if
+    /// all the slots are tombstones).
+    pub fn triangle_len(&self) -> u32 {
+        if self.triangles.is_empty() {
+            0
+        } else {
+            self.triangle_count as u32
+        }
+    }
+
+    /// Returns the number of triangle lights, not including tombstones.
+    pub fn triangle_light_count(&self) -> usize {
+        self.triangles.values().map(|slots| slots.len()).sum()
+    }
+
+    pub fn allocated_size(&self) -> usize {
+        self.buffer.allocated_size() + self.tree.allocated_size()
     }
 
     pub fn flush(
@@ -88,10 +393,17 @@ where
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) -> BufferFlushOutcome {
-        self.buffer.flush(device, queue)
+        let reallocated = self.buffer.flush(device, queue).reallocated
+            | self.tree.flush(device, queue).reallocated;
+
+        BufferFlushOutcome { reallocated }
     }
 
     pub fn bind_readable(&self) -> impl Bindable + '_ {
         self.buffer.bind_readable()
     }
+
+    pub fn bind_tree(&self) -> impl Bindable + '_ {
+        self.tree.bind_readable()
+    }
 }
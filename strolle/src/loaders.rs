@@ -0,0 +1,8 @@
+//! Loaders that allow to use Strolle without any external engine, by reading
+//! scenes straight from files.
+//!
+//! Each loader is hidden behind a cargo feature named after the format it
+//! supports, e.g. `gltf`.
+
+#[cfg(feature = "gltf")]
+pub mod gltf;
@@ -0,0 +1,1093 @@
+//! Loader for glTF 2.0 scenes (both `.gltf` and `.glb`).
+//!
+//! # Example
+//!
+//! ```no_run
+//! use strolle::loaders::gltf::{self, DefaultGltfParams};
+//! use strolle::Engine;
+//!
+//! fn load(engine: &mut Engine<DefaultGltfParams>) {
+//!     gltf::load::<DefaultGltfParams>("scene.glb", 0)
+//!         .unwrap()
+//!         .insert_into(engine);
+//! }
+//! ```
+//!
+//! # Limitations
+//!
+//! - only the first set of UVs is used,
+//! - only triangle lists are supported (strips, fans, lines etc. are skipped),
+//! - lights are infinitely small (point and spot lights get zero radius and
+//!   directional lights get zero angular radius, so all of them cast hard
+//!   shadows) and spot lights' inner cone angle is ignored,
+//! - animations, skins and morph targets are ignored.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use ::gltf::image::Format;
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::material::AlphaMode as GltfAlphaMode;
+use ::gltf::mesh::Mode;
+use ::gltf::{buffer, Document, Node, Primitive};
+use glam::{Affine3A, Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use log::{debug, warn};
+
+use crate::{
+    AlphaMode, Engine, Image, ImageData, Instance, Light, Material, Mesh,
+    Params,
+};
+
+/// Range used for point and spot lights that don't specify one.
+///
+/// glTF says those should have an infinite range, but Strolle treats lights
+/// with an infinite range as sun-like lights (i.e. lights that don't fall off
+/// with distance), so we pick something more sensible (the same as Bevy).
+const DEFAULT_LIGHT_RANGE: f32 = 20.0;
+
+/// Identifies an object loaded from a glTF file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GltfId {
+    /// Number passed to [`load()`] / [`load_slice()`], so that objects loaded
+    /// from different files don't collide.
+    pub file: u32,
+
+    /// Index of the object within the file.
+    ///
+    /// Images and materials use the same indices as the glTF document; meshes,
+    /// instances and lights are numbered in the order they've been loaded.
+    pub index: u32,
+}
+
+/// Extension of [`Params`] that allows the loader to create handles for the
+/// objects it reads.
+pub trait GltfParams: Params {
+    fn image_handle(id: GltfId) -> Self::ImageHandle;
+    fn instance_handle(id: GltfId) -> Self::InstanceHandle;
+    fn light_handle(id: GltfId) -> Self::LightHandle;
+    fn material_handle(id: GltfId) -> Self::MaterialHandle;
+    fn mesh_handle(id: GltfId) -> Self::MeshHandle;
+}
+
+/// [`Params`] that use [`GltfId`] for all of the handles - handy for
+/// applications that don't have handles of their own.
+#[derive(Clone, Debug)]
+pub struct DefaultGltfParams;
+
+impl Params for DefaultGltfParams {
+    type ImageHandle = GltfId;
+    type ImageTexture = Arc<wgpu::Texture>;
+    type InstanceHandle = GltfId;
+    type LightHandle = GltfId;
+    type MaterialHandle = GltfId;
+    type MeshHandle = GltfId;
+}
+
+impl GltfParams for DefaultGltfParams {
+    fn image_handle(id: GltfId) -> GltfId {
+        id
+    }
+
+    fn instance_handle(id: GltfId) -> GltfId {
+        id
+    }
+
+    fn light_handle(id: GltfId) -> GltfId {
+        id
+    }
+
+    fn material_handle(id: GltfId) -> GltfId {
+        id
+    }
+
+    fn mesh_handle(id: GltfId) -> GltfId {
+        id
+    }
+}
+
+/// Scene read from a glTF file, ready to be inserted into the engine.
+#[derive(Debug)]
+pub struct GltfScene<P>
+where
+    P: Params,
+{
+    pub images: Vec<(P::ImageHandle, Image<P>)>,
+    pub materials: Vec<(P::MaterialHandle, Material<P>)>,
+    pub meshes: Vec<(P::MeshHandle, Mesh)>,
+    pub instances: Vec<(P::InstanceHandle, Instance<P>)>,
+    pub lights: Vec<(P::LightHandle, Light)>,
+}
+
+impl<P> GltfScene<P>
+where
+    P: Params,
+{
+    pub fn insert_into(self, engine: &mut Engine<P>) {
+        for (handle, image) in self.images {
+            engine.insert_image(handle, image);
+        }
+
+        for (handle, material) in self.materials {
+            engine.insert_material(handle, material);
+        }
+
+        for (handle, mesh) in self.meshes {
+            engine.insert_mesh(handle, mesh);
+        }
+
+        for (handle, instance) in self.instances {
+            engine.insert_instance(handle, instance);
+        }
+
+        for (handle, light) in self.lights {
+            engine.insert_light(handle, light);
+        }
+    }
+}
+
+impl<P> Default for GltfScene<P>
+where
+    P: Params,
+{
+    fn default() -> Self {
+        Self {
+            images: Default::default(),
+            materials: Default::default(),
+            meshes: Default::default(),
+            instances: Default::default(),
+            lights: Default::default(),
+        }
+    }
+}
+
+/// Loads default scene (or the first one, if the file doesn't specify the
+/// default) from given file.
+///
+/// `file` gets propagated into [`GltfId::file`] - if you're loading many files
+/// into the same engine, each one should get a different number.
+pub fn load<P>(
+    path: impl AsRef<Path>,
+    file: u32,
+) -> Result<GltfScene<P>, ::gltf::Error>
+where
+    P: GltfParams,
+{
+    let (document, buffers, images) = ::gltf::import(path)?;
+
+    Ok(Loader::new(file, &buffers).load(&document, &images))
+}
+
+/// Same as [`load()`], but reads the scene from memory.
+///
+/// Note that for `.gltf` files, buffers must be either embedded as data URIs
+/// or stored in a `.glb` blob, and images must be stored in buffer views -
+/// `gltf::import_slice()` doesn't resolve external files nor data-URI images.
+pub fn load_slice<P>(
+    bytes: &[u8],
+    file: u32,
+) -> Result<GltfScene<P>, ::gltf::Error>
+where
+    P: GltfParams,
+{
+    let (document, buffers, images) = ::gltf::import_slice(bytes)?;
+
+    Ok(Loader::new(file, &buffers).load(&document, &images))
+}
+
+struct Loader<'a, P>
+where
+    P: Params,
+{
+    file: u32,
+    buffers: &'a [buffer::Data],
+    scene: GltfScene<P>,
+
+    /// Maps glTF's mesh index to our mesh handles, one per primitive (`None`
+    /// if given primitive has been skipped)
+    meshes: Vec<Vec<Option<P::MeshHandle>>>,
+
+    /// Indices of images that have been loaded (i.e. not skipped because of
+    /// an unsupported format)
+    images: HashSet<usize>,
+
+    /// Index under which we store glTF's default material, i.e. the one used
+    /// by primitives that don't specify their material
+    default_material_idx: usize,
+
+    /// Whether any primitive uses the default material
+    uses_default_material: bool,
+}
+
+impl<'a, P> Loader<'a, P>
+where
+    P: GltfParams,
+{
+    fn new(file: u32, buffers: &'a [buffer::Data]) -> Self {
+        Self {
+            file,
+            buffers,
+            scene: Default::default(),
+            meshes: Default::default(),
+            images: Default::default(),
+            default_material_idx: 0,
+            uses_default_material: false,
+        }
+    }
+
+    fn load(
+        mut self,
+        document: &Document,
+        images: &[::gltf::image::Data],
+    ) -> GltfScene<P> {
+        let image_spaces = Self::image_spaces(document, images.len());
+
+        for (image_idx, image) in images.iter().enumerate() {
+            self.load_image(image_idx, image, image_spaces[image_idx]);
+        }
+
+        self.default_material_idx = document.materials().len();
+
+        for material in document.materials() {
+            let handle = P::material_handle(
+                self.id(material.index().expect("material has no index")),
+            );
+
+            let material = self.load_material(&material);
+
+            self.scene.materials.push((handle, material));
+        }
+
+        for mesh in document.meshes() {
+            let handles = mesh
+                .primitives()
+                .map(|primitive| self.load_mesh(&primitive))
+                .collect();
+
+            self.meshes.push(handles);
+        }
+
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next());
+
+        if let Some(scene) = scene {
+            for node in scene.nodes() {
+                self.load_node(&node, Affine3A::IDENTITY);
+            }
+        } else {
+            warn!("glTF file #{} contains no scenes", self.file);
+        }
+
+        if self.uses_default_material {
+            let handle = P::material_handle(self.id(self.default_material_idx));
+
+            self.scene.materials.push((handle, Default::default()));
+        }
+
+        debug!(
+            "Loaded glTF file #{}: {} images, {} materials, {} meshes, {} \
+             instances, {} lights",
+            self.file,
+            self.scene.images.len(),
+            self.scene.materials.len(),
+            self.scene.meshes.len(),
+            self.scene.instances.len(),
+            self.scene.lights.len(),
+        );
+
+        self.scene
+    }
+
+    /// Determines color space of each image, depending on how materials use
+    /// it - glTF says that color textures are sRGB, while the rest (normal
+    /// maps, metallic-roughness textures etc.) is linear.
+    fn image_spaces(
+        document: &Document,
+        image_count: usize,
+    ) -> Vec<ColorSpace> {
+        let mut spaces = vec![None; image_count];
+
+        for material in document.materials() {
+            let pbr = material.pbr_metallic_roughness();
+
+            let textures = [
+                (
+                    pbr.base_color_texture().map(|info| info.texture()),
+                    ColorSpace::Srgb,
+                ),
+                (
+                    material.emissive_texture().map(|info| info.texture()),
+                    ColorSpace::Srgb,
+                ),
+                (
+                    pbr.metallic_roughness_texture().map(|info| info.texture()),
+                    ColorSpace::Linear,
+                ),
+                (
+                    material.normal_texture().map(|info| info.texture()),
+                    ColorSpace::Linear,
+                ),
+            ];
+
+            for (texture, space) in textures {
+                let Some(texture) = texture else {
+                    continue;
+                };
+
+                let image_idx = texture.source().index();
+
+                match spaces[image_idx] {
+                    Some(prev_space) if prev_space != space => {
+                        warn!(
+                            "Image #{} is used both as a color and a non-color \
+                             texture; treating it as a color texture",
+                            image_idx,
+                        );
+
+                        spaces[image_idx] = Some(ColorSpace::Srgb);
+                    }
+
+                    _ => {
+                        spaces[image_idx] = Some(space);
+                    }
+                }
+            }
+        }
+
+        spaces
+            .into_iter()
+            .map(|space| space.unwrap_or(ColorSpace::Srgb))
+            .collect()
+    }
+
+    fn load_image(
+        &mut self,
+        image_idx: usize,
+        image: &::gltf::image::Data,
+        space: ColorSpace,
+    ) {
+        let Some(data) = image_to_rgba8(image) else {
+            warn!(
+                "Image #{} uses an unsupported format ({:?}); skipping it",
+                image_idx, image.format
+            );
+
+            return;
+        };
+
+        let texture_descriptor = wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: image.width,
+                height: image.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: match space {
+                ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+                ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+            },
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        };
+
+        let image = Image::new(
+            ImageData::Raw { data },
+            texture_descriptor,
+            Default::default(),
+        );
+
+        self.scene
+            .images
+            .push((P::image_handle(self.id(image_idx)), image));
+
+        self.images.insert(image_idx);
+    }
+
+    fn load_material(&self, material: &::gltf::Material) -> Material<P> {
+        let pbr = material.pbr_metallic_roughness();
+
+        // Textures whose images have been skipped are treated as missing
+        let texture = |texture: ::gltf::Texture| {
+            let image_idx = texture.source().index();
+
+            self.images
+                .contains(&image_idx)
+                .then(|| P::image_handle(self.id(image_idx)))
+        };
+
+        let base_color = Vec4::from(pbr.base_color_factor());
+
+        let (base_color, alpha_mode) = match material.alpha_mode() {
+            GltfAlphaMode::Opaque => {
+                (base_color.xyz().extend(1.0), AlphaMode::Opaque)
+            }
+
+            GltfAlphaMode::Mask => (
+                base_color,
+                AlphaMode::Mask {
+                    cutoff: material.alpha_cutoff().unwrap_or(0.5),
+                },
+            ),
+
+            GltfAlphaMode::Blend => (base_color, AlphaMode::Blend),
+        };
+
+        let emissive = Vec3::from(material.emissive_factor())
+            * material.emissive_strength().unwrap_or(1.0);
+
+        // Similarly to Bevy's `thickness`, index of refraction matters only for
+        // transmissive materials
+        let ior = if material
+            .transmission()
+            .is_some_and(|tr| tr.transmission_factor() > 0.0)
+        {
+            material.ior().unwrap_or(1.5)
+        } else {
+            1.0
+        };
+
+        Material {
+            base_color,
+            base_color_texture: pbr
+                .base_color_texture()
+                .and_then(|info| texture(info.texture())),
+            emissive: emissive.extend(1.0),
+            emissive_texture: material
+                .emissive_texture()
+                .and_then(|info| texture(info.texture())),
+            perceptual_roughness: pbr.roughness_factor(),
+            metallic: pbr.metallic_factor(),
+            metallic_roughness_texture: pbr
+                .metallic_roughness_texture()
+                .and_then(|info| texture(info.texture())),
+            ior,
+            normal_map_texture: material
+                .normal_texture()
+                .and_then(|info| texture(info.texture())),
+            alpha_mode,
+            ..Default::default()
+        }
+    }
+
+    fn load_mesh(&mut self, primitive: &Primitive) -> Option<P::MeshHandle> {
+        if primitive.mode() != Mode::Triangles {
+            warn!(
+                "Primitive #{} uses an unsupported mode ({:?}); skipping it",
+                primitive.index(),
+                primitive.mode(),
+            );
+
+            return None;
+        }
+
+        let buffers = self.buffers;
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        let Some(positions) = reader.read_positions() else {
+            warn!(
+                "Primitive #{} has no positions; skipping it",
+                primitive.index()
+            );
+
+            return None;
+        };
+
+        let positions: Vec<Vec3> = positions.map(Vec3::from).collect();
+
+        let normals: Vec<Vec3> = reader
+            .read_normals()
+            .map(|normals| normals.map(Vec3::from).collect())
+            .unwrap_or_default();
+
+        let uvs: Vec<Vec2> = reader
+            .read_tex_coords(0)
+            .map(|uvs| uvs.into_f32().map(Vec2::from).collect())
+            .unwrap_or_default();
+
+        let tangents: Vec<Vec4> = reader
+            .read_tangents()
+            .map(|tangents| tangents.map(Vec4::from).collect())
+            .unwrap_or_default();
+
+        let colors: Vec<Vec4> = reader
+            .read_colors(0)
+            .map(|colors| colors.into_rgba_f32().map(Vec4::from).collect())
+            .unwrap_or_default();
+
+        let indices: Vec<u32> = reader
+            .read_indices()
+            .map(|indices| indices.into_u32().collect())
+            .unwrap_or_else(|| (0..positions.len() as u32).collect());
+
+        let indices: Vec<u32> = indices
+            .chunks_exact(3)
+            .filter(|ids| ids.iter().all(|&id| (id as usize) < positions.len()))
+            .flatten()
+            .copied()
+            .collect();
+
+        if indices.is_empty() {
+            warn!(
+                "Primitive #{} contains no triangles; skipping it",
+                primitive.index()
+            );
+
+            return None;
+        }
+
+        let mesh = if normals.is_empty() {
+            // When normals are missing, glTF says we should use flat shading,
+            // which requires for triangles not to share vertices
+            let positions = unindex(&positions, &indices);
+
+            let normals: Vec<_> = positions
+                .chunks_exact(3)
+                .flat_map(|positions| {
+                    let normal = (positions[1] - positions[0])
+                        .cross(positions[2] - positions[0])
+                        .normalize_or_zero();
+
+                    [normal; 3]
+                })
+                .collect();
+
+            Mesh::default()
+                .with_positions(positions)
+                .with_normals(normals)
+                .with_uvs(unindex(&uvs, &indices))
+                .with_tangents(unindex(&tangents, &indices))
+                .with_colors(unindex(&colors, &indices))
+        } else {
+            Mesh::default()
+                .with_positions(positions)
+                .with_normals(normals)
+                .with_uvs(uvs)
+                .with_tangents(tangents)
+                .with_colors(colors)
+                .with_indices(indices)
+        };
+
+        let handle = P::mesh_handle(self.id(self.scene.meshes.len()));
+
+        self.scene.meshes.push((handle.clone(), mesh));
+
+        Some(handle)
+    }
+
+    fn load_node(&mut self, node: &Node, parent_xform: Affine3A) {
+        let xform = parent_xform
+            * Affine3A::from_mat4(Mat4::from_cols_array_2d(
+                &node.transform().matrix(),
+            ));
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                let Some(mesh_handle) =
+                    self.meshes[mesh.index()][primitive.index()].clone()
+                else {
+                    continue;
+                };
+
+                let material_idx =
+                    primitive.material().index().unwrap_or_else(|| {
+                        self.uses_default_material = true;
+                        self.default_material_idx
+                    });
+
+                let material_handle = P::material_handle(self.id(material_idx));
+
+                let handle =
+                    P::instance_handle(self.id(self.scene.instances.len()));
+
+                self.scene.instances.push((
+                    handle,
+                    Instance::new(mesh_handle, material_handle, xform),
+                ));
+            }
+        }
+
+        if let Some(light) = node.light() {
+            let (_, rotation, position) = xform.to_scale_rotation_translation();
+
+            // glTF specifies intensity of point and spot lights in candelas
+            // and of directional lights in luxes, which is what Strolle
+            // expects as well
+            let color = Vec3::from(light.color()) * light.intensity();
+            let range = light.range().unwrap_or(DEFAULT_LIGHT_RANGE);
+
+            let light = match light.kind() {
+                Kind::Point => Light::Point {
+                    position,
+                    radius: 0.0,
+                    color,
+                    range,
+                },
+
+                Kind::Spot {
+                    outer_cone_angle, ..
+                } => Light::Spot {
+                    position,
+                    radius: 0.0,
+                    color,
+                    range,
+                    direction: (rotation * -Vec3::Z).normalize(),
+                    angle: outer_cone_angle,
+                },
+
+                Kind::Directional => Light::Directional {
+                    direction: (rotation * -Vec3::Z).normalize(),
+                    color,
+                    angular_radius: 0.0,
+                },
+            };
+
+            let handle = P::light_handle(self.id(self.scene.lights.len()));
+
+            self.scene.lights.push((handle, light));
+        }
+
+        for child in node.children() {
+            self.load_node(&child, xform);
+        }
+    }
+
+    fn id(&self, index: usize) -> GltfId {
+        GltfId {
+            file: self.file,
+            index: index as u32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+/// Converts image into RGBA8, which is what our atlas uses.
+///
+/// Returns `None` for 16-bit and floating-point images.
+fn image_to_rgba8(image: &::gltf::image::Data) -> Option<Vec<u8>> {
+    let channels = match image.format {
+        Format::R8 => 1,
+        Format::R8G8 => 2,
+        Format::R8G8B8 => 3,
+        Format::R8G8B8A8 => return Some(image.pixels.clone()),
+        _ => return None,
+    };
+
+    let data = image
+        .pixels
+        .chunks_exact(channels)
+        .flat_map(|texel| match *texel {
+            [l] => [l, l, l, 255],
+            [r, g] => [r, g, 0, 255],
+            [r, g, b] => [r, g, b, 255],
+            _ => unreachable!(),
+        })
+        .collect();
+
+    Some(data)
+}
+
+/// Expands given vertex attribute so that each index gets its own vertex;
+/// missing attributes (i.e. empty ones) are kept missing.
+fn unindex<T>(values: &[T], indices: &[u32]) -> Vec<T>
+where
+    T: Copy,
+{
+    if values.is_empty() {
+        return Vec::new();
+    }
+
+    indices.iter().map(|&id| values[id as usize]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, vec4};
+
+    use super::*;
+
+    /// Small scene with a data-URI buffer (images are stored in the buffer as
+    /// well, since `import_slice()` doesn't support data-URI images):
+    ///
+    /// - image #0 is an 8-bit RGB image, image #1 is a 16-bit grayscale one
+    ///   (which we don't support),
+    /// - mesh #0 contains an indexed triangle and a line list, mesh #1
+    ///   contains a triangle without normals, mesh #2 contains a triangle
+    ///   without material,
+    /// - node #1 is a child of node #0, with both of them being transformed,
+    /// - there's one point, one spot and one directional light.
+    const SCENE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "extensionsUsed": [
+            "KHR_lights_punctual",
+            "KHR_materials_emissive_strength"
+        ],
+        "extensions": {
+            "KHR_lights_punctual": {
+                "lights": [
+                    {
+                        "type": "point",
+                        "color": [1.0, 0.0, 0.0],
+                        "intensity": 10.0
+                    },
+                    {
+                        "type": "spot",
+                        "intensity": 5.0,
+                        "range": 7.0,
+                        "spot": { "outerConeAngle": 0.5 }
+                    },
+                    {
+                        "type": "directional",
+                        "color": [0.0, 1.0, 0.0],
+                        "intensity": 3.0
+                    }
+                ]
+            }
+        },
+        "buffers": [
+            {
+                "byteLength": 220,
+                "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAAAAAAAAAAAAIA/AAAAAAAAAAAAAIA/AAAAAAAAAAAAAIA/AAABAAIAAACJUE5HDQoaCgAAAA1JSERSAAAAAQAAAAEIAgAAAJB3U94AAAAMSURBVHicY+ASkQMAAGgAPVQIo/cAAAAASUVORK5CYIIAAACJUE5HDQoaCgAAAA1JSERSAAAAAQAAAAEQAAAAAGruRxYAAAALSURBVHicY2BkAgAABwAEdknjKAAAAABJRU5ErkJggg=="
+            }
+        ],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 72, "byteLength": 6 },
+            { "buffer": 0, "byteOffset": 80, "byteLength": 69 },
+            { "buffer": 0, "byteOffset": 152, "byteLength": 68 }
+        ],
+        "accessors": [
+            {
+                "bufferView": 0,
+                "componentType": 5126,
+                "count": 3,
+                "type": "VEC3",
+                "min": [0.0, 0.0, 0.0],
+                "max": [1.0, 1.0, 0.0]
+            },
+            {
+                "bufferView": 1,
+                "componentType": 5126,
+                "count": 3,
+                "type": "VEC3"
+            },
+            {
+                "bufferView": 2,
+                "componentType": 5123,
+                "count": 3,
+                "type": "SCALAR"
+            }
+        ],
+        "images": [
+            { "bufferView": 3, "mimeType": "image/png" },
+            { "bufferView": 4, "mimeType": "image/png" }
+        ],
+        "textures": [
+            { "source": 0 },
+            { "source": 1 }
+        ],
+        "materials": [
+            {
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [0.5, 0.25, 1.0, 0.5],
+                    "baseColorTexture": { "index": 0 },
+                    "metallicFactor": 0.3,
+                    "roughnessFactor": 0.7,
+                    "metallicRoughnessTexture": { "index": 1 }
+                },
+                "emissiveFactor": [1.0, 0.5, 0.0],
+                "extensions": {
+                    "KHR_materials_emissive_strength": {
+                        "emissiveStrength": 2.0
+                    }
+                }
+            },
+            {
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1.0, 1.0, 1.0, 0.5]
+                },
+                "alphaMode": "MASK",
+                "alphaCutoff": 0.25
+            },
+            {
+                "alphaMode": "BLEND"
+            }
+        ],
+        "meshes": [
+            {
+                "primitives": [
+                    {
+                        "attributes": { "POSITION": 0, "NORMAL": 1 },
+                        "indices": 2,
+                        "material": 0
+                    },
+                    {
+                        "attributes": { "POSITION": 0 },
+                        "mode": 1,
+                        "material": 2
+                    }
+                ]
+            },
+            {
+                "primitives": [
+                    {
+                        "attributes": { "POSITION": 0 },
+                        "material": 1
+                    }
+                ]
+            },
+            {
+                "primitives": [
+                    {
+                        "attributes": { "POSITION": 0, "NORMAL": 1 },
+                        "indices": 2
+                    }
+                ]
+            }
+        ],
+        "nodes": [
+            {
+                "mesh": 0,
+                "translation": [1.0, 2.0, 3.0],
+                "children": [1]
+            },
+            {
+                "mesh": 1,
+                "scale": [2.0, 2.0, 2.0]
+            },
+            {
+                "translation": [0.0, 5.0, 0.0],
+                "extensions": { "KHR_lights_punctual": { "light": 0 } }
+            },
+            {
+                "rotation": [0.70710677, 0.0, 0.0, 0.70710677],
+                "extensions": { "KHR_lights_punctual": { "light": 1 } }
+            },
+            {
+                "extensions": { "KHR_lights_punctual": { "light": 2 } }
+            },
+            {
+                "mesh": 2
+            }
+        ],
+        "scenes": [
+            { "nodes": [0, 2, 3, 4, 5] }
+        ],
+        "scene": 0
+    }"#;
+
+    fn load_scene() -> GltfScene<DefaultGltfParams> {
+        load_slice(SCENE.as_bytes(), 7).unwrap()
+    }
+
+    fn id(index: u32) -> GltfId {
+        GltfId { file: 7, index }
+    }
+
+    #[test]
+    fn images() {
+        let scene = load_scene();
+
+        // Image #1 is 16-bit, so it should've been skipped
+        assert_eq!(1, scene.images.len());
+
+        let (handle, image) = &scene.images[0];
+
+        assert_eq!(id(0), *handle);
+
+        assert_eq!(
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            image.texture_descriptor.format
+        );
+
+        match &image.data {
+            ImageData::Raw { data } => {
+                assert_eq!(&[10, 20, 30, 255], data.as_slice());
+            }
+
+            data => panic!("unexpected image data: {data:?}"),
+        }
+    }
+
+    #[test]
+    fn materials() {
+        let scene = load_scene();
+
+        // Three materials from the file + the default one
+        assert_eq!(4, scene.materials.len());
+
+        let (handle, mat) = &scene.materials[0];
+
+        assert_eq!(id(0), *handle);
+        assert_eq!(vec4(0.5, 0.25, 1.0, 1.0), mat.base_color);
+        assert_eq!(Some(id(0)), mat.base_color_texture);
+        assert_eq!(vec4(2.0, 1.0, 0.0, 1.0), mat.emissive);
+        assert_eq!(0.3, mat.metallic);
+        assert_eq!(0.7, mat.perceptual_roughness);
+        assert_eq!(None, mat.metallic_roughness_texture);
+        assert!(matches!(mat.alpha_mode, AlphaMode::Opaque));
+
+        let (handle, mat) = &scene.materials[1];
+
+        assert_eq!(id(1), *handle);
+        assert_eq!(vec4(1.0, 1.0, 1.0, 0.5), mat.base_color);
+        assert!(
+            matches!(mat.alpha_mode, AlphaMode::Mask { cutoff } if cutoff == 0.25)
+        );
+
+        let (handle, mat) = &scene.materials[2];
+
+        assert_eq!(id(2), *handle);
+        assert!(matches!(mat.alpha_mode, AlphaMode::Blend));
+
+        let (handle, mat) = &scene.materials[3];
+
+        assert_eq!(id(3), *handle);
+        assert!(matches!(mat.alpha_mode, AlphaMode::Opaque));
+    }
+
+    #[test]
+    fn meshes() {
+        let scene = load_scene();
+
+        // Line list from mesh #0 should've been skipped
+        assert_eq!(3, scene.meshes.len());
+
+        let (handle, mesh) = &scene.meshes[0];
+
+        assert_eq!(id(0), *handle);
+        assert_eq!(3, mesh.vertex_count());
+        assert_eq!(3, mesh.indices().unwrap().len());
+
+        // Primitive without normals should've been unindexed and given flat
+        // normals
+        let (handle, mesh) = &scene.meshes[1];
+
+        assert_eq!(id(1), *handle);
+        assert!(mesh.indices().is_none());
+
+        assert_eq!(
+            &[
+                vec3(0.0, 0.0, 0.0),
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0)
+            ],
+            mesh.positions(),
+        );
+
+        assert_eq!(&[Vec3::Z; 3], mesh.normals());
+
+        let (handle, _) = &scene.meshes[2];
+
+        assert_eq!(id(2), *handle);
+    }
+
+    #[test]
+    fn instances() {
+        let scene = load_scene();
+
+        assert_eq!(3, scene.instances.len());
+
+        let (handle, instance) = &scene.instances[0];
+
+        assert_eq!(id(0), *handle);
+        assert_eq!(id(0), instance.mesh_handle);
+        assert_eq!(id(0), instance.material_handle);
+
+        assert_eq!(
+            Affine3A::from_translation(vec3(1.0, 2.0, 3.0)),
+            instance.transform
+        );
+
+        // Child's transform should've been combined with its parent's one
+        let (handle, instance) = &scene.instances[1];
+
+        assert_eq!(id(1), *handle);
+        assert_eq!(id(1), instance.mesh_handle);
+        assert_eq!(id(1), instance.material_handle);
+
+        assert_eq!(
+            vec3(3.0, 2.0, 3.0),
+            instance.transform.transform_point3(Vec3::X)
+        );
+
+        // Primitive without material should've got the default one
+        let (handle, instance) = &scene.instances[2];
+
+        assert_eq!(id(2), *handle);
+        assert_eq!(id(2), instance.mesh_handle);
+        assert_eq!(id(3), instance.material_handle);
+    }
+
+    #[test]
+    fn lights() {
+        let scene = load_scene();
+
+        assert_eq!(3, scene.lights.len());
+
+        match &scene.lights[0] {
+            (
+                handle,
+                Light::Point {
+                    position,
+                    color,
+                    range,
+                    ..
+                },
+            ) => {
+                assert_eq!(id(0), *handle);
+                assert_eq!(vec3(0.0, 5.0, 0.0), *position);
+                assert_eq!(vec3(10.0, 0.0, 0.0), *color);
+                assert_eq!(DEFAULT_LIGHT_RANGE, *range);
+            }
+
+            light => panic!("unexpected light: {light:?}"),
+        }
+
+        match &scene.lights[1] {
+            (
+                handle,
+                Light::Spot {
+                    color,
+                    range,
+                    direction,
+                    angle,
+                    ..
+                },
+            ) => {
+                assert_eq!(id(1), *handle);
+                assert_eq!(vec3(5.0, 5.0, 5.0), *color);
+                assert_eq!(7.0, *range);
+                assert!(direction.abs_diff_eq(Vec3::Y, 1e-6), "{direction}");
+                assert_eq!(0.5, *angle);
+            }
+
+            light => panic!("unexpected light: {light:?}"),
+        }
+
+        match &scene.lights[2] {
+            (
+                handle,
+                Light::Directional {
+                    direction, color, ..
+                },
+            ) => {
+                assert_eq!(id(2), *handle);
+                assert_eq!(-Vec3::Z, *direction);
+                assert_eq!(vec3(0.0, 3.0, 0.0), *color);
+            }
+
+            light => panic!("unexpected light: {light:?}"),
+        }
+    }
+}
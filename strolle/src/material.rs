@@ -46,10 +46,79 @@ where
             normal_map_texture: images
                 .lookup_opt(self.normal_map_texture.as_ref())
                 .unwrap_or_default(),
+            alpha_cutoff: match self.alpha_mode {
+                AlphaMode::Mask { cutoff } => cutoff,
+                _ => 0.0,
+            },
+            _padding: Default::default(),
         }
     }
 }
 
+impl<P> Material<P>
+where
+    P: Params,
+{
+    /// Returns images used by this material.
+    pub(crate) fn textures(
+        &self,
+    ) -> impl Iterator<Item = &P::ImageHandle> + '_ {
+        [
+            &self.base_color_texture,
+            &self.emissive_texture,
+            &self.metallic_roughness_texture,
+            &self.normal_map_texture,
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// Stops using given image.
+    pub(crate) fn detach_image(&mut self, image_handle: &P::ImageHandle) {
+        for texture in [
+            &mut self.base_color_texture,
+            &mut self.emissive_texture,
+            &mut self.metallic_roughness_texture,
+            &mut self.normal_map_texture,
+        ] {
+            if texture.as_ref() == Some(image_handle) {
+                *texture = None;
+            }
+        }
+    }
+
+    /// Returns names of parameters that are NaN or out of their range.
+    pub(crate) fn invalid_parameters(&self) -> Vec<&'static str> {
+        let is_unit = |value: f32| (0.0..=1.0).contains(&value);
+
+        let is_color =
+            |color: Vec4| color.is_finite() && color.cmpge(Vec4::ZERO).all();
+
+        [
+            (
+                "base_color",
+                is_color(self.base_color) && is_unit(self.base_color.w),
+            ),
+            ("emissive", is_color(self.emissive)),
+            ("perceptual_roughness", is_unit(self.perceptual_roughness)),
+            ("metallic", is_unit(self.metallic)),
+            ("reflectance", is_unit(self.reflectance)),
+            ("ior", self.ior.is_finite() && self.ior >= 1.0),
+            (
+                "alpha_mode",
+                match self.alpha_mode {
+                    AlphaMode::Mask { cutoff } => is_unit(cutoff),
+                    _ => true,
+                },
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, is_valid)| !is_valid)
+        .map(|(parameter, _)| parameter)
+        .collect()
+    }
+}
+
 impl<P> Default for Material<P>
 where
     P: Params,
@@ -88,4 +157,66 @@ pub enum AlphaMode {
     /// traversal process), so this option should be enabled conservatively,
     /// only for materials that actually use transparency.
     Blend,
+
+    /// Material is either fully opaque or fully transparent, depending on
+    /// whether base color's alpha (including base color texture's alpha
+    /// channel) is below `cutoff`.
+    ///
+    /// Same as [`Self::Blend`], this has negative effects on ray-tracing
+    /// performance.
+    Mask { cutoff: f32 },
+}
+
+impl AlphaMode {
+    /// Returns whether given alpha makes the surface transparent, i.e.
+    /// whether rays should pass through it.
+    pub(crate) fn is_transparent(&self, alpha: f32) -> bool {
+        match *self {
+            AlphaMode::Opaque => false,
+            AlphaMode::Blend => alpha < 1.0,
+            AlphaMode::Mask { cutoff } => alpha < cutoff,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestParams;
+
+    impl Params for TestParams {
+        type ImageHandle = &'static str;
+        type ImageTexture = Arc<wgpu::Texture>;
+        type InstanceHandle = &'static str;
+        type LightHandle = &'static str;
+        type MaterialHandle = &'static str;
+        type MeshHandle = &'static str;
+    }
+
+    #[test]
+    fn detach_image() {
+        let mut material = Material::<TestParams> {
+            base_color_texture: Some("a"),
+            emissive_texture: Some("b"),
+            metallic_roughness_texture: Some("a"),
+            normal_map_texture: Some("c"),
+            ..Default::default()
+        };
+
+        material.detach_image(&"a");
+
+        assert_eq!(None, material.base_color_texture);
+        assert_eq!(Some("b"), material.emissive_texture);
+        assert_eq!(None, material.metallic_roughness_texture);
+        assert_eq!(Some("c"), material.normal_map_texture);
+        assert_eq!(vec![&"b", &"c"], material.textures().collect::<Vec<_>>());
+
+        material.detach_image(&"d");
+
+        assert_eq!(2, material.textures().count());
+    }
 }
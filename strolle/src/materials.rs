@@ -52,6 +52,7 @@ where
             Entry::Vacant(entry) => {
                 let material_id =
                     if let Some(material_id) = self.allocator.take(1) {
+                        self.materials[material_id.start] = material;
                         material_id.start
                     } else {
                         self.materials.push(material);
@@ -67,6 +68,22 @@ where
         self.index.contains_key(material_handle)
     }
 
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (&P::MaterialHandle, &Material<P>)> + '_ {
+        self.index.iter().map(|(material_handle, material_id)| {
+            (material_handle, &self.materials[material_id.get() as usize])
+        })
+    }
+
+    /// Stops using given image in all materials that refer to it.
+    pub fn detach_image(&mut self, image_handle: &P::ImageHandle) {
+        for material_id in self.index.values() {
+            self.materials[material_id.get() as usize]
+                .detach_image(image_handle);
+        }
+    }
+
     pub fn has_specular(&self) -> bool {
         self.has_specular
     }
@@ -85,6 +102,10 @@ where
         self.buffer.len()
     }
 
+    pub fn allocated_size(&self) -> usize {
+        self.buffer.allocated_size()
+    }
+
     pub fn lookup(
         &self,
         material_handle: &P::MaterialHandle,
@@ -1,16 +1,250 @@
-use crate::MeshTriangle;
+mod preprocessor;
 
-#[derive(Clone, Debug)]
+use spirv_std::glam::{Vec2, Vec3, Vec4};
+
+use crate::{MeshPreprocessing, MeshReport, MeshTriangle, Triangle};
+
+/// Triangle mesh, described by vertex attributes and (optionally) indices.
+///
+/// Each vertex has a position, normal and - optionally - UV, tangent and
+/// color; missing attributes default to zero. When indices are present, each
+/// three consecutive indices form a triangle, otherwise each three consecutive
+/// vertices do.
+#[derive(Clone, Debug, Default)]
 pub struct Mesh {
-    triangles: Vec<MeshTriangle>,
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    tangents: Vec<Vec4>,
+    colors: Vec<Vec4>,
+    indices: Option<MeshIndices>,
 }
 
 impl Mesh {
+    /// Creates a non-indexed mesh out of given triangles.
+    ///
+    /// This duplicates vertices shared between triangles, so for larger
+    /// meshes it's better to provide attributes and indices directly.
     pub fn new(triangles: Vec<MeshTriangle>) -> Self {
-        Self { triangles }
+        let mut mesh = Self::default();
+
+        for triangle in triangles {
+            mesh.positions.extend(triangle.positions());
+            mesh.normals.extend(triangle.normals());
+            mesh.uvs.extend(triangle.uvs());
+            mesh.tangents.extend(triangle.tangents());
+        }
+
+        mesh
+    }
+
+    pub fn with_positions(
+        mut self,
+        positions: impl IntoIterator<Item = impl Into<Vec3>>,
+    ) -> Self {
+        self.positions = positions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_normals(
+        mut self,
+        normals: impl IntoIterator<Item = impl Into<Vec3>>,
+    ) -> Self {
+        self.normals = normals.into_iter().map(Into::into).collect();
+        self
     }
 
-    pub(crate) fn triangles(&self) -> &[MeshTriangle] {
-        &self.triangles
+    pub fn with_uvs(
+        mut self,
+        uvs: impl IntoIterator<Item = impl Into<Vec2>>,
+    ) -> Self {
+        self.uvs = uvs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_tangents(
+        mut self,
+        tangents: impl IntoIterator<Item = impl Into<Vec4>>,
+    ) -> Self {
+        self.tangents = tangents.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets vertex colors; note that they are not used by the renderer yet.
+    pub fn with_colors(
+        mut self,
+        colors: impl IntoIterator<Item = impl Into<Vec4>>,
+    ) -> Self {
+        self.colors = colors.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_indices(mut self, indices: impl Into<MeshIndices>) -> Self {
+        self.indices = Some(indices.into());
+        self
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    pub fn uvs(&self) -> &[Vec2] {
+        &self.uvs
+    }
+
+    pub fn tangents(&self) -> &[Vec4] {
+        &self.tangents
+    }
+
+    pub fn colors(&self) -> &[Vec4] {
+        &self.colors
+    }
+
+    pub fn indices(&self) -> Option<&MeshIndices> {
+        self.indices.as_ref()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices
+            .as_ref()
+            .map_or(self.positions.len(), |indices| indices.len())
+            / 3
+    }
+
+    /// Returns indices of vertices that form given triangle.
+    pub fn triangle_indices(&self, triangle_idx: usize) -> [usize; 3] {
+        let idx = 3 * triangle_idx;
+
+        if let Some(indices) = &self.indices {
+            [indices.get(idx), indices.get(idx + 1), indices.get(idx + 2)]
+        } else {
+            [idx, idx + 1, idx + 2]
+        }
+    }
+
+    /// Drops triangles that refer to missing vertices, have NaN or infinite
+    /// positions or (almost) zero area, together with attributes whose length
+    /// doesn't match the number of positions.
+    ///
+    /// This gets called automatically by [`crate::Engine::insert_mesh()`],
+    /// since such triangles would otherwise wreck the BVH; calling it manually
+    /// allows to inspect what has been dropped.
+    pub fn validate(&mut self) -> MeshReport {
+        let mut report = MeshReport::default();
+
+        preprocessor::validate(self, &mut report);
+
+        report
+    }
+
+    /// Validates the mesh (see [`Self::validate()`]) and performs given
+    /// preprocessing steps on it.
+    pub fn preprocess(
+        &mut self,
+        preprocessing: MeshPreprocessing,
+    ) -> MeshReport {
+        let mut report = self.validate();
+
+        if let Some(epsilon) = preprocessing.weld {
+            preprocessor::weld(self, epsilon, &mut report);
+
+            // Welding can collapse some of the triangles
+            preprocessor::validate(self, &mut report);
+        }
+
+        if preprocessing.generate_normals {
+            report.generated_normals = preprocessor::generate_normals(self);
+        }
+
+        if preprocessing.generate_tangents {
+            report.generated_tangents = preprocessor::generate_tangents(self);
+        }
+
+        report
+    }
+
+    /// Returns vertex with given index, as laid out for rasterization:
+    /// position + uv.x, normal + uv.y, tangent.
+    pub(crate) fn vertex(&self, vertex_idx: usize) -> [Vec4; 3] {
+        let uv = self.uvs.get(vertex_idx).copied().unwrap_or_default();
+
+        [
+            self.positions[vertex_idx].extend(uv.x),
+            self.normals
+                .get(vertex_idx)
+                .copied()
+                .unwrap_or_default()
+                .extend(uv.y),
+            self.tangents.get(vertex_idx).copied().unwrap_or_default(),
+        ]
+    }
+
+    pub(crate) fn triangles(&self) -> impl Iterator<Item = Triangle> + '_ {
+        (0..self.triangle_count()).map(|triangle_idx| {
+            let vertices = self.triangle_indices(triangle_idx);
+
+            Triangle {
+                positions: vertices.map(|idx| self.positions[idx]),
+                normals: vertices.map(|idx| {
+                    self.normals.get(idx).copied().unwrap_or_default()
+                }),
+                uvs: vertices
+                    .map(|idx| self.uvs.get(idx).copied().unwrap_or_default()),
+                tangents: vertices.map(|idx| {
+                    self.tangents.get(idx).copied().unwrap_or_default()
+                }),
+            }
+        })
+    }
+}
+
+/// Indices of a [`Mesh`].
+#[derive(Clone, Debug)]
+pub enum MeshIndices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl MeshIndices {
+    pub fn len(&self) -> usize {
+        match self {
+            MeshIndices::U16(indices) => indices.len(),
+            MeshIndices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, idx: usize) -> usize {
+        match self {
+            MeshIndices::U16(indices) => indices[idx] as usize,
+            MeshIndices::U32(indices) => indices[idx] as usize,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len()).map(|idx| self.get(idx))
+    }
+}
+
+impl From<Vec<u16>> for MeshIndices {
+    fn from(indices: Vec<u16>) -> Self {
+        Self::U16(indices)
+    }
+}
+
+impl From<Vec<u32>> for MeshIndices {
+    fn from(indices: Vec<u32>) -> Self {
+        Self::U32(indices)
     }
 }
@@ -0,0 +1,518 @@
+use std::collections::hash_map::Entry;
+
+use fxhash::FxHashMap;
+use spirv_std::glam::{Vec3, Vec4};
+
+use crate::{Mesh, MeshIndices, MeshReport};
+
+/// Drops triangles that refer to missing vertices, have non-finite positions
+/// or (almost) zero area, together with attributes whose length doesn't match
+/// the number of positions.
+pub fn validate(mesh: &mut Mesh, report: &mut MeshReport) {
+    let vertex_count = mesh.positions.len();
+
+    report.mismatched_attributes +=
+        drop_mismatched(&mut mesh.normals, vertex_count)
+            + drop_mismatched(&mut mesh.uvs, vertex_count)
+            + drop_mismatched(&mut mesh.tangents, vertex_count)
+            + drop_mismatched(&mut mesh.colors, vertex_count);
+
+    let keep: Vec<_> = (0..mesh.triangle_count())
+        .map(|triangle_idx| {
+            let ids = mesh.triangle_indices(triangle_idx);
+
+            if ids.iter().any(|&id| id >= vertex_count) {
+                report.out_of_bounds_triangles += 1;
+                return false;
+            }
+
+            let [a, b, c] = ids.map(|id| mesh.positions[id]);
+
+            if !(a.is_finite() && b.is_finite() && c.is_finite()) {
+                report.non_finite_triangles += 1;
+                return false;
+            }
+
+            if is_degenerate(a, b, c) {
+                report.degenerate_triangles += 1;
+                return false;
+            }
+
+            true
+        })
+        .collect();
+
+    // Leftover indices (or positions, for non-indexed meshes) that don't form
+    // a complete triangle have to be dropped as well
+    let is_complete = 3 * mesh.triangle_count()
+        == mesh
+            .indices
+            .as_ref()
+            .map_or(vertex_count, |indices| indices.len());
+
+    if is_complete && keep.iter().all(|&keep| keep) {
+        return;
+    }
+
+    if let Some(indices) = &mut mesh.indices {
+        *indices = match indices {
+            MeshIndices::U16(indices) => {
+                MeshIndices::U16(retain_triangles(indices, &keep))
+            }
+            MeshIndices::U32(indices) => {
+                MeshIndices::U32(retain_triangles(indices, &keep))
+            }
+        };
+    } else {
+        mesh.positions = retain_triangles(&mesh.positions, &keep);
+        mesh.normals = retain_triangles(&mesh.normals, &keep);
+        mesh.uvs = retain_triangles(&mesh.uvs, &keep);
+        mesh.tangents = retain_triangles(&mesh.tangents, &keep);
+        mesh.colors = retain_triangles(&mesh.colors, &keep);
+    }
+}
+
+/// Merges vertices that lie in the same cell of a grid of given size and have
+/// equal remaining attributes.
+///
+/// Expects for the mesh to be validated.
+pub fn weld(mesh: &mut Mesh, epsilon: f32, report: &mut MeshReport) {
+    let mut welded = Mesh::default();
+    let mut welded_ids = FxHashMap::<[u32; 16], u32>::default();
+
+    let vertex_ids: Vec<_> = (0..mesh.positions.len())
+        .map(|id| {
+            let position = mesh.positions[id];
+
+            let position = if epsilon > 0.0 {
+                (position / epsilon).round()
+            } else {
+                position
+            };
+
+            let normal = mesh.normals.get(id).copied().unwrap_or_default();
+            let uv = mesh.uvs.get(id).copied().unwrap_or_default();
+            let tangent = mesh.tangents.get(id).copied().unwrap_or_default();
+            let color = mesh.colors.get(id).copied().unwrap_or_default();
+
+            let mut key = [0.0; 16];
+
+            key[0..3].copy_from_slice(&position.to_array());
+            key[3..6].copy_from_slice(&normal.to_array());
+            key[6..8].copy_from_slice(&uv.to_array());
+            key[8..12].copy_from_slice(&tangent.to_array());
+            key[12..16].copy_from_slice(&color.to_array());
+
+            // Adding zero turns negative zeros into positive ones, so that
+            // both get the same bit pattern
+            let key = key.map(|value: f32| (value + 0.0).to_bits());
+
+            match welded_ids.entry(key) {
+                Entry::Occupied(entry) => *entry.get(),
+
+                Entry::Vacant(entry) => {
+                    let welded_id = welded.positions.len() as u32;
+
+                    welded.positions.push(mesh.positions[id]);
+
+                    if !mesh.normals.is_empty() {
+                        welded.normals.push(normal);
+                    }
+
+                    if !mesh.uvs.is_empty() {
+                        welded.uvs.push(uv);
+                    }
+
+                    if !mesh.tangents.is_empty() {
+                        welded.tangents.push(tangent);
+                    }
+
+                    if !mesh.colors.is_empty() {
+                        welded.colors.push(color);
+                    }
+
+                    *entry.insert(welded_id)
+                }
+            }
+        })
+        .collect();
+
+    let indices = (0..mesh.triangle_count())
+        .flat_map(|triangle_idx| mesh.triangle_indices(triangle_idx))
+        .map(|id| vertex_ids[id]);
+
+    welded.indices = Some(match mesh.indices {
+        Some(MeshIndices::U16(_)) => {
+            MeshIndices::U16(indices.map(|id| id as u16).collect())
+        }
+        _ => MeshIndices::U32(indices.collect()),
+    });
+
+    report.welded_vertices += mesh.positions.len() - welded.positions.len();
+
+    *mesh = welded;
+}
+
+/// Generates normals, if the mesh doesn't have any; returns whether it did
+/// so.
+pub fn generate_normals(mesh: &mut Mesh) -> bool {
+    if !mesh.normals.is_empty() || mesh.positions.is_empty() {
+        return false;
+    }
+
+    let mut normals = vec![Vec3::ZERO; mesh.positions.len()];
+
+    for triangle_idx in 0..mesh.triangle_count() {
+        let ids = mesh.triangle_indices(triangle_idx);
+        let [a, b, c] = ids.map(|id| mesh.positions[id]);
+
+        // Cross product's length is proportional to triangle's area, so
+        // larger triangles get to affect the normal more
+        let normal = (b - a).cross(c - a);
+
+        for id in ids {
+            normals[id] += normal;
+        }
+    }
+
+    for normal in &mut normals {
+        *normal = normal.normalize_or_zero();
+    }
+
+    mesh.normals = normals;
+
+    true
+}
+
+/// Generates MikkTSpace tangents, if the mesh doesn't have any (but has
+/// normals and UVs); returns whether it did so.
+///
+/// MikkTSpace assigns tangents to triangles' corners, so vertices shared by
+/// triangles with different tangents get the tangent of the last one.
+pub fn generate_tangents(mesh: &mut Mesh) -> bool {
+    if !mesh.tangents.is_empty()
+        || mesh.normals.is_empty()
+        || mesh.uvs.is_empty()
+    {
+        return false;
+    }
+
+    let mut geometry = Geometry {
+        tangents: vec![Vec4::ZERO; mesh.positions.len()],
+        mesh,
+    };
+
+    if !bevy_mikktspace::generate_tangents(&mut geometry) {
+        return false;
+    }
+
+    mesh.tangents = geometry.tangents;
+
+    true
+}
+
+fn drop_mismatched<T>(values: &mut Vec<T>, len: usize) -> usize {
+    if values.is_empty() || values.len() == len {
+        0
+    } else {
+        values.clear();
+        1
+    }
+}
+
+fn is_degenerate(a: Vec3, b: Vec3, c: Vec3) -> bool {
+    let longest_edge = (b - a)
+        .length_squared()
+        .max((c - b).length_squared())
+        .max((a - c).length_squared());
+
+    // Comparing the area against the longest edge (instead of some absolute
+    // threshold) makes this independent of mesh's scale
+    (b - a).cross(c - a).length() <= 1e-6 * longest_edge
+}
+
+fn retain_triangles<T>(values: &[T], keep: &[bool]) -> Vec<T>
+where
+    T: Copy,
+{
+    values
+        .chunks_exact(3)
+        .zip(keep)
+        .filter(|&(_, &keep)| keep)
+        .flat_map(|(triangle, _)| triangle)
+        .copied()
+        .collect()
+}
+
+struct Geometry<'a> {
+    mesh: &'a Mesh,
+    tangents: Vec<Vec4>,
+}
+
+impl bevy_mikktspace::Geometry for Geometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.mesh.triangle_count()
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        let id = self.mesh.triangle_indices(face)[vert];
+
+        self.mesh.positions[id].to_array()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        let id = self.mesh.triangle_indices(face)[vert];
+
+        self.mesh.normals[id].to_array()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        let id = self.mesh.triangle_indices(face)[vert];
+
+        self.mesh.uvs[id].to_array()
+    }
+
+    fn set_tangent_encoded(
+        &mut self,
+        tangent: [f32; 4],
+        face: usize,
+        vert: usize,
+    ) {
+        let id = self.mesh.triangle_indices(face)[vert];
+
+        self.tangents[id] = Vec4::from(tangent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spirv_std::glam::{vec2, vec3, Vec2};
+
+    use super::*;
+    use crate::MeshPreprocessing;
+
+    fn quad() -> Vec<Vec3> {
+        vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ]
+    }
+
+    fn indices(mesh: &Mesh) -> Vec<usize> {
+        mesh.indices().unwrap().iter().collect()
+    }
+
+    #[test]
+    fn validate() {
+        let mut target = Mesh::default()
+            .with_positions([
+                vec3(0.0, 0.0, 0.0),
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+                vec3(1.0, 1.0, 0.0),
+                vec3(f32::NAN, 0.0, 0.0),
+                vec3(2.0, 0.0, 0.0),
+            ])
+            .with_normals([Vec3::Z; 2])
+            .with_uvs([Vec2::ZERO; 6])
+            .with_indices(vec![
+                0u32, 1, 2, // valid
+                0, 1, 6, // out of bounds
+                0, 4, 2, // non-finite
+                0, 1, 5, // degenerate
+                1, 3, 2, // valid
+                0, 1, // incomplete
+            ]);
+
+        let report = target.validate();
+
+        assert_eq!(
+            MeshReport {
+                out_of_bounds_triangles: 1,
+                non_finite_triangles: 1,
+                degenerate_triangles: 1,
+                mismatched_attributes: 1,
+                ..Default::default()
+            },
+            report
+        );
+
+        assert_eq!(3, report.dropped_triangles());
+        assert_eq!(vec![0, 1, 2, 1, 3, 2], indices(&target));
+        assert!(target.normals().is_empty());
+        assert_eq!(6, target.uvs().len());
+    }
+
+    #[test]
+    fn validate_non_indexed() {
+        let mut positions = quad();
+
+        positions.insert(3, vec3(0.0, f32::INFINITY, 0.0));
+        positions.insert(4, vec3(0.0, 0.0, 0.0));
+        positions.insert(5, vec3(0.0, 0.0, 1.0));
+
+        let uvs: Vec<_> = (0..positions.len())
+            .map(|idx| vec2(idx as f32, 0.0))
+            .collect();
+
+        let mut target =
+            Mesh::default().with_positions(positions).with_uvs(uvs);
+
+        let report = target.validate();
+
+        assert_eq!(1, report.non_finite_triangles);
+        assert_eq!(1, report.dropped_triangles());
+        assert_eq!(quad(), target.positions());
+
+        // Attributes have to be dropped together with their positions
+        assert_eq!(
+            vec![0.0, 1.0, 2.0, 6.0, 7.0, 8.0],
+            target.uvs().iter().map(|uv| uv.x).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn validate_valid_mesh() {
+        let mut target = Mesh::default()
+            .with_positions(quad())
+            .with_normals([Vec3::Z; 6]);
+
+        assert!(target.validate().is_unchanged());
+        assert_eq!(quad(), target.positions());
+        assert!(target.indices().is_none());
+    }
+
+    #[test]
+    fn weld() {
+        let mut target = Mesh::default().with_positions(quad());
+        let mut report = MeshReport::default();
+
+        super::weld(&mut target, 0.0, &mut report);
+
+        assert_eq!(2, report.welded_vertices);
+        assert_eq!(4, target.vertex_count());
+        assert_eq!(vec![0, 1, 2, 0, 2, 3], indices(&target));
+        assert!(matches!(target.indices(), Some(MeshIndices::U32(_))));
+    }
+
+    #[test]
+    fn weld_with_epsilon() {
+        let mut positions = quad();
+
+        positions[3] += vec3(1e-4, -1e-4, 0.0);
+        positions[4] += vec3(0.0, 1e-4, -1e-4);
+
+        // Without epsilon, only exact duplicates get merged
+        let mut target = Mesh::default().with_positions(positions.clone());
+        let mut report = MeshReport::default();
+
+        super::weld(&mut target, 0.0, &mut report);
+
+        assert_eq!(0, report.welded_vertices);
+        assert_eq!(6, target.vertex_count());
+
+        // ... and with epsilon, close vertices get merged as well
+        let mut target = Mesh::default().with_positions(positions);
+        let mut report = MeshReport::default();
+
+        super::weld(&mut target, 1e-2, &mut report);
+
+        assert_eq!(2, report.welded_vertices);
+        assert_eq!(4, target.vertex_count());
+        assert_eq!(vec![0, 1, 2, 0, 2, 3], indices(&target));
+    }
+
+    #[test]
+    fn weld_u16() {
+        let mut target = Mesh::default()
+            .with_positions(quad())
+            .with_indices(vec![0u16, 1, 2, 3, 4, 5]);
+
+        let mut report = MeshReport::default();
+
+        super::weld(&mut target, 0.0, &mut report);
+
+        assert_eq!(2, report.welded_vertices);
+        assert_eq!(vec![0, 1, 2, 0, 2, 3], indices(&target));
+        assert!(matches!(target.indices(), Some(MeshIndices::U16(_))));
+    }
+
+    #[test]
+    fn weld_keeps_different_attributes() {
+        let mut normals = [Vec3::Z; 6];
+
+        normals[3] = Vec3::X;
+
+        let mut target =
+            Mesh::default().with_positions(quad()).with_normals(normals);
+
+        let mut report = MeshReport::default();
+
+        super::weld(&mut target, 0.0, &mut report);
+
+        assert_eq!(1, report.welded_vertices);
+        assert_eq!(5, target.vertex_count());
+        assert_eq!(5, target.normals().len());
+        assert_eq!(vec![0, 1, 2, 3, 2, 4], indices(&target));
+    }
+
+    #[test]
+    fn generate_normals() {
+        let mut target = Mesh::default().with_positions(quad());
+
+        assert!(super::generate_normals(&mut target));
+        assert_eq!(vec![Vec3::Z; 6], target.normals());
+
+        // ---
+
+        let normals = vec![Vec3::X; 6];
+
+        let mut target = Mesh::default()
+            .with_positions(quad())
+            .with_normals(normals.clone());
+
+        assert!(!super::generate_normals(&mut target));
+        assert_eq!(normals, target.normals());
+    }
+
+    #[test]
+    fn preprocess() {
+        let mut positions = quad();
+
+        // Triangle that collapses once welded
+        positions.extend([
+            vec3(0.0, 0.0, 0.0),
+            vec3(1e-4, 0.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+        ]);
+
+        let mut target = Mesh::default().with_positions(positions);
+
+        let report = target.preprocess(MeshPreprocessing {
+            weld: Some(1e-2),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            MeshReport {
+                degenerate_triangles: 1,
+                welded_vertices: 4,
+                generated_normals: true,
+                ..Default::default()
+            },
+            report
+        );
+
+        assert_eq!(2, target.triangle_count());
+        assert_eq!(target.vertex_count(), target.normals().len());
+        assert!(target.tangents().is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+/// Steps performed by [`crate::Mesh::preprocess()`].
+///
+/// Invalid triangles are always dropped, the rest is configurable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshPreprocessing {
+    /// When set, merges vertices whose positions are closer than this
+    /// distance and whose other attributes are equal; the mesh becomes then
+    /// indexed.
+    ///
+    /// Vertices get merged by snapping them to a grid with cells of this
+    /// size, so two close vertices that happen to lie on opposite sides of a
+    /// cell's boundary are not merged; use `0.0` to merge exact duplicates
+    /// only.
+    pub weld: Option<f32>,
+
+    /// Whether to generate normals if the mesh doesn't have any.
+    ///
+    /// Normals are area-weighted averages of normals of triangles sharing
+    /// given vertex, so they are smooth for indexed meshes and flat for
+    /// non-indexed ones.
+    pub generate_normals: bool,
+
+    /// Whether to generate MikkTSpace tangents if the mesh doesn't have any,
+    /// but has normals and UVs.
+    pub generate_tangents: bool,
+}
+
+impl Default for MeshPreprocessing {
+    fn default() -> Self {
+        Self {
+            weld: None,
+            generate_normals: true,
+            generate_tangents: true,
+        }
+    }
+}
+
+/// Describes what [`crate::Mesh::validate()`] or
+/// [`crate::Mesh::preprocess()`] changed in the mesh.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MeshReport {
+    /// Number of dropped triangles that referred to vertices past the end of
+    /// the positions
+    pub out_of_bounds_triangles: usize,
+
+    /// Number of dropped triangles with NaN or infinite positions
+    pub non_finite_triangles: usize,
+
+    /// Number of dropped triangles with (almost) zero area
+    pub degenerate_triangles: usize,
+
+    /// Number of dropped attributes (normals, UVs etc.) whose length didn't
+    /// match the number of positions
+    pub mismatched_attributes: usize,
+
+    /// Number of vertices removed by welding
+    pub welded_vertices: usize,
+
+    /// Whether normals have been generated
+    pub generated_normals: bool,
+
+    /// Whether tangents have been generated
+    pub generated_tangents: bool,
+}
+
+impl MeshReport {
+    pub fn dropped_triangles(&self) -> usize {
+        self.out_of_bounds_triangles
+            + self.non_finite_triangles
+            + self.degenerate_triangles
+    }
+
+    /// Returns whether the mesh has been left as-is.
+    pub fn is_unchanged(&self) -> bool {
+        *self == Self::default()
+    }
+}
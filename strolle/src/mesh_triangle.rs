@@ -1,7 +1,4 @@
-use glam::Affine3A;
-use spirv_std::glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
-
-use crate::Triangle;
+use spirv_std::glam::{Vec2, Vec3, Vec4};
 
 #[derive(Clone, Debug, Default)]
 pub struct MeshTriangle {
@@ -44,44 +41,7 @@ impl MeshTriangle {
         self.uvs
     }
 
-    pub(crate) fn build(
-        &self,
-        xform: Affine3A,
-        xform_inv: Affine3A,
-    ) -> Triangle {
-        let positions =
-            self.positions.map(|vertex| xform.transform_point3(vertex));
-
-        let normals = {
-            // Transforming normals requires inversing and transposing the
-            // matrix in order to get correct results under scaling, see:
-            //
-            // https://paroj.github.io/gltut/Illumination/Tut09%20Normal%20Transformation.html
-            let mat = Mat4::from(xform_inv).transpose();
-
-            self.normals
-                .map(|normal| mat.transform_vector3(normal).normalize())
-        };
-
-        let tangents = {
-            let sign = if xform.matrix3.determinant().is_sign_positive() {
-                1.0
-            } else {
-                -1.0
-            };
-
-            self.tangents.map(|tangent| {
-                (xform.matrix3 * tangent.xyz())
-                    .normalize()
-                    .extend(tangent.w * sign)
-            })
-        };
-
-        Triangle {
-            positions,
-            normals,
-            uvs: self.uvs,
-            tangents,
-        }
+    pub fn tangents(&self) -> [Vec4; 3] {
+        self.tangents
     }
 }
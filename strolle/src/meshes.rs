@@ -1,16 +1,33 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::mem;
 
 use derivative::Derivative;
+use glam::Vec3;
+use log::warn;
 
-use crate::{Mesh, Params};
+use crate::bvh::Bvh;
+use crate::triangles::Triangles;
+use crate::{BvhPrimitive, Mesh, Metrics, Params};
 
+/// Keeps track of meshes.
+///
+/// Meshes are kept only until the next refresh - once their triangles get
+/// uploaded into [`Triangles`], that's where their data lives (and where
+/// their trees get rebuilt from, see [`Self::invalidate()`]).
 #[derive(Debug, Derivative)]
 #[derivative(Default)]
 pub struct Meshes<P>
 where
     P: Params,
 {
-    meshes: HashMap<P::MeshHandle, Mesh>,
+    /// All meshes, including the ones that haven't been uploaded yet
+    handles: HashSet<P::MeshHandle>,
+
+    /// Meshes created or updated since the last refresh
+    pending: HashMap<P::MeshHandle, Mesh>,
+
+    /// Meshes created, updated, removed or invalidated since the last refresh
+    changed: HashSet<P::MeshHandle>,
 }
 
 impl<P> Meshes<P>
@@ -18,18 +35,81 @@ where
     P: Params,
 {
     pub fn insert(&mut self, mesh_handle: P::MeshHandle, mesh: Mesh) {
-        self.meshes.insert(mesh_handle, mesh);
+        self.handles.insert(mesh_handle.clone());
+        self.changed.insert(mesh_handle.clone());
+        self.pending.insert(mesh_handle, mesh);
     }
 
-    pub fn get(&self, mesh_handle: &P::MeshHandle) -> Option<&Mesh> {
-        self.meshes.get(mesh_handle)
+    pub fn contains(&self, mesh_handle: &P::MeshHandle) -> bool {
+        self.handles.contains(mesh_handle)
     }
 
     pub fn remove(&mut self, mesh_handle: &P::MeshHandle) {
-        self.meshes.remove(mesh_handle);
+        if self.handles.remove(mesh_handle) {
+            self.pending.remove(mesh_handle);
+            self.changed.insert(mesh_handle.clone());
+        }
+    }
+
+    /// Marks all meshes as changed, so that their trees get rebuilt during
+    /// the next refresh.
+    pub fn invalidate(&mut self) {
+        self.changed.extend(self.handles.iter().cloned());
     }
 
     pub fn len(&self) -> usize {
-        self.meshes.len()
+        self.handles.len()
+    }
+
+    /// Uploads triangles and builds trees of meshes that have changed since
+    /// the last refresh; returns handles of those meshes.
+    pub fn refresh(
+        &mut self,
+        triangles: &mut Triangles<P>,
+        bvh: &mut Bvh<P>,
+        metrics: &Metrics,
+    ) -> HashSet<P::MeshHandle> {
+        let changed = mem::take(&mut self.changed);
+
+        for (mesh_handle, mesh) in mem::take(&mut self.pending) {
+            if mesh.triangle_count() > 0 {
+                triangles.insert(mesh_handle, &mesh);
+            } else {
+                warn!("Mesh {mesh_handle:?} contains no triangles");
+
+                self.handles.remove(&mesh_handle);
+            }
+        }
+
+        for mesh_handle in &changed {
+            if !self.handles.contains(mesh_handle) {
+                triangles.remove(mesh_handle);
+                bvh.remove_mesh(mesh_handle);
+                continue;
+            }
+
+            // Trees are built out of the uploaded triangles (instead of the
+            // meshes themselves), so that invalidated meshes can be rebuilt
+            // without keeping the meshes around
+            bvh.insert_mesh(
+                mesh_handle.clone(),
+                triangles.positions(mesh_handle).map(
+                    |(triangle_id, positions)| {
+                        let bounds = positions.into_iter().collect();
+
+                        let primitive = BvhPrimitive {
+                            id: triangle_id as u32,
+                            center: positions.iter().sum::<Vec3>() / 3.0,
+                            bounds,
+                        };
+
+                        (primitive, positions)
+                    },
+                ),
+                metrics,
+            );
+        }
+
+        changed
     }
 }
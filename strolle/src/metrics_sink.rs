@@ -0,0 +1,47 @@
+mod chrome_trace;
+mod logging;
+mod ring_buffer;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub use self::chrome_trace::*;
+pub use self::logging::*;
+pub use self::ring_buffer::*;
+
+/// Receives CPU timings of particular parts of [`crate::Engine::tick()`]; see
+/// [`crate::Engine::set_metrics_sink()`].
+///
+/// Since the engine takes ownership of the sink, sinks that have to be read
+/// later (e.g. [`RingBufferMetricsSink`]) can be wrapped in
+/// `Arc<Mutex<...>>`, which implements this trait as well.
+pub trait MetricsSink: Send {
+    /// Called after each measured span finishes.
+    ///
+    /// Nested spans (e.g. `tick.bvh.build` inside `tick.bvh`) finish - and so
+    /// are reported - before their parents.
+    fn record(&mut self, span: &MetricSpan);
+}
+
+impl<T> MetricsSink for Arc<Mutex<T>>
+where
+    T: MetricsSink,
+{
+    fn record(&mut self, span: &MetricSpan) {
+        self.lock().unwrap().record(span);
+    }
+}
+
+/// Single measured part of a tick.
+#[derive(Clone, Copy, Debug)]
+pub struct MetricSpan {
+    /// Name of the span, e.g. `tick.bvh.build`; the entire tick is reported as
+    /// `tick`
+    pub name: &'static str,
+
+    /// Frame during which the span has been measured
+    pub frame: u32,
+
+    pub start: Instant,
+    pub duration: Duration,
+}
@@ -0,0 +1,386 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use log::warn;
+
+use crate::{MetricSpan, MetricsSink};
+
+/// Sink that writes spans in the Chrome's trace event format, which can be
+/// loaded into `chrome://tracing`, Perfetto etc.
+///
+/// The JSON array gets closed when the sink is dropped (e.g. when it's
+/// replaced through [`crate::Engine::set_metrics_sink()`]), but trace viewers
+/// accept unclosed arrays as well, so it's fine to kill the application while
+/// it's being traced.
+#[derive(Debug)]
+pub struct ChromeTraceMetricsSink<W>
+where
+    W: Write,
+{
+    writer: W,
+    epoch: Instant,
+    has_events: bool,
+    has_failed: bool,
+}
+
+impl ChromeTraceMetricsSink<BufWriter<File>> {
+    /// Creates a sink that writes into given file, overwriting it.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W> ChromeTraceMetricsSink<W>
+where
+    W: Write,
+{
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            epoch: Instant::now(),
+            has_events: false,
+            has_failed: false,
+        }
+    }
+
+    fn write(&mut self, span: &MetricSpan) -> io::Result<()> {
+        let ts = span.start.saturating_duration_since(self.epoch);
+
+        let separator = if self.has_events { ",\n" } else { "[\n" };
+
+        write!(
+            self.writer,
+            "{separator}{{\"name\":{},\"ph\":\"X\",\"ts\":{:.3},\
+             \"dur\":{:.3},\"pid\":0,\"tid\":0,\"args\":{{\"frame\":{}}}}}",
+            JsonString(span.name),
+            ts.as_secs_f64() * 1_000_000.0,
+            span.duration.as_secs_f64() * 1_000_000.0,
+            span.frame,
+        )?;
+
+        self.has_events = true;
+
+        // Flushing once per tick keeps the file usable even if the sink never
+        // gets dropped
+        if span.name == "tick" {
+            self.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W> MetricsSink for ChromeTraceMetricsSink<W>
+where
+    W: Write + Send,
+{
+    fn record(&mut self, span: &MetricSpan) {
+        if self.has_failed {
+            return;
+        }
+
+        if let Err(err) = self.write(span) {
+            warn!("Couldn't write trace event, stopping the trace: {err}");
+            self.has_failed = true;
+        }
+    }
+}
+
+impl<W> Drop for ChromeTraceMetricsSink<W>
+where
+    W: Write,
+{
+    fn drop(&mut self) {
+        if self.has_failed {
+            return;
+        }
+
+        let closing = if self.has_events { "\n]\n" } else { "[]\n" };
+
+        let _ = self
+            .writer
+            .write_all(closing.as_bytes())
+            .and_then(|_| self.writer.flush());
+    }
+}
+
+/// Formats given string as a JSON string literal, quotes included.
+struct JsonString<'a>(&'a str);
+
+impl fmt::Display for JsonString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+
+        for ch in self.0.chars() {
+            match ch {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+
+                // Apart from the characters above, JSON requires escaping
+                // only control characters - everything else (including
+                // non-ASCII characters) can be written as-is
+                ch if ch < ' ' => write!(f, "\\u{:04x}", ch as u32)?,
+                ch => write!(f, "{ch}")?,
+            }
+        }
+
+        f.write_str("\"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::Peekable;
+    use std::str::Chars;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Minimal JSON value, just enough to check what the sink writes.
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> &Json {
+            let Json::Object(entries) = self else {
+                panic!("not an object: {self:?}");
+            };
+
+            entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value)
+                .unwrap_or_else(|| panic!("missing key: {key}"))
+        }
+    }
+
+    /// Parses given JSON document, panicking if it's malformed.
+    fn parse(json: &str) -> Json {
+        let mut chars = json.chars().peekable();
+        let value = parse_value(&mut chars);
+
+        skip_whitespace(&mut chars);
+        assert_eq!(None, chars.next(), "trailing characters");
+
+        value
+    }
+
+    fn skip_whitespace(chars: &mut Peekable<Chars>) {
+        while chars.next_if(|ch| " \t\r\n".contains(*ch)).is_some() {
+            //
+        }
+    }
+
+    fn expect(chars: &mut Peekable<Chars>, expected: &str) {
+        for expected in expected.chars() {
+            assert_eq!(Some(expected), chars.next());
+        }
+    }
+
+    fn parse_value(chars: &mut Peekable<Chars>) -> Json {
+        skip_whitespace(chars);
+
+        match chars.peek().copied() {
+            Some('n') => {
+                expect(chars, "null");
+                Json::Null
+            }
+
+            Some('t') => {
+                expect(chars, "true");
+                Json::Bool(true)
+            }
+
+            Some('f') => {
+                expect(chars, "false");
+                Json::Bool(false)
+            }
+
+            Some('"') => Json::String(parse_string(chars)),
+
+            Some('[') => {
+                chars.next();
+
+                Json::Array(parse_list(chars, ']', parse_value))
+            }
+
+            Some('{') => {
+                chars.next();
+
+                Json::Object(parse_list(chars, '}', |chars| {
+                    skip_whitespace(chars);
+
+                    let key = parse_string(chars);
+
+                    skip_whitespace(chars);
+                    expect(chars, ":");
+
+                    (key, parse_value(chars))
+                }))
+            }
+
+            Some(ch) if ch == '-' || ch.is_ascii_digit() => {
+                let mut number = String::new();
+
+                while let Some(ch) = chars
+                    .next_if(|ch| "+-.eE".contains(*ch) || ch.is_ascii_digit())
+                {
+                    number.push(ch);
+                }
+
+                Json::Number(number.parse().unwrap())
+            }
+
+            ch => panic!("unexpected character: {ch:?}"),
+        }
+    }
+
+    fn parse_list<T>(
+        chars: &mut Peekable<Chars>,
+        end: char,
+        mut parse_item: impl FnMut(&mut Peekable<Chars>) -> T,
+    ) -> Vec<T> {
+        let mut items = Vec::new();
+
+        skip_whitespace(chars);
+
+        if chars.next_if_eq(&end).is_some() {
+            return items;
+        }
+
+        loop {
+            items.push(parse_item(chars));
+            skip_whitespace(chars);
+
+            match chars.next() {
+                Some(',') => continue,
+                Some(ch) if ch == end => return items,
+                ch => panic!("unexpected character: {ch:?}"),
+            }
+        }
+    }
+
+    fn parse_string(chars: &mut Peekable<Chars>) -> String {
+        let mut string = String::new();
+
+        expect(chars, "\"");
+
+        loop {
+            match chars.next().expect("unterminated string") {
+                '"' => return string,
+
+                '\\' => match chars.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('/') => string.push('/'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('n') => string.push('\n'),
+                    Some('r') => string.push('\r'),
+                    Some('t') => string.push('\t'),
+
+                    Some('u') => {
+                        let code: String = chars.take(4).collect();
+                        let code = u32::from_str_radix(&code, 16).unwrap();
+
+                        string.push(char::from_u32(code).unwrap());
+                    }
+
+                    ch => panic!("invalid escape: {ch:?}"),
+                },
+
+                ch if ch < ' ' => panic!("unescaped control character"),
+                ch => string.push(ch),
+            }
+        }
+    }
+
+    fn span(name: &'static str, frame: u32) -> MetricSpan {
+        MetricSpan {
+            name,
+            frame,
+            start: Instant::now(),
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    /// Records given spans and drops the sink, returning what it wrote.
+    fn trace(spans: &[MetricSpan]) -> String {
+        let mut out = Vec::new();
+        let mut sink = ChromeTraceMetricsSink::new(&mut out);
+
+        for span in spans {
+            sink.record(span);
+        }
+
+        drop(sink);
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn events() {
+        let out = trace(&[span("tick.bvh", 1), span("tick", 1)]);
+
+        assert!(out.ends_with("\n]\n"));
+
+        let Json::Array(events) = parse(&out) else {
+            panic!("not an array: {out}");
+        };
+
+        assert_eq!(2, events.len());
+
+        for (event, (name, frame)) in
+            events.iter().zip([("tick.bvh", 1.0), ("tick", 1.0)])
+        {
+            assert_eq!(&Json::String(name.into()), event.get("name"));
+            assert_eq!(&Json::String("X".into()), event.get("ph"));
+            assert_eq!(&Json::Number(1500.0), event.get("dur"));
+            assert_eq!(&Json::Number(frame), event.get("args").get("frame"));
+
+            let Json::Number(ts) = event.get("ts") else {
+                panic!("ts is not a number");
+            };
+
+            assert!(*ts >= 0.0);
+        }
+    }
+
+    #[test]
+    fn escaping() {
+        let name = "a\"b\\c\nd\te\u{1}f\u{e9}g";
+        let out = trace(&[span(name, 0)]);
+
+        let Json::Array(events) = parse(&out) else {
+            panic!("not an array: {out}");
+        };
+
+        assert_eq!(&Json::String(name.into()), events[0].get("name"));
+
+        assert_eq!(
+            "\"a\\\"b\\\\c\\nd\\te\\u0001f\u{e9}g\"",
+            JsonString(name).to_string()
+        );
+    }
+
+    #[test]
+    fn empty_trace() {
+        let out = trace(&[]);
+
+        assert_eq!("[]\n", out);
+        assert_eq!(Json::Array(Vec::new()), parse(&out));
+    }
+}
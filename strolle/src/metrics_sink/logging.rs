@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use log::trace;
+
+use crate::{MetricSpan, MetricsSink};
+
+/// Sink that logs spans through [`log::trace!()`].
+#[derive(Clone, Debug, Default)]
+pub struct LogMetricsSink {
+    threshold: Duration,
+}
+
+impl LogMetricsSink {
+    /// Creates a sink that logs only the spans that took longer than given
+    /// duration.
+    pub fn new(threshold: Duration) -> Self {
+        Self { threshold }
+    }
+
+    /// Creates a sink with threshold read from the `STROLLE_METRIC_THRESHOLD`
+    /// environmental variable (e.g. `STROLLE_METRIC_THRESHOLD=1ms`), logging
+    /// all spans if it's missing.
+    ///
+    /// This is the sink used by default when the `metrics` feature is
+    /// enabled.
+    #[cfg(feature = "metrics")]
+    pub fn from_env() -> Self {
+        let threshold = std::env::var("STROLLE_METRIC_THRESHOLD")
+            .ok()
+            .map(|threshold| humantime::parse_duration(&threshold).unwrap())
+            .unwrap_or_default();
+
+        Self::new(threshold)
+    }
+}
+
+impl MetricsSink for LogMetricsSink {
+    fn record(&mut self, span: &MetricSpan) {
+        if span.duration > self.threshold {
+            trace!("metric({})={:?}", span.name, span.duration);
+        }
+    }
+}
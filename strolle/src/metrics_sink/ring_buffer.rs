@@ -0,0 +1,208 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use crate::{MetricSpan, MetricsSink};
+
+/// Sink that keeps the most recent durations of each span in memory, e.g. to
+/// draw histograms or compute percentiles.
+#[derive(Clone, Debug)]
+pub struct RingBufferMetricsSink {
+    capacity: usize,
+    samples: HashMap<&'static str, VecDeque<Duration>>,
+}
+
+impl RingBufferMetricsSink {
+    /// Creates a sink that keeps up to `capacity` samples per span.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+
+        Self {
+            capacity,
+            samples: Default::default(),
+        }
+    }
+
+    /// Returns names of all spans recorded so far, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.samples.keys().copied()
+    }
+
+    /// Returns samples of given span, from the oldest to the newest one.
+    pub fn samples(
+        &self,
+        name: &str,
+    ) -> impl Iterator<Item = Duration> + Clone + '_ {
+        self.samples.get(name).into_iter().flatten().copied()
+    }
+
+    pub fn average(&self, name: &str) -> Option<Duration> {
+        let samples = self.samples.get(name)?;
+
+        Some(samples.iter().sum::<Duration>() / (samples.len() as u32))
+    }
+
+    /// Returns the sample below which given percentage of samples lies, e.g.
+    /// `percentile("tick", 0.99)`.
+    pub fn percentile(&self, name: &str, percentile: f32) -> Option<Duration> {
+        let mut samples: Vec<_> = self.samples(name).collect();
+
+        if samples.is_empty() {
+            return None;
+        }
+
+        samples.sort_unstable();
+
+        let idx = (percentile.clamp(0.0, 1.0) * (samples.len() - 1) as f32)
+            .round() as usize;
+
+        Some(samples[idx])
+    }
+
+    /// Returns the number of samples that fall into each of `bucket_count`
+    /// buckets of given width, with the last bucket gathering all the longer
+    /// samples.
+    pub fn histogram(
+        &self,
+        name: &str,
+        bucket_width: Duration,
+        bucket_count: usize,
+    ) -> Vec<usize> {
+        let mut buckets = vec![0; bucket_count];
+
+        if bucket_count == 0 || bucket_width.is_zero() {
+            return buckets;
+        }
+
+        for sample in self.samples(name) {
+            let idx = (sample.as_nanos() / bucket_width.as_nanos()) as usize;
+
+            buckets[idx.min(bucket_count - 1)] += 1;
+        }
+
+        buckets
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+impl MetricsSink for RingBufferMetricsSink {
+    fn record(&mut self, span: &MetricSpan) {
+        let samples = self.samples.entry(span.name).or_default();
+
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+
+        samples.push_back(span.duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn sink(
+        capacity: usize,
+        samples: &[(&'static str, u64)],
+    ) -> RingBufferMetricsSink {
+        let mut sink = RingBufferMetricsSink::new(capacity);
+
+        for &(name, duration) in samples {
+            sink.record(&MetricSpan {
+                name,
+                frame: 0,
+                start: Instant::now(),
+                duration: ms(duration),
+            });
+        }
+
+        sink
+    }
+
+    #[test]
+    fn eviction() {
+        let sink = sink(
+            3,
+            &[("a", 1), ("a", 2), ("b", 10), ("a", 3), ("a", 4), ("a", 5)],
+        );
+
+        assert_eq!(
+            vec![ms(3), ms(4), ms(5)],
+            sink.samples("a").collect::<Vec<_>>()
+        );
+
+        assert_eq!(vec![ms(10)], sink.samples("b").collect::<Vec<_>>());
+        assert_eq!(0, sink.samples("c").count());
+
+        let mut names: Vec<_> = sink.names().collect();
+
+        names.sort();
+
+        assert_eq!(vec!["a", "b"], names);
+    }
+
+    #[test]
+    fn average() {
+        let sink = sink(3, &[("a", 100), ("a", 1), ("a", 2), ("a", 6)]);
+
+        assert_eq!(Some(ms(3)), sink.average("a"));
+        assert_eq!(None, sink.average("b"));
+    }
+
+    #[test]
+    fn percentile() {
+        let sink =
+            sink(10, &[("a", 5), ("a", 1), ("a", 4), ("a", 2), ("a", 3)]);
+
+        assert_eq!(Some(ms(1)), sink.percentile("a", 0.0));
+        assert_eq!(Some(ms(2)), sink.percentile("a", 0.25));
+        assert_eq!(Some(ms(3)), sink.percentile("a", 0.5));
+        assert_eq!(Some(ms(5)), sink.percentile("a", 0.99));
+        assert_eq!(Some(ms(5)), sink.percentile("a", 1.0));
+
+        // Out-of-range percentiles get clamped
+        assert_eq!(Some(ms(1)), sink.percentile("a", -1.0));
+        assert_eq!(Some(ms(5)), sink.percentile("a", 2.0));
+
+        assert_eq!(None, sink.percentile("b", 0.5));
+    }
+
+    #[test]
+    fn histogram() {
+        let sink = sink(
+            10,
+            &[
+                ("a", 0),
+                ("a", 9),
+                ("a", 10),
+                ("a", 25),
+                ("a", 30),
+                ("a", 99),
+            ],
+        );
+
+        assert_eq!(vec![2, 1, 3], sink.histogram("a", ms(10), 3));
+        assert_eq!(vec![6], sink.histogram("a", ms(10), 1));
+        assert_eq!(vec![0, 0, 0], sink.histogram("b", ms(10), 3));
+        assert_eq!(vec![0, 0], sink.histogram("a", Duration::ZERO, 2));
+        assert!(sink.histogram("a", ms(10), 0).is_empty());
+    }
+
+    #[test]
+    fn clear() {
+        let mut sink = sink(3, &[("a", 1), ("b", 2)]);
+
+        sink.clear();
+
+        assert_eq!(0, sink.names().count());
+        assert_eq!(None, sink.average("a"));
+    }
+}
@@ -0,0 +1,325 @@
+use glam::{Affine3A, Vec2, Vec3};
+
+use crate::{gpu, Material, Params};
+
+/// Result of [`crate::Engine::raycast()`].
+#[derive(Clone, Debug)]
+pub struct RayHit<P>
+where
+    P: Params,
+{
+    pub instance_handle: P::InstanceHandle,
+    pub material_handle: P::MaterialHandle,
+
+    /// Index of the triangle within instance's mesh (see
+    /// [`crate::Mesh::triangle_indices()`]).
+    pub triangle_idx: usize,
+
+    /// Barycentric coordinates of the hit point, relative to the triangle's
+    /// first, second and third vertex.
+    pub barycentrics: Vec3,
+
+    /// Distance from the ray's origin to the hit point.
+    pub distance: f32,
+
+    /// Hit point, in world-space.
+    pub point: Vec3,
+
+    /// Interpolated normal at the hit point, in world-space.
+    pub normal: Vec3,
+
+    /// Interpolated UV at the hit point.
+    pub uv: Vec2,
+}
+
+impl<P> RayHit<P>
+where
+    P: Params,
+{
+    /// Converts hit returned by [`Self::hit()`] into world-space.
+    ///
+    /// `ray` is the world-space ray, while `triangle` and `hit` are in
+    /// instance's object-space.
+    pub(crate) fn new(
+        instance_handle: P::InstanceHandle,
+        material_handle: P::MaterialHandle,
+        triangle_idx: usize,
+        triangle: &gpu::Triangle,
+        transform_inverse: Affine3A,
+        ray: gpu::Ray,
+        hit: gpu::TriangleHit,
+    ) -> Self {
+        let point = ray.at(hit.distance);
+
+        let barycentrics = Self::barycentrics(
+            triangle.positions(),
+            transform_inverse.transform_point3(point),
+        );
+
+        // `Triangle::hit()` yields normals in object-space
+        let normal =
+            (transform_inverse.matrix3.transpose() * hit.normal).normalize();
+
+        Self {
+            instance_handle,
+            material_handle,
+            triangle_idx,
+            barycentrics,
+            distance: hit.distance,
+            point,
+            normal,
+            uv: hit.uv,
+        }
+    }
+
+    /// Intersects given triangle the same way `Ray::traverse()` does, i.e.
+    /// ignoring hits on transparent parts of alpha-blended materials.
+    pub(crate) fn hit(
+        triangle: &gpu::Triangle,
+        material: &Material<P>,
+        ray: gpu::Ray,
+        hit: &mut gpu::TriangleHit,
+    ) -> bool {
+        let prev_hit = *hit;
+
+        if !triangle.hit(ray, hit) {
+            return false;
+        }
+
+        if material.alpha_mode.is_transparent(material.base_color.w) {
+            *hit = prev_hit;
+            return false;
+        }
+
+        true
+    }
+
+    pub(crate) fn barycentrics(positions: [Vec3; 3], point: Vec3) -> Vec3 {
+        let v0 = positions[1] - positions[0];
+        let v1 = positions[2] - positions[0];
+        let v2 = point - positions[0];
+
+        let d00 = v0.dot(v0);
+        let d01 = v0.dot(v1);
+        let d11 = v1.dot(v1);
+        let d20 = v2.dot(v0);
+        let d21 = v2.dot(v1);
+        let denom = d00 * d11 - d01 * d01;
+
+        let v = (d11 * d20 - d01 * d21) / denom;
+        let w = (d00 * d21 - d01 * d20) / denom;
+
+        Vec3::new(1.0 - v - w, v, w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::{vec2, vec3};
+
+    use super::*;
+    use crate::{AlphaMode, Blas, BvhConfig, BvhPrimitive};
+
+    #[derive(Debug)]
+    struct TestParams;
+
+    impl Params for TestParams {
+        type ImageHandle = usize;
+        type ImageTexture = Arc<wgpu::Texture>;
+        type InstanceHandle = usize;
+        type LightHandle = usize;
+        type MaterialHandle = usize;
+        type MeshHandle = usize;
+    }
+
+    fn triangle(
+        positions: [Vec3; 3],
+        normal: Vec3,
+        uvs: [Vec2; 3],
+    ) -> gpu::Triangle {
+        gpu::Triangle {
+            d0: positions[0].extend(uvs[0].x),
+            d1: normal.extend(uvs[0].y),
+            d3: positions[1].extend(uvs[1].x),
+            d4: normal.extend(uvs[1].y),
+            d6: positions[2].extend(uvs[2].x),
+            d7: normal.extend(uvs[2].y),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn barycentrics() {
+        let positions = [
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 2.0, 0.0),
+            vec3(0.0, 0.0, 3.0),
+        ];
+
+        let barycentrics =
+            |point| RayHit::<TestParams>::barycentrics(positions, point);
+
+        assert_eq!(Vec3::X, barycentrics(positions[0]));
+        assert_eq!(Vec3::Y, barycentrics(positions[1]));
+        assert_eq!(Vec3::Z, barycentrics(positions[2]));
+
+        assert!(barycentrics(positions.iter().sum::<Vec3>() / 3.0)
+            .abs_diff_eq(Vec3::splat(1.0 / 3.0), 1e-6));
+
+        assert!(barycentrics((positions[1] + positions[2]) / 2.0)
+            .abs_diff_eq(vec3(0.0, 0.5, 0.5), 1e-6));
+    }
+
+    #[test]
+    fn alpha_modes() {
+        // Two triangles, one behind another; the nearer one uses the material
+        // under test, the farther one is always opaque
+        let triangles = [1.0, 2.0].map(|z| {
+            triangle(
+                [vec3(-1.0, -1.0, z), vec3(3.0, -1.0, z), vec3(-1.0, 3.0, z)],
+                Vec3::Z,
+                Default::default(),
+            )
+        });
+
+        let blas = Blas::new(
+            triangles.iter().enumerate().map(|(id, triangle)| {
+                let positions = triangle.positions();
+                let bounds = positions.into_iter().collect();
+
+                let primitive = BvhPrimitive {
+                    id: id as u32,
+                    center: positions.iter().sum::<Vec3>() / 3.0,
+                    bounds,
+                };
+
+                (primitive, positions)
+            }),
+            &BvhConfig::default(),
+            None,
+        );
+
+        let opaque = Material::<TestParams>::default();
+
+        let cases = [
+            (AlphaMode::Opaque, 0.0, true),
+            (AlphaMode::Blend, 0.5, false),
+            (AlphaMode::Blend, 1.0, true),
+            (AlphaMode::Mask { cutoff: 0.5 }, 0.25, false),
+            (AlphaMode::Mask { cutoff: 0.5 }, 0.75, true),
+        ];
+
+        for (alpha_mode, alpha, expected_near) in cases {
+            let material = Material::<TestParams> {
+                base_color: Vec3::ONE.extend(alpha),
+                alpha_mode,
+                ..Default::default()
+            };
+
+            let ray = gpu::Ray::new(vec3(0.0, 0.0, -1.0), Vec3::Z);
+            let mut hit = gpu::TriangleHit::none();
+
+            let closest = blas.traverse(ray, &mut hit, |primitive, hit| {
+                let id = primitive.id as usize;
+                let material = if id == 0 { &material } else { &opaque };
+
+                RayHit::hit(&triangles[id], material, ray, hit)
+            });
+
+            let (expected_id, expected_distance) =
+                if expected_near { (0, 2.0) } else { (1, 3.0) };
+
+            assert_eq!(
+                Some(expected_id),
+                closest.map(|primitive| primitive.id),
+                "{alpha_mode:?}, alpha={alpha}"
+            );
+
+            assert_eq!(expected_distance, hit.distance);
+        }
+    }
+
+    #[test]
+    fn transformed_instance() {
+        // Triangle lying on the `x + z = 1` plane
+        let positions = [
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+            vec3(1.0, 1.0, 0.0),
+        ];
+
+        let uvs = [vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0)];
+
+        let triangle =
+            triangle(positions, vec3(1.0, 0.0, 1.0).normalize(), uvs);
+
+        // Non-uniform scale, so that normals cannot be transformed the same
+        // way as positions
+        let transform = Affine3A::from_scale_rotation_translation(
+            vec3(2.0, 1.0, 1.0),
+            Default::default(),
+            vec3(0.0, 0.0, 5.0),
+        );
+
+        let transform_inverse = transform.inverse();
+        let barycentrics = vec3(0.2, 0.3, 0.5);
+
+        let point = transform.transform_point3(
+            positions[0] * barycentrics.x
+                + positions[1] * barycentrics.y
+                + positions[2] * barycentrics.z,
+        );
+
+        let ray = gpu::Ray::new(point + vec3(0.0, 0.0, 4.0), -Vec3::Z);
+
+        // Same as `Bvh::traverse()`
+        let object_ray = gpu::Ray::new(
+            transform_inverse.transform_point3(ray.origin()),
+            transform_inverse.transform_vector3(ray.direction()),
+        );
+
+        let mut hit = gpu::TriangleHit::none();
+
+        assert!(RayHit::hit(
+            &triangle,
+            &Material::<TestParams>::default(),
+            object_ray,
+            &mut hit,
+        ));
+
+        let hit = RayHit::<TestParams>::new(
+            1,
+            2,
+            3,
+            &triangle,
+            transform_inverse,
+            ray,
+            hit,
+        );
+
+        assert_eq!(1, hit.instance_handle);
+        assert_eq!(2, hit.material_handle);
+        assert_eq!(3, hit.triangle_idx);
+        assert!((hit.distance - 4.0).abs() < 1e-5, "{}", hit.distance);
+        assert!(hit.point.abs_diff_eq(point, 1e-5), "{}", hit.point);
+
+        assert!(
+            hit.barycentrics.abs_diff_eq(barycentrics, 1e-5),
+            "{}",
+            hit.barycentrics
+        );
+
+        assert!(hit.uv.abs_diff_eq(vec2(0.3, 0.5), 1e-5), "{}", hit.uv);
+
+        // World-space plane is `x / 2 + z = 6`; `Triangle::hit()` flips the
+        // normal so that it faces the ray
+        assert!(
+            hit.normal
+                .abs_diff_eq(-vec3(0.5, 0.0, 1.0).normalize(), 1e-5),
+            "{}",
+            hit.normal
+        );
+    }
+}
@@ -0,0 +1,448 @@
+use std::fmt;
+
+use crate::{Instance, Light, Material, Params};
+
+/// Problem found in the scene; see [`crate::Engine::validate()`].
+#[derive(Clone, Debug)]
+pub enum SceneIssue<P>
+where
+    P: Params,
+{
+    /// Instance refers to a mesh that doesn't exist, so it's not rendered.
+    MissingMesh {
+        instance_handle: P::InstanceHandle,
+        mesh_handle: P::MeshHandle,
+    },
+
+    /// Instance refers to a material that doesn't exist, so it's not
+    /// rendered.
+    MissingMaterial {
+        instance_handle: P::InstanceHandle,
+        material_handle: P::MaterialHandle,
+    },
+
+    /// Material refers to an image that doesn't exist, so it's rendered as if
+    /// it didn't have this texture.
+    MissingImage {
+        material_handle: P::MaterialHandle,
+        image_handle: P::ImageHandle,
+    },
+
+    /// Instance's transform contains NaNs or infinities or cannot be inverted
+    /// (e.g. because it has zero scale).
+    InvalidTransform { instance_handle: P::InstanceHandle },
+
+    /// Material's parameter is NaN or out of its range (e.g. roughness larger
+    /// than one).
+    InvalidMaterial {
+        material_handle: P::MaterialHandle,
+        parameter: &'static str,
+    },
+
+    /// Light's parameter is NaN or out of its range (e.g. negative radius).
+    InvalidLight {
+        light_handle: P::LightHandle,
+        parameter: &'static str,
+    },
+}
+
+impl<P> SceneIssue<P>
+where
+    P: Params,
+{
+    /// Checks whether given instance refers to existing mesh and material and
+    /// whether its transform is valid.
+    pub(crate) fn check_instance(
+        instance_handle: &P::InstanceHandle,
+        instance: &Instance<P>,
+        has_mesh: impl Fn(&P::MeshHandle) -> bool,
+        has_material: impl Fn(&P::MaterialHandle) -> bool,
+    ) -> Vec<Self> {
+        let mut issues = Vec::new();
+
+        if !has_mesh(&instance.mesh_handle) {
+            issues.push(SceneIssue::MissingMesh {
+                instance_handle: instance_handle.clone(),
+                mesh_handle: instance.mesh_handle.clone(),
+            });
+        }
+
+        if !has_material(&instance.material_handle) {
+            issues.push(SceneIssue::MissingMaterial {
+                instance_handle: instance_handle.clone(),
+                material_handle: instance.material_handle.clone(),
+            });
+        }
+
+        if !instance.has_valid_transform() {
+            issues.push(SceneIssue::InvalidTransform {
+                instance_handle: instance_handle.clone(),
+            });
+        }
+
+        issues
+    }
+
+    /// Checks whether given material refers to existing images and whether
+    /// its parameters are valid.
+    pub(crate) fn check_material(
+        material_handle: &P::MaterialHandle,
+        material: &Material<P>,
+        has_image: impl Fn(&P::ImageHandle) -> bool,
+    ) -> Vec<Self> {
+        let missing_images = material
+            .textures()
+            .filter(|image_handle| !has_image(image_handle))
+            .map(|image_handle| SceneIssue::MissingImage {
+                material_handle: material_handle.clone(),
+                image_handle: image_handle.clone(),
+            });
+
+        let invalid_parameters =
+            material.invalid_parameters().into_iter().map(|parameter| {
+                SceneIssue::InvalidMaterial {
+                    material_handle: material_handle.clone(),
+                    parameter,
+                }
+            });
+
+        missing_images.chain(invalid_parameters).collect()
+    }
+
+    /// Checks whether given light's parameters are valid.
+    pub(crate) fn check_light(
+        light_handle: &P::LightHandle,
+        light: &Light,
+    ) -> Vec<Self> {
+        light
+            .invalid_parameters()
+            .into_iter()
+            .map(|parameter| SceneIssue::InvalidLight {
+                light_handle: light_handle.clone(),
+                parameter,
+            })
+            .collect()
+    }
+}
+
+impl<P> fmt::Display for SceneIssue<P>
+where
+    P: Params,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneIssue::MissingMesh {
+                instance_handle,
+                mesh_handle,
+            } => write!(
+                f,
+                "instance {instance_handle:?} refers to a missing mesh \
+                 {mesh_handle:?}"
+            ),
+
+            SceneIssue::MissingMaterial {
+                instance_handle,
+                material_handle,
+            } => write!(
+                f,
+                "instance {instance_handle:?} refers to a missing material \
+                 {material_handle:?}"
+            ),
+
+            SceneIssue::MissingImage {
+                material_handle,
+                image_handle,
+            } => write!(
+                f,
+                "material {material_handle:?} refers to a missing image \
+                 {image_handle:?}"
+            ),
+
+            SceneIssue::InvalidTransform { instance_handle } => {
+                write!(f, "instance {instance_handle:?} has invalid transform")
+            }
+
+            SceneIssue::InvalidMaterial {
+                material_handle,
+                parameter,
+            } => write!(
+                f,
+                "material {material_handle:?} has invalid {parameter}"
+            ),
+
+            SceneIssue::InvalidLight {
+                light_handle,
+                parameter,
+            } => write!(f, "light {light_handle:?} has invalid {parameter}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::{vec3, vec4, Affine3A, Vec3};
+
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct TestParams;
+
+    impl Params for TestParams {
+        type ImageHandle = &'static str;
+        type ImageTexture = Arc<wgpu::Texture>;
+        type InstanceHandle = &'static str;
+        type LightHandle = &'static str;
+        type MaterialHandle = &'static str;
+        type MeshHandle = &'static str;
+    }
+
+    type Issues = Vec<SceneIssue<TestParams>>;
+
+    fn messages(issues: Issues) -> Vec<String> {
+        issues.iter().map(|issue| issue.to_string()).collect()
+    }
+
+    fn check_instance(
+        transform: Affine3A,
+        has_mesh: bool,
+        has_material: bool,
+    ) -> Issues {
+        SceneIssue::check_instance(
+            &"instance",
+            &Instance::new("mesh", "material", transform),
+            |mesh_handle| {
+                assert_eq!("mesh", *mesh_handle);
+                has_mesh
+            },
+            |material_handle| {
+                assert_eq!("material", *material_handle);
+                has_material
+            },
+        )
+    }
+
+    #[test]
+    fn valid_instance() {
+        assert!(check_instance(Affine3A::IDENTITY, true, true).is_empty());
+    }
+
+    #[test]
+    fn missing_mesh() {
+        let issues = check_instance(Affine3A::IDENTITY, false, true);
+
+        assert!(matches!(
+            issues[..],
+            [SceneIssue::MissingMesh {
+                instance_handle: "instance",
+                mesh_handle: "mesh",
+            }]
+        ));
+
+        assert_eq!(
+            vec![r#"instance "instance" refers to a missing mesh "mesh""#],
+            messages(issues)
+        );
+    }
+
+    #[test]
+    fn missing_material() {
+        let issues = check_instance(Affine3A::IDENTITY, true, false);
+
+        assert!(matches!(
+            issues[..],
+            [SceneIssue::MissingMaterial {
+                instance_handle: "instance",
+                material_handle: "material",
+            }]
+        ));
+
+        assert_eq!(
+            vec![
+                r#"instance "instance" refers to a missing material "material""#
+            ],
+            messages(issues)
+        );
+    }
+
+    #[test]
+    fn invalid_transform() {
+        for transform in [
+            Affine3A::from_scale(Vec3::ZERO),
+            Affine3A::from_translation(vec3(f32::NAN, 0.0, 0.0)),
+            Affine3A::from_scale(vec3(1.0, f32::INFINITY, 1.0)),
+        ] {
+            let issues = check_instance(transform, true, true);
+
+            assert!(
+                matches!(
+                    issues[..],
+                    [SceneIssue::InvalidTransform {
+                        instance_handle: "instance"
+                    }]
+                ),
+                "{transform:?}"
+            );
+
+            assert_eq!(
+                vec![r#"instance "instance" has invalid transform"#],
+                messages(issues)
+            );
+        }
+    }
+
+    #[test]
+    fn many_instance_issues() {
+        let issues =
+            check_instance(Affine3A::from_scale(Vec3::ZERO), false, false);
+
+        assert!(matches!(
+            issues[..],
+            [
+                SceneIssue::MissingMesh { .. },
+                SceneIssue::MissingMaterial { .. },
+                SceneIssue::InvalidTransform { .. },
+            ]
+        ));
+    }
+
+    #[test]
+    fn missing_image() {
+        let material = Material::<TestParams> {
+            base_color_texture: Some("base_color"),
+            emissive_texture: Some("emissive"),
+            normal_map_texture: Some("normal_map"),
+            ..Default::default()
+        };
+
+        let issues = SceneIssue::check_material(
+            &"material",
+            &material,
+            |image_handle| *image_handle == "emissive",
+        );
+
+        assert!(matches!(
+            issues[..],
+            [
+                SceneIssue::MissingImage {
+                    material_handle: "material",
+                    image_handle: "base_color",
+                },
+                SceneIssue::MissingImage {
+                    material_handle: "material",
+                    image_handle: "normal_map",
+                },
+            ]
+        ));
+
+        assert_eq!(
+            vec![
+                r#"material "material" refers to a missing image "base_color""#,
+                r#"material "material" refers to a missing image "normal_map""#,
+            ],
+            messages(issues)
+        );
+    }
+
+    #[test]
+    fn invalid_material() {
+        let material = Material::<TestParams> {
+            base_color: vec4(1.0, 1.0, 1.0, 2.0),
+            perceptual_roughness: f32::NAN,
+            ior: 0.5,
+            ..Default::default()
+        };
+
+        let issues =
+            SceneIssue::check_material(&"material", &material, |_| true);
+
+        assert!(matches!(
+            issues[..],
+            [
+                SceneIssue::InvalidMaterial {
+                    material_handle: "material",
+                    parameter: "base_color",
+                },
+                SceneIssue::InvalidMaterial {
+                    parameter: "perceptual_roughness",
+                    ..
+                },
+                SceneIssue::InvalidMaterial {
+                    parameter: "ior",
+                    ..
+                },
+            ]
+        ));
+
+        assert_eq!(
+            vec![
+                r#"material "material" has invalid base_color"#,
+                r#"material "material" has invalid perceptual_roughness"#,
+                r#"material "material" has invalid ior"#,
+            ],
+            messages(issues)
+        );
+    }
+
+    #[test]
+    fn valid_material() {
+        let material = Material::<TestParams> {
+            base_color_texture: Some("base_color"),
+            ..Default::default()
+        };
+
+        assert!(SceneIssue::check_material(&"material", &material, |_| true)
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_light() {
+        let light = Light::Spot {
+            position: Vec3::ZERO,
+            radius: -1.0,
+            color: Vec3::ONE,
+            range: 10.0,
+            direction: Vec3::ZERO,
+            angle: 0.5,
+        };
+
+        let issues: Issues = SceneIssue::check_light(&"light", &light);
+
+        assert!(matches!(
+            issues[..],
+            [
+                SceneIssue::InvalidLight {
+                    light_handle: "light",
+                    parameter: "radius",
+                },
+                SceneIssue::InvalidLight {
+                    light_handle: "light",
+                    parameter: "direction",
+                },
+            ]
+        ));
+
+        assert_eq!(
+            vec![
+                r#"light "light" has invalid radius"#,
+                r#"light "light" has invalid direction"#,
+            ],
+            messages(issues)
+        );
+    }
+
+    #[test]
+    fn valid_light() {
+        let light = Light::Point {
+            position: Vec3::ZERO,
+            radius: 0.0,
+            color: Vec3::ONE,
+            range: 10.0,
+        };
+
+        let issues: Issues = SceneIssue::check_light(&"light", &light);
+
+        assert!(issues.is_empty());
+    }
+}
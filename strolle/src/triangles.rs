@@ -3,21 +3,35 @@ use std::fmt::Debug;
 use std::mem;
 use std::ops::Range;
 
-use crate::bvh::Bvh;
+use bytemuck::Pod;
+use spirv_std::glam::{Vec3, Vec4};
+
 use crate::utils::Allocator;
 use crate::{
-    gpu, Bindable, BufferFlushOutcome, BvhPrimitive, MappedStorageBuffer,
-    Params, Triangle,
+    gpu, Bindable, BufferFlushOutcome, MappedStorageBuffer, Mesh, Params,
 };
 
+/// Object-space triangles of all meshes.
+///
+/// Apart from the triangles themselves (used for ray-tracing), this keeps
+/// meshes' vertices and indices, which are used for rasterization.
+///
+/// This is the only place meshes' data is kept in once they get uploaded,
+/// see [`crate::Meshes`].
 #[derive(Debug)]
 pub struct Triangles<P>
 where
     P: Params,
 {
-    allocator: Allocator,
-    buffer: MappedStorageBuffer<Vec<gpu::Triangle>>,
-    index: HashMap<P::InstanceHandle, IndexedInstance>,
+    triangles: Slab<gpu::Triangle>,
+
+    /// Vertices, each laid out as: position + uv.x, normal + uv.y, tangent
+    vertices: Slab<[Vec4; 3]>,
+
+    /// Indices, relative to the first vertex of their mesh
+    indices: Slab<u32>,
+
+    index: HashMap<P::MeshHandle, IndexedMesh>,
     dirty: bool,
 }
 
@@ -27,178 +41,211 @@ where
 {
     pub fn new(device: &wgpu::Device) -> Self {
         Self {
-            allocator: Default::default(),
-            buffer: MappedStorageBuffer::new_default(device, "triangles"),
+            triangles: Slab::new(device, "triangles"),
+            vertices: Slab::new(device, "vertices"),
+            indices: Slab::new(device, "indices"),
             index: Default::default(),
             dirty: Default::default(),
         }
     }
 
-    pub fn create(
+    /// Creates or updates triangles of given mesh, returning their ids.
+    pub fn insert(
         &mut self,
-        bvh: &mut Bvh,
-        instance_handle: P::InstanceHandle,
-        triangles: impl Iterator<Item = Triangle> + ExactSizeIterator,
-        material_id: gpu::MaterialId,
-    ) {
-        assert!(
-            !self.index.contains_key(&instance_handle),
-            "instance {instance_handle:?} has been already added - now it can \
-             be only updated or removed"
-        );
+        mesh_handle: P::MeshHandle,
+        mesh: &Mesh,
+    ) -> Range<usize> {
+        let triangle_count = mesh.triangle_count();
+        let vertex_count = mesh.vertex_count();
 
         assert!(
-            triangles.len() > 0,
-            "instance {instance_handle:?} contains no triangles"
+            triangle_count > 0,
+            "mesh {mesh_handle:?} contains no triangles"
         );
 
-        let triangle_ids = if let Some(triangle_ids) =
-            self.allocator.take(triangles.len())
+        let (triangle_ids, vertex_ids, index_ids) =
+            match self.index.get(&mesh_handle) {
+                Some(prev)
+                    if prev.triangle_ids.len() == triangle_count
+                        && prev.vertex_ids.len() == vertex_count =>
+                {
+                    (
+                        prev.triangle_ids.clone(),
+                        prev.vertex_ids.clone(),
+                        prev.index_ids.clone(),
+                    )
+                }
+
+                _ => {
+                    self.remove(&mesh_handle);
+
+                    (
+                        self.triangles.take(triangle_count),
+                        self.vertices.take(vertex_count),
+                        self.indices.take(3 * triangle_count),
+                    )
+                }
+            };
+
+        for (triangle, tri) in mesh
+            .triangles()
+            .zip(&mut self.triangles.buffer[triangle_ids.clone()])
         {
-            self.create_reusing_space(bvh, triangles, material_id, triangle_ids)
-        } else {
-            self.create_allocating_space(bvh, triangles, material_id)
-        };
+            *tri = triangle.serialize();
+        }
+
+        for (vertex_idx, vertex) in self.vertices.buffer[vertex_ids.clone()]
+            .iter_mut()
+            .enumerate()
+        {
+            *vertex = mesh.vertex(vertex_idx);
+        }
+
+        for (triangle_idx, indices) in self.indices.buffer[index_ids.clone()]
+            .chunks_exact_mut(3)
+            .enumerate()
+        {
+            for (index, vertex_idx) in
+                indices.iter_mut().zip(mesh.triangle_indices(triangle_idx))
+            {
+                *index = vertex_idx as u32;
+            }
+        }
 
         self.index.insert(
-            instance_handle,
-            IndexedInstance {
-                triangle_ids,
+            mesh_handle,
+            IndexedMesh {
+                triangle_ids: triangle_ids.clone(),
+                vertex_ids,
+                index_ids,
                 dirty: true,
             },
         );
 
         self.dirty = true;
-    }
-
-    fn create_reusing_space(
-        &mut self,
-        bvh: &mut Bvh,
-        triangles: impl Iterator<Item = Triangle>,
-        material_id: gpu::MaterialId,
-        triangle_ids: Range<usize>,
-    ) -> Range<usize> {
-        let mut triangle_id = triangle_ids.start;
-
-        let iter = triangles
-            .into_iter()
-            .zip(&mut self.buffer[triangle_ids.clone()])
-            .zip(bvh.update(triangle_ids.clone()));
-
-        for ((triangle, tri), prim) in iter {
-            *tri = triangle.serialize();
-
-            *prim = BvhPrimitive {
-                triangle_id: gpu::TriangleId::new(triangle_id as u32),
-                material_id,
-                center: triangle.center(),
-                bounds: triangle.bounds(),
-            };
-
-            triangle_id += 1;
-        }
 
         triangle_ids
     }
 
-    fn create_allocating_space(
-        &mut self,
-        bvh: &mut Bvh,
-        triangles: impl Iterator<Item = Triangle>,
-        material_id: gpu::MaterialId,
-    ) -> Range<usize> {
-        let first_triangle_id = self.buffer.len();
-
-        for (triangle_idx, triangle) in triangles.enumerate() {
-            self.buffer.push(triangle.serialize());
-
-            bvh.add(BvhPrimitive {
-                triangle_id: gpu::TriangleId::new(
-                    (first_triangle_id + triangle_idx) as u32,
-                ),
-                material_id,
-                center: triangle.center(),
-                bounds: triangle.bounds(),
-            });
-        }
+    pub fn remove(&mut self, mesh_handle: &P::MeshHandle) {
+        let Some(mesh) = self.index.remove(mesh_handle) else {
+            return;
+        };
 
-        first_triangle_id..self.buffer.len()
+        self.triangles.give(mesh.triangle_ids);
+        self.vertices.give(mesh.vertex_ids);
+        self.indices.give(mesh.index_ids);
     }
 
-    pub fn update(
-        &mut self,
-        bvh: &mut Bvh,
-        instance_handle: &P::InstanceHandle,
-        triangles: impl Iterator<Item = Triangle> + ExactSizeIterator,
-        material_id: gpu::MaterialId,
-    ) {
-        let instance =
-            self.index.get_mut(instance_handle).unwrap_or_else(|| {
-                panic!("instance not known: {instance_handle:?}")
-            });
-
-        let iter = triangles
-            .into_iter()
-            .zip(&mut self.buffer[instance.triangle_ids.clone()])
-            .zip(bvh.update(instance.triangle_ids.clone()));
-
-        for ((triangle, tri), prim) in iter {
-            *tri = triangle.serialize();
+    /// Returns the number of triangles, not counting holes left by removed
+    /// meshes.
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
 
-            prim.material_id = material_id;
-            prim.center = triangle.center();
-            prim.bounds = triangle.bounds();
-        }
+    /// Returns how many bytes triangles, vertices and indices occupy in VRAM.
+    pub fn allocated_size(&self) -> usize {
+        self.triangles.buffer.allocated_size()
+            + self.vertices.buffer.allocated_size()
+            + self.indices.buffer.allocated_size()
+    }
 
-        instance.dirty = true;
-        self.dirty = true;
+    /// Returns which part of the buffers is occupied by holes left by removed
+    /// meshes, from `0.0` (no holes) to `1.0` (nothing but holes).
+    pub fn fragmentation(&self) -> f32 {
+        self.triangles
+            .fragmentation()
+            .max(self.vertices.fragmentation())
+            .max(self.indices.fragmentation())
     }
 
-    pub fn remove(
+    /// Moves triangles of all meshes next to each other, getting rid of holes
+    /// left by removed meshes; returns meshes whose triangles have been moved,
+    /// together with their old and new ids.
+    ///
+    /// Vertices and indices get compacted as well, but since they are not
+    /// referred to from anywhere else, their relocations are not reported.
+    pub fn compact(
         &mut self,
-        bvh: &mut Bvh,
-        instance_handle: &P::InstanceHandle,
-    ) {
-        let Some(instance) = self.index.remove(instance_handle) else {
-            return;
-        };
+    ) -> Vec<(P::MeshHandle, Range<usize>, Range<usize>)> {
+        let relocated = self.triangles.compact(self.index.iter_mut().map(
+            |(mesh_handle, mesh)| (mesh_handle.clone(), &mut mesh.triangle_ids),
+        ));
+
+        let relocated_vertices = self.vertices.compact(
+            self.index.iter_mut().map(|(mesh_handle, mesh)| {
+                (mesh_handle.clone(), &mut mesh.vertex_ids)
+            }),
+        );
 
-        self.allocator.give(instance.triangle_ids.clone());
+        let relocated_indices = self.indices.compact(
+            self.index.iter_mut().map(|(mesh_handle, mesh)| {
+                (mesh_handle.clone(), &mut mesh.index_ids)
+            }),
+        );
 
-        for prim in bvh.update(instance.triangle_ids) {
-            prim.kill();
+        for (mesh_handle, ..) in relocated
+            .iter()
+            .chain(&relocated_vertices)
+            .chain(&relocated_indices)
+        {
+            if let Some(mesh) = self.index.get_mut(mesh_handle) {
+                mesh.dirty = true;
+            }
         }
+
+        self.dirty = true;
+
+        relocated
     }
 
-    pub fn len(&self) -> usize {
-        self.buffer.len()
+    pub fn get(&self, triangle_id: gpu::TriangleId) -> &gpu::Triangle {
+        &self.triangles.buffer[triangle_id.get() as usize]
     }
 
-    pub fn count(&self, instance_handle: &P::InstanceHandle) -> Option<usize> {
+    /// Returns ids of triangles that belong to given mesh.
+    pub fn ids(&self, mesh_handle: &P::MeshHandle) -> Option<Range<usize>> {
         self.index
-            .get(instance_handle)
-            .map(|instance| instance.triangle_ids.len())
+            .get(mesh_handle)
+            .map(|mesh| mesh.triangle_ids.clone())
     }
 
-    pub fn as_vertex_buffer(
+    /// Returns ids and positions of triangles that belong to given mesh.
+    pub fn positions(
         &self,
-        instance_handle: &P::InstanceHandle,
-    ) -> Option<(usize, wgpu::BufferSlice<'_>)> {
-        let IndexedInstance { triangle_ids, .. } =
-            self.index.get(instance_handle)?;
+        mesh_handle: &P::MeshHandle,
+    ) -> impl Iterator<Item = (usize, [Vec3; 3])> + '_ {
+        self.ids(mesh_handle)
+            .unwrap_or_default()
+            .map(|triangle_id| {
+                (triangle_id, self.triangles.buffer[triangle_id].positions())
+            })
+    }
 
-        let vertices = 3 * triangle_ids.len();
+    /// Returns the buffer containing vertices of all meshes; see
+    /// [`Self::draw_range()`].
+    pub fn vertex_buffer(&self) -> wgpu::BufferSlice<'_> {
+        self.vertices.buffer.as_buffer().slice(..)
+    }
 
-        let vertex_buffer = {
-            let min = triangle_ids.start * mem::size_of::<gpu::Triangle>();
-            let min = min as wgpu::BufferAddress;
+    /// Returns the buffer containing indices of all meshes (as
+    /// [`wgpu::IndexFormat::Uint32`]); see [`Self::draw_range()`].
+    pub fn index_buffer(&self) -> wgpu::BufferSlice<'_> {
+        self.indices.buffer.as_buffer().slice(..)
+    }
 
-            // N.B. we could slice up to some `max`, but GPUs care only about
-            // the start of the buffer and the number of vertices
-            self.buffer.as_buffer().slice(min..)
-        };
+    /// Returns the range of indices and the base vertex that have to be passed
+    /// into `draw_indexed()` in order to rasterize given mesh.
+    pub fn draw_range(
+        &self,
+        mesh_handle: &P::MeshHandle,
+    ) -> Option<(Range<u32>, i32)> {
+        let mesh = self.index.get(mesh_handle)?;
+
+        let indices =
+            (mesh.index_ids.start as u32)..(mesh.index_ids.end as u32);
 
-        Some((vertices, vertex_buffer))
+        Some((indices, mesh.vertex_ids.start as i32))
     }
 
     pub fn flush(
@@ -210,37 +257,232 @@ where
             return BufferFlushOutcome::default();
         }
 
-        let reallocated = self.buffer.reallocate(device, queue);
+        let dirty_meshes: Vec<_> = self
+            .index
+            .values_mut()
+            .filter_map(|mesh| mem::take(&mut mesh.dirty).then_some(mesh))
+            .collect();
+
+        let reallocated = false
+            | self.triangles.flush(
+                device,
+                queue,
+                dirty_meshes.iter().map(|mesh| mesh.triangle_ids.clone()),
+            )
+            | self.vertices.flush(
+                device,
+                queue,
+                dirty_meshes.iter().map(|mesh| mesh.vertex_ids.clone()),
+            )
+            | self.indices.flush(
+                device,
+                queue,
+                dirty_meshes.iter().map(|mesh| mesh.index_ids.clone()),
+            );
+
+        BufferFlushOutcome { reallocated }
+    }
+
+    pub fn bind_readable(&self) -> impl Bindable + '_ {
+        self.triangles.buffer.bind_readable()
+    }
+}
+
+#[derive(Debug)]
+struct IndexedMesh {
+    triangle_ids: Range<usize>,
+    vertex_ids: Range<usize>,
+    index_ids: Range<usize>,
+    dirty: bool,
+}
+
+/// Buffer split into ranges owned by particular meshes.
+#[derive(Debug)]
+struct Slab<T> {
+    allocator: Allocator,
+    buffer: MappedStorageBuffer<Vec<T>>,
+
+    /// Whether the buffer has been compacted since the last flush, in which
+    /// case the GPU buffer can be shrunk
+    compacted: bool,
+}
+
+impl<T> Slab<T>
+where
+    T: Pod + Default,
+{
+    fn new(device: &wgpu::Device, label: &str) -> Self {
+        Self {
+            allocator: Default::default(),
+            buffer: MappedStorageBuffer::new_default(device, label),
+            compacted: Default::default(),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Range<usize> {
+        if let Some(ids) = self.allocator.take(len) {
+            ids
+        } else {
+            let ids = self.buffer.len()..(self.buffer.len() + len);
+
+            self.buffer.resize(ids.end, T::default());
+
+            ids
+        }
+    }
+
+    fn give(&mut self, ids: Range<usize>) {
+        self.allocator.give(ids);
+    }
+
+    fn len(&self) -> usize {
+        self.buffer.len() - self.allocator.available()
+    }
+
+    fn fragmentation(&self) -> f32 {
+        if self.buffer.is_empty() {
+            0.0
+        } else {
+            (self.allocator.available() as f32) / (self.buffer.len() as f32)
+        }
+    }
+
+    /// Moves given ranges next to each other, updating them in place; returns
+    /// the ranges that have been moved, together with their old and new ids.
+    fn compact<K>(
+        &mut self,
+        ranges: impl Iterator<Item = (K, &mut Range<usize>)>,
+    ) -> Vec<(K, Range<usize>, Range<usize>)> {
+        let relocated = compact(&mut self.buffer, ranges);
+
+        self.allocator = Default::default();
+        self.compacted = true;
+
+        relocated
+    }
+
+    /// Sends given ranges to the GPU (or the entire buffer, if it had to be
+    /// reallocated); returns whether the buffer has been reallocated.
+    fn flush(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dirty_ids: impl Iterator<Item = Range<usize>>,
+    ) -> bool {
+        let reallocated = if mem::take(&mut self.compacted) {
+            self.buffer.shrink(device, queue)
+        } else {
+            false
+        };
+
+        let reallocated = reallocated || self.buffer.reallocate(device, queue);
 
         if reallocated {
             // Reallocating already flushes the entire buffer, so there's no
             // need to flush it again
         } else {
-            for instance in self.index.values_mut() {
-                if !mem::take(&mut instance.dirty) {
-                    continue;
-                }
-
-                let offset = instance.triangle_ids.start
-                    * mem::size_of::<gpu::Triangle>();
-
-                let size = instance.triangle_ids.len()
-                    * mem::size_of::<gpu::Triangle>();
+            for ids in dirty_ids {
+                let offset = ids.start * mem::size_of::<T>();
+                let size = ids.len() * mem::size_of::<T>();
 
                 self.buffer.flush_part(queue, offset, size);
             }
         }
 
-        BufferFlushOutcome { reallocated }
+        reallocated
     }
+}
 
-    pub fn bind_readable(&self) -> impl Bindable + '_ {
-        self.buffer.bind_readable()
+/// Moves given ranges of items next to each other, updating them in place;
+/// returns the ranges that have been moved, together with their old and new
+/// ids.
+fn compact<'a, T, K>(
+    items: &mut Vec<T>,
+    ranges: impl Iterator<Item = (K, &'a mut Range<usize>)>,
+) -> Vec<(K, Range<usize>, Range<usize>)>
+where
+    T: Copy,
+{
+    let mut ranges: Vec<_> = ranges.collect();
+
+    ranges.sort_by_key(|(_, ids)| ids.start);
+
+    let mut relocated = Vec::new();
+    let mut len = 0;
+
+    for (key, ids) in ranges {
+        let old_ids = ids.clone();
+        let new_ids = len..(len + old_ids.len());
+
+        // Since we're going through ranges in order, items always move towards
+        // the beginning of the buffer, so we can't overwrite items that haven't
+        // been moved yet
+        if new_ids != old_ids {
+            items.copy_within(old_ids.clone(), new_ids.start);
+
+            *ids = new_ids.clone();
+            relocated.push((key, old_ids, new_ids));
+        }
+
+        len += ids.len();
     }
+
+    items.truncate(len);
+
+    relocated
 }
 
-#[derive(Debug)]
-struct IndexedInstance {
-    triangle_ids: Range<usize>,
-    dirty: bool,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact() {
+        // Buffer with holes at 0..2, 5..6 and 9..12
+        let mut items = vec![0, 0, 1, 1, 1, 0, 2, 2, 2, 0, 0, 0, 3];
+        let mut a = 2..5;
+        let mut b = 6..9;
+        let mut c = 12..13;
+
+        let relocated = super::compact(
+            &mut items,
+            // Order shouldn't matter
+            [("c", &mut c), ("a", &mut a), ("b", &mut b)].into_iter(),
+        );
+
+        assert_eq!(vec![1, 1, 1, 2, 2, 2, 3], items);
+        assert_eq!((0..3, 3..6, 6..7), (a, b, c));
+
+        assert_eq!(
+            vec![("a", 2..5, 0..3), ("b", 6..9, 3..6), ("c", 12..13, 6..7)],
+            relocated
+        );
+    }
+
+    #[test]
+    fn compact_without_holes() {
+        let mut items = vec![1, 1, 2, 2, 2, 0, 0];
+        let mut a = 0..2;
+        let mut b = 2..5;
+
+        let relocated = super::compact(
+            &mut items,
+            [("a", &mut a), ("b", &mut b)].into_iter(),
+        );
+
+        // Ranges that haven't moved shouldn't be reported, but the trailing
+        // hole still gets cut off
+        assert_eq!(vec![1, 1, 2, 2, 2], items);
+        assert_eq!((0..2, 2..5), (a, b));
+        assert!(relocated.is_empty());
+    }
+
+    #[test]
+    fn compact_everything_removed() {
+        let mut items = vec![0, 0, 0];
+        let relocated = super::compact::<_, ()>(&mut items, [].into_iter());
+
+        assert!(items.is_empty());
+        assert!(relocated.is_empty());
+    }
 }
@@ -38,6 +38,11 @@ impl Allocator {
         }
     }
 
+    /// Returns the total number of free items.
+    pub fn available(&self) -> usize {
+        self.slots.iter().map(|slot| slot.len()).sum()
+    }
+
     fn compact(&mut self) {
         if !mem::take(&mut self.dirty) || self.slots.is_empty() {
             return;
@@ -84,6 +89,8 @@ mod tests {
         target.give(0..8);
         target.give(10..15);
 
+        assert_eq!(13, target.available());
+
         assert_eq!(Some(0..4), target.take(4));
         assert_eq!(Some(4..8), target.take(4));
         assert_eq!(Some(10..14), target.take(4));
@@ -1,5 +1,6 @@
 use std::ops::{Add, AddAssign};
 
+use glam::Affine3A;
 use spirv_std::glam::Vec3;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -21,6 +22,46 @@ impl BoundingBox {
         self.max
     }
 
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    /// Returns a bounding box that contains this box transformed by given
+    /// matrix.
+    pub fn transform(&self, xform: Affine3A) -> Self {
+        (0..8)
+            .map(|corner| {
+                let point = Vec3::new(
+                    if corner & 1 == 0 {
+                        self.min.x
+                    } else {
+                        self.max.x
+                    },
+                    if corner & 2 == 0 {
+                        self.min.y
+                    } else {
+                        self.max.y
+                    },
+                    if corner & 4 == 0 {
+                        self.min.z
+                    } else {
+                        self.max.z
+                    },
+                );
+
+                xform.transform_point3(point)
+            })
+            .collect()
+    }
+
+    /// Returns the common part of both boxes, if any.
+    pub fn intersection(&self, other: Self) -> Option<Self> {
+        let min = self.min.max(other.min);
+        let max = self.max.min(other.max);
+
+        min.cmple(max).all().then(|| Self::new(min, max))
+    }
+
     pub fn extent(&self) -> Vec3 {
         self.max() - self.min()
     }
@@ -1,39 +1,88 @@
-use std::time::Instant;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-pub fn measure<T>(name: &str, f: impl FnOnce() -> T) -> T {
-    let tt = Instant::now();
-    let result = f();
+use crate::{MetricSpan, MetricsSink};
 
-    metric(name, tt);
+/// CPU timings collected during a single tick; see
+/// [`crate::EngineStats::timings`].
+///
+/// Apart from keeping the timings, this forwards each measured span into the
+/// metrics sink, if any.
+#[derive(Default)]
+pub struct Metrics {
+    // Uses a mutex so that measurements can be nested (e.g. `tick.bvh` and
+    // `tick.bvh.build`) without having to pass `&mut` around
+    state: Mutex<MetricsState>,
+}
 
-    result
+#[derive(Default)]
+struct MetricsState {
+    timings: Vec<(&'static str, Duration)>,
+    sink: Option<Box<dyn MetricsSink>>,
+    frame: u32,
 }
 
-#[cfg(feature = "metrics")]
-pub fn metric(metric: &str, tt: Instant) {
-    use std::env;
-    use std::sync::OnceLock;
-    use std::time::Duration;
+impl Metrics {
+    pub fn measure<T>(&self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let tt = Instant::now();
+        let result = f();
+
+        self.record(name, tt);
 
-    use log::trace;
+        result
+    }
 
-    static METRIC_THRESHOLD: OnceLock<Duration> = OnceLock::new();
+    /// Records time elapsed since `tt`; if given metric has been already
+    /// recorded (e.g. because it's measured once per mesh), the timings get
+    /// summed.
+    pub fn record(&self, name: &'static str, tt: Instant) {
+        let duration = tt.elapsed();
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
 
-    let threshold = METRIC_THRESHOLD.get_or_init(|| {
-        env::var("STROLLE_METRIC_THRESHOLD")
-            .ok()
-            .map(|threshold| humantime::parse_duration(&threshold).unwrap())
-            .unwrap_or_else(|| Duration::from_millis(0))
-    });
+        if let Some((_, timing)) =
+            state.timings.iter_mut().find(|(name2, _)| *name2 == name)
+        {
+            *timing += duration;
+        } else {
+            state.timings.push((name, duration));
+        }
 
-    let tt = tt.elapsed();
+        if let Some(sink) = &mut state.sink {
+            sink.record(&MetricSpan {
+                name,
+                frame: state.frame,
+                start: tt,
+                duration,
+            });
+        }
+    }
 
-    if tt > *threshold {
-        trace!("metric({metric})={tt:?}");
+    pub fn timings(&self) -> Vec<(&'static str, Duration)> {
+        self.state.lock().unwrap().timings.clone()
+    }
+
+    /// Forgets timings of the previous tick.
+    pub fn begin(&self, frame: u32) {
+        let mut state = self.state.lock().unwrap();
+
+        state.timings.clear();
+        state.frame = frame;
+    }
+
+    pub fn set_sink(&self, sink: Option<Box<dyn MetricsSink>>) {
+        self.state.lock().unwrap().sink = sink;
     }
 }
 
-#[cfg(not(feature = "metrics"))]
-pub fn metric(_metric: &str, _tt: Instant) {
-    //
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+
+        f.debug_struct("Metrics")
+            .field("timings", &state.timings)
+            .field("has_sink", &state.sink.is_some())
+            .finish()
+    }
 }